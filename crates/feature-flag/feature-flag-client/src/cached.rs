@@ -0,0 +1,304 @@
+//! Remote evaluation with an in-process cache of the server's resolutions, keyed by
+//! flag, requested type and a hash of the evaluation context. The targeting rules
+//! stay on the server; the client only keeps answers it has already been given.
+//!
+//! An entry is good until the server says otherwise: the cache follows the backend's
+//! event stream and drops a flag's entries when a `CONFIGURATION_CHANGED` event lists
+//! it, and everything on (re)connect or `RESYNC`. While the stream is down nothing is
+//! served from the cache, since changes could be going unseen. Entries also expire
+//! after [`ENTRY_TTL`], which bounds staleness from what no event reports, such as a
+//! `before`/`after` constraint against `now` coming true.
+//!
+//! Cache hits never reach the server, so like local mode the client counts them and
+//! reports them with `ReportEvaluations`, for the server's usage tracking.
+
+use crate::local::{EvaluationCounts, RECONNECT_MAX, RECONNECT_MIN, report_loop, with_jitter};
+use crate::{IdentifiedChannel, Resolution};
+use feature_flag_proto::evaluation_client::EvaluationClient;
+use feature_flag_proto::{EvaluationContext, EventStreamRequest, EventType};
+use prost::Message;
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+const MAX_ENTRIES: u64 = 10_000;
+const ENTRY_TTL: Duration = Duration::from_secs(60);
+
+/// Flag key, the `Resolution` type asked for, and the context hash.
+type Key = (String, TypeId, u64);
+
+pub(crate) struct ResolutionCache {
+    entries: moka::sync::Cache<Key, Cached>,
+    /// Bumped on every invalidation, so a resolution fetched across one isn't cached.
+    generation: AtomicU64,
+    /// Whether the event stream is up, and so whether entries can be trusted.
+    live: AtomicBool,
+    hits: Arc<EvaluationCounts>,
+}
+
+/// A cache slot claimed before asking the server, to [`ResolutionCache::insert`] the
+/// answer into.
+#[derive(Debug)]
+pub(crate) struct Lookup {
+    key: Key,
+    generation: u64,
+}
+
+impl ResolutionCache {
+    pub(crate) fn spawn(client: EvaluationClient<IdentifiedChannel>) -> Arc<Self> {
+        let cache = Arc::new(Self::new());
+        tokio::spawn(report_loop(client.clone(), cache.hits.clone()));
+        tokio::spawn(invalidation_loop(client, cache.clone()));
+        cache
+    }
+
+    fn new() -> Self {
+        Self {
+            entries: moka::sync::Cache::builder()
+                .max_capacity(MAX_ENTRIES)
+                .time_to_live(ENTRY_TTL)
+                .support_invalidation_closures()
+                .build(),
+            generation: AtomicU64::new(0),
+            live: AtomicBool::new(false),
+            hits: Arc::default(),
+        }
+    }
+
+    /// The cached resolution of `flag_key` as a `T` for `context`, or the slot to
+    /// store the server's answer in.
+    pub(crate) fn get<T: Cacheable>(
+        &self,
+        flag_key: &str,
+        context: &EvaluationContext,
+    ) -> Result<Resolution<T>, Lookup> {
+        let lookup = Lookup {
+            key: (
+                flag_key.to_owned(),
+                TypeId::of::<T>(),
+                context_hash(context),
+            ),
+            generation: self.generation.load(Ordering::Acquire),
+        };
+        if !self.live.load(Ordering::Acquire) {
+            return Err(lookup);
+        }
+        match self.entries.get(&lookup.key).and_then(|c| T::unwrap(&c)) {
+            Some(hit) => {
+                self.hits.count(flag_key, &hit.variant);
+                Ok(hit)
+            }
+            None => Err(lookup),
+        }
+    }
+
+    /// Cache `resolution` unless its flag may have changed since the lookup.
+    pub(crate) fn insert<T: Cacheable>(&self, lookup: Lookup, resolution: Resolution<T>) {
+        if !self.live.load(Ordering::Acquire) {
+            return;
+        }
+        self.entries.insert(lookup.key.clone(), T::wrap(resolution));
+        // An invalidation racing the insert may have missed it; drop it ourselves.
+        if self.generation.load(Ordering::Acquire) != lookup.generation {
+            self.entries.invalidate(&lookup.key);
+        }
+    }
+
+    fn invalidate_flags(&self, flag_keys: Vec<String>) {
+        if flag_keys.is_empty() {
+            return;
+        }
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        let flag_keys: HashSet<String> = flag_keys.into_iter().collect();
+        if let Err(e) = self
+            .entries
+            .invalidate_entries_if(move |(flag_key, _, _), _| flag_keys.contains(flag_key))
+        {
+            tracing::warn!("invalidating cached resolutions failed ({e}), dropping them all");
+            self.entries.invalidate_all();
+        }
+    }
+
+    fn invalidate_all(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.entries.invalidate_all();
+    }
+}
+
+async fn invalidation_loop(
+    client: EvaluationClient<IdentifiedChannel>,
+    cache: Arc<ResolutionCache>,
+) {
+    let mut backoff = RECONNECT_MIN;
+    loop {
+        match client.clone().stream_events(EventStreamRequest {}).await {
+            Ok(stream) => {
+                let mut stream = stream.into_inner();
+                loop {
+                    match stream.message().await {
+                        Ok(Some(event)) => {
+                            match EventType::try_from(event.r#type) {
+                                Ok(EventType::ConfigurationChanged) => {
+                                    cache.invalidate_flags(event.changed_flag_keys)
+                                }
+                                // READY opens every stream: whatever was cached before
+                                // may have changed unseen.
+                                _ => {
+                                    cache.invalidate_all();
+                                    cache.live.store(true, Ordering::Release);
+                                    backoff = RECONNECT_MIN;
+                                }
+                            }
+                        }
+                        Ok(None) => {
+                            tracing::warn!("event stream closed, reconnecting");
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!("event stream error: {e}, reconnecting");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("event stream connect failed: {e}"),
+        }
+        cache.live.store(false, Ordering::Release);
+        cache.invalidate_all();
+        tokio::time::sleep(with_jitter(backoff)).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// A stable hash of the context's encoding. Struct fields are a `BTreeMap`, so equal
+/// contexts encode, and hash, the same.
+fn context_hash(context: &EvaluationContext) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    context.encode_to_vec().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Clone)]
+pub(crate) enum Cached {
+    Bool(Resolution<bool>),
+    String(Resolution<String>),
+    Int(Resolution<i64>),
+    Float(Resolution<f64>),
+    Object(Resolution<prost_types::Struct>),
+}
+
+/// A `Resolution` value type the cache can hold.
+pub(crate) trait Cacheable: Clone + 'static {
+    fn wrap(resolution: Resolution<Self>) -> Cached;
+    fn unwrap(cached: &Cached) -> Option<Resolution<Self>>;
+}
+
+macro_rules! cacheable {
+    ($($t:ty => $variant:ident),* $(,)?) => {$(
+        impl Cacheable for $t {
+            fn wrap(resolution: Resolution<Self>) -> Cached {
+                Cached::$variant(resolution)
+            }
+
+            fn unwrap(cached: &Cached) -> Option<Resolution<Self>> {
+                match cached {
+                    Cached::$variant(resolution) => Some(resolution.clone()),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+cacheable! {
+    bool => Bool,
+    String => String,
+    i64 => Int,
+    f64 => Float,
+    prost_types::Struct => Object,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use feature_flag_proto::Reason;
+
+    fn live_cache() -> ResolutionCache {
+        let cache = ResolutionCache::new();
+        cache.live.store(true, Ordering::Release);
+        cache
+    }
+
+    fn resolution(value: bool) -> Resolution<bool> {
+        Resolution {
+            value,
+            variant: if value { "on" } else { "off" }.into(),
+            reason: Reason::TargetingMatch,
+            error_code: None,
+        }
+    }
+
+    fn context(targeting_key: &str) -> EvaluationContext {
+        EvaluationContext {
+            targeting_key: targeting_key.into(),
+            attributes: None,
+        }
+    }
+
+    #[test]
+    fn hit_after_insert() {
+        let cache = live_cache();
+        let lookup = cache.get::<bool>("f", &context("u1")).unwrap_err();
+        cache.insert(lookup, resolution(true));
+
+        let hit = cache.get::<bool>("f", &context("u1")).unwrap();
+        assert_eq!((hit.value, hit.variant.as_str()), (true, "on"));
+        // Another context, or another requested type, is a different entry.
+        assert!(cache.get::<bool>("f", &context("u2")).is_err());
+        assert!(cache.get::<String>("f", &context("u1")).is_err());
+    }
+
+    #[test]
+    fn answer_fetched_across_an_invalidation_is_dropped() {
+        let cache = live_cache();
+        let lookup = cache.get::<bool>("f", &context("u1")).unwrap_err();
+        // The flag changes while the server is being asked.
+        cache.invalidate_flags(vec!["f".into()]);
+        cache.insert(lookup, resolution(true));
+
+        assert!(cache.get::<bool>("f", &context("u1")).is_err());
+    }
+
+    #[test]
+    fn nothing_is_cached_or_served_while_the_stream_is_down() {
+        let cache = ResolutionCache::new();
+        let lookup = cache.get::<bool>("f", &context("u1")).unwrap_err();
+        cache.insert(lookup, resolution(true));
+        cache.live.store(true, Ordering::Release);
+        assert!(cache.get::<bool>("f", &context("u1")).is_err());
+
+        let lookup = cache.get::<bool>("f", &context("u1")).unwrap_err();
+        cache.insert(lookup, resolution(true));
+        cache.live.store(false, Ordering::Release);
+        assert!(cache.get::<bool>("f", &context("u1")).is_err());
+    }
+
+    #[test]
+    fn invalidating_a_flag_keeps_the_others() {
+        let cache = live_cache();
+        for flag_key in ["f", "g"] {
+            let lookup = cache.get::<bool>(flag_key, &context("u1")).unwrap_err();
+            cache.insert(lookup, resolution(true));
+        }
+
+        cache.invalidate_flags(vec!["f".into()]);
+        assert!(cache.get::<bool>("f", &context("u1")).is_err());
+        assert!(cache.get::<bool>("g", &context("u1")).is_ok());
+
+        cache.invalidate_all();
+        assert!(cache.get::<bool>("g", &context("u1")).is_err());
+    }
+}
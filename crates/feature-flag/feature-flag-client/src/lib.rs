@@ -2,23 +2,31 @@
 //! Rust services. The OpenFeature provider is built on top of this. Wire types come
 //! from [`feature_flag_proto`], re-exported here as [`proto`].
 //!
-//! Evaluation can run in two [modes](EvaluationMode), chosen at construction:
-//! [`Remote`](EvaluationMode::Remote) issues an RPC per evaluation, while
+//! Evaluation can run in three [modes](EvaluationMode), chosen at construction:
+//! [`Remote`](EvaluationMode::Remote) issues an RPC per evaluation,
 //! [`Local`](EvaluationMode::Local) streams the snapshot from the server and evaluates
-//! in-process, avoiding a round-trip per flag.
+//! in-process, avoiding a round-trip per flag, and [`Cached`](EvaluationMode::Cached)
+//! evaluates remotely but reuses answers until the server reports their flag changed.
+//! A local client can also keep its last snapshot on disk, or start from a static
+//! config file, to survive starting while the server is unreachable (see
+//! [`LocalOptions`]).
 
+mod cached;
 mod context;
 mod local;
 
 pub use context::Context;
 pub use feature_flag_proto as proto;
 
+use cached::{Cacheable, ResolutionCache};
 use feature_flag_proto::admin_client::AdminClient;
 use feature_flag_proto::evaluation_client::EvaluationClient;
 use feature_flag_proto::{
     EvaluationContext, Event, Reason, ResolutionMeta, ResolveAllResponse, ResolveRequest,
 };
 use local::LocalEvaluator;
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tonic::Streaming;
@@ -33,6 +41,8 @@ pub enum Error {
     InvalidEndpoint(String),
     #[error("invalid client id: {0}")]
     InvalidClientId(String),
+    #[error("invalid environment: {0}")]
+    InvalidEnvironment(String),
     #[error("connection error: {0}")]
     Connect(#[from] tonic::transport::Error),
     #[error("rpc error: {0}")]
@@ -42,14 +52,18 @@ pub enum Error {
 }
 
 /// Channel wrapped with the [`ClientIdInterceptor`], used by both the evaluation and
-/// admin clients so every request carries the `client-id` metadata header.
+/// admin clients so every request carries the `client-id` (and, if chosen,
+/// `environment`) metadata header.
 pub type IdentifiedChannel = InterceptedService<Channel, ClientIdInterceptor>;
 
 /// Injects the caller's `client-id` into the metadata of every outgoing request,
-/// including streaming opens, so the backend can identify who is connected.
+/// including streaming opens, so the backend can identify who is connected. Also
+/// injects the `environment` the client was connected to, if any; the backend uses
+/// its `default` environment otherwise.
 #[derive(Clone)]
 pub struct ClientIdInterceptor {
     client_id: MetadataValue<Ascii>,
+    environment: Option<MetadataValue<Ascii>>,
 }
 
 impl Interceptor for ClientIdInterceptor {
@@ -60,6 +74,11 @@ impl Interceptor for ClientIdInterceptor {
         request
             .metadata_mut()
             .insert("client-id", self.client_id.clone());
+        if let Some(environment) = &self.environment {
+            request
+                .metadata_mut()
+                .insert("environment", environment.clone());
+        }
         Ok(request)
     }
 }
@@ -72,6 +91,52 @@ pub enum EvaluationMode {
     Remote,
     /// Evaluate in-process against a server-streamed snapshot.
     Local,
+    /// One gRPC call per flag and distinct context, with the answer cached until the
+    /// backend's event stream reports the flag changed. Targeting rules stay on the
+    /// server. `resolve_all` isn't cached.
+    Cached,
+}
+
+/// Where a [`Local`](EvaluationMode::Local) client finds a snapshot when the server
+/// can't be reached at startup, for [`connect_local`](FeatureFlagClient::connect_local).
+/// A fresh enough cached snapshot is preferred over the bootstrap file; with neither,
+/// every flag resolves to an error until the server is reached. Either way the client
+/// keeps reconnecting in the background and switches to the server's snapshot once it
+/// gets one.
+#[derive(Clone, Debug, Default)]
+pub struct LocalOptions {
+    cache_file: Option<PathBuf>,
+    cache_max_age: Option<Duration>,
+    bootstrap_file: Option<PathBuf>,
+}
+
+impl LocalOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save the snapshot received from the server to `path` (as JSON) as it changes,
+    /// at most every few seconds, and start from it when the server is unreachable.
+    pub fn cache_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache_file = Some(path.into());
+        self
+    }
+
+    /// Ignore a cached snapshot last written longer than `max_age` ago. Unset, a
+    /// cached snapshot of any age is used.
+    pub fn cache_max_age(mut self, max_age: Duration) -> Self {
+        self.cache_max_age = Some(max_age);
+        self
+    }
+
+    /// Start from the flags and segments in `path`, a YAML or JSON document in the
+    /// `ffctl config` format with top-level `flags` and `segments` lists (see
+    /// [`ConfigFile`](feature_flag_engine::flag_config::ConfigFile)), for tests and
+    /// air-gapped runs.
+    pub fn bootstrap_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bootstrap_file = Some(path.into());
+        self
+    }
 }
 
 /// A resolved flag value plus its evaluation metadata. `error_code` is set when the
@@ -90,6 +155,7 @@ pub struct FeatureFlagClient {
     evaluation: EvaluationClient<IdentifiedChannel>,
     admin: AdminClient<IdentifiedChannel>,
     local: Option<Arc<LocalEvaluator>>,
+    cache: Option<Arc<ResolutionCache>>,
 }
 
 impl FeatureFlagClient {
@@ -117,26 +183,96 @@ impl FeatureFlagClient {
         Self::from_channel(channel, client_id, mode).await
     }
 
+    /// Connect in [local](EvaluationMode::Local) mode with the fallbacks in `options`.
+    /// When one of them has a snapshot, a server that can't be reached at once is
+    /// retried in the background rather than awaited.
+    pub async fn connect_local(
+        endpoint: impl Into<String>,
+        client_id: impl Into<String>,
+        options: LocalOptions,
+    ) -> Result<Self, Error> {
+        let channel = keepalive(
+            Channel::from_shared(endpoint.into())
+                .map_err(|e| Error::InvalidEndpoint(e.to_string()))?,
+        )
+        .connect_lazy();
+        Self::build(
+            channel,
+            client_id.into(),
+            None,
+            EvaluationMode::Local,
+            options,
+        )
+        .await
+    }
+
+    /// As [`connect_with`](Self::connect_with), reading and writing the backend's
+    /// `environment` namespace instead of its default one.
+    pub async fn connect_to_environment(
+        endpoint: impl Into<String>,
+        client_id: impl Into<String>,
+        environment: impl Into<String>,
+        mode: EvaluationMode,
+    ) -> Result<Self, Error> {
+        let channel = keepalive(
+            Channel::from_shared(endpoint.into())
+                .map_err(|e| Error::InvalidEndpoint(e.to_string()))?,
+        )
+        .connect_lazy();
+        Self::build(
+            channel,
+            client_id.into(),
+            Some(environment.into()),
+            mode,
+            LocalOptions::default(),
+        )
+        .await
+    }
+
     pub async fn from_channel(
         channel: Channel,
         client_id: impl Into<String>,
         mode: EvaluationMode,
     ) -> Result<Self, Error> {
-        let client_id = client_id.into();
+        Self::build(
+            channel,
+            client_id.into(),
+            None,
+            mode,
+            LocalOptions::default(),
+        )
+        .await
+    }
+
+    async fn build(
+        channel: Channel,
+        client_id: String,
+        environment: Option<String>,
+        mode: EvaluationMode,
+        local_options: LocalOptions,
+    ) -> Result<Self, Error> {
         let interceptor = ClientIdInterceptor {
             client_id: client_id
                 .parse()
                 .map_err(|_| Error::InvalidClientId(client_id))?,
+            environment: environment
+                .map(|env| env.parse().map_err(|_| Error::InvalidEnvironment(env)))
+                .transpose()?,
         };
         let evaluation = EvaluationClient::with_interceptor(channel.clone(), interceptor.clone());
-        let local = match mode {
-            EvaluationMode::Remote => None,
-            EvaluationMode::Local => Some(LocalEvaluator::bootstrap(evaluation.clone()).await?),
+        let (local, cache) = match mode {
+            EvaluationMode::Remote => (None, None),
+            EvaluationMode::Local => (
+                Some(LocalEvaluator::bootstrap(evaluation.clone(), local_options).await?),
+                None,
+            ),
+            EvaluationMode::Cached => (None, Some(ResolutionCache::spawn(evaluation.clone()))),
         };
         Ok(Self {
             evaluation,
             admin: AdminClient::with_interceptor(channel, interceptor),
             local,
+            cache,
         })
     }
 
@@ -153,13 +289,11 @@ impl FeatureFlagClient {
         if let Some(local) = &self.local {
             return Ok(local.resolve_bool(&flag_key, context));
         }
-        let resp = self
-            .evaluation
-            .clone()
-            .resolve_boolean(request(flag_key, context))
-            .await?
-            .into_inner();
-        Ok(resolution(resp.value, resp.meta))
+        self.resolve_remote(flag_key, context, |mut client, request| async move {
+            let resp = client.resolve_boolean(request).await?.into_inner();
+            Ok(resolution(resp.value, resp.meta))
+        })
+        .await
     }
 
     pub async fn resolve_string(
@@ -171,13 +305,11 @@ impl FeatureFlagClient {
         if let Some(local) = &self.local {
             return Ok(local.resolve_string(&flag_key, context));
         }
-        let resp = self
-            .evaluation
-            .clone()
-            .resolve_string(request(flag_key, context))
-            .await?
-            .into_inner();
-        Ok(resolution(resp.value, resp.meta))
+        self.resolve_remote(flag_key, context, |mut client, request| async move {
+            let resp = client.resolve_string(request).await?.into_inner();
+            Ok(resolution(resp.value, resp.meta))
+        })
+        .await
     }
 
     pub async fn resolve_int(
@@ -189,13 +321,11 @@ impl FeatureFlagClient {
         if let Some(local) = &self.local {
             return Ok(local.resolve_int(&flag_key, context));
         }
-        let resp = self
-            .evaluation
-            .clone()
-            .resolve_integer(request(flag_key, context))
-            .await?
-            .into_inner();
-        Ok(resolution(resp.value, resp.meta))
+        self.resolve_remote(flag_key, context, |mut client, request| async move {
+            let resp = client.resolve_integer(request).await?.into_inner();
+            Ok(resolution(resp.value, resp.meta))
+        })
+        .await
     }
 
     pub async fn resolve_float(
@@ -207,13 +337,11 @@ impl FeatureFlagClient {
         if let Some(local) = &self.local {
             return Ok(local.resolve_float(&flag_key, context));
         }
-        let resp = self
-            .evaluation
-            .clone()
-            .resolve_float(request(flag_key, context))
-            .await?
-            .into_inner();
-        Ok(resolution(resp.value, resp.meta))
+        self.resolve_remote(flag_key, context, |mut client, request| async move {
+            let resp = client.resolve_float(request).await?.into_inner();
+            Ok(resolution(resp.value, resp.meta))
+        })
+        .await
     }
 
     pub async fn resolve_object(
@@ -225,13 +353,36 @@ impl FeatureFlagClient {
         if let Some(local) = &self.local {
             return Ok(local.resolve_object(&flag_key, context));
         }
-        let resp = self
-            .evaluation
-            .clone()
-            .resolve_object(request(flag_key, context))
-            .await?
-            .into_inner();
-        Ok(resolution(resp.value.unwrap_or_default(), resp.meta))
+        self.resolve_remote(flag_key, context, |mut client, request| async move {
+            let resp = client.resolve_object(request).await?.into_inner();
+            Ok(resolution(resp.value.unwrap_or_default(), resp.meta))
+        })
+        .await
+    }
+
+    /// Ask the server with `call`, through the cache in [`Cached`](EvaluationMode::Cached)
+    /// mode.
+    async fn resolve_remote<T, F, Fut>(
+        &self,
+        flag_key: String,
+        context: EvaluationContext,
+        call: F,
+    ) -> Result<Resolution<T>, Error>
+    where
+        T: Cacheable,
+        F: FnOnce(EvaluationClient<IdentifiedChannel>, ResolveRequest) -> Fut,
+        Fut: Future<Output = Result<Resolution<T>, tonic::Status>>,
+    {
+        let Some(cache) = &self.cache else {
+            return Ok(call(self.evaluation.clone(), request(flag_key, context)).await?);
+        };
+        let lookup = match cache.get::<T>(&flag_key, &context) {
+            Ok(hit) => return Ok(hit),
+            Err(lookup) => lookup,
+        };
+        let resolution = call(self.evaluation.clone(), request(flag_key, context)).await?;
+        cache.insert(lookup, resolution.clone());
+        Ok(resolution)
     }
 
     pub async fn resolve_all(
@@ -1,54 +1,167 @@
-//! In-process local evaluation. The client opens the backend's `StreamSnapshot` RPC,
-//! holds the latest snapshot as an [`Engine`], and resolves flags without a network
-//! round-trip per evaluation. The stream pushes a fresh snapshot on every config
-//! change, so the local engine stays live off a single long-lived stream.
+//! In-process local evaluation. The client opens the backend's `StreamSnapshotDeltas`
+//! RPC, holds the latest snapshot as an [`Engine`], and resolves flags without a
+//! network round-trip per evaluation. The stream pushes only the flags and segments
+//! each config change touched, so the local engine stays live off a single long-lived
+//! stream without re-downloading the whole config.
+//!
+//! A (re)connecting client sends the version it holds and the scope it was built
+//! under (which flags the server limits this client to), and the server answers with
+//! what changed since (or the whole snapshot if it can't tell). A delta that doesn't
+//! start from the held version means something was missed, so the client reconnects
+//! to catch up. Against a server predating deltas it falls back to `StreamSnapshot`.
+//!
+//! With [`LocalOptions`], snapshots are also written to a cache file, at most once per
+//! [`SAVE_INTERVAL`], and a client that can't reach the server at startup begins from
+//! that file (if it's fresh enough) or from a static config file rather than from
+//! nothing.
+//!
+//! Since the server never sees these evaluations, the evaluator counts them per flag
+//! and served variant and reports the counts back with `ReportEvaluations` every
+//! [`REPORT_INTERVAL`], for the server's usage and stale-flag tracking.
 
-use crate::{Error, IdentifiedChannel, Resolution};
+use crate::{Error, IdentifiedChannel, LocalOptions, Resolution};
+use feature_flag_engine::flag_config::ConfigFile;
 use feature_flag_engine::{Engine, EvalContext, Snapshot, convert};
 use feature_flag_proto::evaluation_client::EvaluationClient;
 use feature_flag_proto::{
-    EvaluatedFlag, EvaluationContext, GetSnapshotRequest, Reason, ResolutionMeta,
-    ResolveAllResponse, SnapshotResponse, ValueType,
+    EvaluatedFlag, EvaluationContext, EvaluationCount, GetSnapshotRequest, Reason,
+    ReportEvaluationsRequest, ResolutionMeta, ResolveAllResponse, SnapshotDelta,
+    SnapshotResponse, StreamSnapshotDeltasRequest, ValueType,
 };
 use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Evaluation counts keyed by flag key and served variant key.
+type Counts = HashMap<(String, String), u64>;
+
+/// Evaluations the server didn't see, awaiting the next [`report_loop`] tick.
+#[derive(Default)]
+pub(crate) struct EvaluationCounts(Mutex<Counts>);
+
+impl EvaluationCounts {
+    pub(crate) fn count(&self, flag_key: &str, variant_key: &str) {
+        let mut counts = self.0.lock().unwrap();
+        *counts
+            .entry((flag_key.to_owned(), variant_key.to_owned()))
+            .or_default() += 1;
+    }
+
+    fn take(&self) -> Counts {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    /// Put back counts whose report failed, to go out with the next one.
+    fn restore(&self, batch: Counts) {
+        let mut counts = self.0.lock().unwrap();
+        for (key, n) in batch {
+            *counts.entry(key).or_default() += n;
+        }
+    }
+}
 
 pub(crate) struct LocalEvaluator {
     snapshot: ArcSwap<Snapshot>,
+    /// The server's `scope` for the stream `snapshot` came from; empty if unknown.
+    scope: Mutex<String>,
+    counts: Arc<EvaluationCounts>,
+    /// Signalled when the snapshot changes, for [`save_loop`] to write it out.
+    changed: Notify,
 }
 
 impl LocalEvaluator {
     pub(crate) async fn bootstrap(
         client: EvaluationClient<IdentifiedChannel>,
+        options: LocalOptions,
     ) -> Result<Arc<Self>, Error> {
-        let (stream, evaluator) = match open_initial(&client).await {
+        let fallback = fallback_snapshot(&options)?;
+        // With something to serve meanwhile, don't hold up startup retrying.
+        let attempts = if fallback.is_some() {
+            1
+        } else {
+            BOOTSTRAP_ATTEMPTS
+        };
+        let (stream, held) = match open_initial(&client, attempts).await {
             Ok((stream, first)) => {
-                let evaluator = Arc::new(Self {
-                    snapshot: ArcSwap::from(build_snapshot(first)?),
-                });
-                (Some(stream), evaluator)
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "snapshot bootstrap failed ({e}), serving defaults and retrying in background"
-                );
-                let evaluator = Arc::new(Self {
-                    snapshot: ArcSwap::from(empty_snapshot()),
-                });
-                (None, evaluator)
+                let snapshot = convert::apply_delta(&Snapshot::default(), &first)
+                    .map_err(|e| Error::Snapshot(e.to_string()))?;
+                (Some(stream), CachedSnapshot::owned(first.scope, snapshot))
             }
+            Err(e) => match fallback {
+                Some((held, source)) => {
+                    tracing::warn!(
+                        "snapshot bootstrap failed ({e}), serving the {source} (version {}) meanwhile",
+                        held.snapshot.version
+                    );
+                    (None, held)
+                }
+                None => {
+                    tracing::warn!(
+                        "snapshot bootstrap failed ({e}), serving defaults and retrying in background"
+                    );
+                    (
+                        None,
+                        CachedSnapshot::owned(String::new(), Snapshot::default()),
+                    )
+                }
+            },
         };
+        let evaluator = Arc::new(Self::new(held));
+        if let Some(path) = options.cache_file {
+            if stream.is_some() {
+                evaluator.changed.notify_one();
+            }
+            tokio::spawn(save_loop(path, evaluator.clone()));
+        }
+        tokio::spawn(report_loop(client.clone(), evaluator.counts.clone()));
         tokio::spawn(refresh_loop(client, stream, evaluator.clone()));
         Ok(evaluator)
     }
 
-    fn apply(&self, snapshot: SnapshotResponse) {
-        match build_snapshot(snapshot) {
-            Ok(s) => self.snapshot.store(s),
-            Err(e) => tracing::error!("ignoring invalid snapshot: {e}"),
+    fn new(held: CachedSnapshot<'static>) -> Self {
+        Self {
+            snapshot: ArcSwap::from_pointee(held.snapshot.into_owned()),
+            scope: Mutex::new(held.scope.into_owned()),
+            counts: Arc::default(),
+            changed: Notify::new(),
+        }
+    }
+
+    fn version(&self) -> i64 {
+        self.snapshot.load().version
+    }
+
+    fn scope(&self) -> String {
+        self.scope.lock().unwrap().clone()
+    }
+
+    /// Swap in the snapshot `delta` produces from the current one. Returns false if the
+    /// delta doesn't follow on from the current version, leaving the snapshot as is.
+    /// Only the refresh loop writes, so the load and store can't race.
+    fn apply(&self, delta: &SnapshotDelta) -> bool {
+        let current = self.snapshot.load_full();
+        if !delta.full && delta.base_version != current.version {
+            return false;
+        }
+        if !delta.full && delta.version == current.version {
+            return true;
         }
+        match convert::apply_delta(&current, delta) {
+            Ok(s) => {
+                self.snapshot.store(Arc::new(s));
+                *self.scope.lock().unwrap() = delta.scope.clone();
+                self.changed.notify_one();
+            }
+            Err(e) => tracing::error!("ignoring invalid snapshot delta: {e}"),
+        }
+        true
     }
 
     fn engine(&self) -> Engine {
@@ -93,7 +206,11 @@ impl LocalEvaluator {
     ) -> Resolution<T> {
         let engine = self.engine();
         let eval_ctx = EvalContext::from(ctx);
-        match engine.evaluate(flag_key, &eval_ctx) {
+        let result = engine.evaluate(flag_key, &eval_ctx);
+        if let Ok(res) = &result {
+            self.counts.count(flag_key, &res.variant);
+        }
+        match result {
             Ok(res) => match extract(&res.value) {
                 Some(value) => Resolution {
                     value,
@@ -117,12 +234,15 @@ impl LocalEvaluator {
             }
             let value_type = ValueType::from(flag.value_type) as i32;
             let evaluated = match engine.evaluate(key, &eval_ctx) {
-                Ok(res) => EvaluatedFlag {
-                    flag_key: key.clone(),
-                    value_type,
-                    value: Some(convert::json_to_prost_value(&res.value)),
-                    meta: Some(ResolutionMeta::from(&res)),
-                },
+                Ok(res) => {
+                    self.counts.count(key, &res.variant);
+                    EvaluatedFlag {
+                        flag_key: key.clone(),
+                        value_type,
+                        value: Some(convert::json_to_prost_value(&res.value)),
+                        meta: Some(ResolutionMeta::from(&res)),
+                    }
+                }
                 Err(e) => EvaluatedFlag {
                     flag_key: key.clone(),
                     value_type,
@@ -136,14 +256,77 @@ impl LocalEvaluator {
     }
 }
 
-const RECONNECT_MIN: Duration = Duration::from_secs(1);
-const RECONNECT_MAX: Duration = Duration::from_secs(60);
+pub(crate) const RECONNECT_MIN: Duration = Duration::from_secs(1);
+pub(crate) const RECONNECT_MAX: Duration = Duration::from_secs(60);
 const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
 const BOOTSTRAP_ATTEMPTS: u32 = 8;
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Write the evaluator's snapshot to the cache file at `path` whenever it changes, but
+/// no more than once per [`SAVE_INTERVAL`]: changes in between are folded into one
+/// write of the latest snapshot. A failed write only costs the next cold start its
+/// fallback, so it's logged and otherwise ignored.
+async fn save_loop(path: PathBuf, evaluator: Arc<LocalEvaluator>) {
+    loop {
+        evaluator.changed.notified().await;
+        let scope = evaluator.scope();
+        let snapshot = evaluator.snapshot.load_full();
+        let target = path.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let held = CachedSnapshot {
+                scope: Cow::Owned(scope),
+                snapshot: Cow::Borrowed(&*snapshot),
+            };
+            write_cache(&target, &held)
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        if let Err(e) = written {
+            tracing::warn!("writing snapshot cache {} failed: {e}", path.display());
+        }
+        tokio::time::sleep(SAVE_INTERVAL).await;
+    }
+}
+
+pub(crate) async fn report_loop(
+    mut client: EvaluationClient<IdentifiedChannel>,
+    evaluations: Arc<EvaluationCounts>,
+) {
+    let mut tick = tokio::time::interval(REPORT_INTERVAL);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        let batch = evaluations.take();
+        if batch.is_empty() {
+            continue;
+        }
+        let counts = batch
+            .iter()
+            .map(|((flag_key, variant_key), n)| EvaluationCount {
+                flag_key: flag_key.clone(),
+                variant_key: variant_key.clone(),
+                count: *n,
+            })
+            .collect();
+        match client
+            .report_evaluations(ReportEvaluationsRequest { counts })
+            .await
+        {
+            Ok(_) => {}
+            // A server predating usage reporting; nothing to retry.
+            Err(status) if status.code() == tonic::Code::Unimplemented => {}
+            Err(status) => {
+                tracing::debug!("reporting evaluation counts failed: {status}");
+                evaluations.restore(batch);
+            }
+        }
+    }
+}
 
 async fn refresh_loop(
     client: EvaluationClient<IdentifiedChannel>,
-    initial: Option<tonic::Streaming<SnapshotResponse>>,
+    initial: Option<SnapshotStream>,
     evaluator: Arc<LocalEvaluator>,
 ) {
     let mut stream = initial;
@@ -156,7 +339,8 @@ async fn refresh_loop(
                 let s = loop {
                     tokio::time::sleep(with_jitter(backoff)).await;
                     backoff = (backoff * 2).min(RECONNECT_MAX);
-                    match tokio::time::timeout(OPEN_TIMEOUT, open_stream(client.clone())).await {
+                    let open = open_stream(client.clone(), evaluator.version(), evaluator.scope());
+                    match tokio::time::timeout(OPEN_TIMEOUT, open).await {
                         Ok(Ok(s)) => break s,
                         Ok(Err(e)) => tracing::warn!("snapshot stream reconnect failed: {e}"),
                         Err(_) => tracing::warn!("snapshot stream reconnect timed out"),
@@ -171,9 +355,20 @@ async fn refresh_loop(
 
         loop {
             match active.message().await {
-                Ok(Some(snapshot)) => {
-                    tracing::info!("received new snapshot: {}", snapshot.version);
-                    evaluator.apply(snapshot);
+                Ok(Some(delta)) => {
+                    tracing::info!(
+                        full = delta.full,
+                        "received snapshot delta: {}",
+                        delta.version
+                    );
+                    if !evaluator.apply(&delta) {
+                        tracing::warn!(
+                            "snapshot delta from {} doesn't follow {}, reconnecting",
+                            delta.base_version,
+                            evaluator.version()
+                        );
+                        break;
+                    }
                 }
                 Ok(None) => {
                     tracing::warn!("snapshot stream closed, reconnecting");
@@ -188,34 +383,24 @@ async fn refresh_loop(
     }
 }
 
-fn empty_snapshot() -> Arc<Snapshot> {
-    build_snapshot(SnapshotResponse::default()).expect("empty snapshot always builds")
-}
-
-fn with_jitter(d: Duration) -> Duration {
+pub(crate) fn with_jitter(d: Duration) -> Duration {
     let jitter = rand::random::<f64>() * 0.3 + 0.85;
     d.mul_f64(jitter)
 }
 
 async fn open_initial(
     client: &EvaluationClient<IdentifiedChannel>,
-) -> Result<(tonic::Streaming<SnapshotResponse>, SnapshotResponse), Error> {
+    attempts: u32,
+) -> Result<(SnapshotStream, SnapshotDelta), Error> {
     let mut backoff = RECONNECT_MIN;
     let mut last_err = None;
-    for attempt in 1..=BOOTSTRAP_ATTEMPTS {
-        match open_stream(client.clone()).await {
-            Ok(mut stream) => match stream.message().await {
-                Ok(Some(first)) => return Ok((stream, first)),
-                Ok(None) => {
-                    last_err = Some(Error::Snapshot(
-                        "snapshot stream closed before first message".into(),
-                    ))
-                }
-                Err(e) => last_err = Some(e.into()),
-            },
-            Err(e) => last_err = Some(e),
+    for attempt in 1..=attempts {
+        match tokio::time::timeout(OPEN_TIMEOUT, first_message(client.clone())).await {
+            Ok(Ok(opened)) => return Ok(opened),
+            Ok(Err(e)) => last_err = Some(e),
+            Err(_) => last_err = Some(Error::Snapshot("timed out opening snapshot stream".into())),
         }
-        if attempt < BOOTSTRAP_ATTEMPTS {
+        if attempt < attempts {
             tracing::warn!(attempt, "snapshot bootstrap failed, retrying");
             tokio::time::sleep(with_jitter(backoff)).await;
             backoff = (backoff * 2).min(RECONNECT_MAX);
@@ -224,18 +409,148 @@ async fn open_initial(
     Err(last_err.unwrap_or_else(|| Error::Snapshot("snapshot bootstrap failed".into())))
 }
 
+/// Open the snapshot stream and read its first (full) message.
+async fn first_message(
+    client: EvaluationClient<IdentifiedChannel>,
+) -> Result<(SnapshotStream, SnapshotDelta), Error> {
+    let mut stream = open_stream(client, 0, String::new()).await?;
+    match stream.message().await? {
+        Some(first) => Ok((stream, first)),
+        None => Err(Error::Snapshot(
+            "snapshot stream closed before first message".into(),
+        )),
+    }
+}
+
+/// A snapshot and the `scope` of the stream it came from, as kept in the cache file.
+#[derive(Serialize, Deserialize)]
+struct CachedSnapshot<'a> {
+    /// Empty for a snapshot from anywhere but the delta stream, such as the bootstrap
+    /// file, which the server then won't send deltas against.
+    #[serde(default)]
+    scope: Cow<'a, str>,
+    #[serde(flatten)]
+    snapshot: Cow<'a, Snapshot>,
+}
+
+impl CachedSnapshot<'static> {
+    fn owned(scope: String, snapshot: Snapshot) -> Self {
+        Self {
+            scope: Cow::Owned(scope),
+            snapshot: Cow::Owned(snapshot),
+        }
+    }
+}
+
+/// The snapshot to serve if the server can't be reached at startup, and where it came
+/// from: the cached snapshot if it's recent enough, otherwise the bootstrap file. A
+/// cache that can't be read is skipped; a bootstrap file that can't is an error, since
+/// it was chosen explicitly.
+fn fallback_snapshot(
+    options: &LocalOptions,
+) -> Result<Option<(CachedSnapshot<'static>, &'static str)>, Error> {
+    if let Some(path) = &options.cache_file {
+        match read_cache(path, options.cache_max_age) {
+            Ok(Some(cached)) => return Ok(Some((cached, "cached snapshot"))),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("ignoring snapshot cache {}: {e}", path.display()),
+        }
+    }
+    let Some(path) = &options.bootstrap_file else {
+        return Ok(None);
+    };
+    let invalid = |e: &dyn std::fmt::Display| Error::Snapshot(format!("{}: {e}", path.display()));
+    let raw = fs::read_to_string(path).map_err(|e| invalid(&e))?;
+    // YAML is a superset of JSON, so this reads either.
+    let config: ConfigFile = serde_yaml::from_str(&raw).map_err(|e| invalid(&e))?;
+    let snapshot = config.to_snapshot().map_err(|e| invalid(&e))?;
+    Ok(Some((
+        CachedSnapshot::owned(String::new(), snapshot),
+        "bootstrap file",
+    )))
+}
+
+/// The snapshot cached at `path`, or `None` if there's none or it was written more
+/// than `max_age` ago.
+fn read_cache(
+    path: &Path,
+    max_age: Option<Duration>,
+) -> Result<Option<CachedSnapshot<'static>>, Box<dyn std::error::Error>> {
+    let modified = match fs::metadata(path) {
+        Ok(meta) => meta.modified()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // A modification time in the future (clock skew) counts as just written.
+    let age = modified.elapsed().unwrap_or_default();
+    if max_age.is_some_and(|max_age| age > max_age) {
+        tracing::info!(
+            "snapshot cache {} is {}s old, ignoring it",
+            path.display(),
+            age.as_secs()
+        );
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// Replace the cache at `path` with `held`, through a temporary file so a crash
+/// mid-write can't leave a truncated cache behind.
+fn write_cache(path: &Path, held: &CachedSnapshot<'_>) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(held)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// The snapshot stream, read as deltas. Against a server without
+/// `StreamSnapshotDeltas` each full snapshot is read as a full delta.
+enum SnapshotStream {
+    Deltas(tonic::Streaming<SnapshotDelta>),
+    Full(tonic::Streaming<SnapshotResponse>),
+}
+
+impl SnapshotStream {
+    async fn message(&mut self) -> Result<Option<SnapshotDelta>, tonic::Status> {
+        match self {
+            Self::Deltas(stream) => stream.message().await,
+            Self::Full(stream) => Ok(stream.message().await?.map(full_delta)),
+        }
+    }
+}
+
+/// Open the delta stream from `known_version` (0 for none), built under `known_scope`.
 async fn open_stream(
     mut client: EvaluationClient<IdentifiedChannel>,
-) -> Result<tonic::Streaming<SnapshotResponse>, Error> {
-    Ok(client
-        .stream_snapshot(GetSnapshotRequest {})
-        .await?
-        .into_inner())
+    known_version: i64,
+    known_scope: String,
+) -> Result<SnapshotStream, Error> {
+    match client
+        .stream_snapshot_deltas(StreamSnapshotDeltasRequest {
+            known_version,
+            known_scope,
+            ..Default::default()
+        })
+        .await
+    {
+        Ok(stream) => Ok(SnapshotStream::Deltas(stream.into_inner())),
+        Err(status) if status.code() == tonic::Code::Unimplemented => Ok(SnapshotStream::Full(
+            client
+                .stream_snapshot(GetSnapshotRequest::default())
+                .await?
+                .into_inner(),
+        )),
+        Err(status) => Err(status.into()),
+    }
 }
 
-fn build_snapshot(snapshot: SnapshotResponse) -> Result<Arc<Snapshot>, Error> {
-    let snapshot = Snapshot::try_from(snapshot).map_err(|e| Error::Snapshot(e.to_string()))?;
-    Ok(Arc::new(snapshot))
+fn full_delta(snapshot: SnapshotResponse) -> SnapshotDelta {
+    SnapshotDelta {
+        version: snapshot.version,
+        full: true,
+        flags: snapshot.flags,
+        segments: snapshot.segments,
+        ..Default::default()
+    }
 }
 
 fn error_resolution<T: Default>(code: &str) -> Resolution<T> {
@@ -246,3 +561,122 @@ fn error_resolution<T: Default>(code: &str) -> Resolution<T> {
         error_code: Some(code.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::SystemTime;
+
+    fn snapshot(version: i64) -> Snapshot {
+        Snapshot {
+            version,
+            ..Default::default()
+        }
+    }
+
+    fn write_snapshot(path: &Path, version: i64) {
+        let held = CachedSnapshot::owned("scope".into(), snapshot(version));
+        write_cache(path, &held).unwrap();
+    }
+
+    /// Backdate the file at `path` by `age`.
+    fn age(path: &Path, age: Duration) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn bootstrap_file(dir: &Path) -> PathBuf {
+        let path = dir.join("flags.yaml");
+        let config = "
+flags:
+  - key: beta
+    type: boolean
+    enabled: true
+    default: \"off\"
+    variants: {\"on\": true, \"off\": false}
+";
+        fs::write(&path, config).unwrap();
+        path
+    }
+
+    fn source(options: &LocalOptions) -> Option<(&'static str, i64)> {
+        fallback_snapshot(options)
+            .unwrap()
+            .map(|(held, source)| (source, held.snapshot.version))
+    }
+
+    #[test]
+    fn cache_is_preferred_over_the_bootstrap_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("snapshot.json");
+        write_snapshot(&cache, 7);
+        let options = LocalOptions::new()
+            .cache_file(&cache)
+            .cache_max_age(Duration::from_secs(60))
+            .bootstrap_file(bootstrap_file(dir.path()));
+
+        assert_eq!(source(&options), Some(("cached snapshot", 7)));
+    }
+
+    #[test]
+    fn stale_cache_falls_back_to_the_bootstrap_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("snapshot.json");
+        write_snapshot(&cache, 7);
+        age(&cache, Duration::from_secs(120));
+        let options = LocalOptions::new()
+            .cache_file(&cache)
+            .cache_max_age(Duration::from_secs(60));
+
+        // Without a bootstrap file there's nothing to serve.
+        assert_eq!(source(&options), None);
+        let (held, source) = fallback_snapshot(&options.bootstrap_file(bootstrap_file(dir.path())))
+            .unwrap()
+            .unwrap();
+        assert_eq!(source, "bootstrap file");
+        assert!(held.scope.is_empty());
+        assert!(held.snapshot.flags.contains_key("beta"));
+    }
+
+    #[test]
+    fn without_max_age_a_cache_of_any_age_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("snapshot.json");
+        write_snapshot(&cache, 7);
+        age(&cache, Duration::from_secs(86_400));
+
+        assert_eq!(
+            source(&LocalOptions::new().cache_file(&cache)),
+            Some(("cached snapshot", 7))
+        );
+    }
+
+    #[test]
+    fn missing_or_corrupt_cache_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("snapshot.json");
+        let options = LocalOptions::new()
+            .cache_file(&cache)
+            .bootstrap_file(bootstrap_file(dir.path()));
+        assert_eq!(source(&options), Some(("bootstrap file", 0)));
+
+        fs::write(&cache, "{not json").unwrap();
+        assert_eq!(source(&options), Some(("bootstrap file", 0)));
+    }
+
+    #[test]
+    fn unreadable_bootstrap_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = LocalOptions::new().bootstrap_file(dir.path().join("missing.yaml"));
+        assert!(fallback_snapshot(&missing).is_err());
+
+        let invalid = dir.path().join("invalid.yaml");
+        fs::write(&invalid, "flags: 3").unwrap();
+        assert!(fallback_snapshot(&LocalOptions::new().bootstrap_file(invalid)).is_err());
+    }
+}
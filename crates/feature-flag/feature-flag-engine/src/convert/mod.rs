@@ -7,10 +7,13 @@ mod types;
 
 pub use types::ConversionError;
 
-use crate::engine::{EvalContext, Resolution};
+use crate::engine::{
+    ConstraintTrace, EvalContext, Explanation, GroupTrace, Resolution, RuleTrace, SegmentTrace,
+};
 use crate::model::{
     Constraint, ConstraintGroup, Distribution, Flag, Rule, Segment, Snapshot, Variant,
 };
+use chrono::{DateTime, SecondsFormat, Utc};
 use feature_flag_proto as pb;
 use prost_types::value::Kind;
 use serde_json::Value as Json;
@@ -23,9 +26,7 @@ pub fn prost_value_to_json(v: &prost_types::Value) -> Json {
             .map(Json::Number)
             .unwrap_or(Json::Null),
         Some(Kind::StringValue(s)) => Json::String(s.clone()),
-        Some(Kind::ListValue(l)) => {
-            Json::Array(l.values.iter().map(prost_value_to_json).collect())
-        }
+        Some(Kind::ListValue(l)) => Json::Array(l.values.iter().map(prost_value_to_json).collect()),
         Some(Kind::StructValue(s)) => Json::Object(
             s.fields
                 .iter()
@@ -109,6 +110,91 @@ pub fn type_mismatch(expected: &str) -> pb::ResolutionMeta {
     meta_err("TYPE_MISMATCH", format!("flag is not of type {expected}"))
 }
 
+impl From<&Explanation> for pb::FlagTrace {
+    fn from(x: &Explanation) -> Self {
+        let (value, meta) = match &x.result {
+            Ok(res) => (
+                Some(json_to_prost_value(&res.value)),
+                pb::ResolutionMeta::from(res),
+            ),
+            Err(e) => (None, meta_err(e.code.as_str(), e.message.clone())),
+        };
+        pb::FlagTrace {
+            flag_key: x.flag_key.clone(),
+            value,
+            meta: Some(meta),
+            rules: x.rules.iter().map(pb::RuleTrace::from).collect(),
+        }
+    }
+}
+
+impl From<&RuleTrace> for pb::RuleTrace {
+    fn from(r: &RuleTrace) -> Self {
+        pb::RuleTrace {
+            rank: r.rank,
+            segment: r.segment.as_ref().map(pb::SegmentTrace::from),
+            groups: r
+                .groups
+                .iter()
+                .map(pb::ConstraintGroupTrace::from)
+                .collect(),
+            matched: r.matched,
+            split: r.split.as_ref().map(|s| pb::SplitTrace {
+                bucket: s.bucket,
+                variant_key: s.variant_key.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&SegmentTrace> for pb::SegmentTrace {
+    fn from(s: &SegmentTrace) -> Self {
+        pb::SegmentTrace {
+            key: s.key.clone(),
+            found: s.found,
+            cycle: s.cycle,
+            listed: s.listed,
+            constraints: s
+                .constraints
+                .iter()
+                .map(pb::ConstraintTrace::from)
+                .collect(),
+            groups: s
+                .groups
+                .iter()
+                .map(pb::ConstraintGroupTrace::from)
+                .collect(),
+            included: s.included.iter().map(pb::SegmentTrace::from).collect(),
+            excluded: s.excluded.iter().map(pb::SegmentTrace::from).collect(),
+            matched: s.matched,
+        }
+    }
+}
+
+impl From<&GroupTrace> for pb::ConstraintGroupTrace {
+    fn from(g: &GroupTrace) -> Self {
+        pb::ConstraintGroupTrace {
+            constraints: g
+                .constraints
+                .iter()
+                .map(pb::ConstraintTrace::from)
+                .collect(),
+            matched: g.matched,
+        }
+    }
+}
+
+impl From<&ConstraintTrace> for pb::ConstraintTrace {
+    fn from(c: &ConstraintTrace) -> Self {
+        pb::ConstraintTrace {
+            constraint: Some(domain_constraint_to_pb(&c.constraint)),
+            actual: c.actual.as_ref().map(json_to_prost_value),
+            matched: c.matched,
+            flag: c.flag.as_deref().map(pb::FlagTrace::from),
+        }
+    }
+}
+
 impl From<&Flag> for pb::Flag {
     fn from(flag: &Flag) -> Self {
         pb::Flag {
@@ -119,6 +205,13 @@ impl From<&Flag> for pb::Flag {
             archived: flag.archived,
             variants: flag.variants.iter().map(pb::Variant::from).collect(),
             rules: flag.rules.iter().map(pb::Rule::from).collect(),
+            tags: flag.tags.clone(),
+            owners: flag.owners.clone(),
+            description: flag.description.clone(),
+            expires_at: flag
+                .expires_at
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+                .unwrap_or_default(),
         }
     }
 }
@@ -172,6 +265,21 @@ impl From<&Segment> for pb::Segment {
             key: s.key.clone(),
             name: s.name.clone(),
             constraints: s.constraints.iter().map(domain_constraint_to_pb).collect(),
+            constraint_groups: s
+                .constraint_groups
+                .iter()
+                .map(|g| pb::ConstraintGroup {
+                    constraints: g.constraints.iter().map(domain_constraint_to_pb).collect(),
+                })
+                .collect(),
+            included_segments: s.included_segments.clone(),
+            excluded_segments: s.excluded_segments.clone(),
+            // Sorted so the same set always encodes the same way.
+            targeting_keys: {
+                let mut keys: Vec<_> = s.targeting_keys.iter().cloned().collect();
+                keys.sort_unstable();
+                keys
+            },
         }
     }
 }
@@ -180,14 +288,20 @@ impl From<&pb::Variant> for Variant {
     fn from(v: &pb::Variant) -> Self {
         Variant {
             key: v.key.clone(),
-            value: v.value.as_ref().map(prost_value_to_json).unwrap_or(Json::Null),
+            value: v
+                .value
+                .as_ref()
+                .map(prost_value_to_json)
+                .unwrap_or(Json::Null),
         }
     }
 }
 
 /// Convert a list of proto constraints to the domain model, failing if any operator
 /// is unspecified. Shared by segment and inline rule constraint conversion.
-fn pb_constraints_to_domain(constraints: &[pb::Constraint]) -> Result<Vec<Constraint>, ConversionError> {
+fn pb_constraints_to_domain(
+    constraints: &[pb::Constraint],
+) -> Result<Vec<Constraint>, ConversionError> {
     let mut out = Vec::with_capacity(constraints.len());
     for c in constraints {
         out.push(Constraint {
@@ -237,6 +351,18 @@ impl TryFrom<&pb::Segment> for Segment {
             key: s.key.clone(),
             name: s.name.clone(),
             constraints: pb_constraints_to_domain(&s.constraints)?,
+            constraint_groups: s
+                .constraint_groups
+                .iter()
+                .map(|g| {
+                    Ok(ConstraintGroup {
+                        constraints: pb_constraints_to_domain(&g.constraints)?,
+                    })
+                })
+                .collect::<Result<_, ConversionError>>()?,
+            included_segments: s.included_segments.clone(),
+            excluded_segments: s.excluded_segments.clone(),
+            targeting_keys: s.targeting_keys.iter().cloned().collect(),
         })
     }
 }
@@ -257,10 +383,56 @@ impl TryFrom<&pb::Flag> for Flag {
                 .iter()
                 .map(Rule::try_from)
                 .collect::<Result<_, _>>()?,
+            tags: f.tags.clone(),
+            owners: f.owners.clone(),
+            description: f.description.clone(),
+            expires_at: match f.expires_at.as_str() {
+                "" => None,
+                s => Some(
+                    DateTime::parse_from_rfc3339(s)
+                        .map_err(|e| {
+                            ConversionError(format!(
+                                "flag `{}`: invalid expires_at `{s}`: {e}",
+                                f.key
+                            ))
+                        })?
+                        .with_timezone(&Utc),
+                ),
+            },
         })
     }
 }
 
+/// The snapshot `delta` leaves behind when applied to `base`: a full delta replaces
+/// it, any other upserts and removes on top of a copy. Checking that `base` is at the
+/// delta's `base_version` is left to the caller.
+pub fn apply_delta(
+    base: &Snapshot,
+    delta: &pb::SnapshotDelta,
+) -> Result<Snapshot, ConversionError> {
+    let mut snapshot = if delta.full {
+        Snapshot::default()
+    } else {
+        base.clone()
+    };
+    snapshot.version = delta.version;
+    for key in &delta.removed_flag_keys {
+        snapshot.flags.remove(key);
+    }
+    for key in &delta.removed_segment_keys {
+        snapshot.segments.remove(key);
+    }
+    for f in &delta.flags {
+        let flag = Flag::try_from(f)?;
+        snapshot.flags.insert(flag.key.clone(), flag);
+    }
+    for s in &delta.segments {
+        let segment = Segment::try_from(s)?;
+        snapshot.segments.insert(segment.key.clone(), segment);
+    }
+    Ok(snapshot)
+}
+
 impl TryFrom<pb::SnapshotResponse> for Snapshot {
     type Error = ConversionError;
 
@@ -67,6 +67,14 @@ impl From<Operator> for ConstraintOperator {
             Operator::Exists => ConstraintOperator::Exists,
             Operator::Regex => ConstraintOperator::Regex,
             Operator::FlagMatches => ConstraintOperator::FlagMatches,
+            Operator::SemverEq => ConstraintOperator::SemverEq,
+            Operator::SemverGt => ConstraintOperator::SemverGt,
+            Operator::SemverGte => ConstraintOperator::SemverGte,
+            Operator::SemverLt => ConstraintOperator::SemverLt,
+            Operator::SemverLte => ConstraintOperator::SemverLte,
+            Operator::Before => ConstraintOperator::Before,
+            Operator::After => ConstraintOperator::After,
+            Operator::PercentOf => ConstraintOperator::PercentOf,
         }
     }
 }
@@ -93,6 +101,14 @@ impl TryFrom<ConstraintOperator> for Operator {
             ConstraintOperator::Exists => Operator::Exists,
             ConstraintOperator::Regex => Operator::Regex,
             ConstraintOperator::FlagMatches => Operator::FlagMatches,
+            ConstraintOperator::SemverEq => Operator::SemverEq,
+            ConstraintOperator::SemverGt => Operator::SemverGt,
+            ConstraintOperator::SemverGte => Operator::SemverGte,
+            ConstraintOperator::SemverLt => Operator::SemverLt,
+            ConstraintOperator::SemverLte => Operator::SemverLte,
+            ConstraintOperator::Before => Operator::Before,
+            ConstraintOperator::After => Operator::After,
+            ConstraintOperator::PercentOf => Operator::PercentOf,
         })
     }
 }
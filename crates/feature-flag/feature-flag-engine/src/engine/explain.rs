@@ -0,0 +1,532 @@
+//! A traced variant of [`Engine::evaluate`] for answering "why did this context get
+//! that variant". It walks the same rules with the same matchers, but records every
+//! step instead of short-circuiting: each constraint in a tried rule is evaluated and
+//! kept, segments report their own constraints, flag-match constraints carry the nested
+//! flag's explanation, and splits report the bucket the targeting key hashed to.
+
+use super::{Engine, ErrorCode, EvalContext, EvalError, Reason, Resolution};
+use crate::model::{Constraint, ConstraintGroup, Flag, Operator, Rule};
+use serde_json::Value;
+
+#[derive(Clone, Debug)]
+pub struct Explanation {
+    pub flag_key: String,
+    /// Exactly what [`Engine::evaluate`] returns for the same flag and context.
+    pub result: Result<Resolution, EvalError>,
+    /// Rules in rank order, up to and including the one that matched. Empty when the
+    /// flag is missing, disabled or archived, since no rule is consulted.
+    pub rules: Vec<RuleTrace>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleTrace {
+    pub rank: u32,
+    pub segment: Option<SegmentTrace>,
+    pub groups: Vec<GroupTrace>,
+    pub matched: bool,
+    /// Set when the rule matched and served a percentage split.
+    pub split: Option<SplitTrace>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SegmentTrace {
+    pub key: String,
+    /// False when the segment is missing from the snapshot.
+    pub found: bool,
+    /// True when the segment references itself, directly or through other segments;
+    /// the repeated reference is traced no further.
+    pub cycle: bool,
+    /// Whether the targeting key is in the segment's key list; unset when it has none.
+    pub listed: Option<bool>,
+    pub constraints: Vec<ConstraintTrace>,
+    pub groups: Vec<GroupTrace>,
+    pub included: Vec<SegmentTrace>,
+    pub excluded: Vec<SegmentTrace>,
+    pub matched: bool,
+}
+
+impl SegmentTrace {
+    /// Mirrors `Engine::segment_body_outcome` over the traced parts, so a broken
+    /// reference poisons the trace exactly when it poisons evaluation.
+    fn outcome(&self) -> Option<bool> {
+        if !self.found || self.cycle {
+            return None;
+        }
+        if self.listed == Some(false)
+            || !self.constraints.iter().all(|c| c.matched)
+            || !self.groups.iter().all(|g| g.matched)
+        {
+            return Some(false);
+        }
+        for s in &self.included {
+            if !s.outcome()? {
+                return Some(false);
+            }
+        }
+        for s in &self.excluded {
+            if s.outcome()? {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GroupTrace {
+    pub constraints: Vec<ConstraintTrace>,
+    pub matched: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConstraintTrace {
+    pub constraint: Constraint,
+    /// The context attribute the constraint compared against, if present. Unset for
+    /// flag-match constraints, whose operand is `flag` instead.
+    pub actual: Option<Value>,
+    pub matched: bool,
+    pub flag: Option<Box<Explanation>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SplitTrace {
+    /// The targeting key's bucket in [0,100).
+    pub bucket: u32,
+    pub variant_key: String,
+}
+
+impl Engine {
+    /// Resolve a flag as [`Engine::evaluate`] does, returning the full decision trace
+    /// alongside the result.
+    pub fn explain(&self, flag_key: &str, ctx: &EvalContext) -> Explanation {
+        self.explain_inner(flag_key, ctx, &mut Vec::new())
+    }
+
+    fn explain_inner(
+        &self,
+        flag_key: &str,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> Explanation {
+        let mut explanation = Explanation {
+            flag_key: flag_key.to_string(),
+            result: Err(EvalError {
+                code: ErrorCode::ParseError,
+                message: format!("flag dependency cycle through flag `{flag_key}`"),
+            }),
+            rules: Vec::new(),
+        };
+        if stack.iter().any(|k| k == flag_key) {
+            return explanation;
+        }
+
+        let Some(flag) = self.snapshot.flags.get(flag_key) else {
+            explanation.result = Err(EvalError {
+                code: ErrorCode::FlagNotFound,
+                message: format!("flag `{flag_key}` not found"),
+            });
+            return explanation;
+        };
+
+        if flag.archived || !flag.enabled {
+            explanation.result =
+                Self::resolve_variant(flag, &flag.default_variant_key, Reason::Disabled);
+            return explanation;
+        }
+
+        stack.push(flag_key.to_string());
+        explanation.result = self.explain_rules(flag, flag_key, ctx, stack, &mut explanation.rules);
+        stack.pop();
+        explanation
+    }
+
+    /// Mirrors `resolve_rules`, pushing a trace for every rule it tries.
+    fn explain_rules(
+        &self,
+        flag: &Flag,
+        flag_key: &str,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+        traces: &mut Vec<RuleTrace>,
+    ) -> Result<Resolution, EvalError> {
+        for rule in &flag.rules {
+            let mut trace = self.explain_rule(rule, ctx, stack);
+            if !trace.matched {
+                traces.push(trace);
+                continue;
+            }
+            if !rule.distributions.is_empty() {
+                let bucket = Self::bucket_of(flag_key, &rule.bucket_salt, &ctx.targeting_key);
+                let variant_key = Self::pick_distribution(flag_key, rule, ctx);
+                trace.split = Some(SplitTrace {
+                    bucket,
+                    variant_key: variant_key.to_string(),
+                });
+                traces.push(trace);
+                return Self::resolve_variant(flag, variant_key, Reason::Split);
+            }
+            traces.push(trace);
+            if let Some(variant_key) = &rule.variant_key {
+                return Self::resolve_variant(flag, variant_key, Reason::TargetingMatch);
+            }
+        }
+
+        Self::resolve_variant(flag, &flag.default_variant_key, Reason::Default)
+    }
+
+    fn explain_rule(&self, rule: &Rule, ctx: &EvalContext, stack: &mut Vec<String>) -> RuleTrace {
+        let segment = rule
+            .segment_key
+            .as_deref()
+            .map(|key| self.explain_segment(key, ctx, &mut Vec::new()));
+        let groups: Vec<_> = rule
+            .constraint_groups
+            .iter()
+            .map(|g| self.explain_group(g, ctx, stack))
+            .collect();
+        RuleTrace {
+            rank: rule.rank,
+            matched: segment.as_ref().is_none_or(|s| s.matched) && groups.iter().all(|g| g.matched),
+            segment,
+            groups,
+            split: None,
+        }
+    }
+
+    fn explain_segment(
+        &self,
+        key: &str,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> SegmentTrace {
+        let mut trace = SegmentTrace {
+            key: key.to_string(),
+            found: false,
+            cycle: stack.iter().any(|k| k == key),
+            listed: None,
+            constraints: Vec::new(),
+            groups: Vec::new(),
+            included: Vec::new(),
+            excluded: Vec::new(),
+            matched: false,
+        };
+        let Some(segment) = self.snapshot.segments.get(key) else {
+            return trace;
+        };
+        trace.found = true;
+        if trace.cycle {
+            return trace;
+        }
+
+        stack.push(key.to_string());
+        trace.listed = (!segment.targeting_keys.is_empty())
+            .then(|| segment.targeting_keys.contains(&ctx.targeting_key));
+        trace.constraints = segment
+            .constraints
+            .iter()
+            .map(|c| Self::explain_attr_constraint(c, ctx))
+            .collect();
+        trace.groups = segment
+            .constraint_groups
+            .iter()
+            .map(|g| {
+                let constraints: Vec<_> = g
+                    .constraints
+                    .iter()
+                    .map(|c| Self::explain_attr_constraint(c, ctx))
+                    .collect();
+                GroupTrace {
+                    matched: constraints.is_empty() || constraints.iter().any(|c| c.matched),
+                    constraints,
+                }
+            })
+            .collect();
+        trace.included = segment
+            .included_segments
+            .iter()
+            .map(|k| self.explain_segment(k, ctx, stack))
+            .collect();
+        trace.excluded = segment
+            .excluded_segments
+            .iter()
+            .map(|k| self.explain_segment(k, ctx, stack))
+            .collect();
+        stack.pop();
+        trace.matched = trace.outcome() == Some(true);
+        trace
+    }
+
+    fn explain_group(
+        &self,
+        group: &ConstraintGroup,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> GroupTrace {
+        let constraints: Vec<_> = group
+            .constraints
+            .iter()
+            .map(|c| self.explain_constraint(c, ctx, stack))
+            .collect();
+        GroupTrace {
+            matched: constraints.is_empty() || constraints.iter().any(|c| c.matched),
+            constraints,
+        }
+    }
+
+    fn explain_constraint(
+        &self,
+        c: &Constraint,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> ConstraintTrace {
+        if c.operator != Operator::FlagMatches {
+            return Self::explain_attr_constraint(c, ctx);
+        }
+        let nested = self.explain_inner(&c.attribute, ctx, stack);
+        ConstraintTrace {
+            constraint: c.clone(),
+            actual: None,
+            matched: matches!(&nested.result, Ok(res) if c.values.iter().any(|v| v.as_str() == Some(res.variant.as_str()))),
+            flag: Some(Box::new(nested)),
+        }
+    }
+
+    fn explain_attr_constraint(c: &Constraint, ctx: &EvalContext) -> ConstraintTrace {
+        ConstraintTrace {
+            constraint: c.clone(),
+            actual: ctx.attributes.get(&c.attribute).cloned(),
+            matched: Self::attr_constraint_matches(c, ctx),
+            flag: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn variants() -> Vec<Variant> {
+        vec![
+            Variant {
+                key: "on".into(),
+                value: json!(true),
+            },
+            Variant {
+                key: "off".into(),
+                value: json!(false),
+            },
+        ]
+    }
+
+    fn flag(key: &str, rules: Vec<Rule>) -> Flag {
+        Flag {
+            key: key.into(),
+            value_type: ValueType::Boolean,
+            enabled: true,
+            default_variant_key: "off".into(),
+            archived: false,
+            variants: variants(),
+            rules,
+            tags: vec![],
+            owners: vec![],
+            description: String::new(),
+            expires_at: None,
+        }
+    }
+
+    fn rule(rank: u32, segment_key: Option<&str>, groups: Vec<Vec<Constraint>>) -> Rule {
+        Rule {
+            rank,
+            segment_key: segment_key.map(Into::into),
+            variant_key: Some("on".into()),
+            distributions: vec![],
+            constraint_groups: groups
+                .into_iter()
+                .map(|constraints| ConstraintGroup { constraints })
+                .collect(),
+            bucket_salt: String::new(),
+        }
+    }
+
+    fn eq(attribute: &str, value: Value) -> Constraint {
+        Constraint {
+            attribute: attribute.into(),
+            operator: Operator::Eq,
+            values: vec![value],
+        }
+    }
+
+    fn engine() -> Engine {
+        let master = flag(
+            "master",
+            vec![rule(0, None, vec![vec![eq("plan", json!("pro"))]])],
+        );
+        let mut split = rule(2, None, vec![]);
+        split.variant_key = None;
+        split.distributions = vec![
+            Distribution {
+                variant_key: "on".into(),
+                weight: 30,
+            },
+            Distribution {
+                variant_key: "off".into(),
+                weight: 70,
+            },
+        ];
+        let dependent = flag(
+            "dependent",
+            vec![
+                rule(0, Some("staff"), vec![]),
+                rule(
+                    1,
+                    None,
+                    vec![vec![
+                        eq("country", json!("NZ")),
+                        Constraint {
+                            attribute: "master".into(),
+                            operator: Operator::FlagMatches,
+                            values: vec![json!("on")],
+                        },
+                    ]],
+                ),
+                split,
+            ],
+        );
+        let mut s = Snapshot {
+            version: 1,
+            ..Default::default()
+        };
+        s.flags.insert(master.key.clone(), master);
+        s.flags.insert(dependent.key.clone(), dependent);
+        s.segments.insert(
+            "staff".into(),
+            Segment {
+                key: "staff".into(),
+                name: "Staff".into(),
+                constraints: vec![eq("staff", json!(true))],
+                ..Default::default()
+            },
+        );
+        Engine::new(Arc::new(s))
+    }
+
+    fn ctx(targeting_key: &str, attrs: Value) -> EvalContext {
+        EvalContext {
+            targeting_key: targeting_key.into(),
+            attributes: attrs
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn traces_segments_and_flag_match_recursion() {
+        let e = engine();
+        let x = e.explain(
+            "dependent",
+            &ctx("u1", json!({"plan": "pro", "country": "AU"})),
+        );
+        let res = x.result.as_ref().unwrap();
+        assert_eq!(
+            (res.variant.as_str(), res.reason),
+            ("on", Reason::TargetingMatch)
+        );
+
+        // The staff rule was tried and its segment failed; evaluation stopped at rule 1.
+        assert_eq!(x.rules.len(), 2);
+        let segment = x.rules[0].segment.as_ref().unwrap();
+        assert!(segment.found && !segment.matched);
+        assert_eq!(segment.constraints[0].actual, None);
+        assert!(!x.rules[0].matched);
+
+        let group = &x.rules[1].groups[0];
+        assert!(group.matched && x.rules[1].matched);
+        assert_eq!(group.constraints[0].actual, Some(json!("AU")));
+        assert!(!group.constraints[0].matched);
+        let nested = group.constraints[1].flag.as_ref().unwrap();
+        assert_eq!(nested.flag_key, "master");
+        assert!(nested.rules[0].groups[0].constraints[0].matched);
+        assert!(group.constraints[1].matched);
+    }
+
+    #[test]
+    fn split_reports_bucket_and_agrees_with_evaluate() {
+        let e = engine();
+        for i in 0..200 {
+            let c = ctx(&format!("user-{i}"), json!({"plan": "free"}));
+            let x = e.explain("dependent", &c);
+            let res = e.evaluate("dependent", &c).unwrap();
+            let explained = x.result.unwrap();
+            assert_eq!(
+                (explained.variant.as_str(), explained.reason),
+                (res.variant.as_str(), res.reason)
+            );
+
+            let split = x.rules[2].split.as_ref().unwrap();
+            assert_eq!(split.variant_key, res.variant);
+            assert_eq!(split.variant_key == "on", split.bucket < 30);
+        }
+    }
+
+    #[test]
+    fn nested_segment_traces_agree_with_evaluate() {
+        let mut e = engine();
+        let mut snapshot = (*e.snapshot).clone();
+        let staff = snapshot.segments.get_mut("staff").unwrap();
+        staff.included_segments = vec!["listed".into()];
+        staff.excluded_segments = vec!["loop".into()];
+        snapshot.segments.insert(
+            "listed".into(),
+            Segment {
+                key: "listed".into(),
+                targeting_keys: ["u1".to_string()].into(),
+                ..Default::default()
+            },
+        );
+        snapshot.segments.insert(
+            "loop".into(),
+            Segment {
+                key: "loop".into(),
+                constraints: vec![eq("plan", json!("pro"))],
+                included_segments: vec!["loop".into()],
+                ..Default::default()
+            },
+        );
+        e = Engine::new(Arc::new(snapshot));
+
+        for (user, attrs) in [
+            ("u1", json!({"staff": true})),
+            ("u2", json!({"staff": true})),
+            ("u1", json!({"staff": true, "plan": "pro"})),
+        ] {
+            let c = ctx(user, attrs);
+            let segment = e.explain("dependent", &c).rules[0].segment.clone().unwrap();
+            assert_eq!(
+                segment.matched,
+                e.segment_matches("staff", &c),
+                "{user} {:?}",
+                c.attributes
+            );
+        }
+
+        let c = ctx("u1", json!({"staff": true, "plan": "pro"}));
+        let segment = e.explain("dependent", &c).rules[0].segment.clone().unwrap();
+        assert_eq!(segment.included[0].listed, Some(true));
+        assert!(segment.excluded[0].included[0].cycle);
+        assert!(!segment.matched);
+    }
+
+    #[test]
+    fn missing_flag_errors_without_rule_trace() {
+        let e = engine();
+        let missing = e.explain("nope", &ctx("u1", json!({})));
+        assert_eq!(missing.result.unwrap_err().code, ErrorCode::FlagNotFound);
+        assert!(missing.rules.is_empty());
+    }
+}
@@ -2,15 +2,20 @@
 //! [`Snapshot`] and resolves flags against an [`EvalContext`], yielding a value, the
 //! served variant, and a reason. Kept free of IO so it can be exhaustively tested.
 
+mod explain;
 mod types;
 
+pub use explain::{ConstraintTrace, Explanation, GroupTrace, RuleTrace, SegmentTrace, SplitTrace};
 pub use types::{ErrorCode, EvalContext, EvalError, Reason, Resolution};
 
 use crate::model::{Constraint, ConstraintGroup, Flag, Operator, Rule, Segment, Snapshot};
+use chrono::{DateTime, Utc};
 use moka::sync::Cache;
 use regex::Regex;
+use semver::Version;
 use serde_json::Value;
 use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
 use std::sync::{Arc, LazyLock};
 
 /// Process-wide memo of compiled patterns, shared across every snapshot and request so
@@ -18,8 +23,7 @@ use std::sync::{Arc, LazyLock};
 /// `None` value caches a pattern that failed to compile (or exceeded the size limit),
 /// so repeated bad patterns are not re-attempted. Bounded so a flood of distinct
 /// patterns supplied through context can't grow it without limit.
-static REGEX_CACHE: LazyLock<Cache<String, Option<Regex>>> =
-    LazyLock::new(|| Cache::new(10_000));
+static REGEX_CACHE: LazyLock<Cache<String, Option<Regex>>> = LazyLock::new(|| Cache::new(10_000));
 
 #[derive(Clone)]
 pub struct Engine {
@@ -100,11 +104,7 @@ impl Engine {
     fn rule_matches(&self, rule: &Rule, ctx: &EvalContext, stack: &mut Vec<String>) -> bool {
         let segment_ok = match rule.segment_key.as_deref() {
             None => true,
-            Some(key) => match self.snapshot.segments.get(key) {
-                Some(segment) => Self::segment_matches(segment, ctx),
-                // A rule pointing at a missing segment never matches rather than erroring.
-                None => false,
-            },
+            Some(key) => self.segment_matches(key, ctx),
         };
         segment_ok
             && rule
@@ -115,7 +115,12 @@ impl Engine {
 
     /// A constraint group matches when any of its constraints match (OR). An empty
     /// group matches, so it never blocks a rule.
-    fn group_matches(&self, group: &ConstraintGroup, ctx: &EvalContext, stack: &mut Vec<String>) -> bool {
+    fn group_matches(
+        &self,
+        group: &ConstraintGroup,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> bool {
         group.constraints.is_empty()
             || group
                 .constraints
@@ -125,7 +130,12 @@ impl Engine {
 
     /// Match a single rule constraint, dispatching flag-match constraints to a
     /// recursive flag resolution and all others to the attribute matcher.
-    fn constraint_matches(&self, c: &Constraint, ctx: &EvalContext, stack: &mut Vec<String>) -> bool {
+    fn constraint_matches(
+        &self,
+        c: &Constraint,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> bool {
         if c.operator == Operator::FlagMatches {
             let result = self.evaluate_inner(&c.attribute, ctx, stack);
             return matches!(&result, Ok(res) if c.values.iter().any(|v| v.as_str() == Some(res.variant.as_str())));
@@ -152,8 +162,69 @@ impl Engine {
         })
     }
 
-    pub fn segment_matches(segment: &Segment, ctx: &EvalContext) -> bool {
-        Self::constraints_match(&segment.constraints, ctx)
+    /// Whether the context is in the segment `key`. A missing segment never matches
+    /// rather than erroring, and neither does one whose outcome depends on a missing
+    /// segment or on a reference cycle.
+    pub fn segment_matches(&self, key: &str, ctx: &EvalContext) -> bool {
+        self.segment_outcome(key, ctx, &mut Vec::new()) == Some(true)
+    }
+
+    /// `None` when the outcome hinges on a missing segment or a cycle, which poisons
+    /// every segment that references it. `stack` holds the segments being matched,
+    /// like the flag stack for flag-match constraints.
+    fn segment_outcome(
+        &self,
+        key: &str,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> Option<bool> {
+        if stack.iter().any(|k| k == key) {
+            return None;
+        }
+        let segment = self.snapshot.segments.get(key)?;
+        stack.push(key.to_string());
+        let outcome = self.segment_body_outcome(segment, ctx, stack);
+        stack.pop();
+        outcome
+    }
+
+    fn segment_body_outcome(
+        &self,
+        segment: &Segment,
+        ctx: &EvalContext,
+        stack: &mut Vec<String>,
+    ) -> Option<bool> {
+        let listed = segment.targeting_keys.is_empty()
+            || segment.targeting_keys.contains(&ctx.targeting_key);
+        if !listed
+            || !Self::constraints_match(&segment.constraints, ctx)
+            || !segment
+                .constraint_groups
+                .iter()
+                .all(|g| Self::attr_group_matches(g, ctx))
+        {
+            return Some(false);
+        }
+        for key in &segment.included_segments {
+            if !self.segment_outcome(key, ctx, stack)? {
+                return Some(false);
+            }
+        }
+        for key in &segment.excluded_segments {
+            if self.segment_outcome(key, ctx, stack)? {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// The segment counterpart of [`Self::group_matches`]: attribute constraints only.
+    fn attr_group_matches(group: &ConstraintGroup, ctx: &EvalContext) -> bool {
+        group.constraints.is_empty()
+            || group
+                .constraints
+                .iter()
+                .any(|c| Self::attr_constraint_matches(c, ctx))
     }
 
     /// A context matches a constraint set only when every constraint matches (AND).
@@ -183,6 +254,14 @@ impl Engine {
             Operator::Gte => Self::number_op(attr, first, |a, b| a >= b),
             Operator::Lt => Self::number_op(attr, first, |a, b| a < b),
             Operator::Lte => Self::number_op(attr, first, |a, b| a <= b),
+            Operator::SemverEq => Self::semver_op(attr, first, Ordering::is_eq),
+            Operator::SemverGt => Self::semver_op(attr, first, Ordering::is_gt),
+            Operator::SemverGte => Self::semver_op(attr, first, Ordering::is_ge),
+            Operator::SemverLt => Self::semver_op(attr, first, Ordering::is_lt),
+            Operator::SemverLte => Self::semver_op(attr, first, Ordering::is_le),
+            Operator::Before => Self::time_op(c, ctx, |a, b| a < b),
+            Operator::After => Self::time_op(c, ctx, |a, b| a > b),
+            Operator::PercentOf => Self::percent_of(c, attr),
             // Resolved against another flag, not a context attribute; the engine
             // dispatches it before reaching here, so a bare attribute match cannot.
             Operator::FlagMatches => false,
@@ -194,7 +273,10 @@ impl Engine {
         operand: Option<&Value>,
         f: impl Fn(&str, &str) -> bool,
     ) -> bool {
-        match (attr.and_then(Value::as_str), operand.and_then(Value::as_str)) {
+        match (
+            attr.and_then(Value::as_str),
+            operand.and_then(Value::as_str),
+        ) {
             (Some(a), Some(b)) => f(a, b),
             _ => false,
         }
@@ -205,12 +287,58 @@ impl Engine {
         operand: Option<&Value>,
         f: impl Fn(f64, f64) -> bool,
     ) -> bool {
-        match (attr.and_then(Value::as_f64), operand.and_then(Value::as_f64)) {
+        match (
+            attr.and_then(Value::as_f64),
+            operand.and_then(Value::as_f64),
+        ) {
+            (Some(a), Some(b)) => f(a, b),
+            _ => false,
+        }
+    }
+
+    fn semver_op(
+        attr: Option<&Value>,
+        operand: Option<&Value>,
+        f: impl Fn(Ordering) -> bool,
+    ) -> bool {
+        let parse = |v: Option<&Value>| v.and_then(Value::as_str).and_then(parse_semver);
+        match (parse(attr), parse(operand)) {
+            (Some(a), Some(b)) => f(a.cmp_precedence(&b)),
+            _ => false,
+        }
+    }
+
+    fn time_op(
+        c: &Constraint,
+        ctx: &EvalContext,
+        f: impl Fn(DateTime<Utc>, DateTime<Utc>) -> bool,
+    ) -> bool {
+        let attr = match ctx.attributes.get(&c.attribute) {
+            None if c.attribute == "now" => Some(Utc::now()),
+            attr => attr.and_then(parse_time),
+        };
+        match (attr, c.values.first().and_then(parse_time)) {
             (Some(a), Some(b)) => f(a, b),
             _ => false,
         }
     }
 
+    /// Buckets the attribute's value as [`Self::bucket_of`] buckets targeting keys, so
+    /// a value stays in (or out of) the rollout as the percentage only grows. Strings
+    /// and numbers are bucketed; a missing attribute never matches.
+    fn percent_of(c: &Constraint, attr: Option<&Value>) -> bool {
+        let key = match attr {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Number(n)) => n.to_string(),
+            _ => return false,
+        };
+        let Some(percent) = c.values.first().and_then(Value::as_f64) else {
+            return false;
+        };
+        let salt = c.values.get(1).and_then(Value::as_str).unwrap_or_default();
+        f64::from(Self::bucket_of(&c.attribute, salt, &key)) < percent
+    }
+
     /// Size-bounded so a pathological pattern can't exhaust memory; an uncompilable
     /// or oversized pattern never matches rather than failing the resolution. Compiled
     /// patterns are memoized in [`REGEX_CACHE`] so a hot flag compiles each pattern once.
@@ -261,6 +389,29 @@ impl Engine {
     }
 }
 
+/// Lenient semver: a leading `v` and missing minor/patch components are accepted, since
+/// app versions are rarely full semver (`v2`, `1.4`).
+fn parse_semver(s: &str) -> Option<Version> {
+    let s = s.trim().strip_prefix('v').unwrap_or(s.trim());
+    let core_end = s.find(['-', '+']).unwrap_or(s.len());
+    let padding = match s[..core_end].matches('.').count() {
+        0 => ".0.0",
+        1 => ".0",
+        _ => "",
+    };
+    Version::parse(&format!("{}{padding}{}", &s[..core_end], &s[core_end..])).ok()
+}
+
+/// An RFC 3339 timestamp, or the literal `now` for the evaluation time.
+fn parse_time(v: &Value) -> Option<DateTime<Utc>> {
+    match v.as_str()? {
+        "now" => Some(Utc::now()),
+        s => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -275,10 +426,20 @@ mod tests {
             default_variant_key: "off".into(),
             archived: false,
             variants: vec![
-                Variant { key: "on".into(), value: json!(true) },
-                Variant { key: "off".into(), value: json!(false) },
+                Variant {
+                    key: "on".into(),
+                    value: json!(true),
+                },
+                Variant {
+                    key: "off".into(),
+                    value: json!(false),
+                },
             ],
             rules: vec![],
+            tags: vec![],
+            owners: vec![],
+            description: String::new(),
+            expires_at: None,
         }
     }
 
@@ -302,7 +463,10 @@ mod tests {
     }
 
     fn engine(flag: Flag, segments: Vec<Segment>) -> Engine {
-        let mut s = Snapshot { version: 1, ..Default::default() };
+        let mut s = Snapshot {
+            version: 1,
+            ..Default::default()
+        };
         s.flags.insert(flag.key.clone(), flag);
         for seg in segments {
             s.segments.insert(seg.key.clone(), seg);
@@ -323,13 +487,65 @@ mod tests {
     }
 
     fn engine_with(flags: Vec<Flag>) -> Engine {
-        let mut s = Snapshot { version: 1, ..Default::default() };
+        let mut s = Snapshot {
+            version: 1,
+            ..Default::default()
+        };
         for f in flags {
             s.flags.insert(f.key.clone(), f);
         }
         Engine::new(Arc::new(s))
     }
 
+    #[test]
+    fn scoped_snapshot_keeps_dependencies_and_their_segments() {
+        let mut master = bool_flag();
+        master.key = "master".into();
+        let mut dependent = bool_flag();
+        dependent.key = "dependent".into();
+        dependent.tags = vec!["checkout".into()];
+        let mut in_beta = flag_match_rule("master", "on", "on");
+        in_beta.segment_key = Some("beta".into());
+        dependent.rules = vec![in_beta];
+        let mut unrelated = bool_flag();
+        unrelated.key = "unrelated".into();
+        unrelated.tags = vec!["search".into()];
+        unrelated.rules = vec![Rule {
+            segment_key: Some("other".into()),
+            ..flag_match_rule("x", "on", "on")
+        }];
+
+        let mut s = Snapshot {
+            version: 3,
+            ..Default::default()
+        };
+        for f in [master, dependent, unrelated] {
+            s.flags.insert(f.key.clone(), f);
+        }
+        for (key, excluded) in [
+            ("beta", vec!["staff".into()]),
+            ("staff", vec![]),
+            ("other", vec![]),
+        ] {
+            let seg = Segment {
+                key: key.into(),
+                excluded_segments: excluded,
+                ..Default::default()
+            };
+            s.segments.insert(key.into(), seg);
+        }
+
+        let scoped = s.scoped(&["checkout".into()]);
+        assert_eq!(scoped.version, 3);
+        let mut flags: Vec<_> = scoped.flags.keys().map(String::as_str).collect();
+        flags.sort();
+        assert_eq!(flags, ["dependent", "master"]);
+        let mut segments: Vec<_> = scoped.segments.keys().map(String::as_str).collect();
+        segments.sort();
+        assert_eq!(segments, ["beta", "staff"]);
+        assert!(s.scoped(&[]).flags.is_empty());
+    }
+
     #[test]
     fn flag_match_constraint_met_serves_rule() {
         let mut master = bool_flag();
@@ -427,14 +643,19 @@ mod tests {
                 operator: Operator::EndsWith,
                 values: vec![json!("@anurag.sh")],
             }],
+            ..Default::default()
         };
         let e = engine(flag, vec![beta]);
 
-        let hit = e.evaluate("feature", &ctx("u1", json!({"email": "a@anurag.sh"}))).unwrap();
+        let hit = e
+            .evaluate("feature", &ctx("u1", json!({"email": "a@anurag.sh"})))
+            .unwrap();
         assert_eq!(hit.reason, Reason::TargetingMatch);
         assert_eq!(hit.value, json!(true));
 
-        let miss = e.evaluate("feature", &ctx("u2", json!({"email": "a@other.com"}))).unwrap();
+        let miss = e
+            .evaluate("feature", &ctx("u2", json!({"email": "a@other.com"})))
+            .unwrap();
         assert_eq!(miss.reason, Reason::Default);
     }
 
@@ -459,11 +680,15 @@ mod tests {
         }];
         let e = engine(flag, vec![]);
 
-        let hit = e.evaluate("feature", &ctx("u1", json!({"email": "a@anurag.sh"}))).unwrap();
+        let hit = e
+            .evaluate("feature", &ctx("u1", json!({"email": "a@anurag.sh"})))
+            .unwrap();
         assert_eq!(hit.reason, Reason::TargetingMatch);
         assert_eq!(hit.value, json!(true));
 
-        let miss = e.evaluate("feature", &ctx("u2", json!({"email": "a@other.com"}))).unwrap();
+        let miss = e
+            .evaluate("feature", &ctx("u2", json!({"email": "a@other.com"})))
+            .unwrap();
         assert_eq!(miss.reason, Reason::Default);
     }
 
@@ -478,15 +703,38 @@ mod tests {
             variant_key: Some("on".into()),
             distributions: vec![],
             constraint_groups: vec![group(vec![
-                Constraint { attribute: "country".into(), operator: Operator::Eq, values: vec![json!("AU")] },
-                Constraint { attribute: "country".into(), operator: Operator::Eq, values: vec![json!("NZ")] },
+                Constraint {
+                    attribute: "country".into(),
+                    operator: Operator::Eq,
+                    values: vec![json!("AU")],
+                },
+                Constraint {
+                    attribute: "country".into(),
+                    operator: Operator::Eq,
+                    values: vec![json!("NZ")],
+                },
             ])],
         }];
         let e = engine(flag, vec![]);
 
-        assert_eq!(e.evaluate("feature", &ctx("u1", json!({"country": "AU"}))).unwrap().reason, Reason::TargetingMatch);
-        assert_eq!(e.evaluate("feature", &ctx("u2", json!({"country": "NZ"}))).unwrap().reason, Reason::TargetingMatch);
-        assert_eq!(e.evaluate("feature", &ctx("u3", json!({"country": "US"}))).unwrap().reason, Reason::Default);
+        assert_eq!(
+            e.evaluate("feature", &ctx("u1", json!({"country": "AU"})))
+                .unwrap()
+                .reason,
+            Reason::TargetingMatch
+        );
+        assert_eq!(
+            e.evaluate("feature", &ctx("u2", json!({"country": "NZ"})))
+                .unwrap()
+                .reason,
+            Reason::TargetingMatch
+        );
+        assert_eq!(
+            e.evaluate("feature", &ctx("u3", json!({"country": "US"})))
+                .unwrap()
+                .reason,
+            Reason::Default
+        );
     }
 
     #[test]
@@ -501,17 +749,53 @@ mod tests {
             distributions: vec![],
             constraint_groups: vec![
                 group(vec![
-                    Constraint { attribute: "country".into(), operator: Operator::Eq, values: vec![json!("AU")] },
-                    Constraint { attribute: "country".into(), operator: Operator::Eq, values: vec![json!("NZ")] },
+                    Constraint {
+                        attribute: "country".into(),
+                        operator: Operator::Eq,
+                        values: vec![json!("AU")],
+                    },
+                    Constraint {
+                        attribute: "country".into(),
+                        operator: Operator::Eq,
+                        values: vec![json!("NZ")],
+                    },
                 ]),
-                group(vec![Constraint { attribute: "plan".into(), operator: Operator::Eq, values: vec![json!("pro")] }]),
+                group(vec![Constraint {
+                    attribute: "plan".into(),
+                    operator: Operator::Eq,
+                    values: vec![json!("pro")],
+                }]),
             ],
         }];
         let e = engine(flag, vec![]);
 
-        assert_eq!(e.evaluate("feature", &ctx("u1", json!({"country": "AU", "plan": "pro"}))).unwrap().reason, Reason::TargetingMatch);
-        assert_eq!(e.evaluate("feature", &ctx("u2", json!({"country": "NZ", "plan": "free"}))).unwrap().reason, Reason::Default);
-        assert_eq!(e.evaluate("feature", &ctx("u3", json!({"country": "US", "plan": "pro"}))).unwrap().reason, Reason::Default);
+        assert_eq!(
+            e.evaluate(
+                "feature",
+                &ctx("u1", json!({"country": "AU", "plan": "pro"}))
+            )
+            .unwrap()
+            .reason,
+            Reason::TargetingMatch
+        );
+        assert_eq!(
+            e.evaluate(
+                "feature",
+                &ctx("u2", json!({"country": "NZ", "plan": "free"}))
+            )
+            .unwrap()
+            .reason,
+            Reason::Default
+        );
+        assert_eq!(
+            e.evaluate(
+                "feature",
+                &ctx("u3", json!({"country": "US", "plan": "pro"}))
+            )
+            .unwrap()
+            .reason,
+            Reason::Default
+        );
     }
 
     #[test]
@@ -537,16 +821,23 @@ mod tests {
                 operator: Operator::Eq,
                 values: vec![json!("pro")],
             }],
+            ..Default::default()
         };
         let e = engine(flag, vec![beta]);
 
-        let both = e.evaluate("feature", &ctx("u1", json!({"plan": "pro", "age": 20}))).unwrap();
+        let both = e
+            .evaluate("feature", &ctx("u1", json!({"plan": "pro", "age": 20})))
+            .unwrap();
         assert_eq!(both.reason, Reason::TargetingMatch);
 
-        let segment_only = e.evaluate("feature", &ctx("u2", json!({"plan": "pro", "age": 16}))).unwrap();
+        let segment_only = e
+            .evaluate("feature", &ctx("u2", json!({"plan": "pro", "age": 16})))
+            .unwrap();
         assert_eq!(segment_only.reason, Reason::Default);
 
-        let constraint_only = e.evaluate("feature", &ctx("u3", json!({"plan": "free", "age": 20}))).unwrap();
+        let constraint_only = e
+            .evaluate("feature", &ctx("u3", json!({"plan": "free", "age": 20})))
+            .unwrap();
         assert_eq!(constraint_only.reason, Reason::Default);
     }
 
@@ -554,8 +845,22 @@ mod tests {
     fn rules_evaluated_in_order() {
         let mut flag = bool_flag();
         flag.rules = vec![
-            Rule { rank: 0, segment_key: None, variant_key: Some("on".into()), distributions: vec![], constraint_groups: vec![], bucket_salt: String::new() },
-            Rule { rank: 1, segment_key: None, variant_key: Some("off".into()), distributions: vec![], constraint_groups: vec![], bucket_salt: String::new() },
+            Rule {
+                rank: 0,
+                segment_key: None,
+                variant_key: Some("on".into()),
+                distributions: vec![],
+                constraint_groups: vec![],
+                bucket_salt: String::new(),
+            },
+            Rule {
+                rank: 1,
+                segment_key: None,
+                variant_key: Some("off".into()),
+                distributions: vec![],
+                constraint_groups: vec![],
+                bucket_salt: String::new(),
+            },
         ];
         let e = engine(flag, vec![]);
         let r = e.evaluate("feature", &ctx("u1", json!({}))).unwrap();
@@ -571,15 +876,25 @@ mod tests {
             segment_key: None,
             variant_key: None,
             distributions: vec![
-                Distribution { variant_key: "on".into(), weight: 50 },
-                Distribution { variant_key: "off".into(), weight: 50 },
+                Distribution {
+                    variant_key: "on".into(),
+                    weight: 50,
+                },
+                Distribution {
+                    variant_key: "off".into(),
+                    weight: 50,
+                },
             ],
             constraint_groups: vec![],
         }];
         let e = engine(flag, vec![]);
 
-        let a1 = e.evaluate("feature", &ctx("stable-key", json!({}))).unwrap();
-        let a2 = e.evaluate("feature", &ctx("stable-key", json!({}))).unwrap();
+        let a1 = e
+            .evaluate("feature", &ctx("stable-key", json!({})))
+            .unwrap();
+        let a2 = e
+            .evaluate("feature", &ctx("stable-key", json!({})))
+            .unwrap();
         assert_eq!(a1.variant, a2.variant);
         assert_eq!(a1.reason, Reason::Split);
 
@@ -606,7 +921,10 @@ mod tests {
                 a == b
             })
             .count();
-        assert!((400..600).contains(&agree), "salts not independent: {agree}/1000 agree");
+        assert!(
+            (400..600).contains(&agree),
+            "salts not independent: {agree}/1000 agree"
+        );
 
         // The same salt is deterministic.
         assert_eq!(
@@ -624,7 +942,8 @@ mod tests {
             hasher.update(b"feature");
             hasher.update(b":");
             hasher.update(key.as_bytes());
-            let legacy = (u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap()) % 100) as u32;
+            let legacy =
+                (u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap()) % 100) as u32;
             assert_eq!(Engine::bucket_of("feature", "", key), legacy);
         }
     }
@@ -638,18 +957,281 @@ mod tests {
         assert!(!Engine::regex_match("anything", "("));
     }
 
+    fn matches(
+        op: Operator,
+        attribute: &str,
+        values: serde_json::Value,
+        attrs: serde_json::Value,
+    ) -> bool {
+        let c = Constraint {
+            attribute: attribute.into(),
+            operator: op,
+            values: values.as_array().unwrap().clone(),
+        };
+        Engine::attr_constraint_matches(&c, &ctx("u", attrs))
+    }
+
+    #[test]
+    fn semver_operators_compare_by_precedence() {
+        let v = |version: &str| json!({ "app_version": version });
+        assert!(matches(
+            Operator::SemverGte,
+            "app_version",
+            json!(["2.4.0"]),
+            v("2.10.1")
+        ));
+        assert!(!matches(
+            Operator::SemverGt,
+            "app_version",
+            json!(["2.4.0"]),
+            v("2.4.0-beta.1")
+        ));
+        assert!(matches(
+            Operator::SemverLt,
+            "app_version",
+            json!(["2.4.0"]),
+            v("2.4.0-beta.1")
+        ));
+        assert!(matches(
+            Operator::SemverEq,
+            "app_version",
+            json!(["v2.4"]),
+            v("2.4.0+build.7")
+        ));
+        assert!(matches(
+            Operator::SemverLte,
+            "app_version",
+            json!(["3"]),
+            v("2.99.0")
+        ));
+        // Unparseable on either side never matches.
+        assert!(!matches(
+            Operator::SemverLt,
+            "app_version",
+            json!(["3.0.0"]),
+            v("latest")
+        ));
+        assert!(!matches(
+            Operator::SemverLt,
+            "app_version",
+            json!(["soon"]),
+            v("1.0.0")
+        ));
+        assert!(!matches(
+            Operator::SemverEq,
+            "app_version",
+            json!(["1.0.0"]),
+            json!({})
+        ));
+    }
+
+    #[test]
+    fn datetime_operators_and_now() {
+        let signup = json!({ "signed_up": "2026-03-01T00:00:00+11:00" });
+        assert!(matches(
+            Operator::Before,
+            "signed_up",
+            json!(["2026-03-01T00:00:00Z"]),
+            signup.clone()
+        ));
+        assert!(!matches(
+            Operator::After,
+            "signed_up",
+            json!(["2026-03-01T00:00:00Z"]),
+            signup.clone()
+        ));
+        assert!(matches(
+            Operator::Before,
+            "signed_up",
+            json!(["now"]),
+            signup
+        ));
+        assert!(!matches(
+            Operator::Before,
+            "signed_up",
+            json!(["now"]),
+            json!({ "signed_up": "yesterday" })
+        ));
+
+        // An unset `now` attribute is the evaluation time; a set one pins it.
+        assert!(matches(
+            Operator::After,
+            "now",
+            json!(["2000-01-01T00:00:00Z"]),
+            json!({})
+        ));
+        assert!(!matches(
+            Operator::Before,
+            "now",
+            json!(["2000-01-01T00:00:00Z"]),
+            json!({})
+        ));
+        let pinned = json!({ "now": "1999-06-01T00:00:00Z" });
+        assert!(matches(
+            Operator::Before,
+            "now",
+            json!(["2000-01-01T00:00:00Z"]),
+            pinned
+        ));
+    }
+
+    #[test]
+    fn percent_of_buckets_on_the_attribute() {
+        let org = |id: serde_json::Value| json!({ "org_id": id });
+        // Every user in an org gets the same answer, whatever their targeting key.
+        let inside = (0..100)
+            .map(|i| format!("org-{i}"))
+            .find(|id| matches(Operator::PercentOf, "org_id", json!([20]), org(json!(id))))
+            .unwrap();
+        for user in ["u1", "u2", "u3"] {
+            let c = Constraint {
+                attribute: "org_id".into(),
+                operator: Operator::PercentOf,
+                values: vec![json!(20)],
+            };
+            assert!(Engine::attr_constraint_matches(
+                &c,
+                &ctx(user, org(json!(inside.clone())))
+            ));
+        }
+
+        let share = (0..1000)
+            .filter(|i| matches(Operator::PercentOf, "org_id", json!([20]), org(json!(i))))
+            .count();
+        assert!(
+            (150..250).contains(&share),
+            "unexpected share: {share}/1000"
+        );
+        assert!(matches(
+            Operator::PercentOf,
+            "org_id",
+            json!([100]),
+            org(json!(7))
+        ));
+        assert!(!matches(
+            Operator::PercentOf,
+            "org_id",
+            json!([0]),
+            org(json!(7))
+        ));
+        assert!(!matches(
+            Operator::PercentOf,
+            "org_id",
+            json!([50]),
+            json!({})
+        ));
+
+        // A salt reshuffles which values fall inside.
+        let agree = (0..1000)
+            .filter(|i| {
+                matches(Operator::PercentOf, "org_id", json!([50]), org(json!(i)))
+                    == matches(
+                        Operator::PercentOf,
+                        "org_id",
+                        json!([50, "other"]),
+                        org(json!(i)),
+                    )
+            })
+            .count();
+        assert!(
+            (400..600).contains(&agree),
+            "salt not independent: {agree}/1000 agree"
+        );
+    }
+
     #[test]
     fn numeric_and_membership_operators() {
         let seg = Segment {
             key: "s".into(),
             name: "s".into(),
             constraints: vec![
-                Constraint { attribute: "age".into(), operator: Operator::Gte, values: vec![json!(18)] },
-                Constraint { attribute: "country".into(), operator: Operator::In, values: vec![json!("AU"), json!("NZ")] },
+                Constraint {
+                    attribute: "age".into(),
+                    operator: Operator::Gte,
+                    values: vec![json!(18)],
+                },
+                Constraint {
+                    attribute: "country".into(),
+                    operator: Operator::In,
+                    values: vec![json!("AU"), json!("NZ")],
+                },
             ],
+            ..Default::default()
         };
-        assert!(Engine::segment_matches(&seg, &ctx("u", json!({"age": 20, "country": "AU"}))));
-        assert!(!Engine::segment_matches(&seg, &ctx("u", json!({"age": 16, "country": "AU"}))));
-        assert!(!Engine::segment_matches(&seg, &ctx("u", json!({"age": 20, "country": "US"}))));
+        let e = engine(bool_flag(), vec![seg]);
+        assert!(e.segment_matches("s", &ctx("u", json!({"age": 20, "country": "AU"}))));
+        assert!(!e.segment_matches("s", &ctx("u", json!({"age": 16, "country": "AU"}))));
+        assert!(!e.segment_matches("s", &ctx("u", json!({"age": 20, "country": "US"}))));
+    }
+
+    fn segment(key: &str, f: impl FnOnce(&mut Segment)) -> Segment {
+        let mut s = Segment {
+            key: key.into(),
+            name: key.into(),
+            ..Default::default()
+        };
+        f(&mut s);
+        s
+    }
+
+    fn eq(attribute: &str, value: serde_json::Value) -> Constraint {
+        Constraint {
+            attribute: attribute.into(),
+            operator: Operator::Eq,
+            values: vec![value],
+        }
+    }
+
+    #[test]
+    fn nested_segments_include_and_exclude() {
+        let staff = segment("staff", |s| s.constraints = vec![eq("staff", json!(true))]);
+        let au = segment("au", |s| {
+            s.constraint_groups = vec![ConstraintGroup {
+                constraints: vec![eq("country", json!("AU")), eq("country", json!("NZ"))],
+            }];
+        });
+        let testers = segment("testers", |s| {
+            s.targeting_keys = ["u1", "u2"].map(String::from).into()
+        });
+        let beta = segment("beta", |s| {
+            s.included_segments = vec!["au".into(), "testers".into()];
+            s.excluded_segments = vec!["staff".into()];
+        });
+        let e = engine(bool_flag(), vec![staff, au, testers, beta]);
+
+        assert!(e.segment_matches("beta", &ctx("u1", json!({"country": "NZ"}))));
+        assert!(
+            !e.segment_matches("beta", &ctx("u3", json!({"country": "NZ"}))),
+            "not listed"
+        );
+        assert!(
+            !e.segment_matches("beta", &ctx("u1", json!({"country": "US"}))),
+            "outside the OR group"
+        );
+        assert!(
+            !e.segment_matches("beta", &ctx("u2", json!({"country": "AU", "staff": true}))),
+            "excluded"
+        );
+    }
+
+    #[test]
+    fn segment_cycles_and_dangling_references_never_match() {
+        let a = segment("a", |s| s.included_segments = vec!["b".into()]);
+        let b = segment("b", |s| s.included_segments = vec!["a".into()]);
+        // An exclusion that can't be decided doesn't let the context through.
+        let c = segment("c", |s| s.excluded_segments = vec!["a".into()]);
+        let d = segment("d", |s| s.excluded_segments = vec!["missing".into()]);
+        // ...but one that's decided before reaching the broken reference is fine.
+        let e_seg = segment("e", |s| s.excluded_segments = vec!["f".into()]);
+        let f = segment("f", |s| {
+            s.constraints = vec![eq("plan", json!("pro"))];
+            s.included_segments = vec!["missing".into()];
+        });
+        let e = engine(bool_flag(), vec![a, b, c, d, e_seg, f]);
+        let anyone = ctx("u1", json!({}));
+        for key in ["a", "b", "c", "d", "missing"] {
+            assert!(!e.segment_matches(key, &anyone), "{key}");
+        }
+        assert!(e.segment_matches("e", &anyone));
     }
 }
@@ -28,14 +28,14 @@ impl ErrorCode {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Resolution {
     pub value: Value,
     pub variant: String,
     pub reason: Reason,
 }
 
-#[derive(Clone, Debug, thiserror::Error)]
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
 #[error("{code:?}: {message}")]
 pub struct EvalError {
     pub code: ErrorCode,
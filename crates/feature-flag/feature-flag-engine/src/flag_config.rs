@@ -0,0 +1,479 @@
+//! The declarative flag config format: the on-disk document types behind `ffctl
+//! config` (Terraform-style flag management) and a local-mode client's static
+//! bootstrap file, and their conversions to and from the wire types. The service's
+//! JSON Schemas under `config/schema` are generated from these types (with the
+//! `schema` feature) by its `gen-schema` binary — edit the types, then regenerate;
+//! don't hand-edit the `.json`.
+
+use crate::convert::{ConversionError, json_to_prost_value, prost_value_to_json};
+use crate::model::Snapshot;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use feature_flag_proto as pb;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
+
+/// One flag file (`config/flags/<key>.yaml`). Variants are a `key: value` map; rules
+/// are ordered by position (their rank is assigned server-side on apply).
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FlagDoc {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub value_type: String,
+    #[serde(default)]
+    pub enabled: bool,
+    pub default: String,
+    /// Labels such as the services that read the flag; local-mode clients can be
+    /// limited to the flags carrying their tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Who to ask about the flag.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// When a temporary flag should be removed by: a date (`2025-06-30`, midnight UTC)
+    /// or an RFC 3339 timestamp. `ffctl config plan` warns once it has passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub variants: BTreeMap<String, Json>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RuleDoc>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RuleDoc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub distributions: Vec<DistDoc>,
+    /// Flat AND sugar: each constraint becomes its own single-element group.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<ConstraintDoc>,
+    /// CNF: outer array AND-combined, inner arrays OR-combined. Takes precedence over
+    /// `constraints` when present.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraint_groups: Vec<Vec<ConstraintDoc>>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub bucket_salt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DistDoc {
+    pub variant: String,
+    pub weight: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConstraintDoc {
+    pub attribute: String,
+    /// One of `eq`, `neq`, `in`, `not_in`, `contains`, `starts_with`, `ends_with`, `gt`,
+    /// `gte`, `lt`, `lte`, `exists`, `regex`, `flag_matches`, `semver_eq`, `semver_gt`,
+    /// `semver_gte`, `semver_lt`, `semver_lte`, `before`, `after` or `percent_of`.
+    /// `before`/`after` compare RFC 3339 timestamps, either of which may be `now`;
+    /// `percent_of` takes `[percent]` or `[percent, salt]` and buckets the attribute's
+    /// value the way splits bucket the targeting key.
+    pub operator: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<Json>,
+}
+
+/// The `config/segments.yaml` file: all segments in one document.
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SegmentsFile {
+    #[serde(default)]
+    pub segments: Vec<SegmentDoc>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SegmentDoc {
+    pub key: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraints: Vec<ConstraintDoc>,
+    /// CNF, as on a rule: groups are AND-combined, constraints within a group
+    /// OR-combined. Combined with `constraints` by AND.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraint_groups: Vec<Vec<ConstraintDoc>>,
+    /// Segments the context must also be in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Segments the context must not be in.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// An allowlist of targeting keys; when set, only these keys can match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targeting_keys: Vec<String>,
+}
+
+/// A whole config in one document — the flags of `config/flags/` alongside the
+/// segments of `config/segments.yaml` — as a local-mode client's static bootstrap.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub flags: Vec<FlagDoc>,
+    #[serde(default)]
+    pub segments: Vec<SegmentDoc>,
+}
+
+impl ConfigFile {
+    /// The snapshot this config describes, as the service would serve it after
+    /// applying it (version 0).
+    pub fn to_snapshot(&self) -> Result<Snapshot, ConversionError> {
+        Snapshot::try_from(pb::SnapshotResponse {
+            version: 0,
+            flags: self
+                .flags
+                .iter()
+                .map(pb::Flag::try_from)
+                .collect::<Result<_, _>>()?,
+            segments: self
+                .segments
+                .iter()
+                .map(pb::Segment::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<&FlagDoc> for pb::Flag {
+    type Error = ConversionError;
+
+    fn try_from(doc: &FlagDoc) -> Result<Self, Self::Error> {
+        let in_flag = |e: ConversionError| ConversionError(format!("flag `{}`: {e}", doc.key));
+        let value_type = value_type_from_name(&doc.value_type).map_err(in_flag)?;
+        let variants = doc
+            .variants
+            .iter()
+            .map(|(k, v)| pb::Variant {
+                key: k.clone(),
+                value: Some(json_to_prost_value(v)),
+            })
+            .collect();
+        let rules = doc
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, r)| rule_doc_to_pb(i as u32, r))
+            .collect::<Result<_, _>>()
+            .map_err(in_flag)?;
+        Ok(pb::Flag {
+            key: doc.key.clone(),
+            value_type: value_type as i32,
+            enabled: doc.enabled,
+            default_variant_key: doc.default.clone(),
+            archived: false,
+            variants,
+            rules,
+            tags: doc.tags.clone(),
+            owners: doc.owners.clone(),
+            description: doc.description.clone(),
+            expires_at: doc
+                .expires_at
+                .as_deref()
+                .map(expiry_from_doc)
+                .transpose()
+                .map_err(in_flag)?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl TryFrom<&SegmentDoc> for pb::Segment {
+    type Error = ConversionError;
+
+    fn try_from(doc: &SegmentDoc) -> Result<Self, Self::Error> {
+        let in_segment =
+            |e: ConversionError| ConversionError(format!("segment `{}`: {e}", doc.key));
+        Ok(pb::Segment {
+            key: doc.key.clone(),
+            name: doc.name.clone(),
+            constraints: doc
+                .constraints
+                .iter()
+                .map(constraint_doc_to_pb)
+                .collect::<Result<_, _>>()
+                .map_err(in_segment)?,
+            constraint_groups: doc
+                .constraint_groups
+                .iter()
+                .map(|g| group_doc_to_pb(g))
+                .collect::<Result<_, _>>()
+                .map_err(in_segment)?,
+            included_segments: doc.include.clone(),
+            excluded_segments: doc.exclude.clone(),
+            targeting_keys: doc.targeting_keys.clone(),
+        })
+    }
+}
+
+impl From<&pb::Flag> for FlagDoc {
+    fn from(f: &pb::Flag) -> Self {
+        FlagDoc {
+            key: f.key.clone(),
+            value_type: value_type_name(f.value_type).to_owned(),
+            enabled: f.enabled,
+            default: f.default_variant_key.clone(),
+            variants: f
+                .variants
+                .iter()
+                .map(|v| {
+                    (
+                        v.key.clone(),
+                        v.value
+                            .as_ref()
+                            .map(prost_value_to_json)
+                            .unwrap_or(Json::Null),
+                    )
+                })
+                .collect(),
+            rules: f.rules.iter().map(rule_to_doc).collect(),
+            tags: f.tags.clone(),
+            owners: f.owners.clone(),
+            description: f.description.clone(),
+            expires_at: expiry_to_doc(&f.expires_at),
+        }
+    }
+}
+
+impl From<&pb::Segment> for SegmentDoc {
+    fn from(s: &pb::Segment) -> Self {
+        SegmentDoc {
+            key: s.key.clone(),
+            name: s.name.clone(),
+            constraints: s.constraints.iter().map(constraint_to_doc).collect(),
+            constraint_groups: s
+                .constraint_groups
+                .iter()
+                .map(|g| g.constraints.iter().map(constraint_to_doc).collect())
+                .collect(),
+            include: s.included_segments.clone(),
+            exclude: s.excluded_segments.clone(),
+            targeting_keys: s.targeting_keys.clone(),
+        }
+    }
+}
+
+fn rule_doc_to_pb(rank: u32, r: &RuleDoc) -> Result<pb::Rule, ConversionError> {
+    let constraint_groups = if !r.constraint_groups.is_empty() {
+        r.constraint_groups
+            .iter()
+            .map(|g| group_doc_to_pb(g))
+            .collect::<Result<_, _>>()?
+    } else {
+        r.constraints
+            .iter()
+            .map(|c| {
+                Ok(pb::ConstraintGroup {
+                    constraints: vec![constraint_doc_to_pb(c)?],
+                })
+            })
+            .collect::<Result<_, ConversionError>>()?
+    };
+    Ok(pb::Rule {
+        rank,
+        segment_key: r.segment.clone().unwrap_or_default(),
+        variant_key: r.variant.clone().unwrap_or_default(),
+        distributions: r
+            .distributions
+            .iter()
+            .map(|d| pb::Distribution {
+                variant_key: d.variant.clone(),
+                weight: d.weight,
+            })
+            .collect(),
+        constraint_groups,
+        bucket_salt: r.bucket_salt.clone(),
+    })
+}
+
+fn group_doc_to_pb(group: &[ConstraintDoc]) -> Result<pb::ConstraintGroup, ConversionError> {
+    Ok(pb::ConstraintGroup {
+        constraints: group
+            .iter()
+            .map(constraint_doc_to_pb)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn constraint_doc_to_pb(c: &ConstraintDoc) -> Result<pb::Constraint, ConversionError> {
+    Ok(pb::Constraint {
+        attribute: c.attribute.clone(),
+        operator: operator_from_name(&c.operator)? as i32,
+        values: c.values.iter().map(json_to_prost_value).collect(),
+    })
+}
+
+fn rule_to_doc(r: &pb::Rule) -> RuleDoc {
+    // Collapse to the flat `constraints` form when every group is a single constraint
+    // (plain AND); otherwise keep the CNF `constraint_groups` form.
+    let flat = r.constraint_groups.iter().all(|g| g.constraints.len() == 1);
+    let (constraints, constraint_groups) = if flat {
+        (
+            r.constraint_groups
+                .iter()
+                .map(|g| constraint_to_doc(&g.constraints[0]))
+                .collect(),
+            Vec::new(),
+        )
+    } else {
+        (
+            Vec::new(),
+            r.constraint_groups
+                .iter()
+                .map(|g| g.constraints.iter().map(constraint_to_doc).collect())
+                .collect(),
+        )
+    };
+    RuleDoc {
+        segment: (!r.segment_key.is_empty()).then(|| r.segment_key.clone()),
+        variant: (!r.variant_key.is_empty()).then(|| r.variant_key.clone()),
+        distributions: r
+            .distributions
+            .iter()
+            .map(|d| DistDoc {
+                variant: d.variant_key.clone(),
+                weight: d.weight,
+            })
+            .collect(),
+        constraints,
+        constraint_groups,
+        bucket_salt: r.bucket_salt.clone(),
+    }
+}
+
+fn constraint_to_doc(c: &pb::Constraint) -> ConstraintDoc {
+    ConstraintDoc {
+        attribute: c.attribute.clone(),
+        operator: operator_name(c.operator),
+        values: c.values.iter().map(prost_value_to_json).collect(),
+    }
+}
+
+/// A `FlagDoc` `expires_at` (a date or an RFC 3339 timestamp) as the RFC 3339 time the
+/// API takes.
+fn expiry_from_doc(s: &str) -> Result<String, ConversionError> {
+    let at = match DateTime::parse_from_rfc3339(s) {
+        Ok(at) => at.with_timezone(&Utc),
+        Err(_) => NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map_err(|_| {
+                ConversionError(format!(
+                    "expires_at `{s}` is neither a date nor an RFC 3339 time"
+                ))
+            })?
+            .and_time(NaiveTime::MIN)
+            .and_utc(),
+    };
+    Ok(at.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+/// The API's `expires_at` for a `FlagDoc`, shortened to a date when it's midnight UTC.
+fn expiry_to_doc(s: &str) -> Option<String> {
+    if s.is_empty() {
+        return None;
+    }
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(at) if at.offset().local_minus_utc() == 0 && at.time() == NaiveTime::MIN => {
+            Some(at.date_naive().to_string())
+        }
+        _ => Some(s.to_owned()),
+    }
+}
+
+fn value_type_from_name(s: &str) -> Result<pb::ValueType, ConversionError> {
+    Ok(match s {
+        "boolean" => pb::ValueType::Boolean,
+        "string" => pb::ValueType::String,
+        "integer" => pb::ValueType::Integer,
+        "float" => pb::ValueType::Float,
+        "object" => pb::ValueType::Object,
+        other => return Err(ConversionError(format!("unknown flag type `{other}`"))),
+    })
+}
+
+fn value_type_name(v: i32) -> &'static str {
+    match pb::ValueType::try_from(v).unwrap_or_default() {
+        pb::ValueType::Boolean => "boolean",
+        pb::ValueType::String => "string",
+        pb::ValueType::Integer => "integer",
+        pb::ValueType::Float => "float",
+        pb::ValueType::Object => "object",
+        pb::ValueType::Unspecified => "unspecified",
+    }
+}
+
+/// Resolve the short operator name (e.g. `in`, `starts_with`) to its proto enum.
+fn operator_from_name(s: &str) -> Result<pb::ConstraintOperator, ConversionError> {
+    let name = format!("CONSTRAINT_OPERATOR_{}", s.to_uppercase());
+    pb::ConstraintOperator::from_str_name(&name)
+        .ok_or_else(|| ConversionError(format!("unknown operator `{s}`")))
+}
+
+/// The short name config documents use for a proto constraint operator.
+pub fn operator_name(op: i32) -> String {
+    pb::ConstraintOperator::try_from(op)
+        .unwrap_or_default()
+        .as_str_name()
+        .trim_start_matches("CONSTRAINT_OPERATOR_")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EvalContext};
+    use std::sync::Arc;
+
+    #[test]
+    fn a_config_file_becomes_an_evaluable_snapshot() {
+        let file: ConfigFile = serde_json::from_value(serde_json::json!({
+            "flags": [{
+                "key": "checkout",
+                "type": "boolean",
+                "enabled": true,
+                "default": "off",
+                "expires_at": "2030-01-01",
+                "variants": {"on": true, "off": false},
+                "rules": [{"segment": "beta", "variant": "on"}],
+            }],
+            "segments": [{
+                "key": "beta",
+                "constraints": [{"attribute": "plan", "operator": "eq", "values": ["pro"]}],
+            }],
+        }))
+        .unwrap();
+        let snapshot = file.to_snapshot().unwrap();
+        assert_eq!(
+            snapshot.flags["checkout"].expires_at.unwrap().to_rfc3339(),
+            "2030-01-01T00:00:00+00:00"
+        );
+
+        let engine = Engine::new(Arc::new(snapshot));
+        let ctx = EvalContext {
+            targeting_key: "user-1".into(),
+            attributes: [("plan".into(), serde_json::json!("pro"))].into(),
+        };
+        let resolution = engine.evaluate("checkout", &ctx).unwrap();
+        assert_eq!(resolution.value, serde_json::json!(true));
+
+        let bad = ConfigFile {
+            flags: vec![FlagDoc {
+                value_type: "bool".into(),
+                ..FlagDoc::from(&pb::Flag::try_from(&file.flags[0]).unwrap())
+            }],
+            segments: vec![],
+        };
+        let err = bad.to_snapshot().err().unwrap();
+        assert_eq!(err.to_string(), "flag `checkout`: unknown flag type `bool`");
+    }
+}
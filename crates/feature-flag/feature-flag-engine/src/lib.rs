@@ -1,13 +1,13 @@
 //! The pure feature-flag domain: the data model, the side-effect-free evaluation
 //! [`Engine`], and conversions to/from the [`feature_flag_proto`] wire types. Shared
 //! by the backend service (authoritative evaluation) and the client crate (optional
-//! in-process local evaluation against a server-provided snapshot).
+//! in-process local evaluation against a server-provided snapshot), along with the
+//! declarative [`flag_config`] format both read.
 
 pub mod convert;
 pub mod engine;
+pub mod flag_config;
 pub mod model;
 
-pub use engine::{Engine, ErrorCode, EvalContext, EvalError, Reason, Resolution};
-pub use model::{
-    Constraint, Distribution, Flag, Operator, Segment, Snapshot, ValueType, Variant,
-};
+pub use engine::{Engine, ErrorCode, EvalContext, EvalError, Explanation, Reason, Resolution};
+pub use model::{Constraint, Distribution, Flag, Operator, Segment, Snapshot, ValueType, Variant};
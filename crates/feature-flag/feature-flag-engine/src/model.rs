@@ -2,8 +2,9 @@
 //! from both the generated protobuf types and the database rows; conversions live
 //! in [`crate::convert`] and [`crate::store`].
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ValueType {
@@ -35,6 +36,22 @@ pub enum Operator {
     Lte,
     Exists,
     Regex,
+    /// Semantic-version comparisons of the attribute against `values[0]`. Missing
+    /// minor/patch components and a leading `v` are tolerated (`v2.1` is `2.1.0`).
+    SemverEq,
+    SemverGt,
+    SemverGte,
+    SemverLt,
+    SemverLte,
+    /// RFC 3339 timestamp comparisons of the attribute against `values[0]`. Either side
+    /// may be `now`: an operand of `"now"` is the evaluation time, as is a `now`
+    /// attribute the context doesn't set, so `now before <date>` time-boxes a rule.
+    Before,
+    After,
+    /// Percentage rollout keyed on the attribute's value rather than the targeting key,
+    /// e.g. 10% of organisations by `org_id`. `values[0]` is the percentage; an
+    /// optional `values[1]` salt decorrelates constraints on the same attribute.
+    PercentOf,
     /// Depends on another flag rather than a context attribute: the constraint's
     /// `attribute` names the flag and `values` lists the variant keys it must
     /// resolve to. Only meaningful inside a rule, where the engine can recurse.
@@ -48,11 +65,22 @@ pub struct Constraint {
     pub values: Vec<serde_json::Value>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A reusable audience. A context matches when every part present matches: the
+/// targeting key is in `targeting_keys` (when non-empty), every constraint and
+/// constraint group matches, every included segment matches and no excluded one does.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Segment {
     pub key: String,
     pub name: String,
     pub constraints: Vec<Constraint>,
+    /// CNF like a rule's groups: AND-combined, each matching when any of its
+    /// constraints does.
+    pub constraint_groups: Vec<ConstraintGroup>,
+    pub included_segments: Vec<String>,
+    pub excluded_segments: Vec<String>,
+    /// An allowlist of targeting keys, kept as a set so lists of tens of thousands of
+    /// ids stay cheap to match.
+    pub targeting_keys: HashSet<String>,
 }
 
 /// A group of constraints OR-combined together. Matches when any of its
@@ -93,12 +121,40 @@ pub struct Flag {
     pub archived: bool,
     pub variants: Vec<Variant>,
     pub rules: Vec<Rule>,
+    /// Free-form labels, typically the services or teams that read the flag; see
+    /// [`Snapshot::scoped`].
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Who to ask about the flag. Informational only.
+    #[serde(default)]
+    pub owners: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+    /// When a temporary (e.g. release) flag is meant to be gone by. Evaluation ignores
+    /// it; the server and `ffctl` flag flags that outlive it.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl Flag {
     pub fn variant(&self, key: &str) -> Option<&Variant> {
         self.variants.iter().find(|v| v.key == key)
     }
+
+    /// Whether the flag has outlived its `expires_at` at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// Keys of the flags this one's rules depend on through `FlagMatches` constraints.
+    fn flag_dependencies(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .flat_map(|r| &r.constraint_groups)
+            .flat_map(|g| &g.constraints)
+            .filter(|c| c.operator == Operator::FlagMatches)
+            .map(|c| c.attribute.as_str())
+    }
 }
 
 /// An immutable, fully-resolved view of every flag and segment at a given config
@@ -110,3 +166,55 @@ pub struct Snapshot {
     pub flags: HashMap<String, Flag>,
     pub segments: HashMap<String, Segment>,
 }
+
+impl Snapshot {
+    /// The part of the snapshot a client interested in `tags` needs to evaluate its
+    /// flags locally: the flags carrying any of the tags, the flags they depend on
+    /// (transitively), and the segments all of those reference, directly or through
+    /// other segments' includes and excludes.
+    pub fn scoped(&self, tags: &[String]) -> Snapshot {
+        let mut flags = HashMap::new();
+        let mut pending: Vec<&str> = self
+            .flags
+            .values()
+            .filter(|f| f.tags.iter().any(|t| tags.contains(t)))
+            .map(|f| f.key.as_str())
+            .collect();
+        let mut segment_keys = Vec::new();
+        while let Some(key) = pending.pop() {
+            if flags.contains_key(key) {
+                continue;
+            }
+            let Some(flag) = self.flags.get(key) else {
+                continue;
+            };
+            flags.insert(key.to_owned(), flag.clone());
+            pending.extend(flag.flag_dependencies());
+            segment_keys.extend(flag.rules.iter().filter_map(|r| r.segment_key.as_deref()));
+        }
+
+        let mut segments = HashMap::new();
+        while let Some(key) = segment_keys.pop() {
+            if segments.contains_key(key) {
+                continue;
+            }
+            let Some(segment) = self.segments.get(key) else {
+                continue;
+            };
+            segments.insert(key.to_owned(), segment.clone());
+            segment_keys.extend(
+                segment
+                    .included_segments
+                    .iter()
+                    .chain(&segment.excluded_segments)
+                    .map(String::as_str),
+            );
+        }
+
+        Snapshot {
+            version: self.version,
+            flags,
+            segments,
+        }
+    }
+}
@@ -54,9 +54,9 @@ impl FeatureFlagProvider {
         ))
     }
 
-    /// Connect with an explicit evaluation mode (remote RPC vs in-process local
-    /// evaluation against the streamed snapshot). `client_id` identifies the calling
-    /// service to the backend.
+    /// Connect with an explicit evaluation mode (remote RPC, in-process local
+    /// evaluation against the streamed snapshot, or remote RPC with cached answers).
+    /// `client_id` identifies the calling service to the backend.
     pub async fn connect_with(
         endpoint: impl Into<String>,
         client_id: impl Into<String>,
@@ -0,0 +1,349 @@
+//! Verification of OIDC-issued JWTs, for the services whose admin APIs accept them:
+//! the signature against the issuer's published keys, then `iss`, `aud` and expiry.
+//! What the claims grant is up to each service.
+//!
+//! Signing keys are discovered through the issuer's `/.well-known/openid-configuration`
+//! and cached. Once fetched, keys keep verifying through an issuer outage, and fetches
+//! (failed or not) are spaced at least [`REFETCH_COOLDOWN`] apart, so neither an outage
+//! nor a stream of garbage tokens turns every request into a call to the issuer.
+
+use anyhow::Context;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{AlgorithmFamily, Validation, decode, decode_header};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+/// Signing keys are refetched at least this often, so a rotation that retires a key
+/// takes effect without a restart.
+pub const JWKS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// A token signed by an unknown key triggers a refetch (the issuer may have rotated),
+/// but no more often than this, so garbage tokens can't hammer the issuer.
+pub const REFETCH_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Bounds each discovery and key request, since verification waits on the fetch.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A verified token's claims.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Claims {
+    /// The issuer's stable, unique id for the caller. Unlike `preferred_username`,
+    /// the user can't choose it, so it's what to bind permissions to.
+    pub sub: String,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Claims {
+    /// The values of claim `name`, which issuers send as a single string or an array.
+    pub fn strings(&self, name: &str) -> Vec<&str> {
+        match self.extra.get(name) {
+            Some(Value::String(value)) => vec![value.as_str()],
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct KeyCache {
+    /// The last key set fetched, and when.
+    keys: Option<(JwkSet, Instant)>,
+    /// When a fetch was last tried, whether or not it succeeded.
+    attempted: Option<Instant>,
+}
+
+impl KeyCache {
+    fn find(&self, kid: &str) -> Option<Jwk> {
+        self.keys.as_ref()?.0.find(kid).cloned()
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.keys
+            .as_ref()
+            .is_some_and(|(_, fetched)| now.saturating_duration_since(*fetched) < JWKS_MAX_AGE)
+    }
+
+    fn cooling_down(&self, now: Instant) -> bool {
+        self.attempted
+            .is_some_and(|attempted| now.saturating_duration_since(attempted) < REFETCH_COOLDOWN)
+    }
+
+    /// The key for `kid` as of `now`, or `None` if it's time to fetch: a held key is
+    /// used while fresh or while a fetch is cooling down, and an unknown one is
+    /// rejected during the cooldown.
+    fn lookup(&self, kid: &str, now: Instant) -> Option<anyhow::Result<Jwk>> {
+        match self.find(kid) {
+            Some(jwk) if self.is_fresh(now) || self.cooling_down(now) => Some(Ok(jwk)),
+            _ if self.cooling_down(now) => Some(Err(anyhow::anyhow!("unknown signing key {kid}"))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+/// Verifies JWTs from one issuer for one audience.
+pub struct Verifier {
+    issuer: String,
+    audience: String,
+    http: reqwest::Client,
+    keys: RwLock<KeyCache>,
+    /// Held for the length of a fetch, so concurrent misses wait for one fetch rather
+    /// than each starting their own. `keys` is never held across the fetch, so tokens
+    /// signed by cached keys verify meanwhile.
+    fetching: Mutex<()>,
+}
+
+impl Verifier {
+    pub fn new(
+        issuer: impl Into<String>,
+        audience: impl Into<String>,
+        http: reqwest::Client,
+    ) -> Self {
+        Self {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            http,
+            keys: RwLock::default(),
+            fetching: Mutex::default(),
+        }
+    }
+
+    /// Start from `keys` as if just fetched, rather than fetching on the first token.
+    pub fn with_keys(self, keys: JwkSet) -> Self {
+        let now = Instant::now();
+        Self {
+            keys: RwLock::new(KeyCache {
+                keys: Some((keys, now)),
+                attempted: Some(now),
+            }),
+            ..self
+        }
+    }
+
+    pub async fn verify(&self, token: &str) -> anyhow::Result<Claims> {
+        self.verify_at(token, Instant::now()).await
+    }
+
+    /// [`Self::verify`], aging cached keys as of `now`.
+    async fn verify_at(&self, token: &str, now: Instant) -> anyhow::Result<Claims> {
+        let header = decode_header(token)?;
+        // Issuers sign with asymmetric keys; an HMAC token would be "signed" with the
+        // public key, so it's rejected before key lookup.
+        anyhow::ensure!(
+            header.alg.family() != AlgorithmFamily::Hmac,
+            "unsupported token algorithm {:?}",
+            header.alg
+        );
+        let kid = header.kid.context("token has no key id")?;
+        let jwk = self.key(&kid, now).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        Ok(decode::<Claims>(token, &(&jwk).try_into()?, &validation)?.claims)
+    }
+
+    async fn key(&self, kid: &str, now: Instant) -> anyhow::Result<Jwk> {
+        if let Some(found) = self.keys.read().await.lookup(kid, now) {
+            return found;
+        }
+
+        let _fetching = self.fetching.lock().await;
+        // Another request may have fetched while this one waited its turn.
+        if let Some(found) = self.keys.read().await.lookup(kid, now) {
+            return found;
+        }
+        self.keys.write().await.attempted = Some(now);
+        let fetched = self.fetch_keys().await;
+
+        let mut cache = self.keys.write().await;
+        match fetched {
+            Ok(keys) => cache.keys = Some((keys, now)),
+            // Keys already held still verify; an issuer blip shouldn't lock every
+            // caller out.
+            Err(e) if cache.keys.is_some() => {
+                tracing::warn!("refetching oidc signing keys failed, keeping cached keys: {e:#}")
+            }
+            Err(e) => return Err(e),
+        }
+        cache
+            .find(kid)
+            .with_context(|| format!("unknown signing key {kid}"))
+    }
+
+    async fn fetch_keys(&self) -> anyhow::Result<JwkSet> {
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = self
+            .http
+            .get(&discovery_url)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("reading {discovery_url}"))?;
+
+        let keys: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("reading {}", discovery.jwks_uri))?;
+        tracing::info!(keys = keys.keys.len(), "fetched oidc signing keys");
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{KID, claims, key_set, sign};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use serde_json::json;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn token(issuer: &str, extra: Value) -> String {
+        token_for_kid(KID, issuer, extra)
+    }
+
+    fn token_for_kid(kid: &str, issuer: &str, extra: Value) -> String {
+        sign(kid, &claims(issuer, "admin", extra))
+    }
+
+    /// An issuer whose discovery endpoint answers `status`, expecting `calls` requests.
+    async fn issuer(status: u16, calls: u64) -> MockServer {
+        slow_issuer(status, calls, Duration::ZERO).await
+    }
+
+    /// [`issuer`], with discovery answering after `delay`.
+    async fn slow_issuer(status: u16, calls: u64, delay: Duration) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(path("/.well-known/openid-configuration"))
+            .respond_with(
+                ResponseTemplate::new(status)
+                    .set_body_json(json!({ "jwks_uri": format!("{}/jwks", server.uri()) }))
+                    .set_delay(delay),
+            )
+            .expect(calls)
+            .mount(&server)
+            .await;
+        Mock::given(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(key_set()))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn verifies_claims_and_rejects_bad_tokens() {
+        let verifier = Verifier::new("https://idm.example", "admin", reqwest::Client::new())
+            .with_keys(key_set());
+
+        let claims = verifier
+            .verify(&token(
+                "https://idm.example",
+                json!({ "groups": ["a", "b"] }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "6f4b1c0e");
+        assert_eq!(claims.preferred_username.as_deref(), Some("anurag"));
+        assert_eq!(claims.strings("groups"), ["a", "b"]);
+        let single = token("https://idm.example", json!({ "groups": "a" }));
+        assert_eq!(
+            verifier.verify(&single).await.unwrap().strings("groups"),
+            ["a"]
+        );
+
+        let wrong_aud = token("https://idm.example", json!({ "aud": "grafana" }));
+        assert!(verifier.verify(&wrong_aud).await.is_err());
+        let wrong_iss = token("https://other.example", json!({}));
+        assert!(verifier.verify(&wrong_iss).await.is_err());
+        let expired = token("https://idm.example", json!({ "exp": 1_000_000 }));
+        assert!(verifier.verify(&expired).await.is_err());
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(KID.into());
+        let hmac = encode(
+            &header,
+            &json!({ "iss": "https://idm.example", "aud": "admin", "sub": "x" }),
+            &EncodingKey::from_secret(b"not an asymmetric key"),
+        )
+        .unwrap();
+        assert!(verifier.verify(&hmac).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_keys_on_first_use() {
+        let server = issuer(200, 1).await;
+        let verifier = Verifier::new(server.uri(), "admin", reqwest::Client::new());
+        let token = token(&server.uri(), json!({}));
+        assert!(verifier.verify(&token).await.is_ok());
+        assert!(verifier.verify(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_keys_outlive_a_failed_refetch() {
+        let server = issuer(503, 1).await;
+        let verifier =
+            Verifier::new(server.uri(), "admin", reqwest::Client::new()).with_keys(key_set());
+        let stale = Instant::now() + JWKS_MAX_AGE + Duration::from_secs(1);
+
+        // The refetch fails, but the cached key still verifies; and the failed attempt
+        // starts the cooldown, so the next token doesn't ask the issuer again.
+        let token = token(&server.uri(), json!({}));
+        assert!(verifier.verify_at(&token, stale).await.is_ok());
+        assert!(verifier.verify_at(&token, stale).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cached_keys_verify_while_a_fetch_is_in_flight() {
+        let server = slow_issuer(200, 1, Duration::from_secs(2)).await;
+        let verifier =
+            Verifier::new(server.uri(), "admin", reqwest::Client::new()).with_keys(key_set());
+        // Cooldown over, so the unknown key sends this one to the (slow) issuer.
+        let later = Instant::now() + REFETCH_COOLDOWN;
+        let unknown = token_for_kid("k2", &server.uri(), json!({}));
+        let known = token(&server.uri(), json!({}));
+
+        let (unknown, known) = tokio::join!(verifier.verify_at(&unknown, later), async {
+            tokio::time::timeout(
+                Duration::from_millis(500),
+                verifier.verify_at(&known, later),
+            )
+            .await
+        });
+        assert!(unknown.is_err());
+        assert!(known.expect("verification waited on the fetch").is_ok());
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_spaced_out() {
+        let server = issuer(503, 1).await;
+        let verifier = Verifier::new(server.uri(), "admin", reqwest::Client::new());
+        let token = token(&server.uri(), json!({}));
+        assert!(verifier.verify(&token).await.is_err());
+        assert!(verifier.verify(&token).await.is_err());
+    }
+}
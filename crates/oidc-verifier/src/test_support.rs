@@ -0,0 +1,52 @@
+//! A fixed signing key and token builders for tests of code that verifies tokens. The
+//! key is RFC 8037 Appendix A's Ed25519 example, whose private half is published: never
+//! trust it outside tests.
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde_json::{Value, json};
+
+/// The key id [`key_set`] publishes and [`token`] signs under.
+pub const KID: &str = "k1";
+
+// The RFC 8037 key, as PKCS#8 and as a public JWK `x`.
+const PKCS8_PREFIX: &str = "302e020100300506032b657004220420";
+const SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+const PUBLIC_X: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+
+/// The public key as a JWKS, for [`crate::Verifier::with_keys`] or a mock issuer.
+pub fn key_set() -> JwkSet {
+    serde_json::from_value(json!({ "keys": [{
+        "kty": "OKP", "crv": "Ed25519", "kid": KID, "alg": "EdDSA", "x": PUBLIC_X,
+    }]}))
+    .unwrap()
+}
+
+/// Claims for subject `6f4b1c0e` (username `anurag`) from `issuer` for `audience`,
+/// expiring in five minutes, with `extra` merged over them.
+pub fn claims(issuer: &str, audience: &str, extra: Value) -> Value {
+    let mut claims = json!({
+        "iss": issuer,
+        "aud": audience,
+        "sub": "6f4b1c0e",
+        "preferred_username": "anurag",
+        "exp": jsonwebtoken::get_current_timestamp() + 300,
+    });
+    if let Value::Object(extra) = extra {
+        claims.as_object_mut().unwrap().extend(extra);
+    }
+    claims
+}
+
+/// `claims` signed with the test key, labelled with key id `kid`.
+pub fn sign(kid: &str, claims: &Value) -> String {
+    let der = hex::decode(format!("{PKCS8_PREFIX}{SEED}")).unwrap();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(kid.into());
+    encode(&header, claims, &EncodingKey::from_ed_der(&der)).unwrap()
+}
+
+/// A valid token for [`claims`], signed under [`KID`].
+pub fn token(issuer: &str, audience: &str, extra: Value) -> String {
+    sign(KID, &claims(issuer, audience, extra))
+}
@@ -0,0 +1,15 @@
+-- Who changed which virtual key through the admin API, and how. principal is
+-- "user:<sub>" for an OIDC caller (the issuer's immutable subject id, not a username) or
+-- "admin-token" for the shared token; key_name is the key's name at the time of the
+-- change, since keys can be renamed.
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id          BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    principal   TEXT NOT NULL,
+    action      TEXT NOT NULL,
+    key_id      UUID,
+    key_name    TEXT,
+    detail      JSONB NOT NULL DEFAULT '{}',
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS admin_audit_log_key_id_idx ON admin_audit_log (key_id, created_at DESC);
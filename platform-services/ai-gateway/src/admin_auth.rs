@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use oidc_verifier::{Claims, Verifier};
+use serde::Serialize;
+
+use crate::config::OidcConfig;
+
+/// Admin permission levels, ordered: `Write` implies `Read`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Usage and key listings.
+    Read,
+    /// Creating, updating, revoking and regenerating keys; syncing prices.
+    Write,
+}
+
+/// An authenticated admin caller.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// `user:<sub>` for an OIDC caller: the issuer's immutable subject id, never a
+    /// username the user could change. Recorded in the audit log.
+    pub subject: String,
+    /// `None` for a valid token that maps to no configured role.
+    pub role: Option<Role>,
+}
+
+impl Principal {
+    /// The shared `ADMIN_TOKEN`, used by automation.
+    pub fn admin_token() -> Self {
+        Self {
+            subject: "admin-token".into(),
+            role: Some(Role::Write),
+        }
+    }
+}
+
+/// Verifies OIDC-issued JWTs for the admin API, then maps the configured roles claim
+/// onto a [`Role`].
+#[derive(Clone)]
+pub struct OidcVerifier {
+    config: Arc<OidcConfig>,
+    verifier: Arc<Verifier>,
+}
+
+impl OidcVerifier {
+    pub fn new(config: OidcConfig, http: reqwest::Client) -> Self {
+        let verifier = Verifier::new(config.issuer.clone(), config.audience.clone(), http);
+        Self {
+            config: Arc::new(config),
+            verifier: Arc::new(verifier),
+        }
+    }
+
+    pub async fn verify(&self, token: &str) -> anyhow::Result<Principal> {
+        let claims = self.verifier.verify(token).await?;
+        Ok(Principal {
+            role: self.role_for(&claims),
+            subject: format!("user:{}", claims.sub),
+        })
+    }
+
+    /// The highest role any of the caller's roles claim values grants.
+    fn role_for(&self, claims: &Claims) -> Option<Role> {
+        let granted = claims.strings(&self.config.roles_claim);
+        let any_of = |roles: &[String]| granted.iter().any(|g| roles.iter().any(|r| r == g));
+
+        if any_of(&self.config.write_roles) {
+            Some(Role::Write)
+        } else if any_of(&self.config.read_roles) {
+            Some(Role::Read)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oidc_verifier::test_support;
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    const ISSUER: &str = "https://idm.example/oauth2/openid/ai-gateway";
+
+    fn verifier() -> OidcVerifier {
+        OidcVerifier {
+            config: Arc::new(OidcConfig {
+                issuer: ISSUER.into(),
+                audience: "ai-gateway".into(),
+                roles_claim: "ai_gateway_role".into(),
+                read_roles: vec!["read".into()],
+                write_roles: vec!["write".into()],
+            }),
+            verifier: Arc::new(
+                Verifier::new(ISSUER, "ai-gateway", reqwest::Client::new())
+                    .with_keys(test_support::key_set()),
+            ),
+        }
+    }
+
+    fn token(extra: Value) -> String {
+        test_support::token(ISSUER, "ai-gateway", extra)
+    }
+
+    #[tokio::test]
+    async fn maps_role_claim_to_highest_role() {
+        let verifier = verifier();
+
+        let writer = token(json!({ "ai_gateway_role": ["read", "write"] }));
+        let principal = verifier.verify(&writer).await.unwrap();
+        assert_eq!(principal.subject, "user:6f4b1c0e");
+        assert_eq!(principal.role, Some(Role::Write));
+
+        let reader = token(json!({ "ai_gateway_role": "read" }));
+        assert_eq!(
+            verifier.verify(&reader).await.unwrap().role,
+            Some(Role::Read)
+        );
+
+        let nobody = token(json!({ "ai_gateway_role": ["other"] }));
+        assert_eq!(verifier.verify(&nobody).await.unwrap().role, None);
+    }
+
+    #[tokio::test]
+    async fn subject_ignores_preferred_username() {
+        // A username the user can pick must not let them pass as the admin token or
+        // as another user in the audit log.
+        let impostor = token(json!({ "sub": "admin-token", "preferred_username": "root" }));
+        let principal = verifier().verify(&impostor).await.unwrap();
+        assert_eq!(principal.subject, "user:admin-token");
+        assert_ne!(principal.subject, Principal::admin_token().subject);
+    }
+}
@@ -0,0 +1,51 @@
+use serde_json::Value;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::admin_auth::Principal;
+use crate::error::Result;
+
+/// A key change made through the admin API.
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    CreateKey,
+    UpdateKey,
+    RevokeKey,
+    RegenerateKey,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::CreateKey => "create_key",
+            Action::UpdateKey => "update_key",
+            Action::RevokeKey => "revoke_key",
+            Action::RegenerateKey => "regenerate_key",
+        }
+    }
+}
+
+/// Appends to `admin_audit_log` on `conn`, the transaction making the change, so the
+/// change and its audit row commit together or not at all.
+#[tracing::instrument(skip_all, fields(otel.name = "audit.record", action = action.as_str()))]
+pub async fn record(
+    conn: &mut PgConnection,
+    principal: &Principal,
+    action: Action,
+    key_id: Uuid,
+    key_name: Option<&str>,
+    detail: Value,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO admin_audit_log (principal, action, key_id, key_name, detail) \
+         VALUES ($1, $2, $3, $4, $5)",
+        &principal.subject,
+        action.as_str(),
+        key_id,
+        key_name,
+        detail,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
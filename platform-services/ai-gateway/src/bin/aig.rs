@@ -1,8 +1,16 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use ai_gateway::config::{Config, Resolved};
+use ai_gateway::lint::{self, Severity};
+use ai_gateway::providers::{ModelKind, Registry};
+use anyhow::Context;
 use clap::{Parser, Subcommand};
 use serde_json::json;
 
 /// Admin CLI for ai-gateway. Talks to the `/admin/*` endpoints; point `--url` at a
-/// port-forwarded gateway and supply the admin token.
+/// port-forwarded gateway and supply the admin token or an OIDC access token. `config`
+/// subcommands run locally against a config file and need neither.
 #[derive(Parser)]
 #[command(name = "aig", about = "ai-gateway admin CLI")]
 struct Cli {
@@ -13,13 +21,25 @@ struct Cli {
     )]
     url: String,
     #[arg(long, env = "AIG_ADMIN_TOKEN")]
-    token: String,
+    token: Option<String>,
     #[command(subcommand)]
     command: Command,
 }
 
 #[derive(Subcommand)]
 enum Command {
+    #[command(flatten)]
+    Admin(AdminCommand),
+    /// Check a config file offline
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+/// Subcommands that call the gateway's admin API, and so need a token.
+#[derive(Subcommand)]
+enum AdminCommand {
     /// Manage virtual keys
     Keys {
         #[command(subcommand)]
@@ -36,6 +56,29 @@ enum Command {
     },
 }
 
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Lint the config; exits non-zero on errors (or warnings, with --deny-warnings)
+    Validate {
+        #[arg(long, default_value = "config.yaml")]
+        file: PathBuf,
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+    /// Show how a key's request for a model resolves, and which providers would serve it
+    Resolve {
+        #[arg(long, default_value = "config.yaml")]
+        file: PathBuf,
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        model: String,
+        /// Resolve for the embeddings endpoint rather than chat.
+        #[arg(long)]
+        embedding: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum PriceAction {
     /// Fetch current prices from llm-prices.com and upsert them into the gateway
@@ -95,13 +138,43 @@ enum KeyAction {
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let cli = Cli::parse();
+    match &cli.command {
+        Command::Admin(command) => admin(&cli.url, cli.token.as_deref(), command).await,
+        Command::Config { action } => config(action),
+    }
+}
+
+fn config(action: &ConfigAction) -> anyhow::Result<ExitCode> {
+    match action {
+        ConfigAction::Validate {
+            file,
+            deny_warnings,
+        } => validate(file, *deny_warnings),
+        ConfigAction::Resolve {
+            file,
+            key,
+            model,
+            embedding,
+        } => {
+            let kind = if *embedding {
+                ModelKind::Embedding
+            } else {
+                ModelKind::Chat
+            };
+            resolve(file, key, model, kind)
+        }
+    }
+}
+
+async fn admin(url: &str, token: Option<&str>, command: &AdminCommand) -> anyhow::Result<ExitCode> {
+    let token = token.context("--token or AIG_ADMIN_TOKEN is required")?;
     let http = reqwest::Client::new();
-    let base = cli.url.trim_end_matches('/');
+    let base = url.trim_end_matches('/');
 
-    let request = match &cli.command {
-        Command::Keys { action } => match action {
+    let request = match command {
+        AdminCommand::Keys { action } => match action {
             KeyAction::Create {
                 name,
                 models,
@@ -137,9 +210,9 @@ async fn main() -> anyhow::Result<()> {
             KeyAction::Revoke { id } => http.delete(format!("{base}/admin/keys/{id}")),
             KeyAction::Regenerate { id } => http.post(format!("{base}/admin/keys/{id}/regenerate")),
         },
-        Command::Usage => http.get(format!("{base}/admin/usage")),
-        Command::Models => http.get(format!("{base}/v1/models")),
-        Command::Prices { action } => match action {
+        AdminCommand::Usage => http.get(format!("{base}/admin/usage")),
+        AdminCommand::Models => http.get(format!("{base}/v1/models")),
+        AdminCommand::Prices { action } => match action {
             PriceAction::Sync { source } => {
                 let upstream: UpstreamPrices = http.get(source).send().await?.json().await?;
                 let prices: Vec<_> = upstream
@@ -161,7 +234,82 @@ async fn main() -> anyhow::Result<()> {
         },
     };
 
-    send(request.bearer_auth(&cli.token)).await
+    send(request.bearer_auth(token)).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(file: &Path, deny_warnings: bool) -> anyhow::Result<ExitCode> {
+    let config = Config::from_path(file)?;
+    let findings = lint::lint(&config);
+    for finding in &findings {
+        println!("{finding}");
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    let warnings = findings.len() - errors;
+    println!(
+        "{}: {errors} error(s), {warnings} warning(s)",
+        file.display()
+    );
+
+    Ok(if errors > 0 || (deny_warnings && warnings > 0) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Walks the same steps as the proxy: rule resolution, then provider selection in
+/// priority order. Exits non-zero when the request would be rejected.
+fn resolve(file: &Path, key: &str, model: &str, kind: ModelKind) -> anyhow::Result<ExitCode> {
+    let config = Config::from_path(file)?;
+    let registry = Registry::offline(&config);
+
+    match config.matching_rule(key, model) {
+        Some((i, rule)) => println!("rules[{i}] matches ({})", rule.matcher),
+        None => println!("no rule matches; model passes through"),
+    }
+    if config.key(key).is_some_and(|k| {
+        !k.allowed_models.is_empty() && !k.allowed_models.iter().any(|m| m == model)
+    }) {
+        println!("rejected: key {key} isn't allowed {model}");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let (resolved, pinned) = match config.resolve(key, model) {
+        Resolved::Denied => {
+            println!("denied");
+            return Ok(ExitCode::FAILURE);
+        }
+        Resolved::Route { model, provider } => (model, provider),
+    };
+    println!("resolved model: {resolved}");
+
+    let providers: Vec<String> = match &pinned {
+        Some(name) => {
+            println!("pinned to provider {name}");
+            registry
+                .get(name)
+                .map(|p| p.name().to_owned())
+                .into_iter()
+                .collect()
+        }
+        None => registry
+            .providers_for_model(&resolved, kind)
+            .iter()
+            .map(|p| p.name().to_owned())
+            .collect(),
+    };
+    if providers.is_empty() {
+        println!("rejected: no provider serves {resolved}");
+        return Ok(ExitCode::FAILURE);
+    }
+    println!("providers, in failover order: {}", providers.join(", "));
+    println!("(the runtime model-override flag and routing strategy aren't evaluated offline)");
+    Ok(ExitCode::SUCCESS)
 }
 
 async fn send(request: reqwest::RequestBuilder) -> anyhow::Result<()> {
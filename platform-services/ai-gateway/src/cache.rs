@@ -1,37 +1,122 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use moka::Expiry;
+use moka::ops::compute::{CompResult, Op};
 use redis::AsyncCommands;
+use redis::Script;
 use redis::aio::ConnectionManager;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
-/// Thin wrapper over a Dragonfly (redis-protocol) connection, shared by every replica so
-/// cached keys, budgets and throttles stay consistent across the fleet. Optional: when
-/// `REDIS_URL` is unset the gateway runs uncached and falls back to Postgres per request.
+use crate::metrics;
+
+/// In-process entries never outlive this, whatever TTL the caller asked for. A mutation on
+/// another replica only reaches this one's memory by expiry, so this bounds how long a
+/// revoked key or stale budget can linger locally.
+const LOCAL_MAX_TTL: Duration = Duration::from_secs(30);
+
+/// Memory budget for the in-process cache, weighed by key and value bytes.
+const LOCAL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Retries of a Dragonfly connection that failed at startup wait this long, doubling up
+/// to `MAX_CONNECT_BACKOFF`.
+const CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A key/value store behind [`CacheClient`]. Values are opaque bytes; errors are reported
+/// so a layered backend can route around a failing store.
+pub trait CacheBackend: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>>;
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Sets `key` to `value` only if absent, returning whether this call set it.
+    fn set_nx<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+    /// Resets `key`'s TTL only while it holds `value`, returning whether it did.
+    fn expire_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+    /// Deletes `key` only while it holds `value`, returning whether it did.
+    fn delete_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, anyhow::Result<bool>>;
+
+    /// Deletes `key`, if present.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Deletes every key matching a redis-style glob. Only a trailing `*` is guaranteed
+    /// to be supported.
+    fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// The cache handed to the key store and response cache. Always present: Dragonfly when
+/// `REDIS_URL` is set, with an in-process cache serving whenever it fails, including
+/// until a connection that failed at startup is retried successfully; the in-process
+/// cache alone otherwise. `CACHE_MODE=tiered` additionally reads through
+/// the in-process cache first (L1) before Dragonfly (L2). Every operation is best-effort:
+/// a miss or failure falls back to the source of truth.
 #[derive(Clone)]
 pub struct CacheClient {
-    conn: ConnectionManager,
+    backend: Arc<dyn CacheBackend>,
 }
 
 impl CacheClient {
-    pub async fn from_env() -> Option<Self> {
-        let url = std::env::var("REDIS_URL").ok().filter(|s| !s.is_empty())?;
-        match redis::Client::open(url) {
-            Ok(client) => match client.get_connection_manager().await {
-                Ok(conn) => Some(Self { conn }),
-                Err(e) => {
-                    tracing::error!("failed to connect to dragonfly, caching disabled: {e}");
-                    None
-                }
-            },
+    pub fn new(backend: impl CacheBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// An in-process cache only, for running without Dragonfly.
+    pub fn memory() -> Self {
+        Self::new(MemoryCache::new())
+    }
+
+    pub async fn from_env() -> Self {
+        let Some(url) = std::env::var("REDIS_URL").ok().filter(|s| !s.is_empty()) else {
+            tracing::info!("REDIS_URL unset, using in-process cache");
+            return Self::memory();
+        };
+        let remote: Arc<dyn CacheBackend> = match RedisCache::connect(url.clone()).await {
+            Ok(remote) => Arc::new(remote),
             Err(e) => {
-                tracing::error!("invalid REDIS_URL, caching disabled: {e}");
-                None
+                tracing::error!("failed to connect to dragonfly, retrying in the background: {e}");
+                Arc::new(PendingRedis::connect(url))
             }
-        }
+        };
+
+        let tiered = std::env::var("CACHE_MODE").is_ok_and(|mode| mode == "tiered");
+        tracing::info!(tiered, "dragonfly cache enabled");
+        Self::new(LayeredCache {
+            local: MemoryCache::new(),
+            remote,
+            tiered,
+        })
     }
 
     pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
-        let mut conn = self.conn.clone();
-        let raw: Option<Vec<u8>> = conn.get(key).await.ok().flatten();
+        let raw = self.backend.get(key).await.ok().flatten();
         raw.and_then(|bytes| serde_json::from_slice(&bytes).ok())
     }
 
@@ -39,61 +124,825 @@ impl CacheClient {
         let Ok(bytes) = serde_json::to_vec(value) else {
             return;
         };
-        let mut conn = self.conn.clone();
-        let _: Result<(), _> = conn.set_ex(key, bytes, ttl_secs).await;
+        let _ = self
+            .backend
+            .set(key, bytes, Duration::from_secs(ttl_secs))
+            .await;
     }
 
+    /// Integers are stored as their decimal string, as redis `INCR` and friends expect.
     pub async fn get_i64(&self, key: &str) -> Option<i64> {
-        let mut conn = self.conn.clone();
-        conn.get(key).await.ok().flatten()
+        let raw = self.backend.get(key).await.ok().flatten()?;
+        std::str::from_utf8(&raw).ok()?.parse().ok()
     }
 
     pub async fn set_i64(&self, key: &str, ttl_secs: u64, value: i64) {
-        let mut conn = self.conn.clone();
-        let _: Result<(), _> = conn.set_ex(key, value, ttl_secs).await;
+        let _ = self
+            .backend
+            .set(
+                key,
+                value.to_string().into_bytes(),
+                Duration::from_secs(ttl_secs),
+            )
+            .await;
     }
 
-    /// `SET key NX EX ttl`. Returns true only if the key was absent and is now set, giving
-    /// callers a fleet-wide "once per ttl" throttle. Treats redis errors as not-claimed.
+    /// Returns true only if the key was absent and is now set, giving callers a
+    /// fleet-wide "once per ttl" throttle (per-replica while Dragonfly is down). Treats
+    /// errors as not-claimed.
     pub async fn claim_throttle(&self, key: &str, ttl_secs: u64) -> bool {
-        let mut conn = self.conn.clone();
-        let res: Option<String> = redis::cmd("SET")
-            .arg(key)
-            .arg(1)
-            .arg("NX")
-            .arg("EX")
-            .arg(ttl_secs)
-            .query_async(&mut conn)
+        self.backend
+            .set_nx(key, b"1".to_vec(), Duration::from_secs(ttl_secs))
             .await
-            .ok()
-            .flatten();
-        res.as_deref() == Some("OK")
+            .unwrap_or(false)
     }
 
-    /// Deletes every key matching `pattern` via SCAN, used to flush cached keys after a
-    /// mutation so the change takes effect immediately rather than waiting out the TTL.
+    /// Takes `key` as a lock, like [`Self::claim_throttle`], but tagged with the holder's
+    /// `token` so only that holder can extend or release it.
+    pub async fn acquire_lock(&self, key: &str, token: &str, ttl_secs: u64) -> bool {
+        self.backend
+            .set_nx(
+                key,
+                token.as_bytes().to_vec(),
+                Duration::from_secs(ttl_secs),
+            )
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Pushes the lock's expiry out to `ttl_secs` from now, if `token` still holds it.
+    pub async fn extend_lock(&self, key: &str, token: &str, ttl_secs: u64) -> bool {
+        self.backend
+            .expire_if(key, token.as_bytes(), Duration::from_secs(ttl_secs))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Releases the lock if `token` still holds it. A holder whose lock lapsed and was
+    /// taken by another leaves the new holder's lock alone.
+    pub async fn release_lock(&self, key: &str, token: &str) {
+        let _ = self.backend.delete_if(key, token.as_bytes()).await;
+    }
+
+    /// Deletes a single key. Prefer this over [`Self::invalidate`] for exact keys: on
+    /// Dragonfly a pattern costs a scan of the whole keyspace.
+    pub async fn delete(&self, key: &str) {
+        let _ = self.backend.delete(key).await;
+    }
+
+    /// Deletes every key matching `pattern`, used to flush cached keys after a mutation so
+    /// the change takes effect immediately rather than waiting out the TTL.
     pub async fn invalidate(&self, pattern: &str) {
+        let _ = self.backend.invalidate(pattern).await;
+    }
+}
+
+/// Compare-and-set scripts for [`CacheBackend::expire_if`] and
+/// [`CacheBackend::delete_if`], so the check and the write are one atomic step.
+const EXPIRE_IF_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("EXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+const DELETE_IF_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// A Dragonfly (redis-protocol) connection, shared by every replica so cached keys,
+/// budgets and throttles stay consistent across the fleet.
+pub struct RedisCache {
+    conn: ConnectionManager,
+    expire_if: Script,
+    delete_if: Script,
+}
+
+impl RedisCache {
+    pub async fn connect(url: String) -> anyhow::Result<Self> {
+        let conn = redis::Client::open(url)?.get_connection_manager().await?;
+        Ok(Self {
+            conn,
+            expire_if: Script::new(EXPIRE_IF_SCRIPT),
+            delete_if: Script::new(DELETE_IF_SCRIPT),
+        })
+    }
+}
+
+impl CacheBackend for RedisCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        let mut conn = self.conn.clone();
+        async move { Ok(conn.get(key).await?) }.boxed()
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
         let mut conn = self.conn.clone();
-        let mut cursor: u64 = 0;
-        loop {
-            let res: Result<(u64, Vec<String>), _> = redis::cmd("SCAN")
-                .arg(cursor)
-                .arg("MATCH")
-                .arg(pattern)
-                .arg("COUNT")
-                .arg(100)
+        async move { Ok(conn.set_ex(key, value, ttl.as_secs()).await?) }.boxed()
+    }
+
+    fn set_nx<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        let mut conn = self.conn.clone();
+        async move {
+            let res: Option<String> = redis::cmd("SET")
+                .arg(key)
+                .arg(value)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl.as_secs())
                 .query_async(&mut conn)
+                .await?;
+            Ok(res.as_deref() == Some("OK"))
+        }
+        .boxed()
+    }
+
+    fn expire_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        let mut conn = self.conn.clone();
+        async move {
+            let extended: i64 = self
+                .expire_if
+                .key(key)
+                .arg(value)
+                .arg(ttl.as_secs())
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(extended == 1)
+        }
+        .boxed()
+    }
+
+    fn delete_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        let mut conn = self.conn.clone();
+        async move {
+            let deleted: i64 = self
+                .delete_if
+                .key(key)
+                .arg(value)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(deleted == 1)
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        let mut conn = self.conn.clone();
+        async move { Ok(conn.del(key).await?) }.boxed()
+    }
+
+    fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        let mut conn = self.conn.clone();
+        async move {
+            let mut cursor: u64 = 0;
+            loop {
+                let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(pattern)
+                    .arg("COUNT")
+                    .arg(100)
+                    .query_async(&mut conn)
+                    .await?;
+                if !keys.is_empty() {
+                    let _: () = conn.del(keys).await?;
+                }
+                if next == 0 {
+                    return Ok(());
+                }
+                cursor = next;
+            }
+        }
+        .boxed()
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    value: Bytes,
+    ttl: Duration,
+}
+
+struct PerEntryTtl;
+
+impl Expiry<String, Entry> for PerEntryTtl {
+    fn expire_after_create(&self, _key: &String, entry: &Entry, _now: Instant) -> Option<Duration> {
+        Some(entry.ttl)
+    }
+
+    /// A write restarts the TTL, as `SET EX` and `EXPIRE` do on Dragonfly.
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        entry: &Entry,
+        _now: Instant,
+        _remaining: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(entry.ttl)
+    }
+}
+
+/// Dragonfly that was unreachable at startup. Operations fail, so [`LayeredCache`] serves
+/// them from its local cache, until a background task manages to connect; from then on
+/// they go to the connection, which reconnects by itself.
+struct PendingRedis {
+    conn: Arc<OnceLock<RedisCache>>,
+}
+
+impl PendingRedis {
+    /// Starts the retries. Must be called from within a Tokio runtime.
+    fn connect(url: String) -> Self {
+        let conn = Arc::new(OnceLock::new());
+        let cell = conn.clone();
+        tokio::spawn(async move {
+            let mut backoff = CONNECT_BACKOFF;
+            loop {
+                tokio::time::sleep(backoff).await;
+                match RedisCache::connect(url.clone()).await {
+                    Ok(remote) => {
+                        let _ = cell.set(remote);
+                        tracing::info!("connected to dragonfly");
+                        return;
+                    }
+                    Err(e) => {
+                        backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                        tracing::warn!(retry_in = ?backoff, "failed to connect to dragonfly: {e}");
+                    }
+                }
+            }
+        });
+        Self { conn }
+    }
+
+    fn remote<'a, T: Send + 'a>(
+        &'a self,
+        op: impl FnOnce(&'a RedisCache) -> BoxFuture<'a, anyhow::Result<T>>,
+    ) -> BoxFuture<'a, anyhow::Result<T>> {
+        match self.conn.get() {
+            Some(remote) => op(remote),
+            None => async { Err(anyhow::anyhow!("not connected to dragonfly")) }.boxed(),
+        }
+    }
+}
+
+impl CacheBackend for PendingRedis {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        self.remote(|remote| remote.get(key))
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.remote(|remote| remote.set(key, value, ttl))
+    }
+
+    fn set_nx<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        self.remote(|remote| remote.set_nx(key, value, ttl))
+    }
+
+    fn expire_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        self.remote(|remote| remote.expire_if(key, value, ttl))
+    }
+
+    fn delete_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        self.remote(|remote| remote.delete_if(key, value))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.remote(|remote| remote.delete(key))
+    }
+
+    fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.remote(|remote| remote.invalidate(pattern))
+    }
+}
+
+/// A per-replica cache, with TTLs capped at [`LOCAL_MAX_TTL`]. Never fails.
+#[derive(Clone)]
+pub struct MemoryCache {
+    entries: moka::future::Cache<String, Entry>,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self {
+            entries: moka::future::Cache::builder()
+                .max_capacity(LOCAL_MAX_BYTES)
+                .weigher(|key: &String, entry: &Entry| {
+                    (key.len() + entry.value.len())
+                        .try_into()
+                        .unwrap_or(u32::MAX)
+                })
+                .expire_after(PerEntryTtl)
+                .build(),
+        }
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheBackend for MemoryCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        async move { Ok(self.entries.get(key).await.map(|e| e.value.to_vec())) }.boxed()
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let entry = Entry {
+                value: value.into(),
+                ttl: ttl.min(LOCAL_MAX_TTL),
+            };
+            self.entries.insert(key.to_owned(), entry).await;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    // The conditional writes go through `and_compute_with`, which serializes calls on a
+    // key, so a check and its write can't interleave with another's.
+    fn set_nx<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let entry = Entry {
+                value: value.into(),
+                ttl: ttl.min(LOCAL_MAX_TTL),
+            };
+            let result = self
+                .entries
+                .entry(key.to_owned())
+                .and_compute_with(|existing| async move {
+                    match existing {
+                        Some(_) => Op::Nop,
+                        None => Op::Put(entry),
+                    }
+                })
+                .await;
+            Ok(matches!(result, CompResult::Inserted(_)))
+        }
+        .boxed()
+    }
+
+    fn expire_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let result = self
+                .entries
+                .entry(key.to_owned())
+                .and_compute_with(|existing| async move {
+                    match existing {
+                        Some(existing) if existing.value().value == value => Op::Put(Entry {
+                            value: existing.into_value().value,
+                            ttl: ttl.min(LOCAL_MAX_TTL),
+                        }),
+                        _ => Op::Nop,
+                    }
+                })
+                .await;
+            Ok(matches!(result, CompResult::ReplacedWith(_)))
+        }
+        .boxed()
+    }
+
+    fn delete_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let result = self
+                .entries
+                .entry(key.to_owned())
+                .and_compute_with(|existing| async move {
+                    match existing {
+                        Some(existing) if existing.value().value == value => Op::Remove,
+                        _ => Op::Nop,
+                    }
+                })
                 .await;
-            let Ok((next, keys)) = res else {
-                return;
+            Ok(matches!(result, CompResult::Removed(_)))
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            self.entries.invalidate(key).await;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let matches = |key: &str| match pattern.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == pattern,
             };
-            if !keys.is_empty() {
-                let _: Result<(), _> = conn.del(keys).await;
+            let doomed: Vec<_> = self
+                .entries
+                .iter()
+                .filter(|(key, _)| matches(key))
+                .map(|(key, _)| key)
+                .collect();
+            for key in doomed {
+                self.entries.invalidate(key.as_str()).await;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// A shared `remote` store backed by a per-replica `local` one. The local cache serves
+/// whenever the remote errors, so an outage costs latency rather than a database
+/// stampede. When `tiered`, reads also try the local cache first and fill it from remote
+/// hits, saving a round trip for hot keys at the cost of up to [`LOCAL_MAX_TTL`] of
+/// staleness after another replica's mutation.
+pub struct LayeredCache {
+    pub local: MemoryCache,
+    pub remote: Arc<dyn CacheBackend>,
+    pub tiered: bool,
+}
+
+impl LayeredCache {
+    fn remote_failed(&self, op: &str, e: anyhow::Error) {
+        metrics::record_cache_error(op);
+        tracing::debug!(op, "dragonfly unavailable, using in-process cache: {e}");
+    }
+}
+
+impl CacheBackend for LayeredCache {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+        async move {
+            if self.tiered
+                && let Some(value) = self.local.get(key).await?
+            {
+                return Ok(Some(value));
+            }
+            match self.remote.get(key).await {
+                Ok(Some(value)) => {
+                    if self.tiered {
+                        self.local.set(key, value.clone(), LOCAL_MAX_TTL).await?;
+                    }
+                    Ok(Some(value))
+                }
+                Ok(None) => Ok(None),
+                Err(e) => {
+                    self.remote_failed("get", e);
+                    // A tiered read already missed locally.
+                    if self.tiered {
+                        Ok(None)
+                    } else {
+                        self.local.get(key).await
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn set<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            if self.tiered {
+                self.local.set(key, value.clone(), ttl).await?;
+            }
+            if let Err(e) = self.remote.set(key, value.clone(), ttl).await {
+                self.remote_failed("set", e);
+                if !self.tiered {
+                    self.local.set(key, value, ttl).await?;
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn set_nx<'a>(
+        &'a self,
+        key: &'a str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            match self.remote.set_nx(key, value.clone(), ttl).await {
+                Ok(claimed) => Ok(claimed),
+                Err(e) => {
+                    self.remote_failed("set_nx", e);
+                    self.local.set_nx(key, value, ttl).await
+                }
             }
-            if next == 0 {
-                break;
+        }
+        .boxed()
+    }
+
+    fn expire_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+        ttl: Duration,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            match self.remote.expire_if(key, value, ttl).await {
+                Ok(extended) => Ok(extended),
+                Err(e) => {
+                    self.remote_failed("expire_if", e);
+                    self.local.expire_if(key, value, ttl).await
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn delete_if<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a [u8],
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            // As with `delete`, the local entry may date from an outage.
+            let local = self.local.delete_if(key, value).await?;
+            match self.remote.delete_if(key, value).await {
+                Ok(deleted) => Ok(deleted || local),
+                Err(e) => {
+                    self.remote_failed("delete_if", e);
+                    Ok(local)
+                }
             }
-            cursor = next;
         }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            // As with `invalidate`, the local entry may date from an outage.
+            self.local.delete(key).await?;
+            if let Err(e) = self.remote.delete(key).await {
+                self.remote_failed("delete", e);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            // Local entries may have been written during an outage, so they're always
+            // cleared, whichever mode.
+            self.local.invalidate(pattern).await?;
+            if let Err(e) = self.remote.invalidate(pattern).await {
+                self.remote_failed("invalidate", e);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    /// A remote that can be switched off, standing in for Dragonfly.
+    #[derive(Default)]
+    struct FlakyRemote {
+        store: MemoryCache,
+        down: AtomicBool,
+    }
+
+    impl FlakyRemote {
+        fn check(&self) -> anyhow::Result<()> {
+            anyhow::ensure!(!self.down.load(Ordering::SeqCst), "connection refused");
+            Ok(())
+        }
+    }
+
+    impl CacheBackend for FlakyRemote {
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Option<Vec<u8>>>> {
+            async move {
+                self.check()?;
+                self.store.get(key).await
+            }
+            .boxed()
+        }
+
+        fn set<'a>(
+            &'a self,
+            key: &'a str,
+            value: Vec<u8>,
+            ttl: Duration,
+        ) -> BoxFuture<'a, anyhow::Result<()>> {
+            async move {
+                self.check()?;
+                self.store.set(key, value, ttl).await
+            }
+            .boxed()
+        }
+
+        fn set_nx<'a>(
+            &'a self,
+            key: &'a str,
+            value: Vec<u8>,
+            ttl: Duration,
+        ) -> BoxFuture<'a, anyhow::Result<bool>> {
+            async move {
+                self.check()?;
+                self.store.set_nx(key, value, ttl).await
+            }
+            .boxed()
+        }
+
+        fn expire_if<'a>(
+            &'a self,
+            key: &'a str,
+            value: &'a [u8],
+            ttl: Duration,
+        ) -> BoxFuture<'a, anyhow::Result<bool>> {
+            async move {
+                self.check()?;
+                self.store.expire_if(key, value, ttl).await
+            }
+            .boxed()
+        }
+
+        fn delete_if<'a>(
+            &'a self,
+            key: &'a str,
+            value: &'a [u8],
+        ) -> BoxFuture<'a, anyhow::Result<bool>> {
+            async move {
+                self.check()?;
+                self.store.delete_if(key, value).await
+            }
+            .boxed()
+        }
+
+        fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+            async move {
+                self.check()?;
+                self.store.delete(key).await
+            }
+            .boxed()
+        }
+
+        fn invalidate<'a>(&'a self, pattern: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+            async move {
+                self.check()?;
+                self.store.invalidate(pattern).await
+            }
+            .boxed()
+        }
+    }
+
+    fn layered(tiered: bool) -> (CacheClient, Arc<FlakyRemote>) {
+        let remote = Arc::new(FlakyRemote::default());
+        let client = CacheClient::new(LayeredCache {
+            local: MemoryCache::new(),
+            remote: remote.clone(),
+            tiered,
+        });
+        (client, remote)
+    }
+
+    #[tokio::test]
+    async fn memory_cache_round_trips_and_invalidates_by_prefix() {
+        let cache = CacheClient::memory();
+        cache.set_json("aig:key:a", 3600, &"alpha").await;
+        cache.set_json("aig:key:b", 3600, &"beta").await;
+        cache.set_i64("aig:budget:a", 60, 42).await;
+        assert_eq!(cache.get_i64("aig:budget:a").await, Some(42));
+
+        cache.invalidate("aig:key:*").await;
+        assert_eq!(cache.get_json::<String>("aig:key:a").await, None);
+        assert_eq!(cache.get_json::<String>("aig:key:b").await, None);
+        assert_eq!(cache.get_i64("aig:budget:a").await, Some(42));
+
+        assert!(cache.claim_throttle("t", 60).await);
+        assert!(!cache.claim_throttle("t", 60).await);
+        cache.delete("t").await;
+        assert!(cache.claim_throttle("t", 60).await);
+    }
+
+    #[tokio::test]
+    async fn locks_are_only_extended_or_released_by_their_holder() {
+        let cache = CacheClient::memory();
+        assert!(cache.acquire_lock("l", "first", 30).await);
+        assert!(!cache.acquire_lock("l", "second", 30).await);
+
+        cache.release_lock("l", "second").await;
+        assert!(!cache.extend_lock("l", "second", 30).await);
+        assert!(!cache.acquire_lock("l", "second", 30).await);
+
+        assert!(cache.extend_lock("l", "first", 30).await);
+        cache.release_lock("l", "first").await;
+        assert!(cache.acquire_lock("l", "second", 30).await);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_local_while_remote_is_down() {
+        let (cache, remote) = layered(false);
+        cache.set_i64("up", 60, 1).await;
+
+        remote.down.store(true, Ordering::SeqCst);
+        assert_eq!(cache.get_i64("up").await, None);
+        cache.set_i64("down", 60, 2).await;
+        assert_eq!(cache.get_i64("down").await, Some(2));
+        assert!(cache.claim_throttle("t", 60).await);
+
+        // Once it recovers, the remote is authoritative again.
+        remote.down.store(false, Ordering::SeqCst);
+        assert_eq!(cache.get_i64("up").await, Some(1));
+        assert_eq!(cache.get_i64("down").await, None);
+    }
+
+    #[tokio::test]
+    async fn unreachable_dragonfly_is_stood_in_for_while_it_retries() {
+        let cache = CacheClient::new(LayeredCache {
+            local: MemoryCache::new(),
+            remote: Arc::new(PendingRedis::connect("redis://127.0.0.1:1".into())),
+            tiered: false,
+        });
+        cache.set_i64("k", 60, 3).await;
+        assert_eq!(cache.get_i64("k").await, Some(3));
+        assert!(cache.claim_throttle("t", 60).await);
+        assert!(!cache.claim_throttle("t", 60).await);
+    }
+
+    #[tokio::test]
+    async fn tiered_reads_through_local_first() {
+        let (cache, remote) = layered(true);
+        remote
+            .store
+            .set("k", b"7".to_vec(), LOCAL_MAX_TTL)
+            .await
+            .unwrap();
+
+        // The remote hit fills the local tier, which then serves during an outage.
+        assert_eq!(cache.get_i64("k").await, Some(7));
+        remote.down.store(true, Ordering::SeqCst);
+        assert_eq!(cache.get_i64("k").await, Some(7));
+
+        cache.invalidate("k").await;
+        assert_eq!(cache.get_i64("k").await, None);
     }
 }
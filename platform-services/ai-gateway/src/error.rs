@@ -19,6 +19,18 @@ pub enum GatewayError {
     BudgetExceeded(String),
     #[error("no provider configured for model {0}")]
     NoProvider(String),
+    #[error("request needs an estimated {tokens} tokens, over model {model}'s limit of {limit}")]
+    ContextLengthExceeded {
+        model: String,
+        tokens: u64,
+        limit: u64,
+    },
+    #[error("response failed schema validation after {attempts} attempts: {errors}")]
+    SchemaValidation { attempts: u32, errors: String },
+    #[error("idempotency key was already used for a different request")]
+    IdempotencyKeyReused,
+    #[error("a request with this idempotency key is still in flight")]
+    IdempotencyInFlight,
     #[error("gateway disabled by feature flag")]
     Disabled,
     #[error("bad request: {0}")]
@@ -49,12 +61,27 @@ impl GatewayError {
                 StatusCode::FORBIDDEN
             }
             GatewayError::BudgetExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
-            GatewayError::NoProvider(_) | GatewayError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            GatewayError::NoProvider(_)
+            | GatewayError::BadRequest(_)
+            | GatewayError::ContextLengthExceeded { .. } => StatusCode::BAD_REQUEST,
+            GatewayError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            GatewayError::IdempotencyInFlight => StatusCode::CONFLICT,
             GatewayError::Disabled => StatusCode::SERVICE_UNAVAILABLE,
-            GatewayError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            GatewayError::Upstream(_) | GatewayError::SchemaValidation { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
             GatewayError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    /// The body's `error.type`. Schema failures get their own so clients can tell a
+    /// model that wouldn't comply from a provider outage, both being 502s.
+    fn kind(&self) -> &'static str {
+        match self {
+            GatewayError::SchemaValidation { .. } => "schema_validation_error",
+            _ => "ai_gateway_error",
+        }
+    }
 }
 
 impl IntoResponse for GatewayError {
@@ -68,7 +95,7 @@ impl IntoResponse for GatewayError {
 
         let body = Json(json!({
             "error": {
-                "type": "ai_gateway_error",
+                "type": self.kind(),
                 "message": self.to_string(),
             }
         }));
@@ -0,0 +1,286 @@
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+use crate::cache::CacheClient;
+use crate::error::{GatewayError, Result};
+use crate::response_cache::CachedResponse;
+
+/// Client-chosen request id; a retry carrying the same one replays the first answer.
+pub const HEADER: &str = "idempotency-key";
+
+const NAMESPACE: &str = "aig:idem:";
+const DEFAULT_TTL_SECS: u64 = 24 * 3600;
+const MAX_KEY_LEN: usize = 255;
+
+/// How long a claim holds off duplicates unless refreshed. The holder refreshes it every
+/// [`LOCK_REFRESH`] for as long as its request runs, failover and schema retries
+/// included, so the TTL only matters once a replica crashes. Within the in-process
+/// cache's TTL cap, so the lock behaves the same without Dragonfly.
+const LOCK_TTL: Duration = Duration::from_secs(30);
+const LOCK_REFRESH: Duration = Duration::from_secs(10);
+/// How long a duplicate waits for the request in flight before giving up. Matches the
+/// upstream request timeout.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the first request stored, with a hash of its body so a key reused for a different
+/// request is refused rather than answered with the wrong completion.
+#[derive(Serialize, Deserialize)]
+struct Stored<R> {
+    fingerprint: String,
+    response: R,
+}
+
+pub enum Outcome {
+    /// An earlier request with this key completed; send its response again.
+    Replay(CachedResponse),
+    /// This request is the first: run it, then [`Claim::complete`].
+    Claimed(Claim),
+}
+
+/// The right to run a request for an idempotency key. Dropping it without completing
+/// (an error, a non-2xx upstream answer) releases the key so a retry runs afresh.
+pub struct Claim {
+    cache: CacheClient,
+    result_key: String,
+    lock_key: String,
+    /// Identifies this claim's lock, so releasing it never drops another holder's.
+    token: String,
+    fingerprint: String,
+    /// Keeps the lock alive while the request runs.
+    refresher: Option<AbortHandle>,
+    completed: bool,
+}
+
+fn ttl_secs() -> u64 {
+    std::env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Looks up or claims `header` for this virtual key. Keys are scoped per virtual key, so
+/// two consumers picking the same id never see each other's completions. A duplicate
+/// arriving while the first is in flight waits for it rather than running in parallel.
+///
+/// Entries live in [`CacheClient`]: without Dragonfly they're per replica and expire
+/// after the in-process cap, so the replay window shrinks to seconds.
+pub async fn begin(
+    cache: &CacheClient,
+    key_id: Uuid,
+    header: &HeaderValue,
+    body: &[u8],
+) -> Result<Outcome> {
+    let id = header
+        .to_str()
+        .ok()
+        .filter(|id| !id.is_empty() && id.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| {
+            GatewayError::BadRequest(format!(
+                "{HEADER} must be 1 to {MAX_KEY_LEN} visible characters"
+            ))
+        })?;
+
+    let base = format!("{NAMESPACE}{key_id}:{}", sha256_hex(id.as_bytes()));
+    let claim = Claim {
+        cache: cache.clone(),
+        result_key: format!("{base}:result"),
+        lock_key: format!("{base}:lock"),
+        token: Uuid::new_v4().to_string(),
+        fingerprint: sha256_hex(body),
+        refresher: None,
+        completed: false,
+    };
+
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    loop {
+        if let Some(replay) = claim.stored().await? {
+            return Ok(Outcome::Replay(replay));
+        }
+        if cache
+            .acquire_lock(&claim.lock_key, &claim.token, LOCK_TTL.as_secs())
+            .await
+        {
+            // The previous holder may have stored its result and released between the
+            // lookup and the claim.
+            return match claim.stored().await? {
+                Some(replay) => Ok(Outcome::Replay(replay)),
+                None => Ok(Outcome::Claimed(claim.hold())),
+            };
+        }
+        if Instant::now() >= deadline {
+            return Err(GatewayError::IdempotencyInFlight);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+impl Claim {
+    /// Starts refreshing the lock, until the claim completes or is dropped.
+    fn hold(mut self) -> Self {
+        let cache = self.cache.clone();
+        let lock_key = self.lock_key.clone();
+        let token = self.token.clone();
+        let refresher = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LOCK_REFRESH).await;
+                if !cache
+                    .extend_lock(&lock_key, &token, LOCK_TTL.as_secs())
+                    .await
+                {
+                    tracing::warn!("idempotency lock lost while its request was in flight");
+                }
+            }
+        });
+        self.refresher = Some(refresher.abort_handle());
+        self
+    }
+
+    async fn stored(&self) -> Result<Option<CachedResponse>> {
+        match self
+            .cache
+            .get_json::<Stored<CachedResponse>>(&self.result_key)
+            .await
+        {
+            Some(stored) if stored.fingerprint != self.fingerprint => {
+                Err(GatewayError::IdempotencyKeyReused)
+            }
+            stored => Ok(stored.map(|s| s.response)),
+        }
+    }
+
+    /// Stores the response for replay and releases waiting duplicates.
+    pub async fn complete(mut self, response: &CachedResponse) {
+        let stored = Stored {
+            fingerprint: self.fingerprint.clone(),
+            response,
+        };
+        self.cache
+            .set_json(&self.result_key, ttl_secs(), &stored)
+            .await;
+        self.cache.release_lock(&self.lock_key, &self.token).await;
+        self.completed = true;
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if let Some(refresher) = self.refresher.take() {
+            refresher.abort();
+        }
+        if self.completed {
+            return;
+        }
+        let cache = self.cache.clone();
+        let lock_key = std::mem::take(&mut self.lock_key);
+        let token = std::mem::take(&mut self.token);
+        tokio::spawn(async move { cache.release_lock(&lock_key, &token).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            content_type: "application/json".into(),
+            body: body.as_bytes().to_vec(),
+            input_tokens: 10,
+            output_tokens: 5,
+        }
+    }
+
+    async fn claimed(cache: &CacheClient, key_id: Uuid, id: &str, body: &[u8]) -> Claim {
+        match begin(cache, key_id, &HeaderValue::from_str(id).unwrap(), body).await {
+            Ok(Outcome::Claimed(claim)) => claim,
+            _ => panic!("expected a fresh claim for {id}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_completed_response_for_same_key_and_body() {
+        let cache = CacheClient::memory();
+        let key_id = Uuid::new_v4();
+        let header = HeaderValue::from_static("retry-1");
+
+        claimed(&cache, key_id, "retry-1", b"{}")
+            .await
+            .complete(&response("first"))
+            .await;
+
+        match begin(&cache, key_id, &header, b"{}").await.unwrap() {
+            Outcome::Replay(replay) => assert_eq!(replay.body, b"first"),
+            Outcome::Claimed(_) => panic!("duplicate ran again"),
+        }
+        assert!(matches!(
+            begin(&cache, key_id, &header, br#"{"other":1}"#).await,
+            Err(GatewayError::IdempotencyKeyReused)
+        ));
+        // Another virtual key with the same id is unrelated.
+        claimed(&cache, Uuid::new_v4(), "retry-1", b"{}").await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_waits_for_in_flight_request() {
+        let cache = CacheClient::memory();
+        let key_id = Uuid::new_v4();
+        let first = claimed(&cache, key_id, "slow", b"{}").await;
+
+        let waiter = tokio::spawn({
+            let cache = cache.clone();
+            async move { begin(&cache, key_id, &HeaderValue::from_static("slow"), b"{}").await }
+        });
+        tokio::time::sleep(POLL_INTERVAL * 3).await;
+        assert!(!waiter.is_finished());
+
+        first.complete(&response("done")).await;
+        match waiter.await.unwrap().unwrap() {
+            Outcome::Replay(replay) => assert_eq!(replay.body, b"done"),
+            Outcome::Claimed(_) => panic!("duplicate ran in parallel"),
+        }
+    }
+
+    #[tokio::test]
+    async fn lapsed_claim_leaves_its_successors_lock_alone() {
+        let cache = CacheClient::memory();
+        let key_id = Uuid::new_v4();
+        let first = claimed(&cache, key_id, "slow", b"{}").await;
+        // As if the first claim's lock had expired under it.
+        cache.delete(&first.lock_key).await;
+        let second = claimed(&cache, key_id, "slow", b"{}").await;
+
+        first.complete(&response("late")).await;
+        assert!(
+            !cache
+                .acquire_lock(&second.lock_key, "third", LOCK_TTL.as_secs())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn abandoned_claim_lets_a_retry_run() {
+        let cache = CacheClient::memory();
+        let key_id = Uuid::new_v4();
+        drop(claimed(&cache, key_id, "failed", b"{}").await);
+        // The release is spawned from `Drop`; let it run.
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        claimed(&cache, key_id, "failed", b"{}").await;
+        assert!(
+            begin(&cache, key_id, &HeaderValue::from_static(""), b"{}")
+                .await
+                .is_err()
+        );
+    }
+}
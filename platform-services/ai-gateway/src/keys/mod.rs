@@ -3,10 +3,13 @@ mod types;
 pub use types::{KeyInfo, UpdateKey, VirtualKey};
 
 use rand::RngExt;
+use serde_json::json;
 use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::admin_auth::Principal;
+use crate::audit::{self, Action};
 use crate::cache::CacheClient;
 use crate::error::{GatewayError, Result};
 use types::KeyRow;
@@ -33,22 +36,20 @@ fn budget_key(id: Uuid) -> String {
 #[derive(Clone)]
 pub struct KeyStore {
     pool: PgPool,
-    /// Shared across replicas so cached keys, throttles and budgets stay consistent. When
-    /// absent (no `REDIS_URL`) every lookup falls back to Postgres.
-    cache: Option<CacheClient>,
+    /// Shared across replicas (via Dragonfly) so cached keys, throttles and budgets stay
+    /// consistent; see [`CacheClient`] for how it degrades.
+    cache: CacheClient,
 }
 
 impl KeyStore {
-    pub fn new(pool: PgPool, cache: Option<CacheClient>) -> Self {
+    pub fn new(pool: PgPool, cache: CacheClient) -> Self {
         Self { pool, cache }
     }
 
     /// Flushes every cached virtual key across the fleet so a mutation takes effect
     /// immediately rather than lingering for the TTL.
     async fn invalidate_keys(&self) {
-        if let Some(cache) = &self.cache {
-            cache.invalidate(&format!("{KEY_NAMESPACE}*")).await;
-        }
+        self.cache.invalidate(&format!("{KEY_NAMESPACE}*")).await;
     }
 
     fn hash(raw: &str) -> String {
@@ -62,8 +63,10 @@ impl KeyStore {
     pub async fn authenticate(&self, raw: &str) -> Result<VirtualKey> {
         let hash = Self::hash(raw);
 
-        if let Some(cache) = &self.cache
-            && let Some(key) = cache.get_json::<VirtualKey>(&key_cache_key(&hash)).await
+        if let Some(key) = self
+            .cache
+            .get_json::<VirtualKey>(&key_cache_key(&hash))
+            .await
         {
             return Ok(key);
         }
@@ -91,18 +94,14 @@ impl KeyStore {
         })
         .ok_or(GatewayError::InvalidKey)?;
 
-        if let Some(cache) = &self.cache {
-            cache
-                .set_json(&key_cache_key(&hash), KEY_CACHE_TTL, &key)
-                .await;
-        }
+        self.cache
+            .set_json(&key_cache_key(&hash), KEY_CACHE_TTL, &key)
+            .await;
         Ok(key)
     }
 
     pub async fn month_to_date_tokens(&self, id: Uuid) -> Result<i64> {
-        if let Some(cache) = &self.cache
-            && let Some(total) = cache.get_i64(&budget_key(id)).await
-        {
+        if let Some(total) = self.cache.get_i64(&budget_key(id)).await {
             return Ok(total);
         }
 
@@ -116,17 +115,17 @@ impl KeyStore {
         .await?
         .unwrap_or(0);
 
-        if let Some(cache) = &self.cache {
-            cache
-                .set_i64(&budget_key(id), BUDGET_CACHE_TTL, total)
-                .await;
-        }
+        self.cache
+            .set_i64(&budget_key(id), BUDGET_CACHE_TTL, total)
+            .await;
         Ok(total)
     }
 
-    /// Creates a key and returns the one-time plaintext token alongside its row.
+    /// Creates a key and returns the one-time plaintext token alongside its row. Like
+    /// every admin mutation, it's audited as `actor` in the same transaction.
     pub async fn create(
         &self,
+        actor: &Principal,
         name: &str,
         allowed_models: &[String],
         monthly_token_budget: Option<i64>,
@@ -134,7 +133,8 @@ impl KeyStore {
         let raw = generate_token();
         let hash = Self::hash(&raw);
 
-        let info = sqlx::query_as!(
+        let mut tx = self.pool.begin().await?;
+        let info: KeyInfo = sqlx::query_as!(
             KeyRow,
             "INSERT INTO virtual_keys (name, key_hash, allowed_models, monthly_token_budget) \
              VALUES ($1, $2, $3, $4) \
@@ -144,9 +144,22 @@ impl KeyStore {
             allowed_models,
             monthly_token_budget
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?
         .into();
+        audit::record(
+            &mut tx,
+            actor,
+            Action::CreateKey,
+            info.id,
+            Some(&info.name),
+            json!({
+                "allowed_models": info.allowed_models,
+                "monthly_token_budget": info.monthly_token_budget,
+            }),
+        )
+        .await?;
+        tx.commit().await?;
 
         Ok((raw, info))
     }
@@ -204,10 +217,15 @@ impl KeyStore {
     /// Mints a fresh token for an existing key, replacing its hash, and returns the one-time
     /// plaintext alongside the row. The old token stops authenticating immediately once the
     /// cache is flushed. Returns `None` if no key has that id.
-    pub async fn regenerate(&self, id: Uuid) -> Result<Option<(String, KeyInfo)>> {
+    pub async fn regenerate(
+        &self,
+        actor: &Principal,
+        id: Uuid,
+    ) -> Result<Option<(String, KeyInfo)>> {
         let raw = generate_token();
         let hash = Self::hash(&raw);
 
+        let mut tx = self.pool.begin().await?;
         let info = sqlx::query_as!(
             KeyRow,
             "UPDATE virtual_keys SET key_hash = $2 WHERE id = $1 \
@@ -215,12 +233,22 @@ impl KeyStore {
             id,
             hash,
         )
-        .fetch_optional(&self.pool)
+        .fetch_optional(&mut *tx)
         .await?
         .map(KeyInfo::from);
 
         match info {
             Some(info) => {
+                audit::record(
+                    &mut tx,
+                    actor,
+                    Action::RegenerateKey,
+                    id,
+                    Some(&info.name),
+                    json!({}),
+                )
+                .await?;
+                tx.commit().await?;
                 self.invalidate_keys().await;
                 Ok(Some((raw, info)))
             }
@@ -242,12 +270,15 @@ impl KeyStore {
 
     /// Revokes (soft-deletes) a key, flushing the cache so it stops authenticating
     /// immediately rather than lingering for the cache TTL.
-    pub async fn revoke(&self, id: Uuid) -> Result<bool> {
+    pub async fn revoke(&self, actor: &Principal, id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
         let affected = sqlx::query!("UPDATE virtual_keys SET revoked = TRUE WHERE id = $1", id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?
             .rows_affected();
         if affected > 0 {
+            audit::record(&mut tx, actor, Action::RevokeKey, id, None, json!({})).await?;
+            tx.commit().await?;
             self.invalidate_keys().await;
         }
         Ok(affected > 0)
@@ -256,9 +287,15 @@ impl KeyStore {
     /// Applies a partial update; only the fields present in `fields` change. Returns the
     /// updated row, or `None` if no key has that id. Flushes the cache so changes take
     /// effect immediately rather than waiting out the TTL.
-    pub async fn update(&self, id: Uuid, fields: &UpdateKey) -> Result<Option<KeyInfo>> {
+    pub async fn update(
+        &self,
+        actor: &Principal,
+        id: Uuid,
+        fields: &UpdateKey,
+    ) -> Result<Option<KeyInfo>> {
+        let mut tx = self.pool.begin().await?;
         // keep as runtime because of COALESCE type inference
-        let info = sqlx::query_as!(
+        let info: Option<KeyInfo> = sqlx::query_as!(
             KeyRow,
             r#"
             UPDATE virtual_keys SET
@@ -275,11 +312,21 @@ impl KeyStore {
             fields.monthly_token_budget,
             fields.revoked,
         )
-        .fetch_optional(&self.pool)
+        .fetch_optional(&mut *tx)
         .await?
         .map(Into::into);
 
-        if info.is_some() {
+        if let Some(info) = &info {
+            audit::record(
+                &mut tx,
+                actor,
+                Action::UpdateKey,
+                id,
+                Some(&info.name),
+                json!(fields),
+            )
+            .await?;
+            tx.commit().await?;
             self.invalidate_keys().await;
         }
         Ok(info)
@@ -30,7 +30,7 @@ pub struct KeyInfo {
 
 /// Partial update payload; absent fields are left unchanged. `monthly_token_budget`
 /// can only be set, not cleared back to null, through this path.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UpdateKey {
     pub name: Option<String>,
     pub allowed_models: Option<Vec<String>>,
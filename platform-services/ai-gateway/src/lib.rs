@@ -1,8 +1,12 @@
+pub mod admin_auth;
+pub mod audit;
 pub mod cache;
 pub mod config;
 pub mod error;
 pub mod feature_flag;
+pub mod idempotency;
 pub mod keys;
+pub mod lint;
 pub mod metrics;
 pub mod pricing;
 pub mod providers;
@@ -10,5 +14,6 @@ pub mod response_cache;
 pub mod routes;
 pub mod server;
 pub mod state;
+pub mod structured_output;
 pub mod tracing_setup;
 pub mod usage;
@@ -0,0 +1,269 @@
+use std::fmt;
+
+use crate::config::{Config, Resolved};
+use crate::providers::{ModelKind, Registry};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Routes that fail at request time.
+    Error,
+    /// Config that is legal but almost certainly not what was meant.
+    Warning,
+}
+
+/// One problem found by [`lint`].
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{label}: {}", self.message)
+    }
+}
+
+/// Checks a config for mistakes that otherwise only surface at request time, using the
+/// same rule resolution and provider routing as the gateway (via [`Registry::offline`]).
+/// Findings are ordered errors first, then by position in the file.
+pub fn lint(config: &Config) -> Vec<Finding> {
+    let registry = Registry::offline(config);
+    let served = |model: &str| {
+        !registry
+            .providers_for_model(model, ModelKind::Chat)
+            .is_empty()
+            || !registry
+                .providers_for_model(model, ModelKind::Embedding)
+                .is_empty()
+    };
+    let mut findings = Vec::new();
+    let mut report = |severity, message: String| findings.push(Finding { severity, message });
+
+    let mut providers: Vec<_> = config.providers.iter().collect();
+    providers.sort_by_key(|(name, _)| name.as_str());
+    for (name, provider) in providers {
+        if provider.models.is_empty() && provider.embedding_models.is_empty() && !provider.fallback
+        {
+            report(
+                Severity::Warning,
+                format!(
+                    "provider {name} declares no models and isn't a fallback, so it never serves"
+                ),
+            );
+        }
+    }
+
+    for (i, rule) in config.rules.iter().enumerate() {
+        let at = format!("rules[{i}] ({})", rule.matcher);
+
+        if !rule.has_action() {
+            report(
+                Severity::Warning,
+                format!("{at} has no deny, route or set_model, so it never applies"),
+            );
+            continue;
+        }
+        let actions = [rule.deny, rule.route.is_some(), rule.set_model.is_some()];
+        if actions.iter().filter(|set| **set).count() > 1 {
+            report(
+                Severity::Warning,
+                format!(
+                    "{at} sets several actions; only the first of deny, route, set_model applies"
+                ),
+            );
+        }
+
+        if let Some((j, earlier)) = config.rules[..i]
+            .iter()
+            .enumerate()
+            .find(|(_, earlier)| earlier.has_action() && earlier.matcher.covers(&rule.matcher))
+        {
+            report(
+                Severity::Warning,
+                format!(
+                    "{at} is shadowed by rules[{j}] ({}), which matches everything it does",
+                    earlier.matcher
+                ),
+            );
+        }
+
+        if rule.deny {
+            continue;
+        }
+        if let Some(route) = &rule.route {
+            let Some(provider) = config.providers.get(&route.provider) else {
+                report(
+                    Severity::Error,
+                    format!("{at} routes to undeclared provider {}", route.provider),
+                );
+                continue;
+            };
+            let target = route.as_model.as_ref().or(rule.matcher.model.as_ref());
+            if let Some(target) = target
+                && !provider.fallback
+                && !provider.models.contains(target)
+                && !provider.embedding_models.contains(target)
+            {
+                report(
+                    Severity::Warning,
+                    format!(
+                        "{at} sends {target} to provider {}, which doesn't declare it",
+                        route.provider
+                    ),
+                );
+            }
+        } else if let Some(target) = &rule.set_model
+            && !served(target)
+        {
+            report(
+                Severity::Error,
+                format!("{at} rewrites to {target}, which no provider serves"),
+            );
+        }
+    }
+
+    for key in &config.keys {
+        for model in &key.allowed_models {
+            match config.resolve(&key.name, model) {
+                Resolved::Denied => report(
+                    Severity::Warning,
+                    format!("key {} allows {model}, but a rule denies it", key.name),
+                ),
+                Resolved::Route {
+                    model: resolved,
+                    provider: None,
+                } if !served(&resolved) => report(
+                    Severity::Warning,
+                    format!(
+                        "key {} allows {model}, which resolves to {resolved} that no provider serves",
+                        key.name
+                    ),
+                ),
+                Resolved::Route { .. } => {}
+            }
+        }
+    }
+
+    let mut strategies: Vec<_> = config.routing.models.keys().collect();
+    strategies.sort();
+    for model in strategies {
+        if !served(model) {
+            report(
+                Severity::Warning,
+                format!("routing.models sets a strategy for {model}, which no provider serves"),
+            );
+        }
+    }
+
+    let mut capabilities: Vec<_> = config.models.iter().collect();
+    capabilities.sort_by_key(|(model, _)| model.as_str());
+    for (model, caps) in capabilities {
+        if let Some(overflow) = &caps.overflow
+            && !served(overflow)
+        {
+            report(
+                Severity::Warning,
+                format!("models.{model} overflows to {overflow}, which no provider serves"),
+            );
+        }
+    }
+
+    findings.sort_by_key(|f| f.severity);
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(yaml: &str) -> Vec<String> {
+        let config = Config::from_yaml(yaml).unwrap();
+        lint(&config).iter().map(ToString::to_string).collect()
+    }
+
+    const PROVIDERS: &str = r#"
+version: 2
+providers:
+  anthropic:
+    dialect: anthropic
+    base_url: https://anthropic.test
+    models: [claude-fable-5, claude-opus-4-8]
+  openai:
+    dialect: openai
+    base_url: https://openai.test
+    models: [gpt-5.4]
+"#;
+
+    #[test]
+    fn embedded_config_is_clean() {
+        let config = Config::load().unwrap();
+        let findings: Vec<_> = lint(&config).iter().map(ToString::to_string).collect();
+        assert!(findings.is_empty(), "{findings:#?}");
+    }
+
+    #[test]
+    fn reports_broken_and_shadowed_rules() {
+        let findings = messages(&format!(
+            r#"{PROVIDERS}
+rules:
+  - match: {{ model: gpt-4o }}
+    set_model: gpt-5.4
+  - match: {{ key: tldr-bot, model: gpt-4o }}
+    set_model: gpt-5.4-mini
+  - match: {{ model: claude-fable-5 }}
+    route: {{ provider: bedrock }}
+  - match: {{ model: claude-haiku-5 }}
+    route: {{ provider: anthropic }}
+keys:
+  - name: tldr-bot
+    allowed_models: [gpt-4o]
+"#
+        ));
+
+        assert_eq!(
+            findings,
+            [
+                "error: rules[1] (key tldr-bot, model gpt-4o) rewrites to gpt-5.4-mini, which no provider serves",
+                "error: rules[2] (model claude-fable-5) routes to undeclared provider bedrock",
+                "warning: rules[1] (key tldr-bot, model gpt-4o) is shadowed by rules[0] (model gpt-4o), which matches everything it does",
+                "warning: rules[3] (model claude-haiku-5) sends claude-haiku-5 to provider anthropic, which doesn't declare it",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unservable_keys_and_model_tables() {
+        let findings = messages(&format!(
+            r#"{PROVIDERS}
+rules:
+  - match: {{ model: claude-opus-4-8 }}
+    deny: true
+keys:
+  - name: maccas-api
+    allowed_models: [claude-opus-4-8, claude-fable-5, gpt-9]
+routing:
+  models:
+    gpt-9: latency
+models:
+  claude-fable-5:
+    context_window: 200000
+    overflow: claude-fable-5-1m
+"#
+        ));
+
+        assert_eq!(
+            findings,
+            [
+                "warning: key maccas-api allows claude-opus-4-8, but a rule denies it",
+                "warning: key maccas-api allows gpt-9, which resolves to gpt-9 that no provider serves",
+                "warning: routing.models sets a strategy for gpt-9, which no provider serves",
+                "warning: models.claude-fable-5 overflows to claude-fable-5-1m, which no provider serves",
+            ]
+        );
+    }
+}
@@ -41,9 +41,6 @@ async fn main() -> anyhow::Result<()> {
 
     let features = FeatureFlagClient::from_env().await;
     let cache = CacheClient::from_env().await;
-    if cache.is_some() {
-        tracing::info!("dragonfly cache enabled");
-    }
 
     let pricing = Pricing::load(&pool).await;
     pricing.spawn_refresh(pool.clone());
@@ -70,6 +67,7 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    let usage = state.usage.clone();
     let app = ai_gateway::server::router(state);
 
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
@@ -81,6 +79,7 @@ async fn main() -> anyhow::Result<()> {
         .with_graceful_shutdown(shutdown_signal())
         .await?;
     tracing::info!("shutting down");
+    usage.shutdown().await;
 
     if let Some(provider) = tracer_provider {
         let _ = provider.shutdown();
@@ -88,3 +88,28 @@ pub fn record_upstream_error(provider: &str) {
 pub fn record_stream_truncated(provider: &str) {
     counter!("ai_gateway_stream_truncated_total", "provider" => provider.to_owned()).increment(1);
 }
+
+/// A buffered reply that didn't satisfy the request's response schema, retried or not.
+pub fn record_schema_violation(key_name: &str, model: &str) {
+    counter!(
+        "ai_gateway_schema_violations_total",
+        "key" => key_name.to_owned(),
+        "model" => model.to_owned(),
+    )
+    .increment(1);
+}
+
+/// A failed Dragonfly operation, served from the in-process cache instead.
+pub fn record_cache_error(op: &str) {
+    counter!("ai_gateway_cache_errors_total", "op" => op.to_owned()).increment(1);
+}
+
+/// Usage events written to the local spill file because Postgres couldn't take them.
+pub fn record_usage_spilled(events: u64) {
+    counter!("ai_gateway_usage_events_spilled_total").increment(events);
+}
+
+/// Usage events lost outright: rejected by Postgres or unable to be spilled.
+pub fn record_usage_dropped(events: u64) {
+    counter!("ai_gateway_usage_events_dropped_total").increment(events);
+}
@@ -74,6 +74,25 @@ impl Pricing {
         });
     }
 
+    /// A fixed price table, for callers that don't load from the database.
+    pub fn from_prices(prices: impl IntoIterator<Item = ModelPrice>) -> Self {
+        let map = prices.into_iter().map(|p| (p.id.clone(), p)).collect();
+        Self {
+            prices: Arc::new(RwLock::new(map)),
+        }
+    }
+
+    /// Input plus output USD per million tokens for `model` served by `provider`, used to
+    /// rank providers by cost. A provider-qualified id (`openrouter/gpt-5.4`) takes
+    /// precedence over the bare model id, so one model can be priced per provider.
+    pub fn blended_rate(&self, provider: &str, model: &str) -> Option<f64> {
+        let prices = self.prices.read().unwrap();
+        prices
+            .get(&format!("{provider}/{model}"))
+            .or_else(|| prices.get(model))
+            .map(|p| p.input_usd_per_mtok + p.output_usd_per_mtok)
+    }
+
     /// Estimated USD cost of a request given its token usage. Returns 0 when the resolved
     /// model has no known price.
     pub fn cost(&self, model: &str, usage: Usage) -> f64 {
@@ -118,17 +137,12 @@ mod tests {
     use super::*;
 
     fn pricing_with(model: &str, input: f64, output: f64) -> Pricing {
-        let pricing = Pricing::default();
-        pricing.prices.write().unwrap().insert(
-            model.to_owned(),
-            ModelPrice {
-                id: model.to_owned(),
-                input_usd_per_mtok: input,
-                output_usd_per_mtok: output,
-                cached_usd_per_mtok: None,
-            },
-        );
-        pricing
+        Pricing::from_prices([ModelPrice {
+            id: model.to_owned(),
+            input_usd_per_mtok: input,
+            output_usd_per_mtok: output,
+            cached_usd_per_mtok: None,
+        }])
     }
 
     #[test]
@@ -142,6 +156,27 @@ mod tests {
         assert!((pricing.cost("claude-opus-4-8", usage) - 165.0).abs() < 1e-9);
     }
 
+    #[test]
+    fn provider_qualified_price_wins_for_blended_rate() {
+        let pricing = Pricing::from_prices([
+            ModelPrice {
+                id: "gpt-5.4".into(),
+                input_usd_per_mtok: 2.0,
+                output_usd_per_mtok: 8.0,
+                cached_usd_per_mtok: None,
+            },
+            ModelPrice {
+                id: "openrouter/gpt-5.4".into(),
+                input_usd_per_mtok: 2.5,
+                output_usd_per_mtok: 10.0,
+                cached_usd_per_mtok: None,
+            },
+        ]);
+        assert_eq!(pricing.blended_rate("openai", "gpt-5.4"), Some(10.0));
+        assert_eq!(pricing.blended_rate("openrouter", "gpt-5.4"), Some(12.5));
+        assert_eq!(pricing.blended_rate("openai", "unknown"), None);
+    }
+
     #[test]
     fn unknown_model_is_free() {
         let pricing = pricing_with("known", 1.0, 1.0);
@@ -20,6 +20,27 @@ impl Anthropic {
             version: std::env::var("ANTHROPIC_VERSION").unwrap_or_else(|_| "2023-06-01".into()),
         }
     }
+
+    /// The client may override anthropic-version and opt into betas; auth is always ours.
+    fn post(
+        &self,
+        http: &Client,
+        path: &str,
+        body: Bytes,
+        client_headers: &HeaderMap,
+    ) -> RequestBuilder {
+        let version = client_headers
+            .get("anthropic-version")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or(&self.version);
+        let req = http
+            .post(format!("{}{path}", self.base_url))
+            .header("content-type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", version)
+            .body(body);
+        forward_headers(req, client_headers, &["anthropic-beta"])
+    }
 }
 
 impl Provider for Anthropic {
@@ -38,19 +59,17 @@ impl Provider for Anthropic {
         body: Bytes,
         client_headers: &HeaderMap,
     ) -> RequestBuilder {
-        // Anthropic only serves chat-style traffic; embedding models never route here. The
-        // client may override anthropic-version and opt into betas; auth is always ours.
-        let version = client_headers
-            .get("anthropic-version")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or(&self.version);
-        let req = http
-            .post(format!("{}/v1/messages", self.base_url))
-            .header("content-type", "application/json")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", version)
-            .body(body);
-        forward_headers(req, client_headers, &["anthropic-beta"])
+        // Anthropic only serves chat-style traffic; embedding models never route here.
+        self.post(http, "/v1/messages", body, client_headers)
+    }
+
+    fn build_count_tokens_request(
+        &self,
+        http: &Client,
+        body: Bytes,
+        client_headers: &HeaderMap,
+    ) -> Option<RequestBuilder> {
+        Some(self.post(http, "/v1/messages/count_tokens", body, client_headers))
     }
 
     fn parse_usage(&self, body: &[u8]) -> Usage {
@@ -1,17 +1,20 @@
 pub mod anthropic;
 pub mod openai;
 pub mod registry;
+pub mod stats;
 pub mod translate;
 
 pub use anthropic::Anthropic;
 pub use openai::OpenAiCompatible;
 pub use registry::Registry;
+pub use stats::ProviderStats;
 
 use bytes::Bytes;
 use llm_bridge_core::model::ApiFormat;
 use reqwest::{Client, RequestBuilder, header::HeaderMap};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
+use crate::config::{KeyConfig, PromptPosition};
 use crate::error::{GatewayError, Result};
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
@@ -83,6 +86,18 @@ pub trait Provider: Send + Sync {
         client_headers: &HeaderMap,
     ) -> RequestBuilder;
 
+    /// Builds an exact token-count request for an Anthropic-dialect body, when this
+    /// provider offers one. Providers without a counting API return `None` and the
+    /// gateway falls back to [`ProxyRequest::estimate_input_tokens`].
+    fn build_count_tokens_request(
+        &self,
+        _http: &Client,
+        _body: Bytes,
+        _client_headers: &HeaderMap,
+    ) -> Option<RequestBuilder> {
+        None
+    }
+
     fn parse_usage(&self, body: &[u8]) -> Usage;
     fn parse_stream_usage(&self, body: &[u8]) -> Usage;
 }
@@ -144,6 +159,113 @@ impl ProxyRequest {
         self.json["model"] = Value::String(model.to_owned());
     }
 
+    /// Applies a key's configured policy: its system prompt, then default and forced
+    /// parameters. Runs on the client's dialect, before any translation, so both endpoints
+    /// behave identically whichever provider serves them.
+    pub fn apply_policy(&mut self, dialect: Dialect, policy: &KeyConfig) {
+        if let Some(prompt) = &policy.system_prompt {
+            self.inject_system(dialect, &prompt.text, prompt.position);
+        }
+        self.apply_params(&policy.defaults, false);
+        self.apply_params(&policy.forced, true);
+    }
+
+    /// Adds `text` to the system prompt. Anthropic carries it in the top-level `system`
+    /// (a string or a list of text blocks); OpenAI as leading `system`/`developer`
+    /// messages, so an appended prompt goes after the last of those rather than at the
+    /// end of the conversation.
+    pub fn inject_system(&mut self, dialect: Dialect, text: &str, position: PromptPosition) {
+        match dialect {
+            Dialect::Anthropic => {
+                let block = serde_json::json!({ "type": "text", "text": text });
+                self.json["system"] = match self.json.get_mut("system").map(Value::take) {
+                    Some(Value::String(existing)) if !existing.is_empty() => {
+                        Value::String(match position {
+                            PromptPosition::Prepend => format!("{text}\n\n{existing}"),
+                            PromptPosition::Append => format!("{existing}\n\n{text}"),
+                        })
+                    }
+                    Some(Value::Array(mut blocks)) => {
+                        match position {
+                            PromptPosition::Prepend => blocks.insert(0, block),
+                            PromptPosition::Append => blocks.push(block),
+                        }
+                        Value::Array(blocks)
+                    }
+                    _ => Value::String(text.to_owned()),
+                };
+            }
+            Dialect::OpenAiCompatible => {
+                let Some(messages) = self.json.get_mut("messages").and_then(Value::as_array_mut)
+                else {
+                    return;
+                };
+                let index = match position {
+                    PromptPosition::Prepend => 0,
+                    PromptPosition::Append => messages
+                        .iter()
+                        .take_while(|m| {
+                            matches!(
+                                m.get("role").and_then(Value::as_str),
+                                Some("system" | "developer")
+                            )
+                        })
+                        .count(),
+                };
+                messages.insert(
+                    index,
+                    serde_json::json!({ "role": "system", "content": text }),
+                );
+            }
+        }
+    }
+
+    /// Sets each top-level parameter, overwriting the client's value only when `force`.
+    /// Fields that shape routing or the conversation itself are never touched.
+    fn apply_params(&mut self, params: &Map<String, Value>, force: bool) {
+        const RESERVED: &[&str] = &["model", "stream", "messages", "system"];
+        let Some(body) = self.json.as_object_mut() else {
+            return;
+        };
+        for (name, value) in params {
+            if RESERVED.contains(&name.as_str()) {
+                continue;
+            }
+            if force || !body.contains_key(name) {
+                body.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    /// A rough input size for pre-flight checks and non-Anthropic token counts: about
+    /// [`CHARS_PER_TOKEN`] characters per token over the system prompt, messages and tool
+    /// definitions. Dialect-agnostic, and skips base64 payloads and URLs rather than
+    /// counting them as text.
+    pub fn estimate_input_tokens(&self) -> u64 {
+        let chars: usize = ["system", "messages", "tools"]
+            .iter()
+            .filter_map(|field| self.json.get(*field))
+            .map(text_chars)
+            .sum();
+        (chars as u64).div_ceil(CHARS_PER_TOKEN)
+    }
+
+    /// Output tokens the client asked for (`max_tokens`, or OpenAI's
+    /// `max_completion_tokens`); 0 when unset.
+    pub fn max_output_tokens(&self) -> u64 {
+        ["max_tokens", "max_completion_tokens"]
+            .iter()
+            .find_map(|field| self.json.get(*field).and_then(Value::as_u64))
+            .unwrap_or(0)
+    }
+
+    /// Appends a plain-text turn. Both dialects share the `{role, content}` message shape.
+    pub fn push_message(&mut self, role: &str, text: &str) {
+        if let Some(messages) = self.json.get_mut("messages").and_then(Value::as_array_mut) {
+            messages.push(serde_json::json!({ "role": role, "content": text }));
+        }
+    }
+
     pub fn to_bytes(&self) -> Result<Bytes> {
         serde_json::to_vec(&self.json)
             .map(Bytes::from)
@@ -151,6 +273,23 @@ impl ProxyRequest {
     }
 }
 
+/// Characters per token assumed by [`ProxyRequest::estimate_input_tokens`]; close for
+/// English prose across current tokenizers, and errs high for code.
+const CHARS_PER_TOKEN: u64 = 4;
+
+fn text_chars(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.iter().map(text_chars).sum(),
+        Value::Object(fields) => fields
+            .iter()
+            .filter(|(name, _)| !matches!(name.as_str(), "data" | "url"))
+            .map(|(_, v)| text_chars(v))
+            .sum(),
+        _ => 0,
+    }
+}
+
 /// Shared SSE scanner: yields each non-empty JSON `data:` payload to `f`.
 pub(crate) fn for_each_sse_event(body: &[u8], mut f: impl FnMut(&Value)) {
     for line in String::from_utf8_lossy(body).lines() {
@@ -170,6 +309,7 @@ pub(crate) fn for_each_sse_event(body: &[u8], mut f: impl FnMut(&Value)) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn chat_request_reads_and_rewrites_model() {
@@ -189,6 +329,105 @@ mod tests {
         assert!(!req.is_stream());
     }
 
+    fn policy(yaml: &str) -> KeyConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn apply(body: Value, dialect: Dialect, policy: &KeyConfig) -> Value {
+        let mut req = ProxyRequest::from_slice(&serde_json::to_vec(&body).unwrap()).unwrap();
+        req.apply_policy(dialect, policy);
+        serde_json::from_slice(&req.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn anthropic_system_prompt_is_injected() {
+        let prepend = policy("{ name: k, system_prompt: { text: policy } }");
+        let append = policy("{ name: k, system_prompt: { text: policy, position: append } }");
+
+        let absent = apply(json!({ "model": "m" }), Dialect::Anthropic, &prepend);
+        assert_eq!(absent["system"], "policy");
+
+        let string = json!({ "model": "m", "system": "be brief" });
+        let out = apply(string.clone(), Dialect::Anthropic, &prepend);
+        assert_eq!(out["system"], "policy\n\nbe brief");
+        let out = apply(string, Dialect::Anthropic, &append);
+        assert_eq!(out["system"], "be brief\n\npolicy");
+
+        let blocks = json!({ "model": "m", "system": [{ "type": "text", "text": "be brief" }] });
+        let out = apply(blocks, Dialect::Anthropic, &append);
+        assert_eq!(out["system"][0]["text"], "be brief");
+        assert_eq!(out["system"][1]["text"], "policy");
+    }
+
+    #[test]
+    fn openai_system_prompt_is_injected_around_leading_system_messages() {
+        let body = json!({
+            "model": "m",
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": "hi" },
+            ],
+        });
+
+        let prepend = policy("{ name: k, system_prompt: { text: policy } }");
+        let out = apply(body.clone(), Dialect::OpenAiCompatible, &prepend);
+        assert_eq!(out["messages"][0]["content"], "policy");
+        assert_eq!(out["messages"][1]["content"], "be brief");
+
+        let append = policy("{ name: k, system_prompt: { text: policy, position: append } }");
+        let out = apply(body, Dialect::OpenAiCompatible, &append);
+        assert_eq!(out["messages"][1]["role"], "system");
+        assert_eq!(out["messages"][1]["content"], "policy");
+        assert_eq!(out["messages"][2]["content"], "hi");
+    }
+
+    #[test]
+    fn defaults_fill_gaps_and_forced_overwrites() {
+        let policy = policy(
+            "{ name: k, defaults: { max_tokens: 1024, temperature: 0.2 }, \
+               forced: { temperature: 0, stream: true, model: other } }",
+        );
+        let out = apply(
+            json!({ "model": "m", "max_tokens": 64, "temperature": 0.9 }),
+            Dialect::OpenAiCompatible,
+            &policy,
+        );
+        // The client's max_tokens survives the default; temperature is forced.
+        assert_eq!(out["max_tokens"], 64);
+        assert_eq!(out["temperature"], 0);
+        // Reserved fields are never rewritten.
+        assert_eq!(out["model"], "m");
+        assert!(out.get("stream").is_none());
+
+        let out = apply(json!({ "model": "m" }), Dialect::Anthropic, &policy);
+        assert_eq!(out["max_tokens"], 1024);
+    }
+
+    #[test]
+    fn estimates_text_tokens_and_skips_attachments() {
+        let req = ProxyRequest::from_slice(
+            &serde_json::to_vec(&json!({
+                "model": "ignored-model-name",
+                "max_tokens": 100,
+                "system": "12345678",
+                "messages": [{ "role": "user", "content": [
+                    { "type": "text", "text": "1234" },
+                    { "type": "image", "source": { "type": "base64", "data": "QUJDRA==" } },
+                ] }],
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+        // system (8) + role (4) + block types and text (4 + 4 + 5 + 6) = 31 chars.
+        assert_eq!(req.estimate_input_tokens(), 8);
+        assert_eq!(req.max_output_tokens(), 100);
+
+        let openai =
+            ProxyRequest::from_slice(br#"{"model":"m","max_completion_tokens":7}"#).unwrap();
+        assert_eq!(openai.max_output_tokens(), 7);
+        assert_eq!(openai.estimate_input_tokens(), 0);
+    }
+
     #[test]
     fn embeddings_always_cacheable_chat_requires_zero_temp() {
         let no_temp = ProxyRequest::from_slice(br#"{"model":"m"}"#).unwrap();
@@ -1,8 +1,10 @@
 use std::collections::{BTreeMap, HashMap};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use super::{Anthropic, Dialect, ModelKind, OpenAiCompatible, Provider};
-use crate::config::Config;
+use super::{Anthropic, Dialect, ModelKind, OpenAiCompatible, Provider, ProviderStats};
+use crate::config::{Config, RoutingConfig, RoutingStrategy};
+use crate::pricing::Pricing;
 
 /// Configured upstreams and the routing table. Routes are keyed by `(model, kind)` so an
 /// embedding model is unreachable from chat endpoints, and map to providers in failover
@@ -13,10 +15,26 @@ pub struct Registry {
     routes: HashMap<(String, ModelKind), Vec<String>>,
     /// Providers serving any otherwise-unrouted model, in failover order.
     fallbacks: Vec<String>,
+    routing: RoutingConfig,
+    /// Observed upstream latency and errors, fed by the proxy loop. See [`Self::select`].
+    stats: ProviderStats,
+    /// Per-model request counter for [`RoutingStrategy::RoundRobin`].
+    turns: Arc<Mutex<HashMap<String, usize>>>,
 }
 
 impl Registry {
     pub fn from_config(config: &Config) -> Self {
+        Self::build(config, env_value)
+    }
+
+    /// Every configured provider, enabled or not, with a placeholder credential: routes
+    /// exactly as [`Self::from_config`] would with all API keys set. For offline tooling
+    /// that has no secrets; never used to send requests.
+    pub fn offline(config: &Config) -> Self {
+        Self::build(config, |_| Some("offline".to_owned()))
+    }
+
+    fn build(config: &Config, api_key: impl Fn(&str) -> Option<String>) -> Self {
         let mut providers: HashMap<String, Arc<dyn Provider>> = HashMap::new();
         let mut routes: HashMap<(String, ModelKind), Vec<String>> = HashMap::new();
         let mut fallbacks: Vec<String> = Vec::new();
@@ -38,7 +56,7 @@ impl Registry {
             }
 
             let key_env = pc.api_key_env(name);
-            let Some(key) = env_value(&key_env) else {
+            let Some(key) = api_key(&key_env) else {
                 tracing::warn!(
                     provider = name,
                     env = key_env,
@@ -80,9 +98,16 @@ impl Registry {
             providers,
             routes,
             fallbacks,
+            routing: config.routing.clone(),
+            stats: ProviderStats::default(),
+            turns: Arc::default(),
         }
     }
 
+    pub fn stats(&self) -> &ProviderStats {
+        &self.stats
+    }
+
     pub fn get(&self, name: &str) -> Option<Arc<dyn Provider>> {
         self.providers.get(name).cloned()
     }
@@ -106,6 +131,59 @@ impl Registry {
             .collect()
     }
 
+    /// [`Self::providers_for_model`], reordered by the model's configured
+    /// [`RoutingStrategy`]. Sorts are stable over the priority order, so `priority` breaks
+    /// ties, and every strategy but `priority` moves degraded providers to the back.
+    pub fn select(
+        &self,
+        model: &str,
+        kind: ModelKind,
+        pricing: &Pricing,
+    ) -> Vec<Arc<dyn Provider>> {
+        self.select_at(model, kind, pricing, Instant::now())
+    }
+
+    /// [`Self::select`] with provider stats as of `now`.
+    pub(crate) fn select_at(
+        &self,
+        model: &str,
+        kind: ModelKind,
+        pricing: &Pricing,
+        now: Instant,
+    ) -> Vec<Arc<dyn Provider>> {
+        let mut providers = self.providers_for_model(model, kind);
+        if providers.len() < 2 {
+            return providers;
+        }
+
+        let summary = |p: &Arc<dyn Provider>| self.stats.summary_at(p.name(), model, now);
+        match self.routing.strategy_for(model) {
+            RoutingStrategy::Priority => return providers,
+            RoutingStrategy::Latency => {
+                providers.sort_by_cached_key(|p| summary(p).p50.unwrap_or_default());
+            }
+            RoutingStrategy::Cost => {
+                let rate = |p: &Arc<dyn Provider>| pricing.blended_rate(p.name(), model);
+                providers.sort_by(|a, b| match (rate(a), rate(b)) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                });
+            }
+            RoutingStrategy::RoundRobin => {
+                let turn = {
+                    let mut turns = self.turns.lock().unwrap();
+                    let turn = turns.entry(model.to_owned()).or_default();
+                    *turn = turn.wrapping_add(1);
+                    *turn - 1
+                };
+                let len = providers.len();
+                providers.rotate_left(turn % len);
+            }
+        }
+        providers.sort_by_cached_key(|p| summary(p).is_degraded());
+        providers
+    }
+
     pub fn names(&self) -> Vec<String> {
         self.providers.keys().cloned().collect()
     }
@@ -136,16 +214,163 @@ mod tests {
     use super::*;
 
     fn registry_from_yaml(yaml: &str, key_env: &str) -> Registry {
+        registry_with_routing(yaml, key_env, RoutingConfig::default())
+    }
+
+    fn registry_with_routing(yaml: &str, key_env: &str, routing: RoutingConfig) -> Registry {
         let providers: HashMap<String, crate::config::ProviderConfig> =
             serde_yaml::from_str(yaml).unwrap();
         let config = Config {
             providers,
+            routing,
             ..Default::default()
         };
         unsafe { std::env::set_var(key_env, "secret") };
         Registry::from_config(&config)
     }
 
+    /// Three providers serving `gpt-4o`, in priority order `openai`, `azure`, `openrouter`.
+    const SHARED_MODEL: &str = r#"
+openai:
+  dialect: openai
+  base_url: https://openai.test
+  api_key_env: TEST_STRATEGY_KEY
+  priority: 10
+  models: [gpt-4o]
+azure:
+  dialect: openai
+  base_url: https://azure.test
+  api_key_env: TEST_STRATEGY_KEY
+  priority: 20
+  models: [gpt-4o]
+openrouter:
+  dialect: openai
+  base_url: https://openrouter.test
+  api_key_env: TEST_STRATEGY_KEY
+  priority: 30
+  models: [gpt-4o]
+"#;
+
+    fn strategy(strategy: RoutingStrategy) -> RoutingConfig {
+        RoutingConfig {
+            default: strategy,
+            ..Default::default()
+        }
+    }
+
+    fn selected(registry: &Registry, pricing: &Pricing) -> Vec<String> {
+        registry
+            .select("gpt-4o", ModelKind::Chat, pricing)
+            .iter()
+            .map(|p| p.name().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn latency_strategy_prefers_fastest_and_demotes_degraded() {
+        let registry = registry_with_routing(
+            SHARED_MODEL,
+            "TEST_STRATEGY_KEY",
+            strategy(RoutingStrategy::Latency),
+        );
+        let ms = std::time::Duration::from_millis;
+        registry.stats().record("openai", "gpt-4o", ms(900), true);
+        registry.stats().record("azure", "gpt-4o", ms(300), true);
+        registry
+            .stats()
+            .record("openrouter", "gpt-4o", ms(100), true);
+        assert_eq!(
+            selected(&registry, &Pricing::default()),
+            ["openrouter", "azure", "openai"]
+        );
+
+        // A mostly-failing provider drops behind slower healthy ones.
+        for _ in 0..3 {
+            registry
+                .stats()
+                .record("openrouter", "gpt-4o", ms(10), false);
+        }
+        assert_eq!(
+            selected(&registry, &Pricing::default()),
+            ["azure", "openai", "openrouter"]
+        );
+    }
+
+    #[test]
+    fn degraded_provider_recovers_once_failures_age_out() {
+        let registry = registry_with_routing(
+            SHARED_MODEL,
+            "TEST_STRATEGY_KEY",
+            strategy(RoutingStrategy::Latency),
+        );
+        let ms = std::time::Duration::from_millis;
+        let stats = registry.stats();
+        let start = Instant::now();
+        for _ in 0..10 {
+            stats.record_at("openrouter", "gpt-4o", start, ms(10), false);
+        }
+
+        let later = start + std::time::Duration::from_secs(600);
+        stats.record_at("openai", "gpt-4o", later, ms(900), true);
+        stats.record_at("azure", "gpt-4o", later, ms(300), true);
+        stats.record_at("openrouter", "gpt-4o", later, ms(100), true);
+        let selected_at = |now| -> Vec<String> {
+            registry
+                .select_at("gpt-4o", ModelKind::Chat, &Pricing::default(), now)
+                .iter()
+                .map(|p| p.name().to_owned())
+                .collect()
+        };
+        // Only the one recent success counts, so it's fastest again.
+        assert_eq!(selected_at(later), ["openrouter", "azure", "openai"]);
+        // While its failures were recent, it was demoted.
+        assert_eq!(selected_at(start).last().unwrap(), "openrouter");
+    }
+
+    #[test]
+    fn cost_strategy_prefers_cheapest_and_puts_unpriced_last() {
+        let registry = registry_with_routing(
+            SHARED_MODEL,
+            "TEST_STRATEGY_KEY",
+            strategy(RoutingStrategy::Cost),
+        );
+        let price = |id: &str, input: f64| crate::pricing::ModelPrice {
+            id: id.into(),
+            input_usd_per_mtok: input,
+            output_usd_per_mtok: 0.0,
+            cached_usd_per_mtok: None,
+        };
+        // `azure` has no provider-qualified price, so it costs the bare model's rate.
+        let pricing = Pricing::from_prices([
+            price("gpt-4o", 5.0),
+            price("openai/gpt-4o", 6.0),
+            price("openrouter/gpt-4o", 4.0),
+        ]);
+        assert_eq!(
+            selected(&registry, &pricing),
+            ["openrouter", "azure", "openai"]
+        );
+
+        let only_openai = Pricing::from_prices([price("openai/gpt-4o", 6.0)]);
+        assert_eq!(
+            selected(&registry, &only_openai),
+            ["openai", "azure", "openrouter"]
+        );
+    }
+
+    #[test]
+    fn round_robin_rotates_the_first_provider() {
+        let registry = registry_with_routing(
+            SHARED_MODEL,
+            "TEST_STRATEGY_KEY",
+            strategy(RoutingStrategy::RoundRobin),
+        );
+        let firsts: Vec<_> = (0..4)
+            .map(|_| selected(&registry, &Pricing::default())[0].clone())
+            .collect();
+        assert_eq!(firsts, ["openai", "azure", "openrouter", "openai"]);
+    }
+
     #[test]
     fn routes_model_to_its_provider() {
         let yaml = r#"
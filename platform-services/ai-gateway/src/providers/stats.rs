@@ -0,0 +1,169 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upstream attempts remembered per (provider, model). Small enough that a provider
+/// recovering from an incident is trusted again within a few dozen requests.
+const WINDOW: usize = 64;
+
+/// Attempts older than this are forgotten. A degraded provider is ordered last and
+/// so rarely tried, and its window would otherwise never refill with successes; once
+/// its failures age out it's trusted again and gets traffic to prove itself.
+const MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Error rate over the window at or above which a provider is considered degraded and
+/// ordered after its healthy peers, whatever the routing strategy.
+const DEGRADED_ERROR_RATE: f64 = 0.5;
+
+/// Rolling latency and error stats for upstream attempts, collected in the proxy loop
+/// and read by the [`Registry`](super::Registry) when ordering providers. In-process
+/// only: each replica routes on what it has observed itself.
+#[derive(Clone, Default)]
+pub struct ProviderStats {
+    windows: Arc<Mutex<HashMap<(String, String), Window>>>,
+}
+
+/// The most recent attempts for one (provider, model), oldest first.
+type Window = VecDeque<Attempt>;
+
+#[derive(Clone, Copy)]
+struct Attempt {
+    at: Instant,
+    latency: Duration,
+    ok: bool,
+}
+
+/// A point-in-time view of one provider's window for a model.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Median time to response headers over successful attempts; `None` until one succeeds.
+    pub p50: Option<Duration>,
+    pub error_rate: f64,
+    pub attempts: usize,
+}
+
+impl Summary {
+    pub fn is_degraded(&self) -> bool {
+        self.attempts > 0 && self.error_rate >= DEGRADED_ERROR_RATE
+    }
+}
+
+impl ProviderStats {
+    /// Records one upstream attempt. `ok` is false for transport errors and retryable
+    /// statuses; a non-retryable client error still counts as the provider answering.
+    pub fn record(&self, provider: &str, model: &str, latency: Duration, ok: bool) {
+        self.record_at(provider, model, Instant::now(), latency, ok);
+    }
+
+    pub(crate) fn record_at(
+        &self,
+        provider: &str,
+        model: &str,
+        at: Instant,
+        latency: Duration,
+        ok: bool,
+    ) {
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows
+            .entry((provider.to_owned(), model.to_owned()))
+            .or_default();
+        if window.len() == WINDOW {
+            window.pop_front();
+        }
+        window.push_back(Attempt { at, latency, ok });
+    }
+
+    /// Stats over the window's attempts from the last [`MAX_AGE`].
+    pub fn summary(&self, provider: &str, model: &str) -> Summary {
+        self.summary_at(provider, model, Instant::now())
+    }
+
+    /// [`Self::summary`] as of `now`.
+    pub(crate) fn summary_at(&self, provider: &str, model: &str, now: Instant) -> Summary {
+        let windows = self.windows.lock().unwrap();
+        let Some(window) = windows.get(&(provider.to_owned(), model.to_owned())) else {
+            return Summary::default();
+        };
+
+        let recent: Vec<&Attempt> = window
+            .iter()
+            .filter(|a| now.saturating_duration_since(a.at) < MAX_AGE)
+            .collect();
+        let mut latencies: Vec<Duration> =
+            recent.iter().filter(|a| a.ok).map(|a| a.latency).collect();
+        latencies.sort_unstable();
+        let errors = recent.len() - latencies.len();
+
+        Summary {
+            p50: latencies.get(latencies.len() / 2).copied(),
+            error_rate: errors as f64 / recent.len().max(1) as f64,
+            attempts: recent.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn median_ignores_failed_attempts() {
+        let stats = ProviderStats::default();
+        for latency in [300, 100, 200] {
+            stats.record("openai", "gpt-4o", ms(latency), true);
+        }
+        stats.record("openai", "gpt-4o", ms(5), false);
+
+        let summary = stats.summary("openai", "gpt-4o");
+        assert_eq!(summary.p50, Some(ms(200)));
+        assert_eq!(summary.attempts, 4);
+        assert!(!summary.is_degraded());
+
+        // Stats are per model: another model on the same provider is unobserved.
+        assert_eq!(stats.summary("openai", "gpt-5.4"), Summary::default());
+    }
+
+    #[test]
+    fn window_rolls_and_flags_degraded_providers() {
+        let stats = ProviderStats::default();
+        for _ in 0..WINDOW {
+            stats.record("openrouter", "m", ms(50), false);
+        }
+        assert!(stats.summary("openrouter", "m").is_degraded());
+        assert_eq!(stats.summary("openrouter", "m").p50, None);
+
+        // Recovery pushes the failures out of the window.
+        for _ in 0..WINDOW {
+            stats.record("openrouter", "m", ms(50), true);
+        }
+        let summary = stats.summary("openrouter", "m");
+        assert_eq!(summary.error_rate, 0.0);
+        assert_eq!(summary.attempts, WINDOW);
+    }
+
+    #[test]
+    fn failures_age_out_without_new_attempts() {
+        let stats = ProviderStats::default();
+        let start = Instant::now();
+        for _ in 0..WINDOW {
+            stats.record_at("openrouter", "m", start, ms(50), false);
+        }
+        assert!(stats.summary_at("openrouter", "m", start).is_degraded());
+
+        let later = start + MAX_AGE + Duration::from_secs(1);
+        assert_eq!(
+            stats.summary_at("openrouter", "m", later),
+            Summary::default()
+        );
+
+        stats.record_at("openrouter", "m", later, ms(80), true);
+        let summary = stats.summary_at("openrouter", "m", later);
+        assert_eq!(summary.attempts, 1);
+        assert_eq!(summary.p50, Some(ms(80)));
+        assert!(!summary.is_degraded());
+    }
+}
@@ -11,24 +11,54 @@ use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    error::Result, keys::UpdateKey, metrics, pricing, pricing::ModelPrice, state::AppState, usage,
+    admin_auth::{Principal, Role},
+    error::Result,
+    keys::UpdateKey,
+    metrics, pricing,
+    pricing::ModelPrice,
+    state::AppState,
+    usage,
 };
 
-/// Guards `/admin/*`. Requires the bearer to equal the configured admin token; when no
-/// token is configured admin endpoints are closed entirely.
+/// Guards `/admin/*`. The bearer is either the configured admin token, which grants
+/// [`Role::Write`], or an OIDC token whose roles claim grants at least `needed`. Invalid
+/// or missing credentials are a 401; a valid caller without the role is a 403. With
+/// neither an admin token nor OIDC configured, admin endpoints are closed entirely.
 #[allow(clippy::result_large_err)]
-fn authorize(state: &AppState, headers: &HeaderMap) -> std::result::Result<(), Response> {
+async fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    needed: Role,
+) -> std::result::Result<Principal, Response> {
     let provided = headers
         .get("authorization")
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
         .map(str::trim)
         .unwrap_or("");
+    let unauthorized = || (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+
+    let principal = if provided.is_empty() {
+        return Err(unauthorized());
+    } else if !state.config.admin_token.is_empty() && provided == state.config.admin_token {
+        Principal::admin_token()
+    } else if let Some(oidc) = &state.oidc {
+        match oidc.verify(provided).await {
+            Ok(principal) => principal,
+            Err(e) => {
+                tracing::info!("rejected admin token: {e:#}");
+                return Err(unauthorized());
+            }
+        }
+    } else {
+        return Err(unauthorized());
+    };
 
-    if !state.config.admin_token.is_empty() && provided == state.config.admin_token {
-        Ok(())
+    if principal.role >= Some(needed) {
+        Ok(principal)
     } else {
-        Err((StatusCode::UNAUTHORIZED, "admin token required").into_response())
+        tracing::info!(principal = principal.subject, ?needed, "admin role missing");
+        Err((StatusCode::FORBIDDEN, "insufficient admin role").into_response())
     }
 }
 
@@ -63,13 +93,19 @@ pub async fn create_key(
     headers: HeaderMap,
     Json(body): Json<CreateKey>,
 ) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
-        return Ok(resp);
-    }
+    let principal = match authorize(&state, &headers, Role::Write).await {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
 
     let (token, info) = state
         .keys
-        .create(&body.name, &body.allowed_models, body.monthly_token_budget)
+        .create(
+            &principal,
+            &body.name,
+            &body.allowed_models,
+            body.monthly_token_budget,
+        )
         .await?;
 
     // The plaintext token is returned exactly once, here.
@@ -81,7 +117,7 @@ pub async fn create_key(
 }
 
 pub async fn list_keys(State(state): State<AppState>, headers: HeaderMap) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
+    if let Err(resp) = authorize(&state, &headers, Role::Read).await {
         return Ok(resp);
     }
     Ok(Json(state.keys.list().await?).into_response())
@@ -92,10 +128,11 @@ pub async fn revoke_key(
     headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
-        return Ok(resp);
-    }
-    let found = state.keys.revoke(id).await?;
+    let principal = match authorize(&state, &headers, Role::Write).await {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+    let found = state.keys.revoke(&principal, id).await?;
     Ok(if found {
         StatusCode::NO_CONTENT.into_response()
     } else {
@@ -108,12 +145,15 @@ pub async fn regenerate_key(
     headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
-        return Ok(resp);
-    }
-    Ok(match state.keys.regenerate(id).await? {
-        // The plaintext token is returned exactly once, here.
-        Some((token, info)) => Json(json!({ "key": token, "info": info })).into_response(),
+    let principal = match authorize(&state, &headers, Role::Write).await {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+    Ok(match state.keys.regenerate(&principal, id).await? {
+        Some((token, info)) => {
+            // The plaintext token is returned exactly once, here.
+            Json(json!({ "key": token, "info": info })).into_response()
+        }
         None => StatusCode::NOT_FOUND.into_response(),
     })
 }
@@ -124,17 +164,18 @@ pub async fn update_key(
     Path(id): Path<Uuid>,
     Json(body): Json<UpdateKey>,
 ) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
-        return Ok(resp);
-    }
-    Ok(match state.keys.update(id, &body).await? {
+    let principal = match authorize(&state, &headers, Role::Write).await {
+        Ok(principal) => principal,
+        Err(resp) => return Ok(resp),
+    };
+    Ok(match state.keys.update(&principal, id, &body).await? {
         Some(info) => Json(info).into_response(),
         None => StatusCode::NOT_FOUND.into_response(),
     })
 }
 
 pub async fn usage_summary(State(state): State<AppState>, headers: HeaderMap) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
+    if let Err(resp) = authorize(&state, &headers, Role::Read).await {
         return Ok(resp);
     }
     Ok(Json(usage::summary(&state.pool).await?).into_response())
@@ -145,7 +186,7 @@ pub async fn sync_prices(
     headers: HeaderMap,
     Json(prices): Json<Vec<ModelPrice>>,
 ) -> Result<Response> {
-    if let Err(resp) = authorize(&state, &headers) {
+    if let Err(resp) = authorize(&state, &headers, Role::Write).await {
         return Ok(resp);
     }
     let written = pricing::upsert(&state.pool, &prices).await?;
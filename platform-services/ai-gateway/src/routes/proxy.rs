@@ -12,8 +12,9 @@ use open_feature::EvaluationContext;
 use tracing::{Instrument, Span, field};
 
 use crate::{
-    config::Resolved,
+    config::{Fit, Resolved},
     error::{GatewayError, Result},
+    idempotency::{self, Outcome},
     keys::VirtualKey,
     metrics,
     providers::{
@@ -22,7 +23,8 @@ use crate::{
     },
     response_cache::{self, CachedResponse},
     state::AppState,
-    usage::{self, UsageEvent},
+    structured_output::{self, OutputSchema},
+    usage::UsageEvent,
 };
 
 const ENABLED_FLAG: &str = "ai-gateway-enabled";
@@ -92,6 +94,8 @@ struct RequestContext {
     provider: Arc<dyn Provider>,
     requested_model: String,
     resolved_model: String,
+    /// When the request arrived, for the recorded latency.
+    started: Instant,
 }
 
 #[tracing::instrument(
@@ -152,34 +156,79 @@ async fn proxy(
         }
     }
 
-    // The runtime flag wins as a global override; otherwise the config rules resolve the
-    // model and may pin a provider or deny the request outright.
-    let override_model = state
-        .features
-        .string_flag(MODEL_OVERRIDE_FLAG, evaluation_context.clone(), "")
-        .await;
-    let (resolved_model, pinned_provider) = if !override_model.is_empty() {
-        (override_model, None)
-    } else {
-        match state.config.resolve(&key.name, &requested_model) {
-            Resolved::Route { model, provider } => (model, provider),
-            Resolved::Denied => return Err(GatewayError::ModelDenied(requested_model)),
-        }
-    };
-
-    span.record("resolved_model", resolved_model.as_str());
+    let (mut resolved_model, mut pinned_provider) =
+        resolve_model(&state, &key.name, &requested_model, &evaluation_context).await?;
 
     let kind = ModelKind::for_sub_path(sub_path);
     let client_dialect = Dialect::for_sub_path(sub_path);
 
+    // Key policy is applied before the cache key and any translation, so cached entries
+    // reflect the rewritten body and both dialects see the same policy.
+    if kind == ModelKind::Chat
+        && let Some(policy) = state.config.key(&key.name).filter(|k| k.has_policy())
+    {
+        request.apply_policy(client_dialect, policy);
+    }
+
+    // Checked against the capability table before any provider is contacted, so an
+    // over-length request moves to its overflow model or fails without upstream retries.
+    if kind == ModelKind::Chat {
+        let input = request.estimate_input_tokens();
+        match state
+            .config
+            .fit(&resolved_model, input, request.max_output_tokens())
+        {
+            Fit::Fits => {}
+            Fit::Overflow {
+                model,
+                tokens,
+                limit,
+            } => {
+                // The overflow model must clear the same key and rule checks as the
+                // requested one; if it can't, the request is just too large.
+                let provider = match state.config.resolve(&key.name, &model) {
+                    Resolved::Route {
+                        model: routed,
+                        provider,
+                    } if routed == model && key.allows(&model) => provider,
+                    _ => {
+                        return Err(GatewayError::ContextLengthExceeded {
+                            model: resolved_model,
+                            tokens,
+                            limit,
+                        });
+                    }
+                };
+                tracing::info!(
+                    from = %resolved_model,
+                    to = %model,
+                    estimated_input_tokens = input,
+                    "request exceeds model limits; overflowing to larger model"
+                );
+                resolved_model = model;
+                pinned_provider = provider;
+            }
+            Fit::Exceeds {
+                model,
+                tokens,
+                limit,
+            } => {
+                return Err(GatewayError::ContextLengthExceeded {
+                    model,
+                    tokens,
+                    limit,
+                });
+            }
+        }
+    }
+
+    span.record("resolved_model", resolved_model.as_str());
+
     if resolved_model != requested_model {
         request.set_model(&resolved_model);
     }
 
-    let candidates = match &pinned_provider {
-        Some(name) => state.providers.get(name).into_iter().collect(),
-        None => state.providers.providers_for_model(&resolved_model, kind),
-    };
+    let candidates = candidates_for(&state, &resolved_model, pinned_provider.as_deref(), kind);
 
     let Some(primary) = candidates.first().cloned() else {
         return Err(GatewayError::NoProvider(resolved_model));
@@ -195,11 +244,31 @@ async fn proxy(
         provider: primary.clone(),
         requested_model,
         resolved_model,
+        started,
     };
 
+    // Schema enforcement needs the whole reply, so a stream can't be checked before the
+    // client has it; a key-level schema simply doesn't apply to streams.
+    let schema = match kind {
+        ModelKind::Chat => OutputSchema::for_request(&headers, state.config.key(&ctx.key.name))?,
+        ModelKind::Embedding => None,
+    };
+    let schema = match schema {
+        Some(_) if streaming && headers.contains_key(structured_output::SCHEMA_HEADER) => {
+            return Err(GatewayError::BadRequest(format!(
+                "{} is not supported for streaming requests",
+                structured_output::SCHEMA_HEADER
+            )));
+        }
+        Some(_) if streaming => None,
+        schema => schema,
+    };
+
+    // A cached reply was never checked against this request's schema, so schema-bound
+    // requests bypass the cache entirely.
     let cache_key = if !streaming
+        && schema.is_none()
         && request.is_cacheable(kind)
-        && state.cache.is_some()
         && state
             .features
             .bool_flag(RESPONSE_CACHE_FLAG, evaluation_context, true)
@@ -214,8 +283,8 @@ async fn proxy(
         None
     };
 
-    if let (Some(cache), Some(k)) = (&state.cache, &cache_key)
-        && let Some(hit) = response_cache::get(cache, k).await
+    if let Some(k) = &cache_key
+        && let Some(hit) = response_cache::get(&state.cache, k).await
     {
         span.record("provider", "cache");
         let usage = Usage {
@@ -223,18 +292,173 @@ async fn proxy(
             output: hit.output_tokens,
         };
         let status = hit.status;
-        record(&state, &ctx, usage, status, started, true, None, None).await;
+        record(&state, &ctx, usage, status, true, None, None).await;
         return Ok(hit.into_response());
     }
 
+    // Streams can't be replayed, so the header only applies to buffered requests. The
+    // fingerprint is of the client's own body, before any policy rewrite.
+    let mut claim = match headers.get(idempotency::HEADER) {
+        Some(id) if !streaming => {
+            match idempotency::begin(&state.cache, ctx.key.id, id, &body).await? {
+                Outcome::Replay(replay) => {
+                    span.record("provider", "idempotency");
+                    // Tokens and cost were recorded against the original request.
+                    let status = replay.status;
+                    record(&state, &ctx, Usage::default(), status, true, None, None).await;
+                    let mut response = replay.into_response();
+                    response
+                        .headers_mut()
+                        .insert("idempotent-replayed", HeaderValue::from_static("true"));
+                    return Ok(response);
+                }
+                Outcome::Claimed(claim) => Some(claim),
+            }
+        }
+        _ => None,
+    };
+
+    // Attempts re-ask the same provider chain; each is recorded, since each is billed.
+    let mut attempt = 1;
+    loop {
+        let (provider, response, request_body) = send_upstream(
+            &state,
+            &candidates,
+            &request,
+            client_dialect,
+            kind,
+            &headers,
+            &ctx,
+        )
+        .await?;
+
+        span.record("provider", provider.name());
+        ctx.provider = provider.clone();
+
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .cloned()
+            .unwrap_or_else(|| HeaderValue::from_static("application/json"));
+
+        if streaming {
+            return Ok(stream_response(
+                state,
+                ctx,
+                client_dialect,
+                status,
+                content_type,
+                response,
+                request_body,
+            ));
+        }
+
+        let bytes = response.bytes().await?;
+        let usage = provider.parse_usage(&bytes);
+
+        // Error bodies aren't in the chat/messages schema, so only successful ones are
+        // translated back to the client's dialect.
+        let client_bytes = if provider.dialect() == client_dialect || !status.is_success() {
+            bytes.clone()
+        } else {
+            translate::translate_response(&bytes, provider.dialect(), client_dialect)?
+        };
+
+        let violation = match &schema {
+            Some(schema) if status.is_success() => {
+                schema.check(client_dialect, &client_bytes).err()
+            }
+            _ => None,
+        };
+
+        // Only a successful reply that satisfied any schema is worth replaying.
+        let replayable = (status.is_success() && violation.is_none()).then(|| CachedResponse {
+            status: status.as_u16(),
+            content_type: content_type
+                .to_str()
+                .unwrap_or("application/json")
+                .to_owned(),
+            body: client_bytes.to_vec(),
+            input_tokens: usage.input,
+            output_tokens: usage.output,
+        });
+
+        if let (Some(k), Some(cached)) = (&cache_key, &replayable) {
+            response_cache::put(&state.cache, k, cached).await;
+        }
+
+        record(
+            &state,
+            &ctx,
+            usage,
+            status.as_u16(),
+            false,
+            Some(String::from_utf8_lossy(&request_body).into_owned()),
+            Some(String::from_utf8_lossy(&bytes).into_owned()),
+        )
+        .await;
+
+        if let (Some(violation), Some(schema)) = (violation, &schema) {
+            metrics::record_schema_violation(&ctx.key.name, &ctx.resolved_model);
+            if attempt > schema.max_retries {
+                return Err(GatewayError::SchemaValidation {
+                    attempts: attempt,
+                    errors: violation.errors.join("; "),
+                });
+            }
+            tracing::warn!(
+                attempt,
+                errors = ?violation.errors,
+                "response failed schema validation; retrying"
+            );
+            request.push_message("assistant", &violation.content);
+            request.push_message("user", &violation.feedback());
+            attempt += 1;
+            continue;
+        }
+
+        if let (Some(claim), Some(cached)) = (claim.take(), &replayable) {
+            claim.complete(cached).await;
+        }
+
+        return Ok(Response::builder()
+            .status(status)
+            .header("content-type", content_type)
+            .header(
+                "x-cache",
+                if cache_key.is_some() {
+                    "MISS"
+                } else {
+                    "BYPASS"
+                },
+            )
+            .body(Body::from(client_bytes))
+            .unwrap());
+    }
+}
+
+/// Sends `request` down the candidate chain, retrying each provider before failing over,
+/// and returns the provider that answered with its response and the body it was sent.
+async fn send_upstream(
+    state: &AppState,
+    candidates: &[Arc<dyn Provider>],
+    request: &ProxyRequest,
+    client_dialect: Dialect,
+    kind: ModelKind,
+    headers: &HeaderMap,
+    ctx: &RequestContext,
+) -> Result<(Arc<dyn Provider>, reqwest::Response, Bytes)> {
+    let model = ctx.resolved_model.as_str();
+    let streaming = request.is_stream();
     // `fallback` keeps the last retryable response so an exhausted failover still returns
     // a real upstream status rather than a synthetic error.
     let mut served: Option<(Arc<dyn Provider>, reqwest::Response, Bytes)> = None;
     let mut fallback: Option<(Arc<dyn Provider>, reqwest::Response, Bytes)> = None;
     let mut last_err: Option<GatewayError> = None;
 
-    'failover: for provider in &candidates {
-        let outbound = outbound_for(&request, client_dialect, provider.dialect())?;
+    'failover: for provider in candidates {
+        let outbound = outbound_for(request, client_dialect, provider.dialect())?;
         // Carries an upstream `Retry-After` from the previous attempt to the next sleep.
         let mut retry_after: Option<Duration> = None;
         for attempt in 0..MAX_ATTEMPTS_PER_PROVIDER {
@@ -247,15 +471,25 @@ async fn proxy(
             let upstream_span = tracing::info_span!(
                 "upstream.request",
                 provider = provider.name(),
-                model = %ctx.resolved_model,
+                model = %model,
                 attempt = attempt + 1,
             );
-            let mut request = provider.build_request(&state.http, kind, outbound.clone(), &headers);
+            let mut request = provider.build_request(&state.http, kind, outbound.clone(), headers);
             if !streaming {
                 request = request.timeout(REQUEST_TIMEOUT);
             }
 
-            match request.send().instrument(upstream_span).await {
+            let attempt_started = Instant::now();
+            let result = request.send().instrument(upstream_span).await;
+            // Time to response headers, so streamed and buffered attempts compare fairly.
+            state.providers.stats().record(
+                provider.name(),
+                model,
+                attempt_started.elapsed(),
+                matches!(&result, Ok(resp) if !is_retryable(resp.status())),
+            );
+
+            match result {
                 Ok(resp) if is_retryable(resp.status()) => {
                     metrics::record_upstream_error(provider.name());
                     // Honor Retry-After on 429: a short wait retries this provider, a long
@@ -282,94 +516,146 @@ async fn proxy(
         }
     }
 
-    let (provider, response, request_body) = match served.or(fallback) {
-        Some(v) => v,
-        None => {
-            return Err(
-                last_err.unwrap_or_else(|| GatewayError::NoProvider(ctx.resolved_model.clone()))
-            );
-        }
-    };
+    served
+        .or(fallback)
+        .ok_or_else(|| last_err.unwrap_or_else(|| GatewayError::NoProvider(model.to_owned())))
+}
 
-    span.record("provider", provider.name());
-    ctx.provider = provider.clone();
+/// The runtime flag wins as a global override; otherwise the config rules resolve the
+/// model and may pin a provider or deny the request outright.
+async fn resolve_model(
+    state: &AppState,
+    key_name: &str,
+    requested_model: &str,
+    evaluation_context: &EvaluationContext,
+) -> Result<(String, Option<String>)> {
+    let override_model = state
+        .features
+        .string_flag(MODEL_OVERRIDE_FLAG, evaluation_context.clone(), "")
+        .await;
+    if !override_model.is_empty() {
+        return Ok((override_model, None));
+    }
+    match state.config.resolve(key_name, requested_model) {
+        Resolved::Route { model, provider } => Ok((model, provider)),
+        Resolved::Denied => Err(GatewayError::ModelDenied(requested_model.to_owned())),
+    }
+}
 
-    let status = response.status();
-    let content_type = response
-        .headers()
-        .get("content-type")
-        .cloned()
-        .unwrap_or_else(|| HeaderValue::from_static("application/json"));
+/// Providers to try for `model`, in order: just the pinned provider when a `route` rule
+/// named one, otherwise every provider serving the model per its routing strategy.
+fn candidates_for(
+    state: &AppState,
+    model: &str,
+    pinned: Option<&str>,
+    kind: ModelKind,
+) -> Vec<Arc<dyn Provider>> {
+    match pinned {
+        Some(name) => state.providers.get(name).into_iter().collect(),
+        None => state.providers.select(model, kind, &state.pricing),
+    }
+}
 
-    if streaming {
-        Ok(stream_response(
-            state,
-            ctx,
-            client_dialect,
-            status,
-            content_type,
-            response,
-            started,
-            request_body,
-        ))
-    } else {
-        let bytes = response.bytes().await?;
-        let usage = provider.parse_usage(&bytes);
+/// `/v1/messages/count_tokens`: sizes an Anthropic-dialect prompt for the model it would
+/// route to. Exact when an Anthropic-dialect provider serves that model, estimated
+/// otherwise (or when the count call itself fails); the `x-token-count` header says which.
+/// Counting is free, so nothing is recorded against the key's usage.
+#[tracing::instrument(
+    skip_all,
+    fields(otel.name = "proxy /v1/messages/count_tokens", key = field::Empty)
+)]
+pub async fn count_tokens(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response> {
+    let raw_key = bearer(&headers).ok_or(GatewayError::MissingKey)?;
+    let key = state.keys.authenticate(raw_key).await?;
+    Span::current().record("key", key.name.as_str());
 
-        // Error bodies aren't in the chat/messages schema, so only successful ones are
-        // translated back to the client's dialect.
-        let client_bytes = if provider.dialect() == client_dialect || !status.is_success() {
-            bytes.clone()
-        } else {
-            translate::translate_response(&bytes, provider.dialect(), client_dialect)?
-        };
+    let mut request = ProxyRequest::from_slice(&body)?;
+    let requested_model = request.model()?.to_owned();
+    if !key.allows(&requested_model) {
+        return Err(GatewayError::ModelNotAllowed(
+            key.name.clone(),
+            requested_model,
+        ));
+    }
 
-        if let (Some(cache), Some(k)) = (&state.cache, &cache_key)
-            && status.is_success()
-        {
-            response_cache::put(
-                cache,
-                k,
-                &CachedResponse {
-                    status: status.as_u16(),
-                    content_type: content_type
-                        .to_str()
-                        .unwrap_or("application/json")
-                        .to_owned(),
-                    body: client_bytes.to_vec(),
-                    input_tokens: usage.input,
-                    output_tokens: usage.output,
-                },
-            )
-            .await;
-        }
+    let evaluation_context = EvaluationContext::default()
+        .with_targeting_key(&key.name)
+        .with_custom_field("key", key.name.clone())
+        .with_custom_field("requested_model", requested_model.clone());
+    let (resolved_model, pinned_provider) =
+        resolve_model(&state, &key.name, &requested_model, &evaluation_context).await?;
+    if resolved_model != requested_model {
+        request.set_model(&resolved_model);
+    }
 
-        record(
-            &state,
-            &ctx,
-            usage,
-            status.as_u16(),
-            started,
-            false,
-            Some(String::from_utf8_lossy(&request_body).into_owned()),
-            Some(String::from_utf8_lossy(&bytes).into_owned()),
-        )
-        .await;
+    // Only the system prompt counts towards the input; default and forced parameters
+    // aren't part of the prompt, and the count endpoint rejects unknown fields.
+    if let Some(prompt) = state
+        .config
+        .key(&key.name)
+        .and_then(|k| k.system_prompt.as_ref())
+    {
+        request.inject_system(Dialect::Anthropic, &prompt.text, prompt.position);
+    }
 
-        Ok(Response::builder()
-            .status(status)
-            .header("content-type", content_type)
-            .header(
-                "x-cache",
-                if cache_key.is_some() {
-                    "MISS"
-                } else {
-                    "BYPASS"
-                },
-            )
-            .body(Body::from(client_bytes))
-            .unwrap())
+    let counter = candidates_for(
+        &state,
+        &resolved_model,
+        pinned_provider.as_deref(),
+        ModelKind::Chat,
+    )
+    .into_iter()
+    .find(|p| p.dialect() == Dialect::Anthropic);
+
+    if let Some(provider) = counter
+        && let Some(upstream) =
+            provider.build_count_tokens_request(&state.http, request.to_bytes()?, &headers)
+    {
+        match upstream.timeout(REQUEST_TIMEOUT).send().await {
+            Ok(resp) if !is_retryable(resp.status()) => {
+                let status = resp.status();
+                let content_type = resp
+                    .headers()
+                    .get("content-type")
+                    .cloned()
+                    .unwrap_or_else(|| HeaderValue::from_static("application/json"));
+                return Ok(Response::builder()
+                    .status(status)
+                    .header("content-type", content_type)
+                    .header("x-token-count", "exact")
+                    .body(Body::from(resp.bytes().await?))
+                    .unwrap());
+            }
+            Ok(resp) => {
+                metrics::record_upstream_error(provider.name());
+                tracing::warn!(
+                    provider = provider.name(),
+                    status = resp.status().as_u16(),
+                    "token count failed upstream; estimating"
+                );
+            }
+            Err(e) => {
+                metrics::record_upstream_error(provider.name());
+                tracing::warn!(
+                    provider = provider.name(),
+                    "token count failed upstream; estimating: {e}"
+                );
+            }
+        }
     }
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .header("x-token-count", "estimated")
+        .body(Body::from(
+            serde_json::json!({ "input_tokens": request.estimate_input_tokens() }).to_string(),
+        ))
+        .unwrap())
 }
 
 /// Serializes the request body in the dialect `provider` expects, translating from the
@@ -392,7 +678,6 @@ fn stream_response(
     status: StatusCode,
     content_type: HeaderValue,
     upstream: reqwest::Response,
-    started: Instant,
     request_body: Bytes,
 ) -> Response {
     let (mut tx, rx) = mpsc::channel::<std::result::Result<Bytes, std::io::Error>>(16);
@@ -476,7 +761,6 @@ fn stream_response(
                 &ctx,
                 usage,
                 outcome,
-                started,
                 false,
                 Some(String::from_utf8_lossy(&request_body).into_owned()),
                 Some(String::from_utf8_lossy(&raw).into_owned()),
@@ -498,12 +782,11 @@ async fn record(
     ctx: &RequestContext,
     usage: Usage,
     status: u16,
-    started: Instant,
     cache_hit: bool,
     request_body: Option<String>,
     response_body: Option<String>,
 ) {
-    let elapsed = started.elapsed();
+    let elapsed = ctx.started.elapsed();
     let cost_usd = if cache_hit {
         0.0
     } else {
@@ -538,25 +821,22 @@ async fn record(
         elapsed,
     );
     metrics::record_cost(&ctx.key.name, &ctx.resolved_model, cost_usd);
-    usage::record(
-        &state.pool,
-        &UsageEvent {
-            key_id: Some(ctx.key.id),
-            key_name: ctx.key.name.clone(),
-            provider: ctx.provider.name().to_owned(),
-            requested_model: ctx.requested_model.clone(),
-            resolved_model: ctx.resolved_model.clone(),
-            input_tokens: usage.input,
-            output_tokens: usage.output,
-            latency_ms: elapsed.as_millis() as i64,
-            status: status as i32,
-            cost_usd,
-            cache_hit,
-            request_body,
-            response_body,
-        },
-    )
-    .await;
+    state.usage.record(UsageEvent {
+        created_at: chrono::Utc::now(),
+        key_id: Some(ctx.key.id),
+        key_name: ctx.key.name.clone(),
+        provider: ctx.provider.name().to_owned(),
+        requested_model: ctx.requested_model.clone(),
+        resolved_model: ctx.resolved_model.clone(),
+        input_tokens: usage.input,
+        output_tokens: usage.output,
+        latency_ms: elapsed.as_millis() as i64,
+        status: status as i32,
+        cost_usd,
+        cache_hit,
+        request_body,
+        response_body,
+    });
 }
 
 fn bearer(headers: &HeaderMap) -> Option<&str> {
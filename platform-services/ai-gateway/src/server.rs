@@ -11,6 +11,10 @@ pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/health", get(|| async { StatusCode::OK }))
         .route("/v1/messages", post(routes::proxy::messages))
+        .route(
+            "/v1/messages/count_tokens",
+            post(routes::proxy::count_tokens),
+        )
         .route(
             "/v1/chat/completions",
             post(routes::proxy::chat_completions),
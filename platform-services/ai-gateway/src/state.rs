@@ -3,8 +3,8 @@ use std::time::Duration;
 use sqlx::PgPool;
 
 use crate::{
-    cache::CacheClient, config::Config, feature_flag::FeatureFlagClient, keys::KeyStore,
-    pricing::Pricing, providers::Registry,
+    admin_auth::OidcVerifier, cache::CacheClient, config::Config, feature_flag::FeatureFlagClient,
+    keys::KeyStore, pricing::Pricing, providers::Registry, usage::UsageQueue,
 };
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
@@ -19,10 +19,15 @@ pub struct AppState {
     pub keys: KeyStore,
     pub features: FeatureFlagClient,
     pub pricing: Pricing,
-    /// Shared response/cache store, also handed to [`KeyStore`]. `None` when `REDIS_URL`
-    /// is unset, in which case response caching is off and lookups hit Postgres.
-    pub cache: Option<CacheClient>,
+    /// Shared response/cache store, also handed to [`KeyStore`]. In-process only when
+    /// `REDIS_URL` is unset; while Dragonfly is unreachable, in-process entries stand in.
+    pub cache: CacheClient,
     pub http: reqwest::Client,
+    /// Admin token verifier; `None` unless `admin.oidc` is configured, leaving only the
+    /// shared `ADMIN_TOKEN`.
+    pub oidc: Option<OidcVerifier>,
+    /// Batched usage writer; call [`UsageQueue::shutdown`] on exit to drain it.
+    pub usage: UsageQueue,
 }
 
 impl AppState {
@@ -32,7 +37,7 @@ impl AppState {
         pool: PgPool,
         features: FeatureFlagClient,
         pricing: Pricing,
-        cache: Option<CacheClient>,
+        cache: CacheClient,
     ) -> Self {
         // No total deadline (streams run long), but a stalled connection — no bytes for
         // IDLE_TIMEOUT — fails so it can't pin a task and client connection forever.
@@ -42,8 +47,16 @@ impl AppState {
             .build()
             .expect("failed to build http client");
 
+        let oidc = config
+            .admin
+            .oidc
+            .clone()
+            .map(|oidc| OidcVerifier::new(oidc, http.clone()));
+
         Self {
+            oidc,
             keys: KeyStore::new(pool.clone(), cache.clone()),
+            usage: UsageQueue::spawn(pool.clone(), UsageQueue::spill_path_from_env()),
             config,
             providers,
             pool,
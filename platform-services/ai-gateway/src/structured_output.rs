@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use axum::http::HeaderMap;
+use jsonschema::Validator;
+use serde_json::Value;
+
+use crate::config::KeyConfig;
+use crate::error::{GatewayError, Result};
+use crate::providers::Dialect;
+
+/// A JSON Schema (as JSON) the reply must satisfy, overriding the key's configured one.
+pub const SCHEMA_HEADER: &str = "x-aig-response-schema";
+
+/// Re-asks allowed after the first attempt, overriding the key's `max_retries`.
+pub const RETRIES_HEADER: &str = "x-aig-response-schema-retries";
+
+const DEFAULT_RETRIES: u32 = 2;
+
+/// Each attempt is a full upstream call billed to the key, so a client can't ask for
+/// more than this however it sets the header.
+const MAX_RETRIES: u32 = 5;
+
+/// Violations quoted back to the model and into the error, so a schema with thousands of
+/// failing array items doesn't produce a thousand-line prompt.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// A compiled response schema for one request. Only buffered chat responses are checked:
+/// a stream has already reached the client by the time it could be validated.
+pub struct OutputSchema {
+    validator: Arc<Validator>,
+    pub max_retries: u32,
+}
+
+/// A reply that failed validation, kept so the retry can show the model what it said.
+#[derive(Debug)]
+pub struct Violation {
+    pub content: String,
+    pub errors: Vec<String>,
+}
+
+impl Violation {
+    /// The follow-up user turn sent with a retry.
+    pub fn feedback(&self) -> String {
+        format!(
+            "Your previous reply was not valid JSON matching the required schema:\n- {}\n\
+             Reply again with only the corrected JSON.",
+            self.errors.join("\n- ")
+        )
+    }
+}
+
+impl OutputSchema {
+    /// The schema for this request: the header when present, otherwise the key's
+    /// `response_schema`, compiled when the config loaded. A malformed header or header
+    /// schema is the client's error.
+    pub fn for_request(headers: &HeaderMap, key: Option<&KeyConfig>) -> Result<Option<Self>> {
+        let configured = key.and_then(|k| k.response_schema.as_ref());
+        let header = |name| {
+            headers
+                .get(name)
+                .map(|v| {
+                    v.to_str()
+                        .map_err(|_| GatewayError::BadRequest(format!("{name} is not valid text")))
+                })
+                .transpose()
+        };
+
+        let validator = match header(SCHEMA_HEADER)? {
+            Some(raw) => {
+                let schema = serde_json::from_str(raw).map_err(|e| {
+                    GatewayError::BadRequest(format!("{SCHEMA_HEADER} is not valid JSON: {e}"))
+                })?;
+                Arc::new(compile(&schema).map_err(GatewayError::BadRequest)?)
+            }
+            None => match configured {
+                Some(configured) => configured.validator.clone(),
+                None => return Ok(None),
+            },
+        };
+        let max_retries = match header(RETRIES_HEADER)? {
+            Some(raw) => raw.parse().map_err(|_| {
+                GatewayError::BadRequest(format!("{RETRIES_HEADER} must be a whole number"))
+            })?,
+            None => configured.map_or(DEFAULT_RETRIES, |c| c.max_retries),
+        };
+
+        Ok(Some(Self {
+            validator,
+            max_retries: max_retries.min(MAX_RETRIES),
+        }))
+    }
+
+    /// Validates the assistant text of a successful response body in `dialect`.
+    pub fn check(&self, dialect: Dialect, body: &[u8]) -> std::result::Result<(), Violation> {
+        let content = serde_json::from_slice(body)
+            .ok()
+            .and_then(|body: Value| response_text(dialect, &body))
+            .unwrap_or_default();
+
+        let errors = match serde_json::from_str::<Value>(strip_fences(&content)) {
+            Ok(instance) => self
+                .validator
+                .iter_errors(&instance)
+                .take(MAX_REPORTED_ERRORS)
+                .map(|e| match e.instance_path().as_str() {
+                    "" => e.to_string(),
+                    path => format!("{path}: {e}"),
+                })
+                .collect(),
+            Err(e) => vec![format!("not JSON: {e}")],
+        };
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Violation { content, errors })
+        }
+    }
+}
+
+/// Compiles a schema, with a message fit for a 400 or a config error.
+pub fn compile(schema: &Value) -> std::result::Result<Validator, String> {
+    jsonschema::validator_for(schema).map_err(|e| format!("invalid response schema: {e}"))
+}
+
+/// The assistant's text: Anthropic's text blocks joined, or OpenAI's first choice.
+fn response_text(dialect: Dialect, body: &Value) -> Option<String> {
+    match dialect {
+        Dialect::Anthropic => Some(
+            body.get("content")?
+                .as_array()?
+                .iter()
+                .filter(|block| block.get("type").and_then(Value::as_str) == Some("text"))
+                .filter_map(|block| block.get("text").and_then(Value::as_str))
+                .collect(),
+        ),
+        Dialect::OpenAiCompatible => body
+            .pointer("/choices/0/message/content")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+    }
+}
+
+/// Models often wrap JSON in a markdown code fence even when told not to; the fence is
+/// presentation, not a schema violation.
+fn strip_fences(text: &str) -> &str {
+    let text = text.trim();
+    let Some(inner) = text.strip_prefix("```") else {
+        return text;
+    };
+    let Some(inner) = inner.strip_suffix("```") else {
+        return text;
+    };
+    // Drop the info string (`json`) on the opening fence line.
+    inner
+        .split_once('\n')
+        .map_or(inner, |(_, body)| body)
+        .trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema(headers: &[(&'static str, &str)]) -> Result<Option<OutputSchema>> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap());
+        }
+        OutputSchema::for_request(&map, None)
+    }
+
+    fn openai(content: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": content } }]
+        }))
+        .unwrap()
+    }
+
+    const SUMMARY: &str =
+        r#"{"type":"object","required":["summary"],"properties":{"summary":{"type":"string"}}}"#;
+
+    #[test]
+    fn validates_reply_text_in_either_dialect() {
+        let schema = schema(&[(SCHEMA_HEADER, SUMMARY)]).unwrap().unwrap();
+        assert_eq!(schema.max_retries, DEFAULT_RETRIES);
+
+        assert!(
+            schema
+                .check(Dialect::OpenAiCompatible, &openai(r#"{"summary":"ok"}"#))
+                .is_ok()
+        );
+        let fenced = openai("```json\n{\"summary\": \"ok\"}\n```");
+        assert!(schema.check(Dialect::OpenAiCompatible, &fenced).is_ok());
+
+        let anthropic = serde_json::to_vec(&json!({
+            "content": [
+                { "type": "text", "text": "{\"summary\":" },
+                { "type": "text", "text": " 3}" },
+            ]
+        }))
+        .unwrap();
+        let violation = schema.check(Dialect::Anthropic, &anthropic).unwrap_err();
+        assert_eq!(violation.content, r#"{"summary": 3}"#);
+        assert_eq!(violation.errors, [r#"/summary: 3 is not of type "string""#]);
+
+        let prose = schema
+            .check(Dialect::OpenAiCompatible, &openai("Sure! Here it is."))
+            .unwrap_err();
+        assert!(prose.errors[0].starts_with("not JSON"), "{prose:?}");
+    }
+
+    #[test]
+    fn header_overrides_key_and_is_checked() {
+        let key: KeyConfig = serde_yaml::from_str(
+            "name: tldr-bot\nresponse_schema:\n  max_retries: 1\n  schema: { type: array }\n",
+        )
+        .unwrap();
+        let from_key = OutputSchema::for_request(&HeaderMap::new(), Some(&key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(from_key.max_retries, 1);
+        assert!(
+            from_key
+                .check(Dialect::OpenAiCompatible, &openai("[]"))
+                .is_ok()
+        );
+
+        let capped = schema(&[(SCHEMA_HEADER, SUMMARY), (RETRIES_HEADER, "50")]);
+        assert_eq!(capped.unwrap().unwrap().max_retries, MAX_RETRIES);
+
+        assert!(schema(&[]).unwrap().is_none());
+        assert!(schema(&[(SCHEMA_HEADER, "{not json")]).is_err());
+        assert!(schema(&[(SCHEMA_HEADER, r#"{"type":"nonsense"}"#)]).is_err());
+        assert!(schema(&[(SCHEMA_HEADER, SUMMARY), (RETRIES_HEADER, "-1")]).is_err());
+    }
+}
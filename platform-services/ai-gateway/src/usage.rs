@@ -1,13 +1,45 @@
-use chrono::NaiveDate;
-use serde::Serialize;
-use sqlx::PgPool;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::{error::Result, metrics};
+
+/// Events buffered between the proxy and the writer task. Past this, events go straight
+/// to the spill file rather than applying backpressure to requests.
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Rows per INSERT; 14 binds each keeps a full batch well under Postgres' 65535 limit.
+const BATCH_SIZE: usize = 500;
+
+/// A partial batch is written after this long, bounding how stale `/admin/usage` and
+/// budget checks can be.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Attempts per batch on transient errors before it is spilled; backoff doubles from
+/// `RETRY_BACKOFF`, so a batch gives up after ~2s — about a failover's worth.
+const MAX_ATTEMPTS: u32 = 4;
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Replays paused by a transient failure wait this long, doubling up to
+/// `MAX_REPLAY_BACKOFF`, so a down database isn't sent the spill every tick.
+const MAX_REPLAY_BACKOFF: Duration = Duration::from_secs(60);
 
 /// One billable interaction, written after the upstream response completes.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsageEvent {
+    /// When the request completed; written explicitly so queued and spilled events land
+    /// in the right billing period.
+    pub created_at: DateTime<Utc>,
     pub key_id: Option<Uuid>,
     pub key_name: String,
     pub provider: String,
@@ -27,35 +59,435 @@ pub struct UsageEvent {
     pub response_body: Option<String>,
 }
 
-/// Inserts a usage row. Logged-and-swallowed on failure: telemetry must never break
-/// the proxy path.
-#[tracing::instrument(skip_all, fields(otel.name = "usage.record"))]
-pub async fn record(pool: &PgPool, event: &UsageEvent) {
-    let result = sqlx::query!(
-        r#"INSERT INTO usage_events
-         (key_id, key_name, provider, requested_model, resolved_model,
-          input_tokens, output_tokens, latency_ms, status, cost_usd, cache_hit,
-          request_body, response_body)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"#,
-        event.key_id,
-        &event.key_name,
-        &event.provider,
-        &event.requested_model,
-        &event.resolved_model,
-        event.input_tokens,
-        event.output_tokens,
-        event.latency_ms,
-        event.status,
-        event.cost_usd,
-        event.cache_hit,
-        event.request_body.as_deref(),
-        event.response_body.as_deref(),
-    )
-    .execute(pool)
-    .await;
+/// Handle to the usage writer: a bounded queue drained by a background task that
+/// batch-inserts into `usage_events`. Batches that still fail after retries, and events
+/// arriving while the queue is full or closed, are appended to a local JSONL spill file
+/// and replayed once Postgres accepts writes again. Recording never blocks or fails the
+/// proxy path, and spill file I/O always runs on the blocking pool.
+#[derive(Clone)]
+pub struct UsageQueue {
+    tx: mpsc::Sender<UsageEvent>,
+    spill: Arc<Spill>,
+    shutdown: Arc<watch::Sender<bool>>,
+    writer: Arc<tokio::sync::Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl UsageQueue {
+    /// Starts the writer task. Must be called from within a Tokio runtime.
+    /// Without a `spill_path`, events Postgres can't take are dropped.
+    pub fn spawn(pool: PgPool, spill_path: Option<PathBuf>) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let spill = Arc::new(Spill::new(spill_path));
+        let writer = tokio::spawn(run(pool, rx, spill.clone(), shutdown_rx));
+
+        Self {
+            tx,
+            spill,
+            shutdown: Arc::new(shutdown),
+            writer: Arc::new(tokio::sync::Mutex::new(Some(writer))),
+        }
+    }
+
+    /// Spill file from `USAGE_SPILL_PATH`, on a volume that outlives the process so
+    /// spilled events survive a crash or restart.
+    pub fn spill_path_from_env() -> Option<PathBuf> {
+        let path = std::env::var_os("USAGE_SPILL_PATH").map(PathBuf::from);
+        if path.is_none() {
+            tracing::warn!("USAGE_SPILL_PATH is unset; usage Postgres can't take is dropped");
+        }
+        path
+    }
+
+    /// Queues an event for the writer.
+    pub fn record(&self, event: UsageEvent) {
+        if let Err(e) = self.tx.try_send(event) {
+            let event = match e {
+                mpsc::error::TrySendError::Full(event) => {
+                    tracing::warn!("usage queue full; spilling event to disk");
+                    event
+                }
+                mpsc::error::TrySendError::Closed(event) => event,
+            };
+            let spill = self.spill.clone();
+            tokio::task::spawn_blocking(move || spill.append(std::slice::from_ref(&event)));
+        }
+    }
+
+    /// Stops accepting events, writes everything still queued, and waits for the writer
+    /// to finish. Later calls return immediately.
+    pub async fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        if let Some(writer) = self.writer.lock().await.take()
+            && let Err(e) = writer.await
+        {
+            tracing::error!("usage writer task failed: {e}");
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(otel.name = "usage.writer"))]
+async fn run(
+    pool: PgPool,
+    mut rx: mpsc::Receiver<UsageEvent>,
+    spill: Arc<Spill>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut replay = Replay::new(spill.clone());
+    let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some(event) => {
+                    batch.push(event);
+                    if batch.len() >= BATCH_SIZE {
+                        flush(&pool, &spill, &mut batch).await;
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => {
+                flush(&pool, &spill, &mut batch).await;
+                replay.run(&pool).await;
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // Closing keeps what's already buffered readable; anything recorded from here on
+    // goes straight to the spill file.
+    rx.close();
+    while let Some(event) = rx.recv().await {
+        batch.push(event);
+        if batch.len() >= BATCH_SIZE {
+            flush(&pool, &spill, &mut batch).await;
+        }
+    }
+    flush(&pool, &spill, &mut batch).await;
+    tracing::info!("usage queue drained");
+}
+
+/// Writes and clears `batch`, retrying transient failures and spilling what can't be
+/// written.
+async fn flush(pool: &PgPool, spill: &Arc<Spill>, batch: &mut Vec<UsageEvent>) {
+    if batch.is_empty() {
+        return;
+    }
+    let events = std::mem::replace(batch, Vec::with_capacity(BATCH_SIZE));
+
+    let mut backoff = RETRY_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let e = match insert(pool, &events).await {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        if !is_transient(&e) {
+            tracing::error!("usage batch rejected, inserting rows individually: {e}");
+            insert_each(pool, spill, events).await;
+            return;
+        }
+        if attempt == MAX_ATTEMPTS {
+            tracing::error!(
+                events = events.len(),
+                "failed to record usage batch, spilling to disk: {e}"
+            );
+            blocking(spill, move |spill| spill.append(&events)).await;
+            return;
+        }
+        tracing::warn!(attempt, "failed to record usage batch, retrying: {e}");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Re-inserts spilled events from the writer task. The spill file is claimed by renaming
+/// it aside, so new spills start a fresh file, and is written one attempt per batch: a
+/// transient failure leaves the claim untouched and backs off, and a rejected batch is
+/// retried row by row, as in [`flush`], so one bad row can't hold every other spilled
+/// event back. Progress is saved in a cursor next to the claim, and the claim stays held
+/// here until removing it succeeds, so rows are only sent twice if the process dies
+/// between a batch committing and its cursor being written.
+struct Replay {
+    spill: Arc<Spill>,
+    claim: Option<Claim>,
+    retry_at: Instant,
+    backoff: Duration,
+}
+
+impl Replay {
+    fn new(spill: Arc<Spill>) -> Self {
+        Self {
+            spill,
+            claim: None,
+            retry_at: Instant::now(),
+            backoff: FLUSH_INTERVAL,
+        }
+    }
+
+    async fn run(&mut self, pool: &PgPool) {
+        if Instant::now() < self.retry_at {
+            return;
+        }
+        if self.claim.is_none() {
+            self.claim = blocking(&self.spill, Spill::claim).await;
+        }
+        let Some(claim) = &mut self.claim else {
+            return;
+        };
+
+        while claim.done < claim.events.len() {
+            let end = (claim.done + BATCH_SIZE).min(claim.events.len());
+            let chunk = &claim.events[claim.done..end];
+            match insert(pool, chunk).await {
+                Ok(()) => {}
+                Err(e) if is_transient(&e) => {
+                    tracing::warn!(
+                        pending = claim.events.len() - claim.done,
+                        retry_in = ?self.backoff,
+                        "usage replay failed, keeping events spilled: {e}"
+                    );
+                    self.retry_at = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(MAX_REPLAY_BACKOFF);
+                    return;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "spilled usage batch rejected, inserting rows individually: {e}"
+                    );
+                    insert_each(pool, &self.spill, chunk.to_vec()).await;
+                }
+            }
+            claim.done = end;
+            blocking(&self.spill, move |spill| spill.save_cursor(end)).await;
+        }
+        self.backoff = FLUSH_INTERVAL;
+
+        let events = claim.events.len();
+        if blocking(&self.spill, Spill::release).await {
+            tracing::info!(events, "replayed spilled usage events");
+            self.claim = None;
+        }
+    }
+}
 
-    if let Err(e) = result {
-        tracing::error!("failed to record usage event: {e}");
+/// A permanent error means a bad row rather than a bad database: writing rows one at a
+/// time drops only the offending event.
+async fn insert_each(pool: &PgPool, spill: &Arc<Spill>, events: Vec<UsageEvent>) {
+    for event in events {
+        match insert(pool, std::slice::from_ref(&event)).await {
+            Ok(()) => {}
+            Err(e) if is_transient(&e) => {
+                blocking(spill, move |spill| spill.append(&[event])).await;
+            }
+            Err(e) => {
+                metrics::record_usage_dropped(1);
+                tracing::error!(key = event.key_name, "dropping usage event: {e}");
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(otel.name = "usage.insert", events = events.len()))]
+async fn insert(pool: &PgPool, events: &[UsageEvent]) -> std::result::Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "INSERT INTO usage_events \
+         (created_at, key_id, key_name, provider, requested_model, resolved_model, \
+          input_tokens, output_tokens, latency_ms, status, cost_usd, cache_hit, \
+          request_body, response_body) ",
+    );
+    query.push_values(events, |mut row, event| {
+        row.push_bind(event.created_at)
+            .push_bind(event.key_id)
+            .push_bind(&event.key_name)
+            .push_bind(&event.provider)
+            .push_bind(&event.requested_model)
+            .push_bind(&event.resolved_model)
+            .push_bind(event.input_tokens)
+            .push_bind(event.output_tokens)
+            .push_bind(event.latency_ms)
+            .push_bind(event.status)
+            .push_bind(event.cost_usd)
+            .push_bind(event.cache_hit)
+            .push_bind(event.request_body.as_deref())
+            .push_bind(event.response_body.as_deref());
+    });
+    query.build().execute(pool).await?;
+    Ok(())
+}
+
+/// Errors worth retrying: the database being unreachable, restarting, failing over or
+/// overloaded, as opposed to rejecting the rows themselves.
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::Protocol(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // Connection exceptions (08), insufficient resources (53), operator intervention
+        // such as admin shutdown (57P), and serialization/deadlock rollbacks (40).
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            ["08", "53", "57P", "40"]
+                .iter()
+                .any(|class| code.starts_with(class))
+        }),
+        _ => false,
+    }
+}
+
+/// Runs a spill file operation on the blocking pool.
+async fn blocking<T: Send + 'static>(
+    spill: &Arc<Spill>,
+    op: impl FnOnce(&Spill) -> T + Send + 'static,
+) -> T {
+    let spill = spill.clone();
+    tokio::task::spawn_blocking(move || op(&spill))
+        .await
+        .expect("usage spill task panicked")
+}
+
+/// Spilled events claimed for replay, of which the first `done` are written.
+struct Claim {
+    events: Vec<UsageEvent>,
+    done: usize,
+}
+
+/// Append-only JSONL file of events waiting to be written, plus the claim being replayed
+/// (`<path>.replay`) and its cursor (`<path>.replay.cursor`, the count of claimed events
+/// written). The mutex serialises appends from the writer task and a full queue with
+/// claiming. Only ever used on the blocking pool.
+struct Spill {
+    path: Option<PathBuf>,
+    lock: Mutex<()>,
+}
+
+impl Spill {
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn append(&self, events: &[UsageEvent]) {
+        let Some(path) = &self.path else {
+            metrics::record_usage_dropped(events.len() as u64);
+            tracing::error!(
+                events = events.len(),
+                "no usage spill file, dropping events"
+            );
+            return;
+        };
+        let _guard = self.lock.lock().unwrap();
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let mut buf = Vec::new();
+                for event in events {
+                    serde_json::to_writer(&mut buf, event)?;
+                    buf.push(b'\n');
+                }
+                file.write_all(&buf)
+            });
+
+        match result {
+            Ok(()) => metrics::record_usage_spilled(events.len() as u64),
+            Err(e) => {
+                metrics::record_usage_dropped(events.len() as u64);
+                tracing::error!(
+                    events = events.len(),
+                    path = %path.display(),
+                    "failed to spill usage events, dropping them: {e}"
+                );
+            }
+        }
+    }
+
+    fn sibling(&self, suffix: &str) -> Option<PathBuf> {
+        let mut path = OsString::from(self.path.as_ref()?);
+        path.push(suffix);
+        Some(path.into())
+    }
+
+    /// The claim left by an earlier process, resuming at its cursor, or else the spill
+    /// file renamed into a new claim. Lines that don't parse (a write torn by a crash)
+    /// are skipped.
+    fn claim(&self) -> Option<Claim> {
+        let (path, claim, cursor) = (
+            self.path.as_ref()?,
+            self.sibling(".replay")?,
+            self.sibling(".replay.cursor")?,
+        );
+        let result = (|| {
+            let _guard = self.lock.lock().unwrap();
+            let done = if claim.exists() {
+                match std::fs::read_to_string(&cursor) {
+                    Ok(done) => done.trim().parse().unwrap_or(0),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                    Err(e) => return Err(e),
+                }
+            } else {
+                // A cursor without its claim belongs to a claim already removed.
+                match std::fs::remove_file(&cursor) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                match std::fs::rename(path, &claim) {
+                    Ok(()) => 0,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            };
+            let events = BufReader::new(std::fs::File::open(&claim)?)
+                .lines()
+                .map_while(std::result::Result::ok)
+                .filter_map(|line| match serde_json::from_str(&line) {
+                    Ok(event) => Some(event),
+                    Err(e) => {
+                        tracing::warn!("skipping unreadable spilled usage event: {e}");
+                        None
+                    }
+                })
+                .collect();
+            Ok(Some(Claim { events, done }))
+        })();
+
+        result.unwrap_or_else(|e| {
+            tracing::error!(path = %claim.display(), "failed to claim usage spill: {e}");
+            None
+        })
+    }
+
+    /// Records that the first `done` claimed events are written. A failure only risks
+    /// resending them after a restart, so it is logged and replay carries on.
+    fn save_cursor(&self, done: usize) {
+        let Some(cursor) = self.sibling(".replay.cursor") else {
+            return;
+        };
+        if let Err(e) = std::fs::write(&cursor, done.to_string()) {
+            tracing::error!(path = %cursor.display(), "failed to save usage replay cursor: {e}");
+        }
+    }
+
+    /// Removes a fully written claim, then its cursor. False if the claim is still there.
+    fn release(&self) -> bool {
+        let (Some(claim), Some(cursor)) = (self.sibling(".replay"), self.sibling(".replay.cursor"))
+        else {
+            return true;
+        };
+        if let Err(e) = std::fs::remove_file(&claim) {
+            tracing::error!(path = %claim.display(), "failed to clear usage spill claim: {e}");
+            return false;
+        }
+        // A stale cursor is ignored by the next claim.
+        let _ = std::fs::remove_file(&cursor);
+        true
     }
 }
 
@@ -93,3 +525,72 @@ pub async fn summary(pool: &PgPool) -> Result<Vec<UsageRow>> {
     .await?;
     Ok(rows)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key_name: &str) -> UsageEvent {
+        UsageEvent {
+            created_at: Utc::now(),
+            key_id: None,
+            key_name: key_name.to_owned(),
+            provider: "anthropic".into(),
+            requested_model: "claude-fable-5".into(),
+            resolved_model: "claude-fable-5".into(),
+            input_tokens: 12,
+            output_tokens: 7,
+            latency_ms: 150,
+            status: 200,
+            cost_usd: 0.0,
+            cache_hit: false,
+            request_body: None,
+            response_body: None,
+        }
+    }
+
+    #[test]
+    fn spill_is_claimed_resumed_from_its_cursor_and_released() {
+        let path = std::env::temp_dir().join(format!("aig-spill-{}.jsonl", Uuid::new_v4()));
+        let spill = Spill::new(Some(path.clone()));
+        assert!(spill.claim().is_none());
+
+        spill.append(&[event("a"), event("b")]);
+        spill.append(&[event("c")]);
+        // A torn trailing write from a crash doesn't lose the lines before it.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"created_at\":")
+            .unwrap();
+
+        let claim = spill.claim().unwrap();
+        let names: Vec<_> = claim.events.iter().map(|e| e.key_name.as_str()).collect();
+        assert_eq!((names, claim.done), (vec!["a", "b", "c"], 0));
+        assert!(!path.exists());
+
+        // Spills during replay start a new file; a restarted replay resumes the claim.
+        spill.append(&[event("d")]);
+        spill.save_cursor(2);
+        let claim = spill.claim().unwrap();
+        assert_eq!((claim.events.len(), claim.done), (3, 2));
+
+        assert!(spill.release());
+        let claim = spill.claim().unwrap();
+        let names: Vec<_> = claim.events.iter().map(|e| e.key_name.as_str()).collect();
+        assert_eq!((names, claim.done), (vec!["d"], 0));
+        assert!(spill.release());
+        assert!(spill.claim().is_none());
+    }
+
+    #[test]
+    fn only_connection_level_errors_are_transient() {
+        assert!(is_transient(&sqlx::Error::PoolTimedOut));
+        assert!(is_transient(&sqlx::Error::Io(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset
+        ))));
+        assert!(!is_transient(&sqlx::Error::RowNotFound));
+        assert!(!is_transient(&sqlx::Error::ColumnNotFound("x".into())));
+    }
+}
@@ -7,12 +7,18 @@ use sqlx::PgPool;
 use wiremock::matchers::method;
 use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
-use ai_gateway::config::{Config, ProviderConfig, Rule};
+use ai_gateway::admin_auth::Principal;
+use ai_gateway::cache::CacheClient;
+use ai_gateway::config::{
+    Config, KeyConfig, ModelCapabilities, ProviderConfig, ResponseSchema, RoutingConfig, Rule,
+    SystemPrompt,
+};
 use ai_gateway::feature_flag::FeatureFlagClient;
 use ai_gateway::pricing::Pricing;
 use ai_gateway::providers::{Dialect, Registry};
 use ai_gateway::server;
 use ai_gateway::state::AppState;
+use ai_gateway::usage::{UsageEvent, UsageQueue};
 
 const API_KEY_ENV: &str = "AIG_TEST_UPSTREAM_KEY";
 
@@ -23,9 +29,18 @@ struct Fixture {
     #[serde(default)]
     key: KeyDef,
     request: Value,
+    /// Extra request headers, e.g. a per-request response schema.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Times the request is sent; the last response is snapshotted.
+    #[serde(default = "default_sends")]
+    sends: u32,
     upstream: Option<Upstream>,
     #[serde(default)]
     rules: Vec<Rule>,
+    /// Model capability table, for pre-flight context checks.
+    #[serde(default)]
+    models: HashMap<String, ModelCapabilities>,
 }
 
 #[derive(Deserialize)]
@@ -39,6 +54,10 @@ struct ProviderDef {
     embedding_models: Vec<String>,
 }
 
+fn default_sends() -> u32 {
+    1
+}
+
 fn default_provider_name() -> String {
     "test".into()
 }
@@ -49,6 +68,11 @@ struct KeyDef {
     allowed_models: Vec<String>,
     monthly_token_budget: Option<i64>,
     auth: String,
+    /// Request policy, configured for the fixture's key via [`KeyConfig`].
+    system_prompt: Option<SystemPrompt>,
+    defaults: serde_json::Map<String, Value>,
+    forced: serde_json::Map<String, Value>,
+    response_schema: Option<ResponseSchema>,
 }
 
 impl Default for KeyDef {
@@ -57,6 +81,10 @@ impl Default for KeyDef {
             allowed_models: Vec::new(),
             monthly_token_budget: None,
             auth: "valid".into(),
+            system_prompt: None,
+            defaults: serde_json::Map::new(),
+            forced: serde_json::Map::new(),
+            response_schema: None,
         }
     }
 }
@@ -142,6 +170,33 @@ fixture_test!(
 fixture_test!(messages_model_override, "messages", "model-override");
 fixture_test!(messages_model_denied, "messages", "model-denied");
 fixture_test!(messages_route_pinned, "messages", "route-pinned");
+fixture_test!(
+    messages_key_policy_to_openai_provider,
+    "messages",
+    "key-policy-to-openai-provider"
+);
+fixture_test!(messages_response_schema, "messages", "response-schema");
+fixture_test!(messages_context_overflow, "messages", "context-overflow");
+fixture_test!(
+    messages_context_overflow_not_allowed,
+    "messages",
+    "context-overflow-not-allowed"
+);
+fixture_test!(
+    messages_context_length_exceeded,
+    "messages",
+    "context-length-exceeded"
+);
+fixture_test!(
+    messages_count_tokens_anthropic_provider,
+    "messages",
+    "count-tokens-anthropic-provider"
+);
+fixture_test!(
+    messages_count_tokens_estimated,
+    "messages",
+    "count-tokens-estimated"
+);
 fixture_test!(
     messages_anthropic_streaming,
     "messages",
@@ -159,6 +214,13 @@ fixture_test!(
     "streaming-anthropic-provider"
 );
 fixture_test!(chat_openai_happy_path, "chat", "openai-happy-path");
+fixture_test!(chat_key_policy, "chat", "key-policy");
+fixture_test!(chat_idempotent_replay, "chat", "idempotent-replay");
+fixture_test!(
+    chat_response_schema_exhausted,
+    "chat",
+    "response-schema-exhausted"
+);
 fixture_test!(chat_no_provider_for_model, "chat", "no-provider-for-model");
 fixture_test!(
     chat_endpoint_to_anthropic_provider,
@@ -176,6 +238,46 @@ fixture_test!(
     "no-provider-for-model"
 );
 
+/// Events still queued when the gateway stops are written by `shutdown`, not lost, and
+/// keep the time they were recorded rather than the time of the insert.
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn usage_queue_drains_on_shutdown(pool: PgPool) {
+    let spill = std::env::temp_dir().join(format!("aig-it-{}.jsonl", uuid::Uuid::new_v4()));
+    let queue = UsageQueue::spawn(pool.clone(), Some(spill.clone()));
+    let recorded_at = chrono::Utc::now() - chrono::Duration::hours(1);
+    for n in 0..3 {
+        queue.record(UsageEvent {
+            created_at: recorded_at,
+            key_id: None,
+            key_name: "it-usage-queue".into(),
+            provider: "anthropic".into(),
+            requested_model: "claude-fable-5".into(),
+            resolved_model: "claude-fable-5".into(),
+            input_tokens: 10 + n,
+            output_tokens: 5,
+            latency_ms: 100,
+            status: 200,
+            cost_usd: 0.0,
+            cache_hit: false,
+            request_body: None,
+            response_body: None,
+        });
+    }
+    queue.shutdown().await;
+
+    let (rows, input_tokens, oldest): (i64, i64, chrono::DateTime<chrono::Utc>) = sqlx::query_as(
+        "SELECT COUNT(*), SUM(input_tokens)::bigint, MIN(created_at) \
+             FROM usage_events WHERE key_name = 'it-usage-queue'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!((rows, input_tokens), (3, 33));
+    assert_eq!(oldest.timestamp_micros(), recorded_at.timestamp_micros());
+    assert!(!spill.exists());
+}
+
 async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
     let snapshot_name = format!("{dir}__{file}");
     let content = std::fs::read_to_string(format!("tests/fixtures/{dir}/{file}.yaml")).unwrap();
@@ -212,11 +314,22 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
             fallback: false,
         },
     );
+    let key_name = format!("it-{snapshot_name}");
     let config = Config {
         admin_token: String::new(),
         providers,
-        keys: vec![],
+        keys: vec![KeyConfig {
+            name: key_name.clone(),
+            system_prompt: fixture.key.system_prompt.clone(),
+            defaults: fixture.key.defaults.clone(),
+            forced: fixture.key.forced.clone(),
+            response_schema: fixture.key.response_schema.clone(),
+            ..Default::default()
+        }],
         rules: fixture.rules.clone(),
+        routing: RoutingConfig::default(),
+        models: fixture.models.clone(),
+        ..Default::default()
     };
     let registry = Registry::from_config(&config);
 
@@ -225,14 +338,22 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
     // client at a bogus URL would instead block ~120s per test on a connect timeout before
     // falling back to the same defaults.
     let features = FeatureFlagClient::new(None).await;
-    let state = AppState::new(config, registry, pool, features, Pricing::default(), None);
+    let state = AppState::new(
+        config,
+        registry,
+        pool,
+        features,
+        Pricing::default(),
+        CacheClient::memory(),
+    );
 
     let token = match fixture.key.auth.as_str() {
         "valid" => {
             let (raw, _) = state
                 .keys
                 .create(
-                    &format!("it-{snapshot_name}"),
+                    &Principal::admin_token(),
+                    &key_name,
                     &fixture.key.allowed_models,
                     fixture.key.monthly_token_budget,
                 )
@@ -245,6 +366,7 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
         other => panic!("unknown auth mode: {other}"),
     };
 
+    let usage = state.usage.clone();
     let app = server::router(state);
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
@@ -252,13 +374,22 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
         axum::serve(listener, app).await.unwrap();
     });
 
-    let mut req = reqwest::Client::new()
-        .post(format!("http://{addr}{}", fixture.endpoint))
-        .json(&fixture.request);
-    if let Some(token) = &token {
-        req = req.bearer_auth(token);
+    let send = || {
+        let mut req = reqwest::Client::new()
+            .post(format!("http://{addr}{}", fixture.endpoint))
+            .json(&fixture.request);
+        if let Some(token) = &token {
+            req = req.bearer_auth(token);
+        }
+        for (name, value) in &fixture.headers {
+            req = req.header(name, value);
+        }
+        req.send()
+    };
+    for _ in 1..fixture.sends {
+        send().await.expect("request failed");
     }
-    let resp = req.send().await.expect("request failed");
+    let resp = send().await.expect("request failed");
 
     let streaming = fixture
         .request
@@ -281,6 +412,7 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
     }
 
     server_handle.abort();
+    usage.shutdown().await;
 
     let upstream_requests =
         capture_requests(&upstream.received_requests().await.unwrap_or_default());
@@ -303,7 +435,9 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
 /// responses are validated whole; streamed responses validate each SSE event. Runs on the
 /// real body, before snapshot redaction, so schema breaks surface independently of snapshots.
 fn validate_schema(endpoint: &str, streaming: bool, body: &Value) {
-    let schema_src = if endpoint.ends_with("/messages") {
+    let schema_src = if endpoint.ends_with("/count_tokens") {
+        include_str!("schemas/anthropic-count-tokens.json")
+    } else if endpoint.ends_with("/messages") {
         if streaming {
             include_str!("schemas/anthropic-stream-event.json")
         } else {
@@ -349,3 +483,50 @@ fn sse_events(body: &str) -> Value {
         .collect();
     Value::Array(events)
 }
+
+/// A spilled row Postgres will never accept (here, a NUL byte in a text column) is
+/// dropped on replay instead of keeping every other spilled event out of the table.
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn usage_replay_drops_rejected_rows(pool: PgPool) {
+    let spill = std::env::temp_dir().join(format!("aig-it-{}.jsonl", uuid::Uuid::new_v4()));
+    let event = |key_name: &str| UsageEvent {
+        created_at: chrono::Utc::now(),
+        key_id: None,
+        key_name: key_name.into(),
+        provider: "anthropic".into(),
+        requested_model: "claude-fable-5".into(),
+        resolved_model: "claude-fable-5".into(),
+        input_tokens: 10,
+        output_tokens: 5,
+        latency_ms: 100,
+        status: 200,
+        cost_usd: 0.0,
+        cache_hit: false,
+        request_body: None,
+        response_body: None,
+    };
+    let lines: Vec<String> = [
+        event("it-usage-replay"),
+        event("it-usage-replay\0"),
+        event("it-usage-replay"),
+    ]
+    .iter()
+    .map(|e| serde_json::to_string(e).unwrap() + "\n")
+    .collect();
+    std::fs::write(&spill, lines.concat()).unwrap();
+
+    // The writer's first tick replays the spill file straight away.
+    let queue = UsageQueue::spawn(pool.clone(), Some(spill.clone()));
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    queue.shutdown().await;
+
+    let rows: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM usage_events WHERE key_name = 'it-usage-replay'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(rows, 2);
+    assert!(!spill.exists());
+    assert!(!spill.with_extension("jsonl.replay").exists());
+}
@@ -0,0 +1,19 @@
+-- Flag changes queued for a future time. Deliberately has no bump trigger: queuing
+-- or cancelling a change doesn't alter evaluation; applying it writes the flag tables,
+-- whose own triggers bump the version.
+CREATE TABLE scheduled_changes (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    flag_key text NOT NULL,
+    -- `update_flag` or `set_flag_rules`; `payload` holds its arguments.
+    kind text NOT NULL,
+    payload jsonb NOT NULL,
+    apply_at timestamptz NOT NULL,
+    actor text NOT NULL,
+    status text NOT NULL DEFAULT 'pending',
+    error text NOT NULL DEFAULT '',
+    created_at timestamptz NOT NULL DEFAULT now(),
+    resolved_at timestamptz
+);
+
+CREATE INDEX scheduled_changes_due_idx ON scheduled_changes (apply_at) WHERE status = 'pending';
+CREATE INDEX scheduled_changes_flag_idx ON scheduled_changes (flag_key, apply_at);
@@ -0,0 +1,23 @@
+-- Progressive rollouts of one rule's distribution. No bump trigger, as for
+-- scheduled_changes: each step writes rule_distributions, whose trigger bumps.
+CREATE TABLE rollouts (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    flag_key text NOT NULL,
+    rule_rank integer NOT NULL,
+    -- `[{"distributions": [...], "hold_secs": n}, ...]`
+    steps jsonb NOT NULL,
+    -- The rule's distributions before the rollout, restored on abort.
+    original jsonb NOT NULL,
+    current_step integer NOT NULL DEFAULT 0,
+    step_started_at timestamptz NOT NULL DEFAULT now(),
+    paused_at timestamptz,
+    status text NOT NULL DEFAULT 'active',
+    actor text NOT NULL,
+    error text NOT NULL DEFAULT '',
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Two live rollouts on one rule would fight over its weights.
+CREATE UNIQUE INDEX rollouts_live_rule_idx ON rollouts (flag_key, rule_rank)
+    WHERE status IN ('active', 'paused');
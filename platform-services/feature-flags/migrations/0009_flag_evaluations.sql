@@ -0,0 +1,15 @@
+-- Evaluation counts per flag, served variant and calling client, bucketed by UTC day so
+-- usage can be read over a window. Written by each replica's periodic flush (server-side
+-- evaluations plus batches reported by local-mode clients), so rows are additive. No
+-- bump trigger: usage is not configuration.
+CREATE TABLE flag_evaluations (
+    flag_key text NOT NULL,
+    variant_key text NOT NULL,
+    client_id text NOT NULL,
+    day date NOT NULL,
+    count bigint NOT NULL,
+    last_evaluated_at timestamptz NOT NULL,
+    PRIMARY KEY (flag_key, variant_key, client_id, day)
+);
+
+CREATE INDEX flag_evaluations_day_idx ON flag_evaluations (day);
@@ -0,0 +1,29 @@
+-- Segment constraint groups reuse segment_constraints: rows with a group_index belong
+-- to that OR-group, rows without one are the segment's plain AND constraints.
+ALTER TABLE segment_constraints ADD COLUMN group_index integer;
+
+-- References to other segments by key. Like flag_rules.segment_key these aren't
+-- foreign keys: a dangling reference is allowed and simply never matches.
+CREATE TABLE segment_references (
+    segment_id uuid NOT NULL REFERENCES segments (id) ON DELETE CASCADE,
+    referenced_key text NOT NULL,
+    excluded boolean NOT NULL,
+    position integer NOT NULL,
+    PRIMARY KEY (segment_id, referenced_key, excluded)
+);
+
+-- A segment's targeting-key allowlist, one row per key so large lists can be edited
+-- incrementally.
+CREATE TABLE segment_targeting_keys (
+    segment_id uuid NOT NULL REFERENCES segments (id) ON DELETE CASCADE,
+    targeting_key text NOT NULL,
+    PRIMARY KEY (segment_id, targeting_key)
+);
+
+CREATE TRIGGER bump_on_segment_references
+AFTER INSERT OR UPDATE OR DELETE ON segment_references
+FOR EACH STATEMENT EXECUTE FUNCTION ff_bump_version();
+
+CREATE TRIGGER bump_on_segment_targeting_keys
+AFTER INSERT OR UPDATE OR DELETE ON segment_targeting_keys
+FOR EACH STATEMENT EXECUTE FUNCTION ff_bump_version();
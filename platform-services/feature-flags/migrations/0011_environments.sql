@@ -0,0 +1,63 @@
+-- Environments (namespaces) partition every flag, segment and audit row, and each
+-- carries its own config version. The pre-existing config lives in `default`.
+CREATE TABLE environments (
+    name text PRIMARY KEY CHECK (name ~ '^[a-z0-9][a-z0-9_-]*$'),
+    version bigint NOT NULL DEFAULT 1,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+INSERT INTO environments (name, version) SELECT 'default', version FROM config_version;
+
+ALTER TABLE flags ADD COLUMN environment text NOT NULL DEFAULT 'default'
+    REFERENCES environments (name);
+ALTER TABLE flags DROP CONSTRAINT flags_key_key;
+ALTER TABLE flags ADD CONSTRAINT flags_environment_key_key UNIQUE (environment, key);
+
+ALTER TABLE segments ADD COLUMN environment text NOT NULL DEFAULT 'default'
+    REFERENCES environments (name);
+ALTER TABLE segments DROP CONSTRAINT segments_key_key;
+ALTER TABLE segments ADD CONSTRAINT segments_environment_key_key UNIQUE (environment, key);
+
+ALTER TABLE flag_changes ADD COLUMN environment text NOT NULL DEFAULT 'default';
+DROP INDEX flag_changes_target_idx;
+DROP INDEX flag_changes_created_idx;
+CREATE INDEX flag_changes_target_idx
+    ON flag_changes (environment, target_kind, target_key, created_at DESC);
+CREATE INDEX flag_changes_created_idx ON flag_changes (environment, created_at DESC);
+
+ALTER TABLE scheduled_changes ADD COLUMN environment text NOT NULL DEFAULT 'default';
+DROP INDEX scheduled_changes_flag_idx;
+CREATE INDEX scheduled_changes_flag_idx ON scheduled_changes (environment, flag_key, apply_at);
+
+ALTER TABLE rollouts ADD COLUMN environment text NOT NULL DEFAULT 'default';
+DROP INDEX rollouts_live_rule_idx;
+CREATE UNIQUE INDEX rollouts_live_rule_idx ON rollouts (environment, flag_key, rule_rank)
+    WHERE status IN ('active', 'paused');
+
+ALTER TABLE flag_evaluations ADD COLUMN environment text NOT NULL DEFAULT 'default';
+ALTER TABLE flag_evaluations DROP CONSTRAINT flag_evaluations_pkey;
+ALTER TABLE flag_evaluations
+    ADD PRIMARY KEY (environment, flag_key, variant_key, client_id, day);
+
+-- The store names the environment a transaction writes with the transaction-local
+-- `ff.environment` setting, so the statement-level bump triggers advance only that
+-- environment's version. The notify payload is `<environment>:<version>`. A write
+-- made without the setting (by hand, or by a later migration) can't be attributed,
+-- so it bumps every environment and notifies `*`.
+CREATE OR REPLACE FUNCTION ff_bump_version() RETURNS trigger AS $$
+DECLARE
+    env text := NULLIF(current_setting('ff.environment', true), '');
+    v bigint;
+BEGIN
+    IF env IS NULL THEN
+        UPDATE environments SET version = version + 1;
+        PERFORM pg_notify('flag_changes', '*');
+    ELSE
+        UPDATE environments SET version = version + 1 WHERE name = env RETURNING version INTO v;
+        PERFORM pg_notify('flag_changes', env || ':' || v);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TABLE config_version;
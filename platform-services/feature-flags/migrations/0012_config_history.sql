@@ -0,0 +1,12 @@
+-- The full config of an environment at each version a store write produced, so any
+-- of them can be inspected, diffed against another, or rolled back to. Written by the
+-- writing transaction itself, before it commits. Versions produced before this table
+-- existed (or by writes made outside the store) have no row. The scheduler prunes
+-- versions past the configured retention (HISTORY_KEEP_VERSIONS, HISTORY_KEEP_DAYS).
+CREATE TABLE config_snapshots (
+    environment text NOT NULL REFERENCES environments (name),
+    version bigint NOT NULL,
+    snapshot jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (environment, version)
+);
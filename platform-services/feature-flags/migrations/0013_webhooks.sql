@@ -0,0 +1,37 @@
+-- Outbound webhooks notified of every config change in their environment. Like
+-- scheduled changes these have no bump trigger: registering a webhook doesn't alter
+-- evaluation.
+CREATE TABLE webhooks (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    environment text NOT NULL REFERENCES environments (name),
+    url text NOT NULL,
+    description text NOT NULL DEFAULT '',
+    -- HMAC-SHA256 key for the `X-Flag-Signature-256` header; only shown on creation.
+    secret text NOT NULL,
+    actor text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhooks_environment_idx ON webhooks (environment);
+
+-- One row per webhook per config version, queued by the writing transaction so a
+-- committed change is never missed, and worked off by every replica with SKIP LOCKED.
+CREATE TABLE webhook_deliveries (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    webhook_id uuid NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    version bigint NOT NULL,
+    payload jsonb NOT NULL,
+    -- `pending`, `delivered` or `failed` (attempts exhausted).
+    status text NOT NULL DEFAULT 'pending',
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    -- The last attempt's HTTP status (0 if it got no response) and failure reason.
+    response_status integer NOT NULL DEFAULT 0,
+    error text NOT NULL DEFAULT '',
+    created_at timestamptz NOT NULL DEFAULT now(),
+    delivered_at timestamptz
+);
+
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+    WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, created_at DESC);
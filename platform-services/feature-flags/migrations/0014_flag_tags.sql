@@ -0,0 +1,5 @@
+-- Labels for scoping local-mode snapshots to the flags a client reads, and the
+-- people or teams to ask about a flag.
+ALTER TABLE flags
+    ADD COLUMN tags text[] NOT NULL DEFAULT '{}',
+    ADD COLUMN owners text[] NOT NULL DEFAULT '{}';
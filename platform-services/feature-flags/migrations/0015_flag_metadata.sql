@@ -0,0 +1,5 @@
+-- What a flag is for and, for temporary flags, when it should be gone by. Neither
+-- affects evaluation; flags past `expires_at` are reported for cleanup.
+ALTER TABLE flags
+    ADD COLUMN description text NOT NULL DEFAULT '',
+    ADD COLUMN expires_at timestamptz;
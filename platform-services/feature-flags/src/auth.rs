@@ -0,0 +1,461 @@
+//! Authentication and role-based authorization for the Admin service.
+//!
+//! Callers present a bearer token in the `authorization` metadata header: either a
+//! static token listed (by SHA-256) in the auth config, for automation, or an
+//! OIDC-issued JWT. The resulting [`Principal`] is matched against role bindings, each
+//! granting a [`Role`] on flags whose key starts with one of its prefixes or that carry
+//! one of its tags (or on the whole environment) in some or all environments. Since
+//! tags then grant access, only a caller owning the whole environment may change a
+//! flag's tags. Static tokens are bound as
+//! `token:<name>` and JWT callers as `user:<sub>`: the issuer's immutable subject id,
+//! never a username the user could pick, so neither can claim the other's bindings.
+//!
+//! ```yaml
+//! tokens:
+//!   - name: deploy-bot
+//!     sha256: 5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8
+//! oidc:
+//!   issuer: https://idm.anurag.sh/oauth2/openid/feature-flags
+//!   audience: feature-flags
+//! bindings:
+//!   - subjects: ["group:platform_admins", "token:deploy-bot"]
+//!     role: owner
+//!   - subjects: ["group:payments"]
+//!     role: editor
+//!     flag_prefixes: ["payments."]
+//!     environments: ["staging"]
+//!   - subjects: ["group:checkout"]
+//!     role: editor
+//!     flag_tags: ["checkout"]
+//! ```
+
+use anyhow::Context;
+use oidc_verifier::Verifier;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Admin permission levels, ordered: each implies the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reading flags, segments, the audit log, schedules, rollouts and usage.
+    Viewer,
+    /// Changing existing flags: state, variants, rules, schedules and rollouts. Editors
+    /// bound environment-wide may also change segments.
+    Editor,
+    /// Creating, deleting and promoting flags. Owners bound environment-wide may also
+    /// apply whole configs and create environments.
+    Owner,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+/// What an RPC acts on, for matching against a binding's scope.
+#[derive(Clone, Copy, Debug)]
+pub enum Resource<'a> {
+    /// A single flag, covered by bindings whose prefixes match its key or whose tags
+    /// include one of the tags it currently carries.
+    Flag { key: &'a str, tags: &'a [String] },
+    /// Segments and whole-environment operations, covered only by bindings with no
+    /// flag prefixes or tags.
+    Environment,
+}
+
+impl fmt::Display for Resource<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Flag { key, .. } => write!(f, "flag `{key}`"),
+            Resource::Environment => f.write_str("the environment"),
+        }
+    }
+}
+
+/// An authenticated Admin caller.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principal {
+    /// `token:<name>` for a static token or `user:<sub>` for a JWT; recorded as the
+    /// audit actor.
+    pub subject: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    #[serde(default)]
+    pub bindings: Vec<RoleBinding>,
+}
+
+/// A static bearer token. Only its hash is configured, so the file can live in a
+/// ConfigMap.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    /// The principal's subject, bound as `token:<name>`.
+    pub name: String,
+    /// Hex-encoded SHA-256 of the token.
+    pub sha256: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// Claim listing the caller's groups, bound as `group:<name>`.
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+}
+
+fn default_groups_claim() -> String {
+    "groups".into()
+}
+
+/// Grants `role` to `subjects` on the flags it covers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleBinding {
+    /// `token:<name>`, `user:<sub>` or `group:<name>`.
+    pub subjects: Vec<String>,
+    pub role: Role,
+    /// Flag key prefixes the role applies to. With `flag_tags` also empty, the role
+    /// covers every flag and the environment-wide operations too.
+    #[serde(default)]
+    pub flag_prefixes: Vec<String>,
+    /// Flag tags the role applies to, in addition to the flags `flag_prefixes` covers.
+    #[serde(default)]
+    pub flag_tags: Vec<String>,
+    /// Environments the binding applies in; empty means all of them.
+    #[serde(default)]
+    pub environments: Vec<String>,
+}
+
+impl RoleBinding {
+    fn binds(&self, principal: &Principal) -> bool {
+        self.subjects.iter().any(|s| match s.split_once(':') {
+            Some(("token" | "user", _)) => *s == principal.subject,
+            Some(("group", group)) => principal.groups.iter().any(|g| g == group),
+            _ => false,
+        })
+    }
+
+    fn covers(&self, environment: &str, resource: Resource<'_>) -> bool {
+        let in_environment =
+            self.environments.is_empty() || self.environments.iter().any(|e| e == environment);
+        let unscoped = self.flag_prefixes.is_empty() && self.flag_tags.is_empty();
+        in_environment
+            && match resource {
+                Resource::Flag { key, tags } => {
+                    unscoped
+                        || self
+                            .flag_prefixes
+                            .iter()
+                            .any(|p| key.starts_with(p.as_str()))
+                        || self.flag_tags.iter().any(|t| tags.contains(t))
+                }
+                Resource::Environment => unscoped,
+            }
+    }
+}
+
+/// Authenticates bearer tokens and resolves the role a principal holds on a resource.
+pub struct Authenticator {
+    /// Static tokens by hex SHA-256.
+    tokens: HashMap<String, Principal>,
+    oidc: Option<Oidc>,
+    bindings: Vec<RoleBinding>,
+}
+
+struct Oidc {
+    verifier: Verifier,
+    groups_claim: String,
+}
+
+impl Authenticator {
+    /// Read and validate the YAML auth config at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("reading auth config {}", path.display()))?;
+        let config: AuthConfig = serde_yaml::from_str(&raw)
+            .with_context(|| format!("parsing auth config {}", path.display()))?;
+        Self::new(config)
+    }
+
+    pub fn new(config: AuthConfig) -> anyhow::Result<Self> {
+        for binding in &config.bindings {
+            for subject in &binding.subjects {
+                anyhow::ensure!(
+                    matches!(
+                        subject.split_once(':'),
+                        Some(("token" | "user" | "group", s)) if !s.is_empty()
+                    ),
+                    "binding subject `{subject}` must be `token:<name>`, `user:<sub>` or \
+                     `group:<name>`"
+                );
+            }
+        }
+        let mut tokens = HashMap::new();
+        for token in config.tokens {
+            let hash = token.sha256.to_ascii_lowercase();
+            anyhow::ensure!(
+                hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()),
+                "token `{}` sha256 is not a hex SHA-256 digest",
+                token.name
+            );
+            let principal = Principal {
+                subject: format!("token:{}", token.name),
+                groups: token.groups,
+            };
+            anyhow::ensure!(
+                tokens.insert(hash, principal).is_none(),
+                "duplicate token hash"
+            );
+        }
+        Ok(Self {
+            tokens,
+            oidc: config.oidc.map(|oidc| Oidc {
+                verifier: Verifier::new(oidc.issuer, oidc.audience, reqwest::Client::new()),
+                groups_claim: oidc.groups_claim,
+            }),
+            bindings: config.bindings,
+        })
+    }
+
+    /// The principal `bearer` identifies: a configured static token first, then an
+    /// OIDC JWT when an issuer is configured.
+    pub async fn authenticate(&self, bearer: &str) -> anyhow::Result<Principal> {
+        let hash = hex::encode(Sha256::digest(bearer.as_bytes()));
+        if let Some(principal) = self.tokens.get(&hash) {
+            return Ok(principal.clone());
+        }
+        let Some(oidc) = &self.oidc else {
+            anyhow::bail!("unknown token");
+        };
+        let claims = oidc.verifier.verify(bearer).await?;
+        Ok(Principal {
+            groups: claims
+                .strings(&oidc.groups_claim)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            subject: format!("user:{}", claims.sub),
+        })
+    }
+
+    /// The highest role any of `principal`'s bindings grants on `resource` in
+    /// `environment`.
+    pub fn role(
+        &self,
+        principal: &Principal,
+        environment: &str,
+        resource: Resource<'_>,
+    ) -> Option<Role> {
+        self.bindings
+            .iter()
+            .filter(|b| b.binds(principal) && b.covers(environment, resource))
+            .map(|b| b.role)
+            .max()
+    }
+
+    /// The highest role `principal` holds on anything in `environment`.
+    pub fn any_role(&self, principal: &Principal, environment: &str) -> Option<Role> {
+        self.bindings
+            .iter()
+            .filter(|b| {
+                b.binds(principal)
+                    && (b.environments.is_empty()
+                        || b.environments.iter().any(|e| e == environment))
+            })
+            .map(|b| b.role)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oidc_verifier::test_support;
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    const ISSUER: &str = "https://idm.example/oauth2/openid/feature-flags";
+
+    fn authenticator() -> Authenticator {
+        let config: AuthConfig = serde_yaml::from_value(
+            serde_yaml::to_value(json!({
+                "tokens": [{
+                    "name": "deploy-bot",
+                    "sha256": hex::encode(Sha256::digest(b"s3cret")),
+                }],
+                "oidc": { "issuer": ISSUER, "audience": "feature-flags" },
+                "bindings": [
+                    { "subjects": ["token:deploy-bot"], "role": "owner" },
+                    {
+                        "subjects": ["group:payments"],
+                        "role": "editor",
+                        "flag_prefixes": ["payments."],
+                        "environments": ["staging"],
+                    },
+                    { "subjects": ["group:payments"], "role": "viewer" },
+                    {
+                        "subjects": ["group:checkout"],
+                        "role": "editor",
+                        "flag_tags": ["checkout"],
+                    },
+                ],
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+        let mut auth = Authenticator::new(config).unwrap();
+        let oidc = auth.oidc.take().unwrap();
+        auth.oidc = Some(Oidc {
+            verifier: oidc.verifier.with_keys(test_support::key_set()),
+            ..oidc
+        });
+        auth
+    }
+
+    fn token(extra: Value) -> String {
+        test_support::token(ISSUER, "feature-flags", extra)
+    }
+
+    #[tokio::test]
+    async fn authenticates_static_tokens_and_jwts() {
+        let auth = authenticator();
+
+        let bot = auth.authenticate("s3cret").await.unwrap();
+        assert_eq!(bot.subject, "token:deploy-bot");
+
+        let user = auth
+            .authenticate(&token(json!({ "groups": ["payments", "other"] })))
+            .await
+            .unwrap();
+        assert_eq!(user.subject, "user:6f4b1c0e");
+        assert_eq!(user.groups, ["payments", "other"]);
+
+        assert!(auth.authenticate("wrong").await.is_err());
+        let expired = token(json!({ "exp": 1_000_000 }));
+        assert!(auth.authenticate(&expired).await.is_err());
+        let wrong_aud = token(json!({ "aud": "grafana" }));
+        assert!(auth.authenticate(&wrong_aud).await.is_err());
+    }
+
+    #[test]
+    fn bindings_resolve_by_prefix_and_environment() {
+        let auth = authenticator();
+        let payments = Principal {
+            subject: "user:6f4b1c0e".into(),
+            groups: vec!["payments".into()],
+        };
+        let role = |env, key| auth.role(&payments, env, Resource::Flag { key, tags: &[] });
+
+        assert_eq!(role("staging", "payments.checkout"), Some(Role::Editor));
+        assert_eq!(role("prod", "payments.checkout"), Some(Role::Viewer));
+        assert_eq!(role("staging", "search.v2"), Some(Role::Viewer));
+        assert_eq!(
+            auth.role(&payments, "staging", Resource::Environment),
+            Some(Role::Viewer)
+        );
+        assert_eq!(auth.any_role(&payments, "staging"), Some(Role::Editor));
+
+        let bot = Principal {
+            subject: "token:deploy-bot".into(),
+            groups: vec![],
+        };
+        assert_eq!(
+            auth.role(&bot, "prod", Resource::Environment),
+            Some(Role::Owner)
+        );
+        let stranger = Principal {
+            subject: "user:eve".into(),
+            groups: vec![],
+        };
+        assert_eq!(auth.any_role(&stranger, "prod"), None);
+    }
+
+    #[test]
+    fn tag_bindings_cover_flags_carrying_the_tag() {
+        let auth = authenticator();
+        let checkout = Principal {
+            subject: "user:6f4b1c0e".into(),
+            groups: vec!["checkout".into()],
+        };
+        let tagged = ["checkout".to_owned(), "web".to_owned()];
+        let untagged = ["web".to_owned()];
+        let role = |tags: &[String]| {
+            auth.role(
+                &checkout,
+                "prod",
+                Resource::Flag {
+                    key: "new-cart",
+                    tags,
+                },
+            )
+        };
+
+        assert_eq!(role(&tagged), Some(Role::Editor));
+        assert_eq!(role(&untagged), None);
+        assert_eq!(role(&[]), None);
+        assert_eq!(auth.role(&checkout, "prod", Resource::Environment), None);
+    }
+
+    #[tokio::test]
+    async fn jwts_cannot_claim_token_bindings() {
+        let auth = authenticator();
+        // Whatever the user sets as their username, or even a `sub` equal to a token
+        // name, a JWT principal lives in the `user:` namespace.
+        let impostor = auth
+            .authenticate(&token(json!({
+                "sub": "deploy-bot",
+                "preferred_username": "deploy-bot",
+            })))
+            .await
+            .unwrap();
+        assert_eq!(impostor.subject, "user:deploy-bot");
+        assert_eq!(auth.role(&impostor, "prod", Resource::Environment), None);
+        assert_eq!(auth.any_role(&impostor, "prod"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_subjects_and_hashes() {
+        let bad_subject = AuthConfig {
+            bindings: vec![RoleBinding {
+                subjects: vec!["payments".into()],
+                role: Role::Viewer,
+                flag_prefixes: vec![],
+                flag_tags: vec![],
+                environments: vec![],
+            }],
+            ..Default::default()
+        };
+        assert!(Authenticator::new(bad_subject).is_err());
+
+        let bad_hash = AuthConfig {
+            tokens: vec![TokenConfig {
+                name: "bot".into(),
+                sha256: "s3cret".into(),
+                groups: vec![],
+            }],
+            ..Default::default()
+        };
+        assert!(Authenticator::new(bad_hash).is_err());
+    }
+}
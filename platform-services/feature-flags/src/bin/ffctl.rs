@@ -1,36 +1,74 @@
 //! Admin CLI for the feature-flags service. Talks to the `Admin` gRPC API; point
 //! `--url` at a port-forwarded backend (`kubectl -n feature-flags port-forward
-//! svc/api 50051:50051`). Results are rendered as JSON.
+//! svc/api 50051:50051`). Results are rendered as JSON. Every command acts on the
+//! environment named by `--env` (or `$FFCTL_ENV`), `default` when unset. When the
+//! service requires auth, pass a static token or OIDC access token with `--token` (or
+//! `$FFCTL_TOKEN`); the service then audits under that identity and ignores `--actor`.
 
 use anyhow::{Context as _, bail};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand, ValueEnum};
 use console::style;
-use feature_flags::flag_config::{
-    ConstraintDoc, DistDoc, FlagDoc, RuleDoc, SegmentDoc, SegmentsFile,
-};
+use feature_flags::flag_config::{FlagDoc, SegmentDoc, SegmentsFile, operator_name};
 use feature_flags::pb;
 use pb::admin_client::AdminClient;
+use pb::evaluation_client::EvaluationClient;
 use prost_types::value::Kind;
 use serde_json::{Value as Json, json};
 use similar::{ChangeTag, TextDiff};
 use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
-use tonic::Request;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
+use tonic::{Request, Status};
 
 #[derive(Parser)]
 #[command(name = "ffctl", about = "feature-flags admin CLI")]
 struct Cli {
     #[arg(long, env = "FFCTL_URL", default_value = "http://localhost:50051")]
     url: String,
+    /// The environment to act on, sent as the `environment` header; the service's
+    /// `default` environment when unset.
+    #[arg(long = "env", env = "FFCTL_ENV", global = true)]
+    environment: Option<String>,
+    /// Bearer token sent in the `authorization` header.
+    #[arg(long, env = "FFCTL_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
     #[command(subcommand)]
     command: Command,
 }
 
+/// Adds `--env` as the `environment` metadata header and `--token` as the
+/// `authorization` header on every request.
+#[derive(Clone)]
+struct HeaderInterceptor {
+    environment: Option<AsciiMetadataValue>,
+    authorization: Option<AsciiMetadataValue>,
+}
+
+impl Interceptor for HeaderInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(env) = &self.environment {
+            request.metadata_mut().insert("environment", env.clone());
+        }
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        Ok(request)
+    }
+}
+
+type Admin = AdminClient<InterceptedService<Channel, HeaderInterceptor>>;
+
 #[derive(Subcommand)]
 enum Command {
     /// Manage flags
+    #[command(visible_alias = "flags")]
     Flag {
         #[command(subcommand)]
         action: FlagAction,
@@ -55,8 +93,120 @@ enum Command {
         #[command(subcommand)]
         action: ConfigAction,
     },
+    /// Queue flag changes for a future time
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+    /// Ramp a rule's distribution over time
+    Rollout {
+        #[command(subcommand)]
+        action: RolloutAction,
+    },
+    /// List and create environments
+    #[command(visible_alias = "envs")]
+    Env {
+        #[command(subcommand)]
+        action: EnvAction,
+    },
+    /// Register webhooks notified of config changes, and inspect their deliveries
+    #[command(visible_alias = "webhooks")]
+    Webhook {
+        #[command(subcommand)]
+        action: WebhookAction,
+    },
+    /// Read and diff the config as it stood at past versions
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Restore the config, or one flag, as it stood at a past version. Prints the plan
+    /// and asks to confirm.
+    Rollback {
+        version: i64,
+        /// Restore only this flag, leaving the rest of the config as it is now.
+        #[arg(long)]
+        flag: Option<String>,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+        /// Skip the interactive confirmation prompt.
+        #[arg(long)]
+        auto_approve: bool,
+    },
+    /// Resolve a flag for a context through the `Evaluation` API
+    Eval {
+        flag_key: String,
+        #[arg(long, default_value = "")]
+        targeting_key: String,
+        /// A context attribute as `key=value`; the value is parsed as JSON, falling
+        /// back to a plain string. Repeatable.
+        #[arg(long = "attr")]
+        attrs: Vec<String>,
+        /// Show which rules were tried, each constraint's outcome and the split bucket.
+        #[arg(long)]
+        explain: bool,
+        /// Identity sent as the `client-id` header.
+        #[arg(long, env = "FFCTL_CLIENT_ID", default_value = "ffctl")]
+        client_id: String,
+    },
 }
 
+#[derive(Subcommand)]
+enum EnvAction {
+    /// List environments and their config versions
+    List,
+    /// Create an empty environment
+    Create {
+        name: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum WebhookAction {
+    /// Register a URL to receive every config change in the environment. Prints the
+    /// signing secret, which can't be shown again.
+    Create {
+        url: String,
+        #[arg(long, default_value = "")]
+        description: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    List,
+    Delete {
+        id: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    /// The delivery log, newest first
+    Deliveries {
+        /// Only deliveries to this webhook.
+        #[arg(long)]
+        webhook: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Send a delivery again with a fresh set of attempts
+    Redeliver { delivery_id: String },
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Print the flags and segments as they stood at a version
+    Show { version: i64 },
+    /// Show what changed between two versions, as a plan from the first to the second
+    Diff {
+        from: i64,
+        /// Defaults to the live config.
+        #[arg(default_value_t = 0)]
+        to: i64,
+    },
+}
+
+/// `plan` and `apply` act on the `--env` environment, so one config directory per
+/// environment (e.g. `--dir config/prod --env prod`) keeps them independent.
 #[derive(Subcommand)]
 enum ConfigAction {
     /// Show the diff between the config directory and the live service without writing.
@@ -127,6 +277,30 @@ enum FlagAction {
     },
     /// Permanently delete a flag
     Delete { key: String },
+    /// Evaluation counts per variant and client-id, server-side and reported by
+    /// local-mode clients
+    Usage {
+        /// Only this flag; all flags when omitted.
+        key: Option<String>,
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+    },
+    /// Flags not evaluated in the last `--days`, or serving one variant to all of it
+    Stale {
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// Copy a flag's config from one environment to another. Segments its rules
+    /// reference are copied too, unless the target already has them.
+    Promote {
+        key: String,
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        to: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -149,7 +323,24 @@ enum SegmentAction {
     /// Create or update a segment from a JSON document, e.g.
     /// `{"key":"beta","name":"Beta","constraints":[{"attribute":"plan","operator":"IN","values":["pro"]}]}`.
     /// Operators are the short names from the proto (EQ, IN, STARTS_WITH, ...).
+    /// Optional `constraint_groups` (CNF, as on rules), `included_segments`/`excluded_segments`
+    /// (segment keys the context must / must not be in) and `targeting_keys` (an allowlist)
+    /// are AND-combined with `constraints`.
     Set { json: String },
+    /// Add and/or remove keys on a segment's targeting-key list
+    Keys {
+        key: String,
+        #[arg(long)]
+        add: Vec<String>,
+        #[arg(long)]
+        remove: Vec<String>,
+        /// File of keys to add, one per line
+        #[arg(long)]
+        add_file: Option<String>,
+        /// File of keys to remove, one per line
+        #[arg(long)]
+        remove_file: Option<String>,
+    },
     /// Fetch a single segment
     Get { key: String },
     /// List segments
@@ -164,9 +355,104 @@ enum RulesAction {
     /// `[{"segment_key":"beta","variant_key":"on"},{"constraint_groups":[[{"attribute":"country","operator":"IN","values":["AU","NZ"]}],[{"attribute":"plan","operator":"EQ","values":["pro"]}]],"variant_key":"on"}]`.
     /// `constraint_groups` is CNF: groups are AND-combined, constraints within a group OR-combined; `constraints` (a flat array) is sugar for plain AND. Both work with or without a `segment_key`.
     /// A `FLAG_MATCHES` operator depends on another flag: `attribute` is the flag key and `values` the variant keys it must resolve to (a prerequisite).
+    /// `SEMVER_EQ`/`SEMVER_GT`/`SEMVER_GTE`/`SEMVER_LT`/`SEMVER_LTE` compare versions, `BEFORE`/`AFTER` RFC 3339 timestamps (`"now"` as an operand, or a `now` attribute, is the evaluation time), and `PERCENT_OF` matches `values[0]` percent of the attribute's values, e.g. `{"attribute":"org_id","operator":"PERCENT_OF","values":[10]}`.
     Set { flag_key: String, json: String },
 }
 
+#[derive(Subcommand)]
+enum ScheduleAction {
+    /// Schedule a change to a flag: an enabled state and/or default variant, or a
+    /// replacement rule set (same JSON as `rules set`).
+    Create {
+        flag_key: String,
+        /// When to apply, as an RFC 3339 timestamp (e.g. `2026-11-01T09:00:00+11:00`).
+        #[arg(long)]
+        at: String,
+        #[arg(long, conflicts_with_all = ["disable", "rules"])]
+        enable: bool,
+        #[arg(long, conflicts_with = "rules")]
+        disable: bool,
+        /// The new default variant.
+        #[arg(long, conflicts_with = "rules")]
+        default: Option<String>,
+        /// A JSON array of rules to replace the flag's rules with.
+        #[arg(long)]
+        rules: Option<String>,
+        /// Identity recorded in the audit log when the change applies; falls back to
+        /// `$FFCTL_ACTOR` then `git config user.email`.
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    /// List pending scheduled changes, soonest first
+    List {
+        /// Only changes to this flag.
+        #[arg(long)]
+        flag: Option<String>,
+        /// Include applied, cancelled and failed changes.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Cancel a pending scheduled change
+    Cancel { id: String },
+}
+
+#[derive(Subcommand)]
+enum RolloutAction {
+    /// Start a rollout on the rule at `--rule` (its 0-based rank). Either give explicit
+    /// steps, e.g. `--steps '[{"weights":{"on":10,"off":90},"hold":"1h"},{"weights":{"on":100}}]'`,
+    /// or a linear ramp, e.g. `--ramp on --baseline off --increment 10 --every 1h`.
+    Start {
+        flag_key: String,
+        #[arg(long)]
+        rule: u32,
+        #[arg(long, conflicts_with = "ramp", required_unless_present = "ramp")]
+        steps: Option<String>,
+        /// The variant to ramp up.
+        #[arg(long, requires = "baseline")]
+        ramp: Option<String>,
+        /// The variant that gives up weight as `--ramp` gains it.
+        #[arg(long)]
+        baseline: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        start: u32,
+        #[arg(long, default_value_t = 100)]
+        end: u32,
+        #[arg(long, default_value_t = 10)]
+        increment: u32,
+        /// How long each step is held, e.g. `30m`, `1h`, `1d`.
+        #[arg(long, default_value = "1h")]
+        every: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    /// List rollouts in progress, newest first
+    List {
+        #[arg(long)]
+        flag: Option<String>,
+        /// Include completed and aborted rollouts.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Hold a rollout at its current step
+    Pause {
+        id: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    /// Continue a paused rollout
+    Resume {
+        id: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+    /// Stop a rollout and restore the rule's original split
+    Abort {
+        id: String,
+        #[arg(long, env = "FFCTL_ACTOR")]
+        actor: Option<String>,
+    },
+}
+
 #[derive(Clone, Copy, ValueEnum)]
 enum FlagType {
     Bool,
@@ -191,9 +477,27 @@ impl From<FlagType> for pb::ValueType {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    let mut admin = AdminClient::connect(cli.url.clone())
+    let channel = Channel::from_shared(cli.url.clone())?
+        .connect()
         .await
         .with_context(|| format!("connecting to {}", cli.url))?;
+    let environment = cli
+        .environment
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .context("environment is not a valid header value")?;
+    let authorization = cli
+        .token
+        .as_deref()
+        .map(|token| format!("Bearer {token}").parse())
+        .transpose()
+        .context("token is not a valid header value")?;
+    let interceptor = HeaderInterceptor {
+        environment,
+        authorization,
+    };
+    let mut admin = AdminClient::with_interceptor(channel.clone(), interceptor.clone());
 
     match cli.command {
         Command::Flag { action } => flag(&mut admin, action).await,
@@ -201,10 +505,60 @@ async fn main() -> anyhow::Result<()> {
         Command::Segment { action } => segment(&mut admin, action).await,
         Command::Rules { action } => rules(&mut admin, action).await,
         Command::Config { action } => config(&mut admin, action).await,
+        Command::Schedule { action } => schedule(&mut admin, action).await,
+        Command::Rollout { action } => rollout(&mut admin, action).await,
+        Command::Env { action } => env(&mut admin, action).await,
+        Command::Webhook { action } => webhook(&mut admin, action).await,
+        Command::History { action } => history(&mut admin, action).await,
+        Command::Rollback {
+            version,
+            flag,
+            actor,
+            auto_approve,
+        } => rollback(&mut admin, version, flag, actor, auto_approve).await,
+        Command::Eval {
+            flag_key,
+            targeting_key,
+            attrs,
+            explain,
+            client_id,
+        } => {
+            let attributes = attrs
+                .iter()
+                .map(|spec| {
+                    let (k, v) = spec
+                        .split_once('=')
+                        .with_context(|| format!("attribute `{spec}` must be key=value"))?;
+                    Ok((k.to_owned(), json_to_value(&parse_json(v))))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let mut request = Request::new(pb::ResolveRequest {
+                flag_key,
+                context: Some(pb::EvaluationContext {
+                    targeting_key,
+                    attributes: Some(prost_types::Struct { fields: attributes }),
+                }),
+            });
+            request.metadata_mut().insert(
+                "client-id",
+                client_id.parse().context("client id is not a valid header value")?,
+            );
+            let trace = EvaluationClient::with_interceptor(channel, interceptor)
+                .explain_resolve(request)
+                .await?
+                .into_inner()
+                .trace
+                .unwrap_or_default();
+            if explain {
+                print(flag_trace_to_json(&trace))
+            } else {
+                print(resolution_to_json(&trace))
+            }
+        }
     }
 }
 
-async fn flag(admin: &mut AdminClient<Channel>, action: FlagAction) -> anyhow::Result<()> {
+async fn flag(admin: &mut Admin, action: FlagAction) -> anyhow::Result<()> {
     match action {
         FlagAction::Create {
             key,
@@ -276,10 +630,70 @@ async fn flag(admin: &mut AdminClient<Channel>, action: FlagAction) -> anyhow::R
             println!("ok");
             Ok(())
         }
+        FlagAction::Usage { key, days } => {
+            let usage = admin
+                .get_flag_usage(pb::GetFlagUsageRequest {
+                    flag_key: key.unwrap_or_default(),
+                    days,
+                })
+                .await?
+                .into_inner()
+                .usage;
+            print(Json::Array(usage.iter().map(|u| json!({
+                "flag_key": u.flag_key,
+                "variant_key": u.variant_key,
+                "client_id": u.client_id,
+                "count": u.count,
+                "last_evaluated_at": u.last_evaluated_at,
+            })).collect()))
+        }
+        FlagAction::Stale { days } => {
+            let flags = admin
+                .list_stale_flags(pb::ListStaleFlagsRequest { days })
+                .await?
+                .into_inner()
+                .flags;
+            print(Json::Array(flags.iter().map(|f| json!({
+                "flag_key": f.flag_key,
+                "reason": pb::StaleReason::try_from(f.reason)
+                    .unwrap_or_default()
+                    .as_str_name()
+                    .trim_start_matches("STALE_REASON_")
+                    .to_lowercase(),
+                "variant_key": f.variant_key,
+                "last_evaluated_at": f.last_evaluated_at,
+            })).collect()))
+        }
+        FlagAction::Promote {
+            key,
+            from,
+            to,
+            actor,
+        } => {
+            let actor = resolve_actor(actor);
+            let resp = admin
+                .promote_flag(request_with_actor(
+                    pb::PromoteFlagRequest {
+                        flag_key: key,
+                        from_environment: from,
+                        to_environment: to,
+                    },
+                    &actor,
+                ))
+                .await?
+                .into_inner();
+            print(json!({
+                "flag": resp.flag.as_ref().map(flag_to_json),
+                "created": resp.created,
+                "changed": resp.changed,
+                "copied_segments": resp.copied_segments,
+                "version": resp.version,
+            }))
+        }
     }
 }
 
-async fn variant(admin: &mut AdminClient<Channel>, action: VariantAction) -> anyhow::Result<()> {
+async fn variant(admin: &mut Admin, action: VariantAction) -> anyhow::Result<()> {
     match action {
         VariantAction::Set {
             flag_key,
@@ -314,7 +728,18 @@ async fn variant(admin: &mut AdminClient<Channel>, action: VariantAction) -> any
     }
 }
 
-async fn segment(admin: &mut AdminClient<Channel>, action: SegmentAction) -> anyhow::Result<()> {
+/// Non-empty trimmed lines of `path`.
+fn read_keys(path: &str) -> anyhow::Result<Vec<String>> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+async fn segment(admin: &mut Admin, action: SegmentAction) -> anyhow::Result<()> {
     match action {
         SegmentAction::Set { json } => {
             let segment = parse_segment(&parse_json(&json))?;
@@ -326,6 +751,29 @@ async fn segment(admin: &mut AdminClient<Channel>, action: SegmentAction) -> any
                 .into_inner();
             print(segment_to_json(&saved))
         }
+        SegmentAction::Keys {
+            key,
+            mut add,
+            mut remove,
+            add_file,
+            remove_file,
+        } => {
+            if let Some(path) = add_file {
+                add.extend(read_keys(&path)?);
+            }
+            if let Some(path) = remove_file {
+                remove.extend(read_keys(&path)?);
+            }
+            let resp = admin
+                .update_segment_keys(pb::UpdateSegmentKeysRequest {
+                    segment_key: key,
+                    add,
+                    remove,
+                })
+                .await?
+                .into_inner();
+            print(json!({ "key_count": resp.key_count }))
+        }
         SegmentAction::Get { key } => {
             let segment = admin
                 .get_segment(pb::GetSegmentRequest { key })
@@ -351,15 +799,9 @@ async fn segment(admin: &mut AdminClient<Channel>, action: SegmentAction) -> any
     }
 }
 
-async fn rules(admin: &mut AdminClient<Channel>, action: RulesAction) -> anyhow::Result<()> {
+async fn rules(admin: &mut Admin, action: RulesAction) -> anyhow::Result<()> {
     let RulesAction::Set { flag_key, json } = action;
-    let Json::Array(items) = parse_json(&json) else {
-        bail!("rules must be a JSON array");
-    };
-    let rules = items
-        .iter()
-        .map(parse_rule)
-        .collect::<anyhow::Result<_>>()?;
+    let rules = parse_rules(&json)?;
     let flag = admin
         .set_flag_rules(pb::SetFlagRulesRequest { flag_key, rules })
         .await?
@@ -367,9 +809,340 @@ async fn rules(admin: &mut AdminClient<Channel>, action: RulesAction) -> anyhow:
     print(flag_to_json(&flag))
 }
 
+async fn schedule(admin: &mut Admin, action: ScheduleAction) -> anyhow::Result<()> {
+    use pb::schedule_change_request::Change;
+    match action {
+        ScheduleAction::Create {
+            flag_key,
+            at,
+            enable,
+            disable,
+            default,
+            rules,
+            actor,
+        } => {
+            let change = match rules {
+                Some(json) => Change::Rules(pb::RuleSet {
+                    rules: parse_rules(&json)?,
+                }),
+                None if enable || disable || default.is_some() => Change::Update(pb::FlagUpdate {
+                    enabled: (enable || disable).then_some(enable),
+                    default_variant_key: default,
+                }),
+                None => bail!("nothing to schedule: pass --enable, --disable, --default or --rules"),
+            };
+            let actor = resolve_actor(actor);
+            let scheduled = admin
+                .schedule_change(request_with_actor(
+                    pb::ScheduleChangeRequest {
+                        flag_key,
+                        apply_at: at,
+                        change: Some(change),
+                    },
+                    &actor,
+                ))
+                .await?
+                .into_inner();
+            print(scheduled_to_json(&scheduled))
+        }
+        ScheduleAction::List { flag, all } => {
+            let resp = admin
+                .list_scheduled_changes(pb::ListScheduledChangesRequest {
+                    flag_key: flag.unwrap_or_default(),
+                    include_resolved: all,
+                })
+                .await?
+                .into_inner();
+            print(Json::Array(
+                resp.changes.iter().map(scheduled_to_json).collect(),
+            ))
+        }
+        ScheduleAction::Cancel { id } => {
+            let cancelled = admin
+                .cancel_scheduled_change(pb::CancelScheduledChangeRequest { id })
+                .await?
+                .into_inner();
+            print(scheduled_to_json(&cancelled))
+        }
+    }
+}
+
+async fn env(admin: &mut Admin, action: EnvAction) -> anyhow::Result<()> {
+    match action {
+        EnvAction::List => {
+            let environments = admin
+                .list_environments(pb::ListEnvironmentsRequest {})
+                .await?
+                .into_inner()
+                .environments;
+            print(Json::Array(environments.iter().map(environment_to_json).collect()))
+        }
+        EnvAction::Create { name, actor } => {
+            let actor = resolve_actor(actor);
+            let environment = admin
+                .create_environment(request_with_actor(
+                    pb::CreateEnvironmentRequest { name },
+                    &actor,
+                ))
+                .await?
+                .into_inner();
+            print(environment_to_json(&environment))
+        }
+    }
+}
+
+async fn rollout(admin: &mut Admin, action: RolloutAction) -> anyhow::Result<()> {
+    use pb::start_rollout_request::Plan;
+    let rollout = match action {
+        RolloutAction::Start {
+            flag_key,
+            rule,
+            steps,
+            ramp,
+            baseline,
+            start,
+            end,
+            increment,
+            every,
+            actor,
+        } => {
+            let plan = match (steps, ramp) {
+                (Some(json), _) => Plan::Steps(pb::RolloutSteps {
+                    steps: parse_rollout_steps(&json)?,
+                }),
+                (None, Some(variant_key)) => Plan::Linear(pb::LinearRamp {
+                    variant_key,
+                    baseline_variant_key: baseline.unwrap_or_default(),
+                    start_weight: start,
+                    end_weight: end,
+                    increment,
+                    step_secs: parse_duration(&every)?,
+                }),
+                (None, None) => bail!("pass --steps or --ramp"),
+            };
+            let actor = resolve_actor(actor);
+            admin
+                .start_rollout(request_with_actor(
+                    pb::StartRolloutRequest {
+                        flag_key,
+                        rule_rank: rule,
+                        plan: Some(plan),
+                    },
+                    &actor,
+                ))
+                .await?
+        }
+        RolloutAction::List { flag, all } => {
+            let resp = admin
+                .list_rollouts(pb::ListRolloutsRequest {
+                    flag_key: flag.unwrap_or_default(),
+                    include_finished: all,
+                })
+                .await?
+                .into_inner();
+            return print(Json::Array(
+                resp.rollouts.iter().map(rollout_to_json).collect(),
+            ));
+        }
+        RolloutAction::Pause { id, actor } => {
+            let actor = resolve_actor(actor);
+            admin
+                .pause_rollout(request_with_actor(pb::PauseRolloutRequest { id }, &actor))
+                .await?
+        }
+        RolloutAction::Resume { id, actor } => {
+            let actor = resolve_actor(actor);
+            admin
+                .resume_rollout(request_with_actor(pb::ResumeRolloutRequest { id }, &actor))
+                .await?
+        }
+        RolloutAction::Abort { id, actor } => {
+            let actor = resolve_actor(actor);
+            admin
+                .abort_rollout(request_with_actor(pb::AbortRolloutRequest { id }, &actor))
+                .await?
+        }
+    };
+    print(rollout_to_json(&rollout.into_inner()))
+}
+
+async fn webhook(admin: &mut Admin, action: WebhookAction) -> anyhow::Result<()> {
+    match action {
+        WebhookAction::Create {
+            url,
+            description,
+            actor,
+        } => {
+            let actor = resolve_actor(actor);
+            let created = admin
+                .create_webhook(request_with_actor(
+                    pb::CreateWebhookRequest { url, description },
+                    &actor,
+                ))
+                .await?
+                .into_inner();
+            let mut out = created
+                .webhook
+                .as_ref()
+                .map(webhook_to_json)
+                .unwrap_or_default();
+            out["secret"] = Json::String(created.secret);
+            print(out)
+        }
+        WebhookAction::List => {
+            let webhooks = admin
+                .list_webhooks(pb::ListWebhooksRequest {})
+                .await?
+                .into_inner()
+                .webhooks;
+            print(Json::Array(webhooks.iter().map(webhook_to_json).collect()))
+        }
+        WebhookAction::Delete { id, actor } => {
+            let actor = resolve_actor(actor);
+            admin
+                .delete_webhook(request_with_actor(
+                    pb::DeleteWebhookRequest { id: id.clone() },
+                    &actor,
+                ))
+                .await?;
+            println!("Deleted webhook {id}.");
+            Ok(())
+        }
+        WebhookAction::Deliveries { webhook, limit } => {
+            let deliveries = admin
+                .list_webhook_deliveries(pb::ListWebhookDeliveriesRequest {
+                    webhook_id: webhook.unwrap_or_default(),
+                    limit,
+                })
+                .await?
+                .into_inner()
+                .deliveries;
+            print(Json::Array(deliveries.iter().map(delivery_to_json).collect()))
+        }
+        WebhookAction::Redeliver { delivery_id } => {
+            let delivery = admin
+                .redeliver_webhook(pb::RedeliverWebhookRequest { delivery_id })
+                .await?
+                .into_inner();
+            print(delivery_to_json(&delivery))
+        }
+    }
+}
+
+async fn history(admin: &mut Admin, action: HistoryAction) -> anyhow::Result<()> {
+    match action {
+        HistoryAction::Show { version } => {
+            let snapshot = admin
+                .get_snapshot_at_version(pb::GetSnapshotAtVersionRequest { version })
+                .await?
+                .into_inner();
+            print(json!({
+                "version": snapshot.version,
+                "created_at": snapshot.created_at,
+                "flags": snapshot.flags.iter().map(flag_to_json).collect::<Vec<_>>(),
+                "segments": snapshot.segments.iter().map(segment_to_json).collect::<Vec<_>>(),
+            }))
+        }
+        HistoryAction::Diff { from, to } => {
+            let diff = admin
+                .diff_versions(pb::DiffVersionsRequest {
+                    from_version: from,
+                    to_version: to,
+                })
+                .await?
+                .into_inner();
+            if diff.changes.is_empty() {
+                println!(
+                    "No changes between versions {} and {}.",
+                    diff.from_version, diff.to_version
+                );
+                return Ok(());
+            }
+            let before = state_at(admin, from).await?;
+            let after = state_at(admin, to).await?;
+            let flags: Vec<_> = after.flags.into_values().collect();
+            let segments: Vec<_> = after.segments.into_values().collect();
+            render_plan(&diff.changes, &flags, &segments, &before);
+            Ok(())
+        }
+    }
+}
+
+/// Dry-run the rollback, show its plan, then apply it against the version the plan was
+/// computed at so a write in between aborts rather than being silently reverted.
+async fn rollback(
+    admin: &mut Admin,
+    version: i64,
+    flag: Option<String>,
+    actor: Option<String>,
+    auto_approve: bool,
+) -> anyhow::Result<()> {
+    let actor = resolve_actor(actor);
+    let request = |dry_run, expected_version| pb::RollbackToVersionRequest {
+        version,
+        flag_key: flag.clone().unwrap_or_default(),
+        dry_run,
+        expected_version,
+    };
+    let plan = admin
+        .rollback_to_version(request_with_actor(request(true, 0), &actor))
+        .await?
+        .into_inner();
+    let target = state_at(admin, version).await?;
+    let live = fetch_live(admin).await?;
+    let flags: Vec<_> = target.flags.into_values().collect();
+    let segments: Vec<_> = target.segments.into_values().collect();
+    render_plan(&plan.changes, &flags, &segments, &live);
+    if plan.changes.is_empty() {
+        return Ok(());
+    }
+    if !auto_approve && !confirm(&format!("Roll back to version {version}?"))? {
+        println!("Aborted.");
+        return Ok(());
+    }
+    let resp = admin
+        .rollback_to_version(request_with_actor(
+            request(false, plan.from_version),
+            &actor,
+        ))
+        .await
+        .context("rollback failed (config may have changed; re-run it)")?
+        .into_inner();
+    println!(
+        "Applied {} change(s); config version {} -> {}.",
+        resp.changes.len(),
+        resp.from_version,
+        resp.to_version
+    );
+    Ok(())
+}
+
+/// The flags and segments at `version`, or live for 0.
+async fn state_at(admin: &mut Admin, version: i64) -> anyhow::Result<LiveState> {
+    if version == 0 {
+        return fetch_live(admin).await;
+    }
+    let snapshot = admin
+        .get_snapshot_at_version(pb::GetSnapshotAtVersionRequest { version })
+        .await?
+        .into_inner();
+    Ok(LiveState {
+        flags: snapshot
+            .flags
+            .into_iter()
+            .map(|f| (f.key.clone(), f))
+            .collect(),
+        segments: snapshot
+            .segments
+            .into_iter()
+            .map(|s| (s.key.clone(), s))
+            .collect(),
+    })
+}
+
 // -- Declarative config (`ffctl config`) ------------------------------------------
 
-async fn config(admin: &mut AdminClient<Channel>, action: ConfigAction) -> anyhow::Result<()> {
+async fn config(admin: &mut Admin, action: ConfigAction) -> anyhow::Result<()> {
     match action {
         ConfigAction::Plan { dir } => {
             let (flags, segments) = load_config(&dir)?;
@@ -384,6 +1157,7 @@ async fn config(admin: &mut AdminClient<Channel>, action: ConfigAction) -> anyho
                 .into_inner();
             let live = fetch_live(admin).await?;
             render_plan(&resp.changes, &flags, &segments, &live);
+            warn_expired(&flags, Utc::now());
             Ok(())
         }
         ConfigAction::Apply {
@@ -407,6 +1181,7 @@ async fn config(admin: &mut AdminClient<Channel>, action: ConfigAction) -> anyho
                 .into_inner();
             let live = fetch_live(admin).await?;
             render_plan(&plan.changes, &flags, &segments, &live);
+            warn_expired(&flags, Utc::now());
             if plan.changes.is_empty() {
                 return Ok(());
             }
@@ -477,7 +1252,7 @@ fn load_config(dir: &str) -> anyhow::Result<(Vec<pb::Flag>, Vec<pb::Segment>)> {
             let text = fs::read_to_string(&path)?;
             let doc: FlagDoc = serde_yaml::from_str(&text)
                 .with_context(|| format!("parsing {}", path.display()))?;
-            flags.push(flag_doc_to_pb(&doc).with_context(|| format!("in {}", path.display()))?);
+            flags.push(pb::Flag::try_from(&doc).with_context(|| format!("in {}", path.display()))?);
         }
     }
 
@@ -488,7 +1263,7 @@ fn load_config(dir: &str) -> anyhow::Result<(Vec<pb::Flag>, Vec<pb::Segment>)> {
         let file: SegmentsFile = serde_yaml::from_str(&text)
             .with_context(|| format!("parsing {}", segments_path.display()))?;
         for doc in &file.segments {
-            segments.push(segment_doc_to_pb(doc)?);
+            segments.push(pb::Segment::try_from(doc)?);
         }
     }
     Ok((flags, segments))
@@ -498,213 +1273,16 @@ fn export_config(dir: &str, flags: &[pb::Flag], segments: &[pb::Segment]) -> any
     let flags_dir = Path::new(dir).join("flags");
     fs::create_dir_all(&flags_dir)?;
     for flag in flags {
-        let yaml = serde_yaml::to_string(&flag_to_doc(flag))?;
+        let yaml = serde_yaml::to_string(&FlagDoc::from(flag))?;
         fs::write(flags_dir.join(format!("{}.yaml", flag.key)), yaml)?;
     }
     let file = SegmentsFile {
-        segments: segments.iter().map(segment_to_doc).collect(),
+        segments: segments.iter().map(SegmentDoc::from).collect(),
     };
     fs::write(Path::new(dir).join("segments.yaml"), serde_yaml::to_string(&file)?)?;
     Ok(())
 }
 
-fn flag_doc_to_pb(doc: &FlagDoc) -> anyhow::Result<pb::Flag> {
-    let value_type = value_type_from_name(&doc.value_type)?;
-    let variants = doc
-        .variants
-        .iter()
-        .map(|(k, v)| pb::Variant {
-            key: k.clone(),
-            value: Some(json_to_value(v)),
-        })
-        .collect();
-    let rules = doc
-        .rules
-        .iter()
-        .enumerate()
-        .map(|(i, r)| rule_doc_to_pb(i as u32, r))
-        .collect::<anyhow::Result<_>>()?;
-    Ok(pb::Flag {
-        key: doc.key.clone(),
-        value_type: value_type as i32,
-        enabled: doc.enabled,
-        default_variant_key: doc.default.clone(),
-        archived: false,
-        variants,
-        rules,
-    })
-}
-
-fn rule_doc_to_pb(rank: u32, r: &RuleDoc) -> anyhow::Result<pb::Rule> {
-    let constraint_groups = if !r.constraint_groups.is_empty() {
-        r.constraint_groups
-            .iter()
-            .map(|g| {
-                Ok(pb::ConstraintGroup {
-                    constraints: g
-                        .iter()
-                        .map(constraint_doc_to_pb)
-                        .collect::<anyhow::Result<_>>()?,
-                })
-            })
-            .collect::<anyhow::Result<_>>()?
-    } else {
-        r.constraints
-            .iter()
-            .map(|c| {
-                Ok(pb::ConstraintGroup {
-                    constraints: vec![constraint_doc_to_pb(c)?],
-                })
-            })
-            .collect::<anyhow::Result<_>>()?
-    };
-    Ok(pb::Rule {
-        rank,
-        segment_key: r.segment.clone().unwrap_or_default(),
-        variant_key: r.variant.clone().unwrap_or_default(),
-        distributions: r
-            .distributions
-            .iter()
-            .map(|d| pb::Distribution {
-                variant_key: d.variant.clone(),
-                weight: d.weight,
-            })
-            .collect(),
-        constraint_groups,
-        bucket_salt: r.bucket_salt.clone(),
-    })
-}
-
-fn constraint_doc_to_pb(c: &ConstraintDoc) -> anyhow::Result<pb::Constraint> {
-    Ok(pb::Constraint {
-        attribute: c.attribute.clone(),
-        operator: operator_from_name(&c.operator)? as i32,
-        values: c.values.iter().map(json_to_value).collect(),
-    })
-}
-
-fn segment_doc_to_pb(doc: &SegmentDoc) -> anyhow::Result<pb::Segment> {
-    Ok(pb::Segment {
-        key: doc.key.clone(),
-        name: doc.name.clone(),
-        constraints: doc
-            .constraints
-            .iter()
-            .map(constraint_doc_to_pb)
-            .collect::<anyhow::Result<_>>()?,
-    })
-}
-
-fn flag_to_doc(f: &pb::Flag) -> FlagDoc {
-    FlagDoc {
-        key: f.key.clone(),
-        value_type: value_type_name(f.value_type).to_owned(),
-        enabled: f.enabled,
-        default: f.default_variant_key.clone(),
-        variants: f
-            .variants
-            .iter()
-            .map(|v| {
-                (
-                    v.key.clone(),
-                    v.value.as_ref().map(value_to_json).unwrap_or(Json::Null),
-                )
-            })
-            .collect(),
-        rules: f.rules.iter().map(rule_to_doc).collect(),
-    }
-}
-
-fn rule_to_doc(r: &pb::Rule) -> RuleDoc {
-    // Collapse to the flat `constraints` form when every group is a single constraint
-    // (plain AND); otherwise keep the CNF `constraint_groups` form.
-    let flat = r.constraint_groups.iter().all(|g| g.constraints.len() == 1);
-    let (constraints, constraint_groups) = if flat {
-        (
-            r.constraint_groups
-                .iter()
-                .map(|g| constraint_to_doc(&g.constraints[0]))
-                .collect(),
-            Vec::new(),
-        )
-    } else {
-        (
-            Vec::new(),
-            r.constraint_groups
-                .iter()
-                .map(|g| g.constraints.iter().map(constraint_to_doc).collect())
-                .collect(),
-        )
-    };
-    RuleDoc {
-        segment: (!r.segment_key.is_empty()).then(|| r.segment_key.clone()),
-        variant: (!r.variant_key.is_empty()).then(|| r.variant_key.clone()),
-        distributions: r
-            .distributions
-            .iter()
-            .map(|d| DistDoc {
-                variant: d.variant_key.clone(),
-                weight: d.weight,
-            })
-            .collect(),
-        constraints,
-        constraint_groups,
-        bucket_salt: r.bucket_salt.clone(),
-    }
-}
-
-fn segment_to_doc(s: &pb::Segment) -> SegmentDoc {
-    SegmentDoc {
-        key: s.key.clone(),
-        name: s.name.clone(),
-        constraints: s.constraints.iter().map(constraint_to_doc).collect(),
-    }
-}
-
-fn constraint_to_doc(c: &pb::Constraint) -> ConstraintDoc {
-    ConstraintDoc {
-        attribute: c.attribute.clone(),
-        operator: operator_name(c.operator),
-        values: c.values.iter().map(value_to_json).collect(),
-    }
-}
-
-fn value_type_from_name(s: &str) -> anyhow::Result<pb::ValueType> {
-    Ok(match s {
-        "boolean" => pb::ValueType::Boolean,
-        "string" => pb::ValueType::String,
-        "integer" => pb::ValueType::Integer,
-        "float" => pb::ValueType::Float,
-        "object" => pb::ValueType::Object,
-        other => bail!("unknown flag type `{other}`"),
-    })
-}
-
-fn value_type_name(v: i32) -> &'static str {
-    match pb::ValueType::try_from(v).unwrap_or_default() {
-        pb::ValueType::Boolean => "boolean",
-        pb::ValueType::String => "string",
-        pb::ValueType::Integer => "integer",
-        pb::ValueType::Float => "float",
-        pb::ValueType::Object => "object",
-        pb::ValueType::Unspecified => "unspecified",
-    }
-}
-
-/// Resolve the short operator name (e.g. `in`, `starts_with`) to its proto enum.
-fn operator_from_name(s: &str) -> anyhow::Result<pb::ConstraintOperator> {
-    let name = format!("CONSTRAINT_OPERATOR_{}", s.to_uppercase());
-    pb::ConstraintOperator::from_str_name(&name).with_context(|| format!("unknown operator `{s}`"))
-}
-
-fn operator_name(op: i32) -> String {
-    pb::ConstraintOperator::try_from(op)
-        .unwrap_or_default()
-        .as_str_name()
-        .trim_start_matches("CONSTRAINT_OPERATOR_")
-        .to_lowercase()
-}
-
 /// Live flags and segments keyed by name, used to render before/after diffs.
 struct LiveState {
     flags: BTreeMap<String, pb::Flag>,
@@ -713,7 +1291,7 @@ struct LiveState {
 
 /// Fetch the full live state (including archived flags) so the plan can show a
 /// before/after diff of every changed resource.
-async fn fetch_live(admin: &mut AdminClient<Channel>) -> anyhow::Result<LiveState> {
+async fn fetch_live(admin: &mut Admin) -> anyhow::Result<LiveState> {
     let flags = admin
         .list_flags(pb::ListFlagsRequest {
             include_archived: true,
@@ -792,12 +1370,36 @@ fn render_plan(
     );
 }
 
+/// Warn about configured flags that are past their `expires_at` at `now`, so temporary
+/// flags get removed rather than forgotten.
+fn warn_expired(flags: &[pb::Flag], now: DateTime<Utc>) {
+    for flag in flags {
+        let Ok(expires_at) = DateTime::parse_from_rfc3339(&flag.expires_at) else {
+            continue;
+        };
+        if expires_at > now {
+            continue;
+        }
+        let owners = if flag.owners.is_empty() {
+            String::new()
+        } else {
+            format!(" (owners: {})", flag.owners.join(", "))
+        };
+        eprintln!(
+            "{} flag `{}` expired on {}{owners}; remove it or extend `expires_at`.",
+            style("Warning:").yellow().bold(),
+            flag.key,
+            expires_at.date_naive(),
+        );
+    }
+}
+
 fn yaml_of_flag(f: &pb::Flag) -> String {
-    serde_yaml::to_string(&flag_to_doc(f)).unwrap_or_default()
+    serde_yaml::to_string(&FlagDoc::from(f)).unwrap_or_default()
 }
 
 fn yaml_of_segment(s: &pb::Segment) -> String {
-    serde_yaml::to_string(&segment_to_doc(s)).unwrap_or_default()
+    serde_yaml::to_string(&SegmentDoc::from(s)).unwrap_or_default()
 }
 
 /// Render a line-oriented unified diff, indented under the resource header.
@@ -874,6 +1476,12 @@ fn parse_segment(json: &Json) -> anyhow::Result<pb::Segment> {
         })
         .transpose()?
         .unwrap_or_default();
+    // Only the explicit CNF form here: a flat `constraints` array is already plain AND.
+    let constraint_groups = if obj.contains_key("constraint_groups") {
+        parse_constraint_groups(obj)?
+    } else {
+        Vec::new()
+    };
     Ok(pb::Segment {
         key: str_field(obj, "key")?.to_owned(),
         name: obj
@@ -882,6 +1490,10 @@ fn parse_segment(json: &Json) -> anyhow::Result<pb::Segment> {
             .unwrap_or_default()
             .to_owned(),
         constraints,
+        constraint_groups,
+        included_segments: str_list(obj, "included_segments")?,
+        excluded_segments: str_list(obj, "excluded_segments")?,
+        targeting_keys: str_list(obj, "targeting_keys")?,
     })
 }
 
@@ -990,6 +1602,83 @@ fn str_field<'a>(obj: &'a serde_json::Map<String, Json>, field: &str) -> anyhow:
         .with_context(|| format!("missing string field `{field}`"))
 }
 
+/// An optional array-of-strings field; absent is empty.
+fn str_list(obj: &serde_json::Map<String, Json>, field: &str) -> anyhow::Result<Vec<String>> {
+    let Some(items) = obj.get(field) else {
+        return Ok(Vec::new());
+    };
+    items
+        .as_array()
+        .with_context(|| format!("`{field}` must be an array of strings"))?
+        .iter()
+        .map(|v| {
+            v.as_str()
+                .map(String::from)
+                .with_context(|| format!("`{field}` must be an array of strings"))
+        })
+        .collect()
+}
+
+fn parse_rules(json: &str) -> anyhow::Result<Vec<pb::Rule>> {
+    let Json::Array(items) = parse_json(json) else {
+        bail!("rules must be a JSON array");
+    };
+    items.iter().map(parse_rule).collect()
+}
+
+/// Steps as `[{"weights": {"on": 10, "off": 90}, "hold": "1h"}, ...]`; `hold` may be
+/// omitted on the final step.
+fn parse_rollout_steps(json: &str) -> anyhow::Result<Vec<pb::RolloutStep>> {
+    let Json::Array(items) = parse_json(json) else {
+        bail!("steps must be a JSON array");
+    };
+    items
+        .iter()
+        .map(|item| {
+            let weights = item
+                .get("weights")
+                .and_then(Json::as_object)
+                .context("each step needs a `weights` object")?;
+            let distributions = weights
+                .iter()
+                .map(|(variant_key, weight)| {
+                    let weight = weight
+                        .as_u64()
+                        .with_context(|| format!("weight for `{variant_key}` must be a number"))?;
+                    Ok(pb::Distribution {
+                        variant_key: variant_key.clone(),
+                        weight: weight as u32,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let hold_secs = match item.get("hold").and_then(Json::as_str) {
+                Some(hold) => parse_duration(hold)?,
+                None => 0,
+            };
+            Ok(pb::RolloutStep {
+                distributions,
+                hold_secs,
+            })
+        })
+        .collect()
+}
+
+/// A whole number of seconds, minutes, hours or days: `90s`, `30m`, `1h`, `2d`.
+fn parse_duration(s: &str) -> anyhow::Result<u32> {
+    let scale = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => bail!("duration `{s}` must end in s, m, h or d"),
+    };
+    let n: u32 = s[..s.len() - 1]
+        .parse()
+        .with_context(|| format!("duration `{s}` must be a whole number and a unit"))?;
+    n.checked_mul(scale)
+        .with_context(|| format!("duration `{s}` is too long"))
+}
+
 fn parse_json(s: &str) -> Json {
     serde_json::from_str(s).unwrap_or_else(|_| Json::String(s.to_owned()))
 }
@@ -999,6 +1688,14 @@ fn print(value: Json) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn environment_to_json(e: &pb::Environment) -> Json {
+    json!({
+        "name": e.name,
+        "version": e.version,
+        "created_at": e.created_at,
+    })
+}
+
 fn flag_to_json(flag: &pb::Flag) -> Json {
     json!({
         "key": flag.key,
@@ -1012,28 +1709,199 @@ fn flag_to_json(flag: &pb::Flag) -> Json {
             "key": v.key,
             "value": v.value.as_ref().map(value_to_json).unwrap_or(Json::Null),
         })).collect::<Vec<_>>(),
-        "rules": flag.rules.iter().map(|r| json!({
-            "rank": r.rank,
-            "segment_key": r.segment_key,
-            "variant_key": r.variant_key,
-            "bucket_salt": r.bucket_salt,
-            "distributions": r.distributions.iter().map(|d| json!({
-                "variant_key": d.variant_key,
-                "weight": d.weight,
-            })).collect::<Vec<_>>(),
-            "constraint_groups": r.constraint_groups.iter().map(|g| {
-                g.constraints.iter().map(|c| json!({
-                    "attribute": c.attribute,
-                    "operator": pb::ConstraintOperator::try_from(c.operator)
-                        .unwrap_or_default()
-                        .as_str_name(),
-                    "values": c.values.iter().map(value_to_json).collect::<Vec<_>>(),
-                })).collect::<Vec<_>>()
-            }).collect::<Vec<_>>(),
+        "rules": flag.rules.iter().map(rule_to_json).collect::<Vec<_>>(),
+        "tags": flag.tags,
+        "owners": flag.owners,
+        "description": flag.description,
+        "expires_at": flag.expires_at,
+    })
+}
+
+fn rule_to_json(r: &pb::Rule) -> Json {
+    json!({
+        "rank": r.rank,
+        "segment_key": r.segment_key,
+        "variant_key": r.variant_key,
+        "bucket_salt": r.bucket_salt,
+        "distributions": r.distributions.iter().map(|d| json!({
+            "variant_key": d.variant_key,
+            "weight": d.weight,
+        })).collect::<Vec<_>>(),
+        "constraint_groups": r.constraint_groups.iter().map(|g| {
+            g.constraints.iter().map(|c| json!({
+                "attribute": c.attribute,
+                "operator": pb::ConstraintOperator::try_from(c.operator)
+                    .unwrap_or_default()
+                    .as_str_name(),
+                "values": c.values.iter().map(value_to_json).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>()
+        }).collect::<Vec<_>>(),
+    })
+}
+
+fn rollout_to_json(r: &pb::Rollout) -> Json {
+    json!({
+        "id": r.id,
+        "flag_key": r.flag_key,
+        "rule_rank": r.rule_rank,
+        "status": pb::RolloutStatus::try_from(r.status)
+            .unwrap_or_default()
+            .as_str_name(),
+        "current_step": r.current_step,
+        "steps": r.steps.iter().map(|s| json!({
+            "weights": s.distributions.iter()
+                .map(|d| (d.variant_key.clone(), Json::from(d.weight)))
+                .collect::<serde_json::Map<_, _>>(),
+            "hold_secs": s.hold_secs,
         })).collect::<Vec<_>>(),
+        "step_started_at": r.step_started_at,
+        "next_step_at": r.next_step_at,
+        "actor": r.actor,
+        "error": r.error,
+        "created_at": r.created_at,
+        "updated_at": r.updated_at,
+    })
+}
+
+fn webhook_to_json(w: &pb::Webhook) -> Json {
+    json!({
+        "id": w.id,
+        "url": w.url,
+        "description": w.description,
+        "actor": w.actor,
+        "created_at": w.created_at,
+    })
+}
+
+fn delivery_to_json(d: &pb::WebhookDelivery) -> Json {
+    json!({
+        "id": d.id,
+        "webhook_id": d.webhook_id,
+        "version": d.version,
+        "status": pb::WebhookDeliveryStatus::try_from(d.status)
+            .unwrap_or_default()
+            .as_str_name(),
+        "attempts": d.attempts,
+        "next_attempt_at": d.next_attempt_at,
+        "response_status": d.response_status,
+        "error": d.error,
+        "created_at": d.created_at,
+        "delivered_at": d.delivered_at,
+        "payload": serde_json::from_str::<Json>(&d.payload).unwrap_or(Json::Null),
     })
 }
 
+fn scheduled_to_json(c: &pb::ScheduledChange) -> Json {
+    let change = match &c.change {
+        Some(pb::scheduled_change::Change::Update(u)) => json!({
+            "enabled": u.enabled,
+            "default_variant_key": u.default_variant_key,
+        }),
+        Some(pb::scheduled_change::Change::Rules(set)) => json!({
+            "rules": set.rules.iter().map(rule_to_json).collect::<Vec<_>>(),
+        }),
+        None => Json::Null,
+    };
+    json!({
+        "id": c.id,
+        "flag_key": c.flag_key,
+        "apply_at": c.apply_at,
+        "status": pb::ScheduledChangeStatus::try_from(c.status)
+            .unwrap_or_default()
+            .as_str_name(),
+        "actor": c.actor,
+        "change": change,
+        "error": c.error,
+        "created_at": c.created_at,
+        "resolved_at": c.resolved_at,
+    })
+}
+
+fn resolution_to_json(t: &pb::FlagTrace) -> Json {
+    let meta = t.meta.clone().unwrap_or_default();
+    let mut out = json!({
+        "flag_key": t.flag_key,
+        "value": t.value.as_ref().map(value_to_json),
+        "variant": meta.variant,
+        "reason": pb::Reason::try_from(meta.reason)
+            .unwrap_or_default()
+            .as_str_name()
+            .trim_start_matches("REASON_"),
+    });
+    if !meta.error_code.is_empty() {
+        out["error_code"] = json!(meta.error_code);
+        out["error_message"] = json!(meta.error_message);
+    }
+    out
+}
+
+/// The resolution plus every rule tried, nesting the trace of any flag a
+/// `flag_matches` constraint resolved.
+fn flag_trace_to_json(t: &pb::FlagTrace) -> Json {
+    let mut out = resolution_to_json(t);
+    out["rules"] = t.rules.iter().map(|r| {
+        let mut rule = json!({
+            "rank": r.rank,
+            "matched": r.matched,
+            "constraint_groups": r.groups.iter().map(|g| json!({
+                "matched": g.matched,
+                "constraints": g.constraints.iter().map(constraint_trace_to_json).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(s) = &r.segment {
+            rule["segment"] = segment_trace_to_json(s);
+        }
+        if let Some(s) = &r.split {
+            rule["split"] = json!({ "bucket": s.bucket, "variant_key": s.variant_key });
+        }
+        rule
+    }).collect();
+    out
+}
+
+fn segment_trace_to_json(s: &pb::SegmentTrace) -> Json {
+    let mut out = json!({
+        "key": s.key,
+        "found": s.found,
+        "matched": s.matched,
+        "constraints": s.constraints.iter().map(constraint_trace_to_json).collect::<Vec<_>>(),
+    });
+    if s.cycle {
+        out["cycle"] = json!(true);
+    }
+    if let Some(listed) = s.listed {
+        out["listed"] = json!(listed);
+    }
+    if !s.groups.is_empty() {
+        out["constraint_groups"] = s.groups.iter().map(|g| json!({
+            "matched": g.matched,
+            "constraints": g.constraints.iter().map(constraint_trace_to_json).collect::<Vec<_>>(),
+        })).collect();
+    }
+    if !s.included.is_empty() {
+        out["included"] = s.included.iter().map(segment_trace_to_json).collect();
+    }
+    if !s.excluded.is_empty() {
+        out["excluded"] = s.excluded.iter().map(segment_trace_to_json).collect();
+    }
+    out
+}
+
+fn constraint_trace_to_json(c: &pb::ConstraintTrace) -> Json {
+    let constraint = c.constraint.clone().unwrap_or_default();
+    let mut out = json!({
+        "attribute": constraint.attribute,
+        "operator": operator_name(constraint.operator),
+        "values": constraint.values.iter().map(value_to_json).collect::<Vec<_>>(),
+        "matched": c.matched,
+    });
+    match &c.flag {
+        Some(flag) => out["flag"] = flag_trace_to_json(flag),
+        None => out["actual"] = c.actual.as_ref().map(value_to_json).into(),
+    }
+    out
+}
+
 fn segment_to_json(segment: &pb::Segment) -> Json {
     json!({
         "key": segment.key,
@@ -1045,6 +1913,19 @@ fn segment_to_json(segment: &pb::Segment) -> Json {
                 .as_str_name(),
             "values": c.values.iter().map(value_to_json).collect::<Vec<_>>(),
         })).collect::<Vec<_>>(),
+        "constraint_groups": segment.constraint_groups.iter().map(|g| {
+            g.constraints.iter().map(|c| json!({
+                "attribute": c.attribute,
+                "operator": pb::ConstraintOperator::try_from(c.operator)
+                    .unwrap_or_default()
+                    .as_str_name(),
+                "values": c.values.iter().map(value_to_json).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>()
+        }).collect::<Vec<_>>(),
+        "included_segments": segment.included_segments,
+        "excluded_segments": segment.excluded_segments,
+        // Lists can run to tens of thousands of keys; report the size only.
+        "targeting_key_count": segment.targeting_keys.len(),
     })
 }
 
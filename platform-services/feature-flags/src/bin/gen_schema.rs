@@ -1,6 +1,7 @@
 //! Generate the JSON Schemas for the `ffctl config` YAML files from the Rust types
-//! in `feature_flags::flag_config`. Run with `cargo run --bin gen-schema`; the
-//! output under `config/schema` is what the `# yaml-language-server` lines point at.
+//! in `feature_flags::flag_config` (the engine's, re-exported). Run with `cargo run
+//! --bin gen-schema`; the output under `config/schema` is what the
+//! `# yaml-language-server` lines point at.
 
 use feature_flags::flag_config::{FlagDoc, SegmentsFile};
 use std::path::PathBuf;
@@ -7,7 +7,8 @@ use crate::model::Snapshot;
 use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
 
-const SNAPSHOT_KEY: &str = "ff:snapshot";
+/// Prefix of the per-environment snapshot key, `ff:snapshot:<environment>`.
+const SNAPSHOT_KEY_PREFIX: &str = "ff:snapshot";
 
 /// Expiry on the cached snapshot. The boot-time version check already rejects a stale
 /// entry for correctness; this bounds how long a no-longer-advancing entry can linger
@@ -38,17 +39,22 @@ impl CacheClient {
         }
     }
 
-    pub async fn get_snapshot(&self) -> Option<Snapshot> {
+    pub async fn get_snapshot(&self, environment: &str) -> Option<Snapshot> {
         let mut conn = self.conn.clone();
-        let raw: Option<Vec<u8>> = conn.get(SNAPSHOT_KEY).await.ok().flatten();
+        let raw: Option<Vec<u8>> = conn
+            .get(format!("{SNAPSHOT_KEY_PREFIX}:{environment}"))
+            .await
+            .ok()
+            .flatten();
         raw.and_then(|bytes| serde_json::from_slice(&bytes).ok())
     }
 
-    pub async fn put_snapshot(&self, snapshot: &Snapshot) {
+    pub async fn put_snapshot(&self, environment: &str, snapshot: &Snapshot) {
         let Ok(bytes) = serde_json::to_vec(snapshot) else {
             return;
         };
         let mut conn = self.conn.clone();
-        let _: Result<(), _> = conn.set_ex(SNAPSHOT_KEY, bytes, SNAPSHOT_TTL_SECS).await;
+        let key = format!("{SNAPSHOT_KEY_PREFIX}:{environment}");
+        let _: Result<(), _> = conn.set_ex(key, bytes, SNAPSHOT_TTL_SECS).await;
     }
 }
@@ -0,0 +1,83 @@
+//! Server-side limits on what local-mode clients are sent, keyed by `client-id`. A
+//! listed client only ever receives the flags carrying one of its tags (plus their
+//! dependencies, see [`Snapshot::scoped`](crate::model::Snapshot::scoped)), whatever
+//! it asks for; unlisted clients get the tags they request, or everything.
+//!
+//! ```yaml
+//! clients:
+//!   checkout-api:
+//!     tags: ["checkout", "payments"]
+//!   search-indexer:
+//!     tags: ["search"]
+//! ```
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientScopes {
+    #[serde(default)]
+    clients: HashMap<String, ClientScope>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientScope {
+    tags: Vec<String>,
+}
+
+impl ClientScopes {
+    /// Read the YAML client scopes at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("reading client scopes {}", path.display()))?;
+        serde_yaml::from_str(&raw)
+            .with_context(|| format!("parsing client scopes {}", path.display()))
+    }
+
+    /// The tags `client_id`'s snapshot is limited to, given those it `requested`, or
+    /// `None` for the whole snapshot. A listed client's request can only narrow its
+    /// configured tags.
+    pub fn tags_for(&self, client_id: &str, requested: &[String]) -> Option<Vec<String>> {
+        match (self.clients.get(client_id), requested.is_empty()) {
+            (None, true) => None,
+            (None, false) => Some(requested.to_vec()),
+            (Some(scope), true) => Some(scope.tags.clone()),
+            (Some(scope), false) => Some(
+                requested
+                    .iter()
+                    .filter(|t| scope.tags.contains(t))
+                    .cloned()
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listed_clients_can_only_narrow_their_tags() {
+        let scopes: ClientScopes =
+            serde_yaml::from_str("clients:\n  checkout-api:\n    tags: [checkout, payments]\n")
+                .unwrap();
+        let tags = |client: &str, requested: &[&str]| {
+            let requested: Vec<String> = requested.iter().map(|t| t.to_string()).collect();
+            scopes.tags_for(client, &requested)
+        };
+
+        assert_eq!(tags("other", &[]), None);
+        assert_eq!(tags("other", &["search"]).unwrap(), ["search"]);
+        assert_eq!(tags("checkout-api", &[]).unwrap(), ["checkout", "payments"]);
+        assert_eq!(
+            tags("checkout-api", &["payments", "search"]).unwrap(),
+            ["payments"]
+        );
+        assert!(tags("checkout-api", &["search"]).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,6 @@
+use crate::store::HistoryRetention;
 use clap::Parser;
+use std::path::PathBuf;
 
 /// Runtime configuration, sourced entirely from the environment to match the other
 /// platform-services. Postgres is required; Dragonfly and OTLP are optional.
@@ -14,10 +16,46 @@ pub struct Config {
     /// Address the gRPC server binds to.
     #[arg(long, env = "GRPC_ADDR", default_value = "0.0.0.0:50051")]
     pub grpc_addr: String,
+
+    /// Address the OFREP HTTP server binds to.
+    #[arg(long, env = "HTTP_ADDR", default_value = "0.0.0.0:8080")]
+    pub http_addr: String,
+
+    /// YAML file of Admin API tokens, OIDC issuer and role bindings (see
+    /// [`crate::auth`]). When unset, the Admin API is unauthenticated and trusts the
+    /// `actor` header.
+    #[arg(long, env = "AUTH_CONFIG")]
+    pub auth_config: Option<PathBuf>,
+
+    /// YAML file limiting which flags each `client-id`'s snapshots carry (see
+    /// [`crate::client_scopes`]). When unset, clients get the tags they ask for.
+    #[arg(long, env = "CLIENT_SCOPES")]
+    pub client_scopes: Option<PathBuf>,
+
+    /// Config versions each environment keeps in its history whatever their age.
+    #[arg(
+        long,
+        env = "HISTORY_KEEP_VERSIONS",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(i64).range(1..)
+    )]
+    pub history_keep_versions: i64,
+
+    /// Days a config version is kept in history, beyond the newest
+    /// `HISTORY_KEEP_VERSIONS`.
+    #[arg(long, env = "HISTORY_KEEP_DAYS", default_value_t = 90)]
+    pub history_keep_days: u32,
 }
 
 impl Config {
     pub fn from_env() -> Self {
         Config::parse()
     }
+
+    pub fn history_retention(&self) -> HistoryRetention {
+        HistoryRetention {
+            versions: self.history_keep_versions,
+            max_age: chrono::Duration::days(self.history_keep_days.into()),
+        }
+    }
 }
@@ -1,10 +1,19 @@
-use crate::model::{Rule, Segment, ValueType, Variant};
+use crate::auth::{Authenticator, Principal, Resource, Role};
+use crate::grpc::environment_of;
+use crate::model::{Distribution, Rule, Segment, ValueType, Variant};
 use crate::pb;
 use crate::pb::admin_server::Admin;
-use crate::snapshot::SnapshotManager;
-use crate::store::{ChangeOp, ConfigChange, FlagChange, Store};
+use crate::snapshot::{Environments, SnapshotManager};
+use crate::store::{
+    ApplyOutcome, ChangeOp, ConfigChange, ConfigSnapshot, DEFAULT_ENVIRONMENT, DeliveryStatus,
+    Environment, FlagChange, FlagUsage, Rollout, RolloutStatus, RolloutStep, ScheduleStatus,
+    ScheduledAction, ScheduledChange, StaleFlag, StaleReason, Webhook, WebhookDelivery,
+    linear_steps,
+};
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
+use uuid::Uuid;
 
 impl From<&ConfigChange> for pb::ConfigChange {
     fn from(c: &ConfigChange) -> Self {
@@ -22,6 +31,76 @@ impl From<&ConfigChange> for pb::ConfigChange {
     }
 }
 
+impl From<&ApplyOutcome> for pb::ApplyConfigResponse {
+    fn from(outcome: &ApplyOutcome) -> Self {
+        pb::ApplyConfigResponse {
+            changes: outcome.changes.iter().map(pb::ConfigChange::from).collect(),
+            from_version: outcome.from_version,
+            to_version: outcome.to_version,
+            applied: outcome.applied,
+        }
+    }
+}
+
+impl From<&ConfigSnapshot> for pb::ConfigSnapshot {
+    fn from(c: &ConfigSnapshot) -> Self {
+        let mut flags: Vec<_> = c.snapshot.flags.values().map(pb::Flag::from).collect();
+        flags.sort_by(|a, b| a.key.cmp(&b.key));
+        let mut segments: Vec<_> = c
+            .snapshot
+            .segments
+            .values()
+            .map(pb::Segment::from)
+            .collect();
+        segments.sort_by(|a, b| a.key.cmp(&b.key));
+        pb::ConfigSnapshot {
+            version: c.snapshot.version,
+            flags,
+            segments,
+            created_at: c.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&Webhook> for pb::Webhook {
+    fn from(w: &Webhook) -> Self {
+        pb::Webhook {
+            id: w.id.to_string(),
+            url: w.url.clone(),
+            description: w.description.clone(),
+            actor: w.actor.clone(),
+            created_at: w.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&WebhookDelivery> for pb::WebhookDelivery {
+    fn from(d: &WebhookDelivery) -> Self {
+        let status = match d.status {
+            DeliveryStatus::Pending => pb::WebhookDeliveryStatus::Pending,
+            DeliveryStatus::Delivered => pb::WebhookDeliveryStatus::Delivered,
+            DeliveryStatus::Failed => pb::WebhookDeliveryStatus::Failed,
+        };
+        let next_attempt_at = match d.status {
+            DeliveryStatus::Pending => d.next_attempt_at.to_rfc3339(),
+            _ => String::new(),
+        };
+        pb::WebhookDelivery {
+            id: d.id.to_string(),
+            webhook_id: d.webhook_id.to_string(),
+            version: d.version,
+            payload: d.payload.to_string(),
+            status: status as i32,
+            attempts: d.attempts,
+            next_attempt_at,
+            response_status: d.response_status,
+            error: d.error.clone(),
+            created_at: d.created_at.to_rfc3339(),
+            delivered_at: d.delivered_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        }
+    }
+}
+
 impl From<&FlagChange> for pb::FlagChange {
     fn from(c: &FlagChange) -> Self {
         pb::FlagChange {
@@ -37,8 +116,155 @@ impl From<&FlagChange> for pb::FlagChange {
     }
 }
 
-/// Identity of the caller, forwarded by the authenticated frontend in the `actor`
-/// gRPC metadata header. Falls back to `unknown` so an audit row is always written.
+impl From<&Environment> for pb::Environment {
+    fn from(e: &Environment) -> Self {
+        pb::Environment {
+            name: e.name.clone(),
+            version: e.version,
+            created_at: e.created_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&ScheduledChange> for pb::ScheduledChange {
+    fn from(c: &ScheduledChange) -> Self {
+        let status = match c.status {
+            ScheduleStatus::Pending => pb::ScheduledChangeStatus::Pending,
+            ScheduleStatus::Applied => pb::ScheduledChangeStatus::Applied,
+            ScheduleStatus::Cancelled => pb::ScheduledChangeStatus::Cancelled,
+            ScheduleStatus::Failed => pb::ScheduledChangeStatus::Failed,
+        };
+        let change = match &c.action {
+            ScheduledAction::UpdateFlag {
+                enabled,
+                default_variant_key,
+            } => pb::scheduled_change::Change::Update(pb::FlagUpdate {
+                enabled: *enabled,
+                default_variant_key: default_variant_key.clone(),
+            }),
+            ScheduledAction::SetFlagRules { rules } => {
+                pb::scheduled_change::Change::Rules(pb::RuleSet {
+                    rules: rules.iter().map(pb::Rule::from).collect(),
+                })
+            }
+        };
+        pb::ScheduledChange {
+            id: c.id.to_string(),
+            flag_key: c.flag_key.clone(),
+            apply_at: c.apply_at.to_rfc3339(),
+            created_at: c.created_at.to_rfc3339(),
+            resolved_at: c.resolved_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            actor: c.actor.clone(),
+            status: status as i32,
+            error: c.error.clone(),
+            change: Some(change),
+        }
+    }
+}
+
+impl From<&RolloutStep> for pb::RolloutStep {
+    fn from(step: &RolloutStep) -> Self {
+        pb::RolloutStep {
+            distributions: step
+                .distributions
+                .iter()
+                .map(|d| pb::Distribution {
+                    variant_key: d.variant_key.clone(),
+                    weight: d.weight,
+                })
+                .collect(),
+            hold_secs: step.hold_secs,
+        }
+    }
+}
+
+impl From<&pb::RolloutStep> for RolloutStep {
+    fn from(step: &pb::RolloutStep) -> Self {
+        RolloutStep {
+            distributions: step
+                .distributions
+                .iter()
+                .map(|d| Distribution {
+                    variant_key: d.variant_key.clone(),
+                    weight: d.weight,
+                })
+                .collect(),
+            hold_secs: step.hold_secs,
+        }
+    }
+}
+
+impl From<&Rollout> for pb::Rollout {
+    fn from(r: &Rollout) -> Self {
+        let status = match r.status {
+            RolloutStatus::Active => pb::RolloutStatus::Active,
+            RolloutStatus::Paused => pb::RolloutStatus::Paused,
+            RolloutStatus::Completed => pb::RolloutStatus::Completed,
+            RolloutStatus::Aborted => pb::RolloutStatus::Aborted,
+        };
+        pb::Rollout {
+            id: r.id.to_string(),
+            flag_key: r.flag_key.clone(),
+            rule_rank: r.rule_rank,
+            steps: r.steps.iter().map(pb::RolloutStep::from).collect(),
+            current_step: r.current_step as u32,
+            status: status as i32,
+            step_started_at: r.step_started_at.to_rfc3339(),
+            next_step_at: r.next_step_at().map(|t| t.to_rfc3339()).unwrap_or_default(),
+            actor: r.actor.clone(),
+            error: r.error.clone(),
+            created_at: r.created_at.to_rfc3339(),
+            updated_at: r.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&FlagUsage> for pb::FlagUsage {
+    fn from(u: &FlagUsage) -> Self {
+        pb::FlagUsage {
+            flag_key: u.flag_key.clone(),
+            variant_key: u.variant_key.clone(),
+            client_id: u.client_id.clone(),
+            count: u.count,
+            last_evaluated_at: u.last_evaluated_at.to_rfc3339(),
+        }
+    }
+}
+
+impl From<&StaleFlag> for pb::StaleFlag {
+    fn from(f: &StaleFlag) -> Self {
+        let (reason, variant_key) = match &f.reason {
+            StaleReason::NotEvaluated => (pb::StaleReason::NotEvaluated, String::new()),
+            StaleReason::SingleVariant(v) => (pb::StaleReason::SingleVariant, v.clone()),
+        };
+        pb::StaleFlag {
+            flag_key: f.flag_key.clone(),
+            reason: reason as i32,
+            variant_key,
+            last_evaluated_at: f
+                .last_evaluated_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument(format!("`{id}` is not a valid id")))
+}
+
+fn parse_rules(rules: &[pb::Rule]) -> Result<Vec<Rule>, Status> {
+    rules
+        .iter()
+        .map(Rule::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|e: crate::convert::ConversionError| Status::invalid_argument(e.to_string()))
+}
+
+/// Identity of the caller when auth is disabled, forwarded by the frontend in the
+/// `actor` gRPC metadata header. Falls back to `unknown` so an audit row is always
+/// written.
 fn actor_of<T>(request: &Request<T>) -> String {
     request
         .metadata()
@@ -49,22 +275,158 @@ fn actor_of<T>(request: &Request<T>) -> String {
         .to_owned()
 }
 
+/// The caller of an Admin RPC and what it may do in the environment the request
+/// names. Without an [`Authenticator`] every caller may do anything.
+struct Caller {
+    principal: Principal,
+    auth: Option<Arc<Authenticator>>,
+    environment: String,
+}
+
+impl Caller {
+    /// Who to record in the audit log.
+    fn actor(&self) -> &str {
+        &self.principal.subject
+    }
+
+    fn allows_in(&self, environment: &str, resource: Resource<'_>, role: Role) -> bool {
+        self.auth
+            .as_ref()
+            .is_none_or(|auth| auth.role(&self.principal, environment, resource) >= Some(role))
+    }
+
+    fn allows(&self, resource: Resource<'_>, role: Role) -> bool {
+        self.allows_in(&self.environment, resource, role)
+    }
+
+    fn require_in(
+        &self,
+        environment: &str,
+        resource: Resource<'_>,
+        role: Role,
+    ) -> Result<(), Status> {
+        if self.allows_in(environment, resource, role) {
+            return Ok(());
+        }
+        Err(Status::permission_denied(format!(
+            "`{}` needs the {role} role on {resource} in `{environment}`",
+            self.principal.subject
+        )))
+    }
+
+    fn require(&self, resource: Resource<'_>, role: Role) -> Result<(), Status> {
+        self.require_in(&self.environment, resource, role)
+    }
+
+    /// Whether the caller holds `role` on flag `key` as it stands in `mgr`'s snapshot,
+    /// tags included.
+    fn allows_flag(&self, mgr: &SnapshotManager, key: &str, role: Role) -> bool {
+        let tags = mgr.flag_tags(key);
+        self.allows_in(mgr.environment(), Resource::Flag { key, tags: &tags }, role)
+    }
+
+    fn require_flag(&self, mgr: &SnapshotManager, key: &str, role: Role) -> Result<(), Status> {
+        let tags = mgr.flag_tags(key);
+        self.require_in(mgr.environment(), Resource::Flag { key, tags: &tags }, role)
+    }
+
+    /// Changing a flag's tags changes which bindings cover it, so it takes the owner
+    /// role on the whole environment.
+    fn require_retag(
+        &self,
+        mgr: &SnapshotManager,
+        key: &str,
+        tags: &[String],
+    ) -> Result<(), Status> {
+        if mgr.flag_tags(key) == tags {
+            return Ok(());
+        }
+        self.require_in(mgr.environment(), Resource::Environment, Role::Owner)
+    }
+
+    /// Whether the caller holds `role` on anything in `environment`.
+    fn allows_any_in(&self, environment: &str, role: Role) -> bool {
+        self.auth
+            .as_ref()
+            .is_none_or(|auth| auth.any_role(&self.principal, environment) >= Some(role))
+    }
+
+    /// Require `role` on something in the environment, for reads of segments, which
+    /// any flag's rules may reference.
+    fn require_any(&self, role: Role) -> Result<(), Status> {
+        if self.allows_any_in(&self.environment, role) {
+            return Ok(());
+        }
+        Err(Status::permission_denied(format!(
+            "`{}` needs the {role} role in `{}`",
+            self.principal.subject, self.environment
+        )))
+    }
+}
+
 pub struct AdminService {
-    store: Store,
-    mgr: Arc<SnapshotManager>,
+    envs: Arc<Environments>,
+    auth: Option<Arc<Authenticator>>,
 }
 
 impl AdminService {
-    pub fn new(store: Store, mgr: Arc<SnapshotManager>) -> Self {
-        Self { store, mgr }
+    pub fn new(envs: Arc<Environments>) -> Self {
+        Self { envs, auth: None }
     }
 
-    /// Refresh the in-memory snapshot immediately after a write rather than waiting
-    /// for the LISTEN/NOTIFY round-trip, so read-your-writes holds on this replica.
-    async fn refresh(&self) {
-        if let Err(e) = self.mgr.reload().await {
-            tracing::error!("post-write snapshot reload failed: {e}");
-        }
+    /// Require a bearer token on every RPC, authorize it against `auth`'s role bindings
+    /// and audit under the authenticated principal rather than the `actor` header.
+    pub fn with_auth(mut self, auth: Authenticator) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Authenticate the request's bearer token, if auth is enabled.
+    async fn caller<T>(&self, request: &Request<T>) -> Result<Caller, Status> {
+        let environment = environment_of(request);
+        let Some(auth) = &self.auth else {
+            return Ok(Caller {
+                principal: Principal {
+                    subject: actor_of(request),
+                    groups: Vec::new(),
+                },
+                auth: None,
+                environment,
+            });
+        };
+        let bearer = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| Status::unauthenticated("bearer token required"))?;
+        let principal = auth.authenticate(bearer).await.map_err(|e| {
+            tracing::info!("rejected admin token: {e:#}");
+            Status::unauthenticated("invalid bearer token")
+        })?;
+        Ok(Caller {
+            principal,
+            auth: Some(auth.clone()),
+            environment,
+        })
+    }
+
+    /// The snapshot, and through it the store, of the environment the request names.
+    async fn scope<T>(&self, request: &Request<T>) -> Result<Arc<SnapshotManager>, Status> {
+        Ok(self.envs.get(&environment_of(request)).await?)
+    }
+}
+
+/// Refresh the in-memory snapshot immediately after a write rather than waiting for the
+/// LISTEN/NOTIFY round-trip, so read-your-writes holds on this replica.
+async fn refresh(mgr: &SnapshotManager) {
+    if let Err(e) = mgr.reload().await {
+        tracing::error!(
+            environment = mgr.environment(),
+            "post-write snapshot reload failed: {e}"
+        );
     }
 }
 
@@ -74,14 +436,16 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::CreateFlagRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
+        caller.require_flag(&mgr, &req.key, Role::Owner)?;
         let value_type = ValueType::try_from(req.value_type())
             .map_err(|e| Status::invalid_argument(e.to_string()))?;
         let variants: Vec<_> = req.variants.iter().map(Variant::from).collect();
-        self.store
+        mgr.store()
             .create_flag(
-                &actor,
+                caller.actor(),
                 &req.key,
                 value_type,
                 req.enabled,
@@ -89,15 +453,19 @@ impl Admin for AdminService {
                 &variants,
             )
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(&self.mgr.get_flag(&req.key)?)))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.key)?)))
     }
 
     async fn get_flag(
         &self,
         request: Request<pb::GetFlagRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let flag = self.mgr.get_flag(&request.into_inner().key)?;
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let key = request.into_inner().key;
+        caller.require_flag(&mgr, &key, Role::Viewer)?;
+        let flag = mgr.get_flag(&key)?;
         Ok(Response::new(pb::Flag::from(&flag)))
     }
 
@@ -105,9 +473,21 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::ListFlagsRequest>,
     ) -> Result<Response<pb::ListFlagsResponse>, Status> {
-        let flags = self.mgr.list_flags(request.into_inner().include_archived);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let flags = mgr.list_flags(request.into_inner().include_archived);
         Ok(Response::new(pb::ListFlagsResponse {
-            flags: flags.iter().map(pb::Flag::from).collect(),
+            flags: flags
+                .iter()
+                .filter(|f| {
+                    let resource = Resource::Flag {
+                        key: &f.key,
+                        tags: &f.tags,
+                    };
+                    caller.allows(resource, Role::Viewer)
+                })
+                .map(pb::Flag::from)
+                .collect(),
         }))
     }
 
@@ -115,37 +495,47 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::UpdateFlagRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
-        self.store
-            .update_flag(&actor, &req.key, req.enabled, &req.default_variant_key)
+        caller.require_flag(&mgr, &req.key, Role::Editor)?;
+        mgr.store()
+            .update_flag(
+                caller.actor(),
+                &req.key,
+                req.enabled,
+                &req.default_variant_key,
+            )
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(&self.mgr.get_flag(&req.key)?)))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.key)?)))
     }
 
     async fn archive_flag(
         &self,
         request: Request<pb::ArchiveFlagRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
-        self.store
-            .archive_flag(&actor, &req.key, req.archived)
+        caller.require_flag(&mgr, &req.key, Role::Editor)?;
+        mgr.store()
+            .archive_flag(caller.actor(), &req.key, req.archived)
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(&self.mgr.get_flag(&req.key)?)))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.key)?)))
     }
 
     async fn delete_flag(
         &self,
         request: Request<pb::DeleteFlagRequest>,
     ) -> Result<Response<pb::DeleteFlagResponse>, Status> {
-        let actor = actor_of(&request);
-        self.store
-            .delete_flag(&actor, &request.into_inner().key)
-            .await?;
-        self.refresh().await;
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let key = request.into_inner().key;
+        caller.require_flag(&mgr, &key, Role::Owner)?;
+        mgr.store().delete_flag(caller.actor(), &key).await?;
+        refresh(&mgr).await;
         Ok(Response::new(pb::DeleteFlagResponse {}))
     }
 
@@ -153,43 +543,45 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::UpsertVariantRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
+        caller.require_flag(&mgr, &req.flag_key, Role::Editor)?;
         let variant = req
             .variant
             .as_ref()
             .map(Variant::from)
             .ok_or_else(|| Status::invalid_argument("variant is required"))?;
-        self.store
-            .upsert_variant(&actor, &req.flag_key, &variant)
+        mgr.store()
+            .upsert_variant(caller.actor(), &req.flag_key, &variant)
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(
-            &self.mgr.get_flag(&req.flag_key)?,
-        )))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.flag_key)?)))
     }
 
     async fn delete_variant(
         &self,
         request: Request<pb::DeleteVariantRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
-        self.store
-            .delete_variant(&actor, &req.flag_key, &req.variant_key)
+        caller.require_flag(&mgr, &req.flag_key, Role::Editor)?;
+        mgr.store()
+            .delete_variant(caller.actor(), &req.flag_key, &req.variant_key)
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(
-            &self.mgr.get_flag(&req.flag_key)?,
-        )))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.flag_key)?)))
     }
 
     async fn create_segment(
         &self,
         request: Request<pb::CreateSegmentRequest>,
     ) -> Result<Response<pb::Segment>, Status> {
-        let actor = actor_of(&request);
-        self.upsert_segment_inner(&actor, request.into_inner().segment)
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Editor)?;
+        self.upsert_segment_inner(&mgr, caller.actor(), request.into_inner().segment)
             .await
     }
 
@@ -197,15 +589,21 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::GetSegmentRequest>,
     ) -> Result<Response<pb::Segment>, Status> {
-        let segment = self.mgr.get_segment(&request.into_inner().key)?;
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require_any(Role::Viewer)?;
+        let segment = mgr.get_segment(&request.into_inner().key)?;
         Ok(Response::new(pb::Segment::from(&segment)))
     }
 
     async fn list_segments(
         &self,
-        _request: Request<pb::ListSegmentsRequest>,
+        request: Request<pb::ListSegmentsRequest>,
     ) -> Result<Response<pb::ListSegmentsResponse>, Status> {
-        let segments = self.mgr.list_segments();
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require_any(Role::Viewer)?;
+        let segments = mgr.list_segments();
         Ok(Response::new(pb::ListSegmentsResponse {
             segments: segments.iter().map(pb::Segment::from).collect(),
         }))
@@ -215,8 +613,10 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::UpdateSegmentRequest>,
     ) -> Result<Response<pb::Segment>, Status> {
-        let actor = actor_of(&request);
-        self.upsert_segment_inner(&actor, request.into_inner().segment)
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Editor)?;
+        self.upsert_segment_inner(&mgr, caller.actor(), request.into_inner().segment)
             .await
     }
 
@@ -224,43 +624,64 @@ impl Admin for AdminService {
         &self,
         request: Request<pb::DeleteSegmentRequest>,
     ) -> Result<Response<pb::DeleteSegmentResponse>, Status> {
-        let actor = actor_of(&request);
-        self.store
-            .delete_segment(&actor, &request.into_inner().key)
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Editor)?;
+        mgr.store()
+            .delete_segment(caller.actor(), &request.into_inner().key)
             .await?;
-        self.refresh().await;
+        refresh(&mgr).await;
         Ok(Response::new(pb::DeleteSegmentResponse {}))
     }
 
+    async fn update_segment_keys(
+        &self,
+        request: Request<pb::UpdateSegmentKeysRequest>,
+    ) -> Result<Response<pb::UpdateSegmentKeysResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Editor)?;
+        let req = request.into_inner();
+        let key_count = mgr
+            .store()
+            .update_segment_keys(caller.actor(), &req.segment_key, &req.add, &req.remove)
+            .await?;
+        refresh(&mgr).await;
+        Ok(Response::new(pb::UpdateSegmentKeysResponse {
+            key_count: key_count as u64,
+        }))
+    }
+
     async fn set_flag_rules(
         &self,
         request: Request<pb::SetFlagRulesRequest>,
     ) -> Result<Response<pb::Flag>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
-        let rules: Vec<_> = req
-            .rules
-            .iter()
-            .map(Rule::try_from)
-            .collect::<Result<_, _>>()
-            .map_err(|e: crate::convert::ConversionError| {
-                Status::invalid_argument(e.to_string())
-            })?;
-        self.store
-            .set_flag_rules(&actor, &req.flag_key, &rules)
+        caller.require_flag(&mgr, &req.flag_key, Role::Editor)?;
+        let rules = parse_rules(&req.rules)?;
+        mgr.store()
+            .set_flag_rules(caller.actor(), &req.flag_key, &rules)
             .await?;
-        self.refresh().await;
-        Ok(Response::new(pb::Flag::from(
-            &self.mgr.get_flag(&req.flag_key)?,
-        )))
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Flag::from(&mgr.get_flag(&req.flag_key)?)))
     }
 
     async fn apply_config(
         &self,
         request: Request<pb::ApplyConfigRequest>,
     ) -> Result<Response<pb::ApplyConfigResponse>, Status> {
-        let actor = actor_of(&request);
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
+        // A dry run only reads, so previewing a plan needs no more than viewing.
+        let needed = if req.dry_run {
+            Role::Viewer
+        } else {
+            Role::Owner
+        };
+        caller.require(Resource::Environment, needed)?;
         let flags = req
             .flags
             .iter()
@@ -273,49 +694,479 @@ impl Admin for AdminService {
             .map(Segment::try_from)
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| Status::invalid_argument(e.to_string()))?;
-        let outcome = self
-            .store
-            .apply_config(&actor, &flags, &segments, req.dry_run, req.expected_version)
+        let outcome = mgr
+            .store()
+            .apply_config(
+                caller.actor(),
+                &flags,
+                &segments,
+                req.dry_run,
+                req.expected_version,
+            )
             .await?;
         if outcome.applied && !outcome.changes.is_empty() {
-            self.refresh().await;
+            refresh(&mgr).await;
         }
-        Ok(Response::new(pb::ApplyConfigResponse {
-            changes: outcome.changes.iter().map(pb::ConfigChange::from).collect(),
-            from_version: outcome.from_version,
-            to_version: outcome.to_version,
-            applied: outcome.applied,
-        }))
+        Ok(Response::new(pb::ApplyConfigResponse::from(&outcome)))
     }
 
     async fn list_changes(
         &self,
         request: Request<pb::ListChangesRequest>,
     ) -> Result<Response<pb::ListChangesResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
         let req = request.into_inner();
-        let changes = self
-            .store
+        let changes = mgr
+            .store()
             .list_changes(&req.target_kind, &req.target_key, req.limit.into())
             .await?;
+        // Rows the caller may not view are dropped after the limit, so a scoped caller
+        // can see fewer than `limit` rows.
         Ok(Response::new(pb::ListChangesResponse {
-            changes: changes.iter().map(pb::FlagChange::from).collect(),
+            changes: changes
+                .iter()
+                .filter(|c| match c.target_kind.as_str() {
+                    "flag" => caller.allows_flag(&mgr, &c.target_key, Role::Viewer),
+                    _ => caller.allows(Resource::Environment, Role::Viewer),
+                })
+                .map(pb::FlagChange::from)
+                .collect(),
+        }))
+    }
+
+    async fn schedule_change(
+        &self,
+        request: Request<pb::ScheduleChangeRequest>,
+    ) -> Result<Response<pb::ScheduledChange>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        caller.require_flag(&mgr, &req.flag_key, Role::Editor)?;
+        let apply_at = DateTime::parse_from_rfc3339(&req.apply_at)
+            .map_err(|e| Status::invalid_argument(format!("apply_at `{}`: {e}", req.apply_at)))?
+            .with_timezone(&Utc);
+        let action = match req.change {
+            Some(pb::schedule_change_request::Change::Update(update)) => {
+                ScheduledAction::UpdateFlag {
+                    enabled: update.enabled,
+                    default_variant_key: update.default_variant_key,
+                }
+            }
+            Some(pb::schedule_change_request::Change::Rules(set)) => {
+                ScheduledAction::SetFlagRules {
+                    rules: parse_rules(&set.rules)?,
+                }
+            }
+            None => return Err(Status::invalid_argument("change is required")),
+        };
+        let scheduled = mgr
+            .store()
+            .schedule_change(caller.actor(), &req.flag_key, &action, apply_at)
+            .await?;
+        Ok(Response::new(pb::ScheduledChange::from(&scheduled)))
+    }
+
+    async fn list_scheduled_changes(
+        &self,
+        request: Request<pb::ListScheduledChangesRequest>,
+    ) -> Result<Response<pb::ListScheduledChangesResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        let changes = mgr
+            .store()
+            .list_scheduled_changes(&req.flag_key, req.include_resolved)
+            .await?;
+        Ok(Response::new(pb::ListScheduledChangesResponse {
+            changes: changes
+                .iter()
+                .filter(|c| caller.allows_flag(&mgr, &c.flag_key, Role::Viewer))
+                .map(pb::ScheduledChange::from)
+                .collect(),
         }))
     }
+
+    async fn cancel_scheduled_change(
+        &self,
+        request: Request<pb::CancelScheduledChangeRequest>,
+    ) -> Result<Response<pb::ScheduledChange>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let id = parse_id(&request.into_inner().id)?;
+        let flag_key = mgr.store().scheduled_change_flag_key(id).await?;
+        caller.require_flag(&mgr, &flag_key, Role::Editor)?;
+        let cancelled = mgr.store().cancel_scheduled_change(id).await?;
+        Ok(Response::new(pb::ScheduledChange::from(&cancelled)))
+    }
+
+    async fn start_rollout(
+        &self,
+        request: Request<pb::StartRolloutRequest>,
+    ) -> Result<Response<pb::Rollout>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        caller.require_flag(&mgr, &req.flag_key, Role::Editor)?;
+        let steps = match req.plan {
+            Some(pb::start_rollout_request::Plan::Steps(plan)) => {
+                plan.steps.iter().map(RolloutStep::from).collect()
+            }
+            Some(pb::start_rollout_request::Plan::Linear(ramp)) => linear_steps(
+                &ramp.variant_key,
+                &ramp.baseline_variant_key,
+                ramp.start_weight,
+                ramp.end_weight,
+                ramp.increment,
+                ramp.step_secs,
+            )?,
+            None => return Err(Status::invalid_argument("plan is required")),
+        };
+        let rollout = mgr
+            .store()
+            .start_rollout(caller.actor(), &req.flag_key, req.rule_rank, &steps)
+            .await?;
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Rollout::from(&rollout)))
+    }
+
+    async fn list_rollouts(
+        &self,
+        request: Request<pb::ListRolloutsRequest>,
+    ) -> Result<Response<pb::ListRolloutsResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        let rollouts = mgr
+            .store()
+            .list_rollouts(&req.flag_key, req.include_finished)
+            .await?;
+        Ok(Response::new(pb::ListRolloutsResponse {
+            rollouts: rollouts
+                .iter()
+                .filter(|r| caller.allows_flag(&mgr, &r.flag_key, Role::Viewer))
+                .map(pb::Rollout::from)
+                .collect(),
+        }))
+    }
+
+    async fn pause_rollout(
+        &self,
+        request: Request<pb::PauseRolloutRequest>,
+    ) -> Result<Response<pb::Rollout>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let id = parse_id(&request.into_inner().id)?;
+        let flag_key = mgr.store().rollout_flag_key(id).await?;
+        caller.require_flag(&mgr, &flag_key, Role::Editor)?;
+        let rollout = mgr.store().pause_rollout(caller.actor(), id).await?;
+        Ok(Response::new(pb::Rollout::from(&rollout)))
+    }
+
+    async fn resume_rollout(
+        &self,
+        request: Request<pb::ResumeRolloutRequest>,
+    ) -> Result<Response<pb::Rollout>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let id = parse_id(&request.into_inner().id)?;
+        let flag_key = mgr.store().rollout_flag_key(id).await?;
+        caller.require_flag(&mgr, &flag_key, Role::Editor)?;
+        let rollout = mgr.store().resume_rollout(caller.actor(), id).await?;
+        Ok(Response::new(pb::Rollout::from(&rollout)))
+    }
+
+    async fn abort_rollout(
+        &self,
+        request: Request<pb::AbortRolloutRequest>,
+    ) -> Result<Response<pb::Rollout>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let id = parse_id(&request.into_inner().id)?;
+        let flag_key = mgr.store().rollout_flag_key(id).await?;
+        caller.require_flag(&mgr, &flag_key, Role::Editor)?;
+        let rollout = mgr.store().abort_rollout(caller.actor(), id).await?;
+        refresh(&mgr).await;
+        Ok(Response::new(pb::Rollout::from(&rollout)))
+    }
+
+    async fn get_flag_usage(
+        &self,
+        request: Request<pb::GetFlagUsageRequest>,
+    ) -> Result<Response<pb::GetFlagUsageResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        caller.require_flag(&mgr, &req.flag_key, Role::Viewer)?;
+        let days = match req.days {
+            0 => 7,
+            days => days,
+        };
+        let usage = mgr
+            .store()
+            .flag_usage(&req.flag_key, days, Utc::now())
+            .await?;
+        Ok(Response::new(pb::GetFlagUsageResponse {
+            usage: usage.iter().map(pb::FlagUsage::from).collect(),
+        }))
+    }
+
+    async fn list_stale_flags(
+        &self,
+        request: Request<pb::ListStaleFlagsRequest>,
+    ) -> Result<Response<pb::ListStaleFlagsResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let days = match request.into_inner().days {
+            0 => 30,
+            days => days,
+        };
+        let flags = mgr.store().stale_flags(days, Utc::now()).await?;
+        Ok(Response::new(pb::ListStaleFlagsResponse {
+            flags: flags
+                .iter()
+                .filter(|f| caller.allows_flag(&mgr, &f.flag_key, Role::Viewer))
+                .map(pb::StaleFlag::from)
+                .collect(),
+        }))
+    }
+
+    async fn list_environments(
+        &self,
+        request: Request<pb::ListEnvironmentsRequest>,
+    ) -> Result<Response<pb::ListEnvironmentsResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let environments = self
+            .envs
+            .store(DEFAULT_ENVIRONMENT)
+            .list_environments()
+            .await?;
+        Ok(Response::new(pb::ListEnvironmentsResponse {
+            environments: environments
+                .iter()
+                .filter(|e| caller.allows_any_in(&e.name, Role::Viewer))
+                .map(pb::Environment::from)
+                .collect(),
+        }))
+    }
+
+    async fn create_environment(
+        &self,
+        request: Request<pb::CreateEnvironmentRequest>,
+    ) -> Result<Response<pb::Environment>, Status> {
+        let caller = self.caller(&request).await?;
+        let name = request.into_inner().name;
+        caller.require_in(&name, Resource::Environment, Role::Owner)?;
+        let created = self
+            .envs
+            .store(DEFAULT_ENVIRONMENT)
+            .create_environment(caller.actor(), &name)
+            .await?;
+        Ok(Response::new(pb::Environment::from(&created)))
+    }
+
+    async fn promote_flag(
+        &self,
+        request: Request<pb::PromoteFlagRequest>,
+    ) -> Result<Response<pb::PromoteFlagResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let req = request.into_inner();
+        if req.from_environment.is_empty() || req.to_environment.is_empty() {
+            return Err(Status::invalid_argument(
+                "from_environment and to_environment are required",
+            ));
+        }
+        let from = self.envs.get(&req.from_environment).await?;
+        let to = self.envs.get(&req.to_environment).await?;
+        caller.require_flag(&from, &req.flag_key, Role::Viewer)?;
+        caller.require_flag(&to, &req.flag_key, Role::Owner)?;
+        // The tags the promotion will write, read from the store as it reads them
+        // rather than from a snapshot that may trail it.
+        let source = from.store().load_snapshot().await?;
+        let tags = source.flags.get(&req.flag_key).map(|f| f.tags.clone());
+        caller.require_retag(&to, &req.flag_key, &tags.unwrap_or_default())?;
+        let promotion = from
+            .store()
+            .promote_flag(caller.actor(), &req.flag_key, to.store())
+            .await?;
+        if promotion.changed {
+            refresh(&to).await;
+        }
+        Ok(Response::new(pb::PromoteFlagResponse {
+            flag: Some(pb::Flag::from(&to.get_flag(&req.flag_key)?)),
+            created: promotion.created,
+            copied_segments: promotion.copied_segments,
+            changed: promotion.changed,
+            version: promotion.version,
+        }))
+    }
+
+    async fn get_snapshot_at_version(
+        &self,
+        request: Request<pb::GetSnapshotAtVersionRequest>,
+    ) -> Result<Response<pb::ConfigSnapshot>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Viewer)?;
+        let snapshot = mgr
+            .store()
+            .snapshot_at_version(request.into_inner().version)
+            .await?;
+        Ok(Response::new(pb::ConfigSnapshot::from(&snapshot)))
+    }
+
+    async fn diff_versions(
+        &self,
+        request: Request<pb::DiffVersionsRequest>,
+    ) -> Result<Response<pb::DiffVersionsResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Viewer)?;
+        let req = request.into_inner();
+        let diff = mgr
+            .store()
+            .diff_versions(req.from_version, req.to_version)
+            .await?;
+        Ok(Response::new(pb::DiffVersionsResponse {
+            changes: diff.changes.iter().map(pb::ConfigChange::from).collect(),
+            from_version: diff.from_version,
+            to_version: diff.to_version,
+        }))
+    }
+
+    async fn rollback_to_version(
+        &self,
+        request: Request<pb::RollbackToVersionRequest>,
+    ) -> Result<Response<pb::ApplyConfigResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        let req = request.into_inner();
+        let flag_key = Some(req.flag_key.as_str()).filter(|k| !k.is_empty());
+        let needed = if req.dry_run {
+            Role::Viewer
+        } else {
+            Role::Owner
+        };
+        // Restoring one flag is like recreating it; restoring everything, like an apply.
+        match flag_key {
+            Some(key) => {
+                caller.require_flag(&mgr, key, needed)?;
+                if !req.dry_run {
+                    let then = mgr.store().snapshot_at_version(req.version).await?;
+                    let tags = then.snapshot.flags.get(key).map(|f| f.tags.clone());
+                    caller.require_retag(&mgr, key, &tags.unwrap_or_default())?;
+                }
+            }
+            None => caller.require(Resource::Environment, needed)?,
+        }
+        let outcome = mgr
+            .store()
+            .rollback_to_version(
+                caller.actor(),
+                req.version,
+                flag_key,
+                req.dry_run,
+                req.expected_version,
+            )
+            .await?;
+        if outcome.applied && !outcome.changes.is_empty() {
+            refresh(&mgr).await;
+        }
+        Ok(Response::new(pb::ApplyConfigResponse::from(&outcome)))
+    }
+
+    async fn create_webhook(
+        &self,
+        request: Request<pb::CreateWebhookRequest>,
+    ) -> Result<Response<pb::CreateWebhookResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        // A webhook sees every change in the environment.
+        caller.require(Resource::Environment, Role::Owner)?;
+        let req = request.into_inner();
+        let (webhook, secret) = mgr
+            .store()
+            .create_webhook(caller.actor(), &req.url, &req.description)
+            .await?;
+        Ok(Response::new(pb::CreateWebhookResponse {
+            webhook: Some(pb::Webhook::from(&webhook)),
+            secret,
+        }))
+    }
+
+    async fn list_webhooks(
+        &self,
+        request: Request<pb::ListWebhooksRequest>,
+    ) -> Result<Response<pb::ListWebhooksResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Viewer)?;
+        let webhooks = mgr.store().list_webhooks().await?;
+        Ok(Response::new(pb::ListWebhooksResponse {
+            webhooks: webhooks.iter().map(pb::Webhook::from).collect(),
+        }))
+    }
+
+    async fn delete_webhook(
+        &self,
+        request: Request<pb::DeleteWebhookRequest>,
+    ) -> Result<Response<pb::DeleteWebhookResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Owner)?;
+        let id = parse_id(&request.into_inner().id)?;
+        mgr.store().delete_webhook(caller.actor(), id).await?;
+        Ok(Response::new(pb::DeleteWebhookResponse {}))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        request: Request<pb::ListWebhookDeliveriesRequest>,
+    ) -> Result<Response<pb::ListWebhookDeliveriesResponse>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Viewer)?;
+        let req = request.into_inner();
+        let webhook_id = match req.webhook_id.as_str() {
+            "" => None,
+            id => Some(parse_id(id)?),
+        };
+        let deliveries = mgr
+            .store()
+            .list_webhook_deliveries(webhook_id, req.limit)
+            .await?;
+        Ok(Response::new(pb::ListWebhookDeliveriesResponse {
+            deliveries: deliveries.iter().map(pb::WebhookDelivery::from).collect(),
+        }))
+    }
+
+    async fn redeliver_webhook(
+        &self,
+        request: Request<pb::RedeliverWebhookRequest>,
+    ) -> Result<Response<pb::WebhookDelivery>, Status> {
+        let caller = self.caller(&request).await?;
+        let mgr = self.scope(&request).await?;
+        caller.require(Resource::Environment, Role::Owner)?;
+        let id = parse_id(&request.into_inner().delivery_id)?;
+        let delivery = mgr.store().redeliver(id).await?;
+        Ok(Response::new(pb::WebhookDelivery::from(&delivery)))
+    }
 }
 
 impl AdminService {
     async fn upsert_segment_inner(
         &self,
+        mgr: &SnapshotManager,
         actor: &str,
         segment: Option<pb::Segment>,
     ) -> Result<Response<pb::Segment>, Status> {
         let proto = segment.ok_or_else(|| Status::invalid_argument("segment is required"))?;
         let domain =
             Segment::try_from(&proto).map_err(|e| Status::invalid_argument(e.to_string()))?;
-        self.store.upsert_segment(actor, &domain).await?;
-        self.refresh().await;
+        mgr.store().upsert_segment(actor, &domain).await?;
+        refresh(mgr).await;
         Ok(Response::new(pb::Segment::from(
-            &self.mgr.get_segment(&domain.key)?,
+            &mgr.get_segment(&domain.key)?,
         )))
     }
 }
@@ -1,11 +1,17 @@
+use crate::client_scopes::ClientScopes;
 use crate::convert;
-use crate::engine::{Engine, EvalContext, Resolution};
-use crate::model::ValueType;
+use crate::engine::{EvalContext, EvalError, Resolution};
+use crate::error::AppError;
+use crate::grpc::environment_of;
+use crate::model::{Snapshot, ValueType};
 use crate::pb;
 use crate::pb::evaluation_server::Evaluation;
-use crate::snapshot::SnapshotManager;
+use crate::snapshot::{ConfigUpdate, Delta, Environments, SnapshotManager};
+use crate::store::Store;
+use crate::telemetry::Telemetry;
 use futures::stream::StreamExt;
 use serde_json::Value as Json;
+use std::borrow::Cow;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio_stream::wrappers::BroadcastStream;
@@ -13,17 +19,45 @@ use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 use tonic::{Request, Response, Status};
 
 pub struct EvaluationService {
-    mgr: Arc<SnapshotManager>,
+    envs: Arc<Environments>,
+    telemetry: Arc<Telemetry>,
+    scopes: Arc<ClientScopes>,
 }
 
 impl EvaluationService {
-    pub fn new(mgr: Arc<SnapshotManager>) -> Self {
-        Self { mgr }
+    pub fn new(envs: Arc<Environments>, telemetry: Arc<Telemetry>) -> Self {
+        Self {
+            envs,
+            telemetry,
+            scopes: Arc::default(),
+        }
+    }
+
+    /// Limit the snapshots each client is sent to the flags `scopes` allows it.
+    pub fn with_client_scopes(mut self, scopes: ClientScopes) -> Self {
+        self.scopes = Arc::new(scopes);
+        self
     }
 
-    fn resolve(&self, req: pb::ResolveRequest) -> (Engine, EvalContext, String) {
+    /// The snapshot of the environment the request names.
+    async fn scope<T>(&self, request: &Request<T>) -> Result<Arc<SnapshotManager>, Status> {
+        Ok(self.envs.get(&environment_of(request)).await?)
+    }
+
+    /// Evaluate the requested flag, counting the served variant against `client_id`.
+    fn resolve(
+        &self,
+        mgr: &SnapshotManager,
+        client_id: &str,
+        req: pb::ResolveRequest,
+    ) -> Result<Resolution, EvalError> {
         let ctx = req.context.unwrap_or_default().into();
-        (self.mgr.engine(), ctx, req.flag_key)
+        let result = mgr.engine().evaluate(&req.flag_key, &ctx);
+        if let Ok(res) = &result {
+            self.telemetry
+                .record(mgr.environment(), client_id, &req.flag_key, &res.variant);
+        }
+        result
     }
 }
 
@@ -48,13 +82,11 @@ enum Typed<T> {
 }
 
 fn resolved<T>(
-    engine: &Engine,
-    flag_key: &str,
-    ctx: &EvalContext,
+    result: Result<Resolution, EvalError>,
     extract: impl Fn(&Json) -> Option<T>,
     type_name: &str,
 ) -> Typed<T> {
-    match engine.evaluate(flag_key, ctx) {
+    match result {
         Ok(res) => match extract(&res.value) {
             Some(value) => Typed::Ok(value, pb::ResolutionMeta::from(&res)),
             None => Typed::Err(convert::type_mismatch(type_name)),
@@ -67,7 +99,28 @@ type EventStream = Pin<Box<dyn futures::Stream<Item = Result<pb::Event, Status>>
 type SnapshotStream =
     Pin<Box<dyn futures::Stream<Item = Result<pb::SnapshotResponse, Status>> + Send>>;
 
-fn snapshot_response(snapshot: &crate::model::Snapshot) -> pb::SnapshotResponse {
+type DeltaStream = Pin<Box<dyn futures::Stream<Item = Result<pb::SnapshotDelta, Status>> + Send>>;
+
+/// The reported counts naming a flag and variant that exist in `snapshot`. Anything
+/// else is a client bug or junk, and would otherwise become a usage row and metric
+/// series of its own.
+fn known_counts(
+    snapshot: &Snapshot,
+    counts: Vec<pb::EvaluationCount>,
+) -> Vec<(String, String, u64)> {
+    counts
+        .into_iter()
+        .filter(|c| {
+            snapshot
+                .flags
+                .get(&c.flag_key)
+                .is_some_and(|f| f.variants.iter().any(|v| v.key == c.variant_key))
+        })
+        .map(|c| (c.flag_key, c.variant_key, c.count))
+        .collect()
+}
+
+fn snapshot_response(snapshot: &Snapshot) -> pb::SnapshotResponse {
     pb::SnapshotResponse {
         version: snapshot.version,
         flags: snapshot.flags.values().map(pb::Flag::from).collect(),
@@ -75,6 +128,120 @@ fn snapshot_response(snapshot: &crate::model::Snapshot) -> pb::SnapshotResponse
     }
 }
 
+/// What a client limited to `tags` sees of `snapshot`: all of it for `None`.
+fn visible<'a>(snapshot: &'a Snapshot, tags: Option<&[String]>) -> Cow<'a, Snapshot> {
+    match tags {
+        Some(tags) => Cow::Owned(snapshot.scoped(tags)),
+        None => Cow::Borrowed(snapshot),
+    }
+}
+
+fn full_delta(snapshot: &Snapshot) -> pb::SnapshotDelta {
+    let resp = snapshot_response(snapshot);
+    pb::SnapshotDelta {
+        version: resp.version,
+        full: true,
+        flags: resp.flags,
+        segments: resp.segments,
+        ..Default::default()
+    }
+}
+
+fn delta_response(delta: &Delta) -> pb::SnapshotDelta {
+    pb::SnapshotDelta {
+        version: delta.version,
+        base_version: delta.base_version,
+        full: false,
+        flags: delta.flags.iter().map(pb::Flag::from).collect(),
+        segments: delta.segments.iter().map(pb::Segment::from).collect(),
+        removed_flag_keys: delta.removed_flag_keys.clone(),
+        removed_segment_keys: delta.removed_segment_keys.clone(),
+        ..Default::default()
+    }
+}
+
+/// The `scope` a stream limited to `tags` reports, for telling on reconnect whether
+/// the client's snapshot was built from the same part of the config.
+fn scope_id(tags: Option<&[String]>) -> String {
+    match tags {
+        None => "*".to_owned(),
+        Some(tags) => {
+            let mut tags = tags.to_vec();
+            tags.sort();
+            tags.dedup();
+            serde_json::to_string(&tags).expect("strings serialize")
+        }
+    }
+}
+
+/// The first message for a client holding version `known` of the part of the config
+/// `tags` selects: an empty delta when that's current, a delta from the saved snapshot
+/// of `known` when history still has it, and the whole (visible) snapshot otherwise.
+/// `known` must already be vetted as built under the same `tags`.
+async fn catch_up(
+    store: &Store,
+    known: i64,
+    current: &Snapshot,
+    tags: Option<&[String]>,
+) -> pb::SnapshotDelta {
+    if known == current.version {
+        return pb::SnapshotDelta {
+            version: known,
+            base_version: known,
+            ..Default::default()
+        };
+    }
+    let visible_now = visible(current, tags);
+    if known > 0 && known < current.version {
+        match store.snapshot_at_version(known).await {
+            Ok(old) => {
+                let delta = Delta::between(&visible(&old.snapshot, tags), &visible_now);
+                return delta_response(&delta);
+            }
+            Err(AppError::NotFound(_)) => {}
+            Err(e) => tracing::warn!(known, "loading snapshot to catch up from failed: {e}"),
+        }
+    }
+    full_delta(&visible_now)
+}
+
+/// The message a delta stream whose client is at version `*sent` sends for `update`,
+/// advancing `*sent`; `None` if the client already has it. After a gap (a lagged
+/// subscriber, or an update that doesn't start where the client is) the client gets
+/// the whole current snapshot instead.
+fn next_delta(
+    sent: &mut i64,
+    update: Result<ConfigUpdate, BroadcastStreamRecvError>,
+    current: &Snapshot,
+) -> Option<pb::SnapshotDelta> {
+    let msg = match update {
+        Ok(update) if update.delta.version <= *sent => return None,
+        Ok(update) if update.delta.base_version == *sent => delta_response(&update.delta),
+        _ if current.version <= *sent => return None,
+        _ => full_delta(current),
+    };
+    *sent = msg.version;
+    Some(msg)
+}
+
+/// The message a stream scoped to `tags` sends when the config changes: the delta from
+/// the scoped snapshot the client holds, `*last`, to `current`'s, which becomes the new
+/// `*last`. `None` if the client already has `current`. Since the stream keeps what it
+/// sent, a lagged subscriber needs no full resend.
+fn next_scoped_delta(
+    last: &mut Snapshot,
+    current: &Snapshot,
+    tags: &[String],
+) -> Option<pb::SnapshotDelta> {
+    if current.version <= last.version {
+        return None;
+    }
+    let scoped = current.scoped(tags);
+    let msg = delta_response(&Delta::between(last, &scoped));
+    *last = scoped;
+    Some(msg)
+}
+
 #[tonic::async_trait]
 impl Evaluation for EvaluationService {
     async fn resolve_boolean(
@@ -82,9 +249,10 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveRequest>,
     ) -> Result<Response<pb::ResolveBooleanResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve");
-        let (engine, ctx, flag_key) = self.resolve(request.into_inner());
-        let (value, meta) = match resolved(&engine, &flag_key, &ctx, Json::as_bool, "boolean") {
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve");
+        let result = self.resolve(&mgr, &client_id, request.into_inner());
+        let (value, meta) = match resolved(result, Json::as_bool, "boolean") {
             Typed::Ok(v, m) => (v, m),
             Typed::Err(m) => (false, m),
         };
@@ -99,15 +267,10 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveRequest>,
     ) -> Result<Response<pb::ResolveStringResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve");
-        let (engine, ctx, flag_key) = self.resolve(request.into_inner());
-        let (value, meta) = match resolved(
-            &engine,
-            &flag_key,
-            &ctx,
-            |j| j.as_str().map(str::to_owned),
-            "string",
-        ) {
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve");
+        let result = self.resolve(&mgr, &client_id, request.into_inner());
+        let (value, meta) = match resolved(result, |j| j.as_str().map(str::to_owned), "string") {
             Typed::Ok(v, m) => (v, m),
             Typed::Err(m) => (String::new(), m),
         };
@@ -122,9 +285,10 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveRequest>,
     ) -> Result<Response<pb::ResolveIntegerResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve");
-        let (engine, ctx, flag_key) = self.resolve(request.into_inner());
-        let (value, meta) = match resolved(&engine, &flag_key, &ctx, Json::as_i64, "integer") {
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve");
+        let result = self.resolve(&mgr, &client_id, request.into_inner());
+        let (value, meta) = match resolved(result, Json::as_i64, "integer") {
             Typed::Ok(v, m) => (v, m),
             Typed::Err(m) => (0, m),
         };
@@ -139,9 +303,10 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveRequest>,
     ) -> Result<Response<pb::ResolveFloatResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve");
-        let (engine, ctx, flag_key) = self.resolve(request.into_inner());
-        let (value, meta) = match resolved(&engine, &flag_key, &ctx, Json::as_f64, "float") {
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve");
+        let result = self.resolve(&mgr, &client_id, request.into_inner());
+        let (value, meta) = match resolved(result, Json::as_f64, "float") {
             Typed::Ok(v, m) => (v, m),
             Typed::Err(m) => (0.0, m),
         };
@@ -156,10 +321,11 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveRequest>,
     ) -> Result<Response<pb::ResolveObjectResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve");
-        let (engine, ctx, flag_key) = self.resolve(request.into_inner());
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve");
+        let result = self.resolve(&mgr, &client_id, request.into_inner());
         let extract = |j: &Json| j.as_object().map(|_| convert::json_to_struct(j));
-        let (value, meta) = match resolved(&engine, &flag_key, &ctx, extract, "object") {
+        let (value, meta) = match resolved(result, extract, "object") {
             Typed::Ok(v, m) => (Some(v), m),
             Typed::Err(m) => (None, m),
         };
@@ -174,16 +340,21 @@ impl Evaluation for EvaluationService {
         request: Request<pb::ResolveAllRequest>,
     ) -> Result<Response<pb::ResolveAllResponse>, Status> {
         let client_id = client_id_of(&request)?;
-        tracing::debug!(client_id, "resolve_all");
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "resolve_all");
         let ctx: EvalContext = request.into_inner().context.unwrap_or_default().into();
-        let engine = self.mgr.engine();
+        let engine = mgr.engine();
         let mut flags = Vec::new();
         for (key, flag) in &engine.snapshot().flags {
             if flag.archived {
                 continue;
             }
             let evaluated = match engine.evaluate(key, &ctx) {
-                Ok(res) => evaluated_flag(key, flag.value_type, &res),
+                Ok(res) => {
+                    self.telemetry
+                        .record(mgr.environment(), &client_id, key, &res.variant);
+                    evaluated_flag(key, flag.value_type, &res)
+                }
                 Err(e) => pb::EvaluatedFlag {
                     flag_key: key.clone(),
                     value_type: pb::ValueType::from(flag.value_type) as i32,
@@ -200,10 +371,49 @@ impl Evaluation for EvaluationService {
         &self,
         request: Request<pb::GetSnapshotRequest>,
     ) -> Result<Response<pb::SnapshotResponse>, Status> {
-        client_id_of(&request)?;
-        Ok(Response::new(snapshot_response(
-            self.mgr.engine().snapshot(),
-        )))
+        let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
+        let tags = self.scopes.tags_for(&client_id, &request.get_ref().tags);
+        let engine = mgr.engine();
+        Ok(Response::new(snapshot_response(&visible(
+            engine.snapshot(),
+            tags.as_deref(),
+        ))))
+    }
+
+    async fn explain_resolve(
+        &self,
+        request: Request<pb::ResolveRequest>,
+    ) -> Result<Response<pb::ExplainResolveResponse>, Status> {
+        let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
+        tracing::debug!(client_id, environment = mgr.environment(), "explain");
+        let req = request.into_inner();
+        let ctx: EvalContext = req.context.unwrap_or_default().into();
+        let explanation = mgr.engine().explain(&req.flag_key, &ctx);
+        Ok(Response::new(pb::ExplainResolveResponse {
+            trace: Some(pb::FlagTrace::from(&explanation)),
+        }))
+    }
+
+    async fn report_evaluations(
+        &self,
+        request: Request<pb::ReportEvaluationsRequest>,
+    ) -> Result<Response<pb::ReportEvaluationsResponse>, Status> {
+        let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
+        let reported = request.into_inner().counts;
+        let rows = reported.len();
+        let counts = known_counts(mgr.engine().snapshot(), reported);
+        tracing::debug!(
+            client_id,
+            rows,
+            unknown = rows - counts.len(),
+            "evaluations reported"
+        );
+        self.telemetry
+            .record_reported(mgr.environment(), &client_id, &counts);
+        Ok(Response::new(pb::ReportEvaluationsResponse {}))
     }
 
     type StreamSnapshotStream = SnapshotStream;
@@ -213,23 +423,102 @@ impl Evaluation for EvaluationService {
         request: Request<pb::GetSnapshotRequest>,
     ) -> Result<Response<Self::StreamSnapshotStream>, Status> {
         let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
         tracing::info!(
             client_id,
-            version = self.mgr.version(),
+            environment = mgr.environment(),
+            version = mgr.version(),
             "snapshot stream connected"
         );
-        let rx = self.mgr.subscribe();
-        let head_mgr = self.mgr.clone();
-        let head =
-            futures::stream::once(
-                async move { Ok(snapshot_response(head_mgr.engine().snapshot())) },
-            );
-        let tail_mgr = self.mgr.clone();
-        let tail = BroadcastStream::new(rx)
-            .map(move |_| Ok(snapshot_response(tail_mgr.engine().snapshot())));
+        let tags = self.scopes.tags_for(&client_id, &request.get_ref().tags);
+        let rx = mgr.subscribe();
+        let head_mgr = mgr.clone();
+        let head_tags = tags.clone();
+        let head = futures::stream::once(async move {
+            let engine = head_mgr.engine();
+            Ok(snapshot_response(&visible(
+                engine.snapshot(),
+                head_tags.as_deref(),
+            )))
+        });
+        let tail_mgr = mgr;
+        let tail = BroadcastStream::new(rx).map(move |_| {
+            let engine = tail_mgr.engine();
+            Ok(snapshot_response(&visible(
+                engine.snapshot(),
+                tags.as_deref(),
+            )))
+        });
         Ok(Response::new(Box::pin(head.chain(tail))))
     }
 
+    type StreamSnapshotDeltasStream = DeltaStream;
+
+    async fn stream_snapshot_deltas(
+        &self,
+        request: Request<pb::StreamSnapshotDeltasRequest>,
+    ) -> Result<Response<Self::StreamSnapshotDeltasStream>, Status> {
+        let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
+        let req = request.get_ref();
+        let tags = self.scopes.tags_for(&client_id, &req.tags);
+        let scope = scope_id(tags.as_deref());
+        // A snapshot built under another scope (or an unknown one) can be missing flags
+        // this stream carries, or hold ones it doesn't, so no delta can fix it up.
+        let known = if req.known_scope == scope {
+            req.known_version
+        } else {
+            0
+        };
+        tracing::info!(
+            client_id,
+            environment = mgr.environment(),
+            version = mgr.version(),
+            known,
+            "snapshot delta stream connected"
+        );
+        // Subscribe before reading the head so no change can fall between them; any
+        // the head already covers are skipped by version.
+        let rx = mgr.subscribe();
+        let tail_mgr = mgr.clone();
+        let current = mgr.engine();
+        let head = catch_up(mgr.store(), known, current.snapshot(), tags.as_deref()).await;
+        let stream: DeltaStream = match tags {
+            None => {
+                let tail = BroadcastStream::new(rx)
+                    .scan(head.version, move |sent, update| {
+                        let current = tail_mgr.engine();
+                        futures::future::ready(Some(next_delta(sent, update, current.snapshot())))
+                    })
+                    .filter_map(|msg| futures::future::ready(msg.map(Ok)));
+                let head = futures::stream::once(async move { Ok(head) });
+                Box::pin(head.chain(tail))
+            }
+            Some(tags) => {
+                let scoped = current.snapshot().scoped(&tags);
+                let tail = BroadcastStream::new(rx)
+                    .scan(scoped, move |last, _| {
+                        let current = tail_mgr.engine();
+                        futures::future::ready(Some(next_scoped_delta(
+                            last,
+                            current.snapshot(),
+                            &tags,
+                        )))
+                    })
+                    .filter_map(|msg| futures::future::ready(msg.map(Ok)));
+                let head = futures::stream::once(async move { Ok(head) });
+                Box::pin(head.chain(tail))
+            }
+        };
+        let stream = stream.map(move |msg| {
+            msg.map(|msg| pb::SnapshotDelta {
+                scope: scope.clone(),
+                ..msg
+            })
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
     type StreamEventsStream = EventStream;
 
     async fn stream_events(
@@ -237,19 +526,21 @@ impl Evaluation for EvaluationService {
         request: Request<pb::EventStreamRequest>,
     ) -> Result<Response<Self::StreamEventsStream>, Status> {
         let client_id = client_id_of(&request)?;
+        let mgr = self.scope(&request).await?;
         tracing::info!(
             client_id,
-            version = self.mgr.version(),
+            environment = mgr.environment(),
+            version = mgr.version(),
             "event stream connected"
         );
-        let rx = self.mgr.subscribe();
+        let rx = mgr.subscribe();
         let ready = pb::Event {
             r#type: pb::EventType::Ready as i32,
-            config_version: self.mgr.version(),
+            config_version: mgr.version(),
             changed_flag_keys: Vec::new(),
         };
         let head = futures::stream::once(async move { Ok(ready) });
-        let tail_mgr = self.mgr.clone();
+        let tail_mgr = mgr;
         let tail =
             BroadcastStream::new(rx).map(move |item| Ok(config_event(item, tail_mgr.version())));
         Ok(Response::new(Box::pin(head.chain(tail))))
@@ -286,7 +577,38 @@ fn evaluated_flag(key: &str, value_type: ValueType, res: &Resolution) -> pb::Eva
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::snapshot::ConfigUpdate;
+
+    #[test]
+    fn reported_counts_must_name_known_variants() {
+        let snapshot: Snapshot = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "flags": { "checkout": {
+                "key": "checkout",
+                "value_type": "Boolean",
+                "enabled": true,
+                "default_variant_key": "on",
+                "archived": false,
+                "variants": [{ "key": "on", "value": true }],
+                "rules": [],
+            }},
+            "segments": {},
+        }))
+        .unwrap();
+        let count = |flag: &str, variant: &str| pb::EvaluationCount {
+            flag_key: flag.into(),
+            variant_key: variant.into(),
+            count: 3,
+        };
+        let counts = known_counts(
+            &snapshot,
+            vec![
+                count("checkout", "on"),
+                count("checkout", "bogus"),
+                count("nope", "on"),
+            ],
+        );
+        assert_eq!(counts, [("checkout".to_owned(), "on".to_owned(), 3)]);
+    }
 
     #[test]
     fn update_maps_to_configuration_changed() {
@@ -294,6 +616,7 @@ mod tests {
             Ok(ConfigUpdate {
                 version: 7,
                 changed_flag_keys: std::sync::Arc::new(vec!["a".to_owned()]),
+                delta: Default::default(),
             }),
             5,
         );
@@ -302,6 +625,83 @@ mod tests {
         assert_eq!(event.changed_flag_keys, vec!["a"]);
     }
 
+    fn update(base_version: i64, version: i64) -> Result<ConfigUpdate, BroadcastStreamRecvError> {
+        Ok(ConfigUpdate {
+            version,
+            changed_flag_keys: Default::default(),
+            delta: std::sync::Arc::new(Delta {
+                base_version,
+                version,
+                ..Default::default()
+            }),
+        })
+    }
+
+    #[test]
+    fn deltas_follow_on_from_the_sent_version() {
+        let current = Snapshot {
+            version: 9,
+            ..Default::default()
+        };
+        let mut sent = 5;
+        // Already covered by the head.
+        assert!(next_delta(&mut sent, update(4, 5), &current).is_none());
+        let msg = next_delta(&mut sent, update(5, 6), &current).unwrap();
+        assert!(!msg.full);
+        assert_eq!((msg.base_version, msg.version, sent), (5, 6, 6));
+        // A gap resends the whole current snapshot, after which older updates are skipped.
+        let msg = next_delta(&mut sent, update(7, 8), &current).unwrap();
+        assert!(msg.full);
+        assert_eq!((msg.version, sent), (9, 9));
+        assert!(next_delta(&mut sent, update(8, 9), &current).is_none());
+        assert!(
+            next_delta(
+                &mut sent,
+                Err(BroadcastStreamRecvError::Lagged(3)),
+                &current
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn scoped_deltas_follow_the_last_scoped_snapshot() {
+        let flag = |key: &str, tags: &[&str]| crate::model::Flag {
+            key: key.into(),
+            value_type: ValueType::Boolean,
+            enabled: true,
+            default_variant_key: "on".into(),
+            archived: false,
+            variants: vec![],
+            rules: vec![],
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            owners: vec![],
+            description: String::new(),
+            expires_at: None,
+        };
+        let at = |version: i64, flags: Vec<crate::model::Flag>| Snapshot {
+            version,
+            flags: flags.into_iter().map(|f| (f.key.clone(), f)).collect(),
+            ..Default::default()
+        };
+        let tags = ["checkout".to_string()];
+        let mut last = at(1, vec![flag("a", &["checkout"]), flag("b", &[])]).scoped(&tags);
+
+        // A change outside the scope only moves the version along.
+        let current = at(2, vec![flag("a", &["checkout"]), flag("b", &["search"])]);
+        let msg = next_scoped_delta(&mut last, &current, &tags).unwrap();
+        assert_eq!((msg.base_version, msg.version), (1, 2));
+        assert!(msg.flags.is_empty() && msg.removed_flag_keys.is_empty());
+        assert!(next_scoped_delta(&mut last, &current, &tags).is_none());
+
+        // Untagging drops the flag from the client's snapshot.
+        let current = at(3, vec![flag("a", &[]), flag("b", &["checkout"])]);
+        let msg = next_scoped_delta(&mut last, &current, &tags).unwrap();
+        assert_eq!(msg.flags[0].key, "b");
+        assert_eq!(msg.removed_flag_keys, ["a"]);
+        assert_eq!(last.version, 3);
+    }
+
     #[test]
     fn lag_maps_to_resync_with_current_version() {
         let event = config_event(Err(BroadcastStreamRecvError::Lagged(99)), 5);
@@ -1,10 +1,24 @@
 pub mod admin;
 pub mod evaluation;
 
+use crate::store::DEFAULT_ENVIRONMENT;
 pub use admin::AdminService;
 pub use evaluation::EvaluationService;
+use tonic::Request;
 use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 
+/// The environment a request targets, from the `environment` gRPC metadata header sent
+/// alongside `client-id`. Absent or empty selects [`DEFAULT_ENVIRONMENT`].
+pub(crate) fn environment_of<T>(request: &Request<T>) -> String {
+    request
+        .metadata()
+        .get("environment")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(DEFAULT_ENVIRONMENT)
+        .to_owned()
+}
+
 /// Per-request span for the gRPC server. Links to the caller's trace by extracting the
 /// W3C `traceparent` from request metadata, and emits a line on every request so the
 /// logs show which RPC was called.
@@ -1,11 +1,17 @@
+pub mod auth;
 pub mod cache;
+pub mod client_scopes;
 pub mod config;
 pub mod error;
-pub mod flag_config;
 pub mod grpc;
+pub mod metrics;
+pub mod ofrep;
+pub mod scheduler;
 pub mod snapshot;
 pub mod store;
+pub mod telemetry;
 pub mod tracing_setup;
+pub mod webhooks;
 
-pub use feature_flag_engine::{convert, engine, model};
+pub use feature_flag_engine::{convert, engine, flag_config, model};
 pub use feature_flag_proto as pb;
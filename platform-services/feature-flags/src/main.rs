@@ -1,11 +1,16 @@
+use feature_flags::auth::Authenticator;
 use feature_flags::cache::CacheClient;
+use feature_flags::client_scopes::ClientScopes;
 use feature_flags::config::Config;
 use feature_flags::grpc::{AdminService, EvaluationService};
 use feature_flags::pb::admin_server::AdminServer;
 use feature_flags::pb::evaluation_server::EvaluationServer;
-use feature_flags::snapshot::SnapshotManager;
+use feature_flags::scheduler;
+use feature_flags::snapshot::Environments;
 use feature_flags::store::Store;
+use feature_flags::telemetry::Telemetry;
 use feature_flags::tracing_setup;
+use feature_flags::{metrics, ofrep, webhooks};
 use sqlx::postgres::PgPoolOptions;
 use std::time::Duration;
 use tonic::codec::CompressionEncoding;
@@ -14,6 +19,7 @@ use tonic::transport::Server;
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let _otel = tracing_setup::init();
+    metrics::init();
     let config = Config::from_env();
 
     let pool = PgPoolOptions::new()
@@ -25,10 +31,18 @@ async fn main() -> anyhow::Result<()> {
 
     let store = Store::new(pool);
     let cache = CacheClient::from_env().await;
-    let manager = SnapshotManager::bootstrap(store.clone(), cache).await?;
+    let envs = Environments::bootstrap(store.clone(), cache).await?;
 
-    tokio::spawn(manager.clone().listen(config.database_url.clone()));
-    tokio::spawn(manager.clone().reconcile_loop());
+    tokio::spawn(envs.clone().listen(config.database_url.clone()));
+    tokio::spawn(envs.clone().reconcile_loop());
+    tokio::spawn(scheduler::run(
+        store.clone(),
+        config.database_url.clone(),
+        config.history_retention(),
+    ));
+    tokio::spawn(webhooks::run(store.clone()));
+    let telemetry = Telemetry::new(store.clone());
+    tokio::spawn(telemetry.clone().run());
 
     let (health_reporter, health_service) = tonic_health::server::health_reporter();
     health_reporter
@@ -42,6 +56,37 @@ async fn main() -> anyhow::Result<()> {
         .register_encoded_file_descriptor_set(feature_flags::pb::FILE_DESCRIPTOR_SET)
         .build_v1()?;
 
+    let http = tokio::net::TcpListener::bind(&config.http_addr).await?;
+    tracing::info!(
+        "feature-flags HTTP (OFREP, metrics) listening on {}",
+        config.http_addr
+    );
+    let http_router = ofrep::router(envs.clone(), telemetry.clone()).route(
+        "/metrics",
+        axum::routing::get(|| async { metrics::render() }),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(http, http_router)
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+        {
+            tracing::error!("OFREP server failed: {e}");
+        }
+    });
+
+    let admin = match &config.auth_config {
+        Some(path) => AdminService::new(envs.clone()).with_auth(Authenticator::load(path)?),
+        None => {
+            tracing::warn!("AUTH_CONFIG is unset; the Admin API is unauthenticated");
+            AdminService::new(envs.clone())
+        }
+    };
+
+    let mut evaluation = EvaluationService::new(envs.clone(), telemetry.clone());
+    if let Some(path) = &config.client_scopes {
+        evaluation = evaluation.with_client_scopes(ClientScopes::load(path)?);
+    }
+
     let addr = config.grpc_addr.parse()?;
     tracing::info!("feature-flags gRPC listening on {addr}");
 
@@ -53,16 +98,17 @@ async fn main() -> anyhow::Result<()> {
         .add_service(health_service)
         .add_service(reflection)
         .add_service(
-            EvaluationServer::new(EvaluationService::new(manager.clone()))
+            EvaluationServer::new(evaluation)
                 .send_compressed(CompressionEncoding::Zstd)
                 .send_compressed(CompressionEncoding::Gzip)
                 .accept_compressed(CompressionEncoding::Zstd)
                 .accept_compressed(CompressionEncoding::Gzip),
         )
-        .add_service(AdminServer::new(AdminService::new(store, manager)))
+        .add_service(AdminServer::new(admin))
         .serve_with_shutdown(addr, shutdown_signal())
         .await?;
 
+    telemetry.flush().await;
     tracing::info!("feature-flags gRPC shut down");
     Ok(())
 }
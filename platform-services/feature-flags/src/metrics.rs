@@ -0,0 +1,53 @@
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::sync::OnceLock;
+
+static RECORDER_HANDLE: OnceLock<metrics_exporter_prometheus::PrometheusHandle> = OnceLock::new();
+
+pub fn init() {
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    metrics::set_global_recorder(recorder).expect("failed to set metrics recorder");
+
+    RECORDER_HANDLE
+        .set(handle)
+        .expect("metrics already initialized");
+}
+
+pub fn render() -> String {
+    RECORDER_HANDLE
+        .get()
+        .expect("metrics not initialized")
+        .render()
+}
+
+/// Flag evaluations labelled by flag and served variant. `source` is `server` for
+/// evaluations this replica ran and `client` for counts reported by local-mode
+/// clients. The caller's `client-id` is self-asserted, so it's kept out of the labels;
+/// per-client counts live in `flag_evaluations`.
+pub fn record_evaluations(flag: &str, variant: &str, source: &'static str, n: u64) {
+    counter!(
+        "feature_flags_evaluations_total",
+        "flag" => flag.to_owned(),
+        "variant" => variant.to_owned(),
+        "source" => source,
+    )
+    .increment(n);
+}
+
+/// Evaluation count rows dropped because too many were pending, e.g. while Postgres
+/// rejects flushes.
+pub fn record_usage_dropped(rows: u64) {
+    counter!("feature_flags_usage_rows_dropped_total").increment(rows);
+}
+
+/// Webhook delivery attempts by `outcome`: `delivered`, `retrying` or `failed` (the
+/// last attempt failed).
+pub fn record_webhook_delivery(outcome: &'static str) {
+    counter!("feature_flags_webhook_deliveries_total", "outcome" => outcome).increment(1);
+}
+
+/// Live, unarchived flags in `environment` past their `expires_at`.
+pub fn set_expired_flags(environment: &str, n: usize) {
+    gauge!("feature_flags_expired_flags", "environment" => environment.to_owned()).set(n as f64);
+}
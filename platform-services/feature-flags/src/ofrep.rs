@@ -0,0 +1,258 @@
+//! OpenFeature Remote Evaluation Protocol over HTTP, for callers without a gRPC stack
+//! (shell scripts, browser frontends, curl). Evaluates against the same in-memory
+//! snapshot as the gRPC `Evaluation` service and requires the same `client-id`. The
+//! optional `environment` header selects the environment, as the gRPC metadata does.
+//!
+//! ```text
+//! curl -X POST localhost:8080/ofrep/v1/evaluate/flags/new-checkout \
+//!   -H 'client-id: my-script' -H 'environment: staging' \
+//!   -d '{"context":{"targetingKey":"user-1","plan":"pro"}}'
+//! ```
+
+use crate::engine::{ErrorCode, EvalContext, EvalError, Reason, Resolution};
+use crate::error::AppError;
+use crate::model::{Operator, Snapshot};
+use crate::snapshot::{Environments, SnapshotManager};
+use crate::store::DEFAULT_ENVIRONMENT;
+use crate::telemetry::Telemetry;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+#[derive(Clone)]
+struct Ofrep {
+    envs: Arc<Environments>,
+    telemetry: Arc<Telemetry>,
+}
+
+pub fn router(envs: Arc<Environments>, telemetry: Arc<Telemetry>) -> Router {
+    Router::new()
+        .route("/ofrep/v1/evaluate/flags", post(evaluate_all))
+        .route("/ofrep/v1/evaluate/flags/{key}", post(evaluate_one))
+        .with_state(Ofrep { envs, telemetry })
+}
+
+/// An OFREP error body: `errorCode` is one of the spec's codes.
+fn error(status: StatusCode, key: Option<&str>, code: &str, details: String) -> Response {
+    let mut body = json!({ "errorCode": code, "errorDetails": details });
+    if let Some(key) = key {
+        body["key"] = json!(key);
+    }
+    (status, Json(body)).into_response()
+}
+
+/// Same rule as the gRPC `client-id` metadata: every caller names itself.
+fn client_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("client-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+}
+
+/// The snapshot of the environment the `environment` header names, `default` when
+/// absent. An unknown environment is a 404 with no flag key.
+async fn environment(state: &Ofrep, headers: &HeaderMap) -> Result<Arc<SnapshotManager>, Response> {
+    let name = headers
+        .get("environment")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(DEFAULT_ENVIRONMENT);
+    state.envs.get(name).await.map_err(|e| match e {
+        AppError::NotFound(what) => error(
+            StatusCode::NOT_FOUND,
+            None,
+            "GENERAL",
+            format!("{what} not found"),
+        ),
+        e => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            None,
+            "GENERAL",
+            e.to_string(),
+        ),
+    })
+}
+
+fn missing_client_id() -> Response {
+    error(
+        StatusCode::UNAUTHORIZED,
+        None,
+        "GENERAL",
+        "missing client-id header".into(),
+    )
+}
+
+/// The request's `context` object, as sent. An empty body is an empty context. Errors
+/// are `(errorCode, errorDetails)`, both answered with 400.
+fn request_context(body: &[u8]) -> Result<Map<String, Value>, (&'static str, String)> {
+    if body.is_empty() {
+        return Ok(Map::new());
+    }
+    let parsed: Value = serde_json::from_slice(body)
+        .map_err(|e| ("PARSE_ERROR", format!("request body is not JSON: {e}")))?;
+    match parsed.get("context") {
+        None | Some(Value::Null) => Ok(Map::new()),
+        Some(Value::Object(context)) => Ok(context.clone()),
+        Some(_) => Err(("INVALID_CONTEXT", "context must be an object".into())),
+    }
+}
+
+/// OFREP puts the targeting key inside the context as `targetingKey`; everything else
+/// is an attribute, as in the gRPC `EvaluationContext`.
+fn eval_context(mut context: Map<String, Value>) -> EvalContext {
+    let targeting_key = match context.remove("targetingKey") {
+        Some(Value::String(key)) => key,
+        _ => String::new(),
+    };
+    EvalContext {
+        targeting_key,
+        attributes: context.into_iter().collect(),
+    }
+}
+
+fn reason_name(reason: Reason) -> &'static str {
+    match reason {
+        Reason::Static => "STATIC",
+        Reason::Default => "DEFAULT",
+        Reason::TargetingMatch => "TARGETING_MATCH",
+        Reason::Split => "SPLIT",
+        Reason::Disabled => "DISABLED",
+        Reason::Error => "ERROR",
+    }
+}
+
+fn success(key: &str, res: &Resolution) -> Value {
+    json!({
+        "key": key,
+        "value": res.value,
+        "reason": reason_name(res.reason),
+        "variant": res.variant,
+        "metadata": {},
+    })
+}
+
+fn failure(key: &str, e: &EvalError) -> Value {
+    json!({
+        "key": key,
+        "errorCode": e.code.as_str(),
+        "errorDetails": e.message,
+    })
+}
+
+async fn evaluate_one(
+    State(state): State<Ofrep>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(client_id) = client_id(&headers) else {
+        return missing_client_id();
+    };
+    let mgr = match environment(&state, &headers).await {
+        Ok(mgr) => mgr,
+        Err(response) => return response,
+    };
+    tracing::debug!(client_id, flag_key = key, "ofrep evaluate");
+    let context = match request_context(&body) {
+        Ok(context) => context,
+        Err((code, details)) => return error(StatusCode::BAD_REQUEST, Some(&key), code, details),
+    };
+
+    match mgr.engine().evaluate(&key, &eval_context(context)) {
+        Ok(res) => {
+            state
+                .telemetry
+                .record(mgr.environment(), client_id, &key, &res.variant);
+            Json(success(&key, &res)).into_response()
+        }
+        Err(e) => {
+            let status = match e.code {
+                ErrorCode::FlagNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            (status, Json(failure(&key, &e))).into_response()
+        }
+    }
+}
+
+/// Every non-archived flag, as `ResolveAll`. The ETag covers both the config version and
+/// the context, so a client polling with `If-None-Match` gets a 304 only when neither
+/// has changed. A snapshot with `before`/`after` constraints can resolve differently
+/// as time passes with neither changing, so its responses carry no ETag.
+async fn evaluate_all(State(state): State<Ofrep>, headers: HeaderMap, body: Bytes) -> Response {
+    let Some(client_id) = client_id(&headers) else {
+        return missing_client_id();
+    };
+    let mgr = match environment(&state, &headers).await {
+        Ok(mgr) => mgr,
+        Err(response) => return response,
+    };
+    tracing::debug!(client_id, "ofrep evaluate all");
+    let context = match request_context(&body) {
+        Ok(context) => context,
+        Err((code, details)) => return error(StatusCode::BAD_REQUEST, None, code, details),
+    };
+
+    let engine = mgr.engine();
+    let snapshot = engine.snapshot();
+    let etag_header = (!depends_on_time(snapshot))
+        .then(|| HeaderValue::from_str(&etag(snapshot.version, &context)).expect("etag is ascii"));
+    if let Some(etag_header) = etag_header.clone()
+        && headers
+            .get(header::IF_NONE_MATCH)
+            .is_some_and(|v| v == etag_header)
+    {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag_header)]).into_response();
+    }
+
+    let ctx = eval_context(context);
+    let flags: Vec<Value> = snapshot
+        .flags
+        .iter()
+        .filter(|(_, flag)| !flag.archived)
+        .map(|(key, _)| match engine.evaluate(key, &ctx) {
+            Ok(res) => {
+                state
+                    .telemetry
+                    .record(mgr.environment(), client_id, key, &res.variant);
+                success(key, &res)
+            }
+            Err(e) => failure(key, &e),
+        })
+        .collect();
+    let mut response = Json(json!({ "flags": flags })).into_response();
+    if let Some(etag_header) = etag_header {
+        response.headers_mut().insert(header::ETAG, etag_header);
+    }
+    response
+}
+
+/// Whether any constraint in `snapshot` compares against the current time.
+fn depends_on_time(snapshot: &Snapshot) -> bool {
+    let rules = snapshot
+        .flags
+        .values()
+        .flat_map(|f| &f.rules)
+        .flat_map(|r| &r.constraint_groups);
+    let segments = snapshot.segments.values().flat_map(|s| {
+        s.constraints
+            .iter()
+            .chain(s.constraint_groups.iter().flat_map(|g| &g.constraints))
+    });
+    rules
+        .flat_map(|g| &g.constraints)
+        .chain(segments)
+        .any(|c| matches!(c.operator, Operator::Before | Operator::After))
+}
+
+fn etag(version: i64, context: &Map<String, Value>) -> String {
+    let digest = Sha256::digest(Value::Object(context.clone()).to_string());
+    let hash: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    format!("\"{version}-{hash}\"")
+}
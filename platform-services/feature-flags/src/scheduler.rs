@@ -0,0 +1,139 @@
+//! Applies due [`ScheduledChange`](crate::store::ScheduledChange)s, advances
+//! [`Rollout`](crate::store::Rollout)s and, every [`PRUNE_INTERVAL`], prunes config
+//! history past its [`HistoryRetention`]. Every replica runs the loop, but only the one
+//! holding a Postgres session advisory lock applies anything, so a change is written
+//! once however many replicas are up. Each pass walks every environment in turn.
+//! Applying goes through the ordinary flag tables, so the version bump and
+//! `flag_changes` notify reach every replica's snapshot the same way an admin write
+//! does.
+
+use crate::store::{HistoryRetention, Store};
+use chrono::Utc;
+use sqlx::{Connection, PgConnection};
+use std::time::{Duration, Instant};
+
+/// Arbitrary, but must be unique among advisory locks taken against this database.
+const LEADER_LOCK_KEY: i64 = 0x6666_5f73_6368_6564; // "ff_sched"
+
+const TICK: Duration = Duration::from_secs(10);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Long-lived task. The lock lives on a dedicated connection rather than a pooled one:
+/// a pooled connection returned to the pool would keep the lock with no loop behind
+/// it. Losing the connection (DB restart, failover) drops leadership, and whichever
+/// replica next wins the lock takes over on its following tick.
+pub async fn run(store: Store, database_url: String, retention: HistoryRetention) {
+    let mut leader: Option<PgConnection> = None;
+    let mut last_pruned: Option<Instant> = None;
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+
+        if let Some(conn) = &mut leader
+            && let Err(e) = conn.ping().await
+        {
+            tracing::warn!("scheduler lost its leader connection: {e}");
+            leader = None;
+        }
+        if leader.is_none() {
+            leader = try_lead(&database_url).await;
+        }
+        if leader.is_none() {
+            continue;
+        }
+
+        let environments = match store.list_environments().await {
+            Ok(environments) => environments,
+            Err(e) => {
+                tracing::error!("listing environments failed: {e}");
+                continue;
+            }
+        };
+        let prune = last_pruned.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL);
+        for env in &environments {
+            let store = store.in_environment(&env.name);
+            tick_environment(&store).await;
+            if prune {
+                prune_history(&store, retention).await;
+            }
+        }
+        if prune {
+            last_pruned = Some(Instant::now());
+        }
+    }
+}
+
+async fn prune_history(store: &Store, retention: HistoryRetention) {
+    match store.prune_history(retention, Utc::now()).await {
+        Ok(0) => {}
+        Ok(pruned) => tracing::info!(
+            environment = store.environment(),
+            pruned,
+            "pruned config history"
+        ),
+        Err(e) => tracing::error!("pruning config history failed: {e}"),
+    }
+}
+
+/// Apply due scheduled changes and advance rollouts in `store`'s environment.
+async fn tick_environment(store: &Store) {
+    let environment = store.environment();
+    match store.apply_due_changes(Utc::now()).await {
+        Ok(resolved) => {
+            for change in resolved {
+                tracing::info!(
+                    id = %change.id,
+                    environment,
+                    flag = change.flag_key,
+                    action = change.action.kind(),
+                    status = ?change.status,
+                    error = change.error,
+                    "scheduled change resolved"
+                );
+            }
+        }
+        Err(e) => tracing::error!("applying scheduled changes failed: {e}"),
+    }
+    match store.advance_rollouts(Utc::now()).await {
+        Ok(advanced) => {
+            for rollout in advanced {
+                tracing::info!(
+                    id = %rollout.id,
+                    environment,
+                    flag = rollout.flag_key,
+                    rule = rollout.rule_rank,
+                    step = rollout.current_step,
+                    status = ?rollout.status,
+                    error = rollout.error,
+                    "rollout advanced"
+                );
+            }
+        }
+        Err(e) => tracing::error!("advancing rollouts failed: {e}"),
+    }
+}
+
+async fn try_lead(database_url: &str) -> Option<PgConnection> {
+    let mut conn = match PgConnection::connect(database_url).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::error!("scheduler failed to connect: {e}");
+            return None;
+        }
+    };
+    match sqlx::query_scalar!("SELECT pg_try_advisory_lock($1)", LEADER_LOCK_KEY)
+        .fetch_one(&mut conn)
+        .await
+    {
+        Ok(Some(true)) => {
+            tracing::info!("acquired scheduler leadership");
+            Some(conn)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            tracing::error!("scheduler leader election failed: {e}");
+            None
+        }
+    }
+}
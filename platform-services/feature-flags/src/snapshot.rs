@@ -1,16 +1,19 @@
-//! Holds the live evaluation [`Snapshot`] in memory and keeps it current. A Postgres
-//! `LISTEN flag_changes` task reloads on every admin mutation and fans the new config
-//! version out to connected `StreamEvents` subscribers.
+//! Holds each environment's live evaluation [`Snapshot`] in memory and keeps it
+//! current. A Postgres `LISTEN flag_changes` task reloads the environment named by
+//! every admin mutation and fans its new config version out to that environment's
+//! connected `StreamEvents` subscribers.
 
 use crate::cache::CacheClient;
 use crate::engine::Engine;
 use crate::error::{AppError, AppResult};
-use crate::model::{Flag, Segment, Snapshot};
+use crate::metrics;
+use crate::model::{Constraint, Flag, Operator, Segment, Snapshot};
 use crate::store::Store;
 use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
 use sqlx::postgres::PgListener;
-use std::collections::BTreeSet;
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 use tokio::sync::broadcast;
 
@@ -19,19 +22,53 @@ const CHANNEL: &str = "flag_changes";
 const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// A published config change: the new version plus the flag keys whose evaluation may
-/// have changed, so subscribers can invalidate selectively rather than re-diffing.
+/// have changed, so subscribers can invalidate selectively rather than re-diffing, and
+/// the delta from the previous snapshot for `StreamSnapshotDeltas`.
 #[derive(Clone, Debug)]
 pub struct ConfigUpdate {
     pub version: i64,
     pub changed_flag_keys: Arc<Vec<String>>,
+    pub delta: Arc<Delta>,
 }
 
+/// The flags and segments that differ between two snapshots, streamed to local-mode
+/// clients in place of the whole new snapshot. Computed once per change and shared by
+/// every subscriber.
+#[derive(Debug, Default)]
+pub struct Delta {
+    pub base_version: i64,
+    pub version: i64,
+    /// Added or modified, ordered by key.
+    pub flags: Vec<Flag>,
+    pub segments: Vec<Segment>,
+    pub removed_flag_keys: Vec<String>,
+    pub removed_segment_keys: Vec<String>,
+}
+
+impl Delta {
+    pub fn between(old: &Snapshot, new: &Snapshot) -> Self {
+        let (flags, removed_flag_keys) = changed_entries(&old.flags, &new.flags);
+        let (segments, removed_segment_keys) = changed_entries(&old.segments, &new.segments);
+        Delta {
+            base_version: old.version,
+            version: new.version,
+            flags,
+            segments,
+            removed_flag_keys,
+            removed_segment_keys,
+        }
+    }
+}
+
+/// One environment's snapshot.
 pub struct SnapshotManager {
     store: Store,
     cache: Option<CacheClient>,
     current: ArcSwap<Snapshot>,
     reload_lock: tokio::sync::Mutex<()>,
     tx: broadcast::Sender<ConfigUpdate>,
+    /// Keys of the flags last found expired, so each is logged once.
+    expired: Mutex<BTreeSet<String>>,
 }
 
 impl SnapshotManager {
@@ -50,24 +87,34 @@ impl SnapshotManager {
             current: ArcSwap::from(Arc::new(snapshot)),
             reload_lock: tokio::sync::Mutex::new(()),
             tx,
+            expired: Mutex::default(),
         }))
     }
 
     async fn initial(store: &Store, cache: Option<&CacheClient>) -> AppResult<Snapshot> {
         let version = store.config_version().await?;
         if let Some(cache) = cache
-            && let Some(cached) = cache.get_snapshot().await
+            && let Some(cached) = cache.get_snapshot(store.environment()).await
             && cached.version == version
         {
             return Ok(cached);
         }
         let snapshot = store.load_snapshot().await?;
         if let Some(cache) = cache {
-            cache.put_snapshot(&snapshot).await;
+            cache.put_snapshot(store.environment(), &snapshot).await;
         }
         Ok(snapshot)
     }
 
+    /// The store scoped to this snapshot's environment.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn environment(&self) -> &str {
+        self.store.environment()
+    }
+
     pub fn engine(&self) -> Engine {
         Engine::new(self.current.load_full())
     }
@@ -88,6 +135,17 @@ impl SnapshotManager {
             .ok_or_else(|| AppError::NotFound(format!("flag `{key}`")))
     }
 
+    /// The tags flag `key` carries, for authorizing against tag-scoped role bindings;
+    /// none if there's no such flag.
+    pub fn flag_tags(&self, key: &str) -> Vec<String> {
+        self.current
+            .load()
+            .flags
+            .get(key)
+            .map(|f| f.tags.clone())
+            .unwrap_or_default()
+    }
+
     pub fn list_flags(&self, include_archived: bool) -> Vec<Flag> {
         let mut flags: Vec<Flag> = self
             .current
@@ -122,6 +180,23 @@ impl SnapshotManager {
         segments
     }
 
+    /// Report the flags that have outlived their `expires_at` at `now`: the count as a
+    /// gauge, and a warning the first time each is seen expired.
+    pub fn check_expiry(&self, now: DateTime<Utc>) {
+        let snapshot = self.current.load();
+        let mut reported = self.expired.lock().unwrap();
+        for flag in newly_expired(&snapshot, now, &mut reported) {
+            tracing::warn!(
+                environment = self.environment(),
+                flag = flag.key,
+                expires_at = %flag.expires_at.unwrap_or_default(),
+                owners = ?flag.owners,
+                "flag has outlived its expiry and should be removed"
+            );
+        }
+        metrics::set_expired_flags(self.environment(), reported.len());
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<ConfigUpdate> {
         self.tx.subscribe()
     }
@@ -141,18 +216,98 @@ impl SnapshotManager {
         }
 
         if let Some(cache) = &self.cache {
-            cache.put_snapshot(&snapshot).await;
+            cache.put_snapshot(self.environment(), &snapshot).await;
         }
 
         let changed = changed_flag_keys(&current, &snapshot);
+        let delta = Delta::between(&current, &snapshot);
         self.current.store(Arc::new(snapshot));
 
         let _ = self.tx.send(ConfigUpdate {
             version,
             changed_flag_keys: Arc::new(changed),
+            delta: Arc::new(delta),
         });
         Ok(())
     }
+}
+
+/// Every environment's [`SnapshotManager`]. Environments present at boot are loaded
+/// up front; one created later is loaded by the first request that names it.
+pub struct Environments {
+    store: Store,
+    cache: Option<CacheClient>,
+    managers: RwLock<HashMap<String, Arc<SnapshotManager>>>,
+}
+
+impl Environments {
+    pub async fn bootstrap(store: Store, cache: Option<CacheClient>) -> AppResult<Arc<Self>> {
+        let envs = Arc::new(Self {
+            store,
+            cache,
+            managers: RwLock::new(HashMap::new()),
+        });
+        match envs.store.list_environments().await {
+            Ok(list) => {
+                for env in list {
+                    envs.get(&env.name).await?;
+                }
+            }
+            Err(e) => tracing::error!("listing environments failed ({e}), loading lazily"),
+        }
+        Ok(envs)
+    }
+
+    /// The snapshot for `name`, loading it on first use. `NotFound` if no such
+    /// environment exists.
+    pub async fn get(&self, name: &str) -> AppResult<Arc<SnapshotManager>> {
+        if let Some(mgr) = self.managers.read().unwrap().get(name) {
+            return Ok(mgr.clone());
+        }
+        let store = self.store.in_environment(name);
+        store.config_version().await?;
+        if let Err(e) = store.record_snapshot().await {
+            tracing::warn!(environment = name, "saving initial config snapshot failed: {e}");
+        }
+        let mgr = SnapshotManager::bootstrap(store, self.cache.clone()).await?;
+        // Two first requests can race here; the loser's manager is dropped.
+        Ok(self
+            .managers
+            .write()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert(mgr)
+            .clone())
+    }
+
+    /// The store scoped to `name`, without loading its snapshot.
+    pub fn store(&self, name: &str) -> Store {
+        self.store.in_environment(name)
+    }
+
+    fn loaded(&self) -> Vec<Arc<SnapshotManager>> {
+        self.managers.read().unwrap().values().cloned().collect()
+    }
+
+    /// Reload the environments a notification payload names: `<environment>:<version>`
+    /// from a store write, or `*` from a write the triggers couldn't attribute.
+    async fn reload_notified(&self, payload: &str) {
+        let managers = match payload.split_once(':') {
+            Some((env, _)) => {
+                let mgr = self.managers.read().unwrap().get(env).cloned();
+                mgr.into_iter().collect()
+            }
+            None => self.loaded(),
+        };
+        for mgr in managers {
+            if let Err(e) = mgr.reload().await {
+                tracing::error!(
+                    environment = mgr.environment(),
+                    "snapshot reload failed: {e}"
+                );
+            }
+        }
+    }
 
     /// Long-lived task: reload whenever Postgres notifies a config change. On listener
     /// errors it reconnects with a short backoff so a transient DB blip is self-healing.
@@ -168,9 +323,7 @@ impl SnapshotManager {
                     tracing::info!("listening for flag changes on `{CHANNEL}`");
                     while let Ok(notification) = listener.recv().await {
                         tracing::debug!(payload = notification.payload(), "flag change notified");
-                        if let Err(e) = self.reload().await {
-                            tracing::error!("snapshot reload failed: {e}");
-                        }
+                        self.reload_notified(notification.payload()).await;
                     }
                     tracing::warn!("flag change listener disconnected, reconnecting");
                 }
@@ -187,51 +340,134 @@ impl SnapshotManager {
         tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
         loop {
             tick.tick().await;
-            if let Err(e) = self.reload().await {
-                tracing::warn!("periodic snapshot reconcile failed: {e}");
+            for mgr in self.loaded() {
+                if let Err(e) = mgr.reload().await {
+                    tracing::warn!(
+                        environment = mgr.environment(),
+                        "periodic snapshot reconcile failed: {e}"
+                    );
+                }
+                mgr.check_expiry(Utc::now());
             }
         }
     }
 }
 
+/// The unarchived flags expired at `now` that aren't in `reported`, which becomes the
+/// full expired set: a flag extended or removed drops out, and is reported again if it
+/// expires again.
+fn newly_expired<'a>(
+    snapshot: &'a Snapshot,
+    now: DateTime<Utc>,
+    reported: &mut BTreeSet<String>,
+) -> Vec<&'a Flag> {
+    let mut expired: Vec<&Flag> = snapshot
+        .flags
+        .values()
+        .filter(|f| !f.archived && f.is_expired(now))
+        .collect();
+    expired.sort_by(|a, b| a.key.cmp(&b.key));
+    let previous = std::mem::replace(reported, expired.iter().map(|f| f.key.clone()).collect());
+    expired.retain(|f| !previous.contains(&f.key));
+    expired
+}
+
 /// Flags whose resolved value may differ between two snapshots: those added, removed,
-/// or directly modified, plus any flag whose rules reference a segment that changed.
+/// or directly modified, plus any flag that depends on a change — through a rule's
+/// segment (or that segment's includes and excludes) or a `flag_matches` constraint,
+/// transitively. Remote-mode clients drop cached resolutions by these keys, so a
+/// dependent missing here would keep being served stale.
 fn changed_flag_keys(old: &Snapshot, new: &Snapshot) -> Vec<String> {
-    let mut changed: BTreeSet<&str> = BTreeSet::new();
-
-    for key in old.flags.keys().chain(new.flags.keys()) {
-        if old.flags.get(key) != new.flags.get(key) {
-            changed.insert(key);
-        }
-    }
-
-    let changed_segments: BTreeSet<&str> = old
+    let mut changed: BTreeSet<&str> = old
+        .flags
+        .keys()
+        .chain(new.flags.keys())
+        .filter(|key| old.flags.get(*key) != new.flags.get(*key))
+        .map(String::as_str)
+        .collect();
+    let mut changed_segments: BTreeSet<&str> = old
         .segments
         .keys()
         .chain(new.segments.keys())
         .filter(|key| old.segments.get(*key) != new.segments.get(*key))
         .map(String::as_str)
         .collect();
-    if !changed_segments.is_empty() {
+
+    // Spread to dependents until a pass reaches nothing new.
+    loop {
+        let reached = (changed.len(), changed_segments.len());
+        for segment in new.segments.values() {
+            let touched = segment
+                .included_segments
+                .iter()
+                .chain(&segment.excluded_segments)
+                .any(|s| changed_segments.contains(s.as_str()))
+                || flag_matches(
+                    segment
+                        .constraints
+                        .iter()
+                        .chain(segment.constraint_groups.iter().flat_map(|g| &g.constraints)),
+                )
+                .any(|f| changed.contains(f));
+            if touched {
+                changed_segments.insert(&segment.key);
+            }
+        }
         for flag in new.flags.values() {
             let touched = flag.rules.iter().any(|r| {
                 r.segment_key
                     .as_deref()
                     .is_some_and(|s| changed_segments.contains(s))
+                    || flag_matches(r.constraint_groups.iter().flat_map(|g| &g.constraints))
+                        .any(|f| changed.contains(f))
             });
             if touched {
                 changed.insert(&flag.key);
             }
         }
+        if (changed.len(), changed_segments.len()) == reached {
+            break;
+        }
     }
 
     changed.into_iter().map(String::from).collect()
 }
 
+/// The flag keys `flag_matches` constraints among `constraints` depend on.
+fn flag_matches<'a>(
+    constraints: impl Iterator<Item = &'a Constraint> + 'a,
+) -> impl Iterator<Item = &'a str> + 'a {
+    constraints
+        .filter(|c| c.operator == Operator::FlagMatches)
+        .map(|c| c.attribute.as_str())
+}
+
+/// The entries `new` adds or changes relative to `old`, and the keys only `old` has,
+/// each ordered by key.
+fn changed_entries<V: Clone + PartialEq>(
+    old: &HashMap<String, V>,
+    new: &HashMap<String, V>,
+) -> (Vec<V>, Vec<String>) {
+    let mut changed: Vec<(&String, &V)> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .collect();
+    changed.sort_by(|a, b| a.0.cmp(b.0));
+    let mut removed: Vec<String> = old
+        .keys()
+        .filter(|key| !new.contains_key(*key))
+        .cloned()
+        .collect();
+    removed.sort();
+    (changed.into_iter().map(|(_, v)| v.clone()).collect(), removed)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::changed_flag_keys;
-    use crate::model::{Constraint, Flag, Operator, Rule, Segment, Snapshot, ValueType, Variant};
+    use super::{Delta, changed_flag_keys, newly_expired};
+    use crate::model::{
+        Constraint, ConstraintGroup, Flag, Operator, Rule, Segment, Snapshot, ValueType, Variant,
+    };
     use serde_json::json;
 
     fn flag(key: &str, enabled: bool, segment: Option<&str>) -> Flag {
@@ -256,6 +492,10 @@ mod tests {
                 })
                 .into_iter()
                 .collect(),
+            tags: vec![],
+            owners: vec![],
+            description: String::new(),
+            expires_at: None,
         }
     }
 
@@ -268,6 +508,7 @@ mod tests {
                 operator: Operator::Eq,
                 values: vec![json!(value)],
             }],
+            ..Default::default()
         }
     }
 
@@ -295,6 +536,33 @@ mod tests {
         assert_eq!(changed_flag_keys(&old, &new), vec!["a", "b", "c"]);
     }
 
+    #[test]
+    fn expired_flags_are_reported_once_until_they_expire_again() {
+        let now = chrono::Utc::now();
+        let expiring = |key: &str, days: i64| Flag {
+            expires_at: Some(now + chrono::Duration::days(days)),
+            ..flag(key, true, None)
+        };
+        let archived = Flag { archived: true, ..expiring("archived", -1) };
+        let mut reported = Default::default();
+
+        let flags = vec![expiring("old", -1), expiring("new", 1), archived, flag("forever", true, None)];
+        let s = snapshot(1, flags, vec![]);
+        let keys = |flags: Vec<&Flag>| flags.iter().map(|f| f.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys(newly_expired(&s, now, &mut reported)), ["old"]);
+        assert!(newly_expired(&s, now, &mut reported).is_empty());
+
+        let later = now + chrono::Duration::days(2);
+        assert_eq!(keys(newly_expired(&s, later, &mut reported)), ["new"]);
+        assert_eq!(reported.len(), 2);
+
+        // Extending drops a flag from the set, so it's reported afresh if it lapses again.
+        let s = snapshot(2, vec![expiring("old", 5), expiring("new", 1)], vec![]);
+        assert!(newly_expired(&s, later, &mut reported).is_empty());
+        let much_later = now + chrono::Duration::days(6);
+        assert_eq!(keys(newly_expired(&s, much_later, &mut reported)), ["old"]);
+    }
+
     #[test]
     fn unchanged_flag_is_not_reported() {
         let old = snapshot(1, vec![flag("a", true, None)], vec![]);
@@ -316,4 +584,63 @@ mod tests {
         );
         assert_eq!(changed_flag_keys(&old, &new), vec!["a"]);
     }
+
+    #[test]
+    fn changes_propagate_through_segment_includes_and_flag_dependencies() {
+        let nested = Segment {
+            included_segments: vec!["s".into()],
+            ..segment("nested", "AU")
+        };
+        // `b` reaches `s` through `nested`; `c` depends on `b`; `d` on nothing changed.
+        let dependent = |key: &str, on: &str| Flag {
+            rules: vec![Rule {
+                rank: 0,
+                segment_key: None,
+                variant_key: Some("off".into()),
+                distributions: vec![],
+                constraint_groups: vec![ConstraintGroup {
+                    constraints: vec![Constraint {
+                        attribute: on.into(),
+                        operator: Operator::FlagMatches,
+                        values: vec![json!("off")],
+                    }],
+                }],
+                bucket_salt: String::new(),
+            }],
+            ..flag(key, true, None)
+        };
+        let flags = || {
+            vec![
+                flag("b", true, Some("nested")),
+                dependent("c", "b"),
+                dependent("d", "e"),
+                flag("e", true, None),
+            ]
+        };
+        let segments = |value: &str| vec![segment("s", value), nested.clone()];
+        let old = snapshot(1, flags(), segments("AU"));
+        let new = snapshot(2, flags(), segments("NZ"));
+        assert_eq!(changed_flag_keys(&old, &new), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn delta_carries_only_changed_and_removed_entries() {
+        let old = snapshot(
+            1,
+            vec![flag("a", true, None), flag("b", true, None)],
+            vec![segment("s", "AU"), segment("t", "AU")],
+        );
+        let new = snapshot(
+            2,
+            vec![flag("a", true, None), flag("c", true, Some("s"))],
+            vec![segment("s", "NZ")],
+        );
+        let delta = Delta::between(&old, &new);
+        assert_eq!((delta.base_version, delta.version), (1, 2));
+        let keys: Vec<_> = delta.flags.iter().map(|f| f.key.as_str()).collect();
+        assert_eq!(keys, ["c"]);
+        assert_eq!(delta.removed_flag_keys, ["b"]);
+        assert_eq!(delta.segments, [segment("s", "NZ")]);
+        assert_eq!(delta.removed_segment_keys, ["t"]);
+    }
 }
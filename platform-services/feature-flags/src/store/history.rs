@@ -0,0 +1,209 @@
+//! Config history: every store write saves the environment's full config at the
+//! version it produces, so an old version can be read back, diffed against another,
+//! or restored. Restoring goes through the same diff-and-reconcile path as
+//! [`Store::apply_config`], so a rollback is audited and versioned like any apply.
+//! The scheduler prunes history past its [`HistoryRetention`].
+
+use super::{ApplyOutcome, ConfigChange, Store, diff_config};
+use crate::error::{AppError, AppResult};
+use crate::model::{Flag, Segment, Snapshot};
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+
+/// An environment's config as it stood at one version.
+#[derive(Clone, Debug)]
+pub struct ConfigSnapshot {
+    pub snapshot: Snapshot,
+    /// When the write that produced the version committed.
+    pub created_at: DateTime<Utc>,
+}
+
+/// How much config history to keep: a saved version is pruned once it's both older
+/// than `max_age` and outside the newest `versions`, so a quiet environment keeps
+/// its last few versions however old they are.
+#[derive(Clone, Copy, Debug)]
+pub struct HistoryRetention {
+    pub versions: i64,
+    pub max_age: chrono::Duration,
+}
+
+/// The result of [`Store::diff_versions`].
+#[derive(Debug)]
+pub struct VersionDiff {
+    pub from_version: i64,
+    pub to_version: i64,
+    pub changes: Vec<ConfigChange>,
+}
+
+impl Store {
+    /// Commit a write transaction, first saving the config at the version it leaves
+    /// the environment at and queueing webhook deliveries for it, unless that version
+    /// already has a snapshot (as when the transaction changed nothing the bump
+    /// triggers track). The version row is locked so no other write can commit between
+    /// reading the version and the config.
+    pub(super) async fn commit(&self, mut tx: Transaction<'static, Postgres>) -> AppResult<()> {
+        let missing = sqlx::query_scalar!(
+            "SELECT e.version FROM environments e \
+             WHERE e.name = $1 AND NOT EXISTS ( \
+               SELECT 1 FROM config_snapshots s \
+               WHERE s.environment = e.name AND s.version = e.version) \
+             FOR SHARE",
+            self.environment
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(version) = missing {
+            let snapshot = self.load_snapshot_on(&mut tx).await?;
+            let json = serde_json::to_value(&snapshot)
+                .map_err(|e| AppError::Other(anyhow::anyhow!("serializing snapshot: {e}")))?;
+            sqlx::query!(
+                "INSERT INTO config_snapshots (environment, version, snapshot) \
+                 VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                self.environment,
+                version,
+                json,
+            )
+            .execute(&mut *tx)
+            .await?;
+            self.queue_deliveries(&mut tx, &snapshot).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Save the live config if its version has no snapshot yet, so history starts at
+    /// the version an environment had when first loaded rather than at its next write.
+    pub async fn record_snapshot(&self) -> AppResult<()> {
+        let tx = self.begin().await?;
+        self.commit(tx).await
+    }
+
+    /// The config as it stood at `version`. `NotFound` for versions without a saved
+    /// snapshot: those before history was kept, or produced outside the store.
+    pub async fn snapshot_at_version(&self, version: i64) -> AppResult<ConfigSnapshot> {
+        let row = sqlx::query!(
+            "SELECT snapshot, created_at FROM config_snapshots \
+             WHERE environment = $1 AND version = $2",
+            self.environment,
+            version,
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("snapshot of version {version}")))?;
+        let snapshot = serde_json::from_value(row.snapshot).map_err(|e| {
+            AppError::Other(anyhow::anyhow!(
+                "decoding snapshot of version {version}: {e}"
+            ))
+        })?;
+        Ok(ConfigSnapshot {
+            snapshot,
+            created_at: row.created_at,
+        })
+    }
+
+    /// The changes that take the config at `from` to the config at `to`, as
+    /// [`Store::apply_config`] would plan them. 0 on either side means the live config.
+    pub async fn diff_versions(&self, from: i64, to: i64) -> AppResult<VersionDiff> {
+        let from = self.snapshot_or_live(from).await?;
+        let to = self.snapshot_or_live(to).await?;
+        let (flags, segments) = desired(&to);
+        Ok(VersionDiff {
+            from_version: from.version,
+            to_version: to.version,
+            changes: diff_config(&from, &flags, &segments),
+        })
+    }
+
+    /// Restore the config at `version` by reconciling to it like
+    /// [`Store::apply_config`]: the whole config, or with `flag_key` only that flag
+    /// (recreated, reverted or deleted as needed) with everything else left live.
+    pub async fn rollback_to_version(
+        &self,
+        actor: &str,
+        version: i64,
+        flag_key: Option<&str>,
+        dry_run: bool,
+        expected_version: i64,
+    ) -> AppResult<ApplyOutcome> {
+        let target = self.snapshot_at_version(version).await?.snapshot;
+        let Some(key) = flag_key else {
+            let (flags, segments) = desired(&target);
+            return self
+                .reconcile(
+                    actor,
+                    &flags,
+                    &segments,
+                    dry_run,
+                    expected_version,
+                    Some(version),
+                )
+                .await;
+        };
+
+        let mut live = self.load_snapshot().await?;
+        if !live.flags.contains_key(key) && !target.flags.contains_key(key) {
+            return Err(AppError::NotFound(format!(
+                "flag `{key}` at version {version} or now"
+            )));
+        }
+        live.flags.remove(key);
+        if let Some(flag) = target.flags.get(key) {
+            live.flags.insert(key.to_owned(), flag.clone());
+        }
+        // The desired set is the live config with one flag swapped, so a write landing
+        // before the reconcile must abort it rather than be reverted along with it.
+        let expected_version = match expected_version {
+            0 => live.version,
+            v => v,
+        };
+        let (flags, segments) = desired(&live);
+        self.reconcile(
+            actor,
+            &flags,
+            &segments,
+            dry_run,
+            expected_version,
+            Some(version),
+        )
+        .await
+    }
+
+    /// Delete the saved versions `retention` no longer covers as of `now`, returning
+    /// how many went. The live version is always among the newest, so it's kept.
+    pub async fn prune_history(
+        &self,
+        retention: HistoryRetention,
+        now: DateTime<Utc>,
+    ) -> AppResult<u64> {
+        let pruned = sqlx::query!(
+            "DELETE FROM config_snapshots \
+             WHERE environment = $1 AND created_at < $2 AND version < ( \
+               SELECT min(version) FROM ( \
+                 SELECT version FROM config_snapshots WHERE environment = $1 \
+                 ORDER BY version DESC LIMIT $3) newest)",
+            self.environment,
+            now - retention.max_age,
+            retention.versions.max(1),
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(pruned)
+    }
+
+    async fn snapshot_or_live(&self, version: i64) -> AppResult<Snapshot> {
+        match version {
+            0 => self.load_snapshot().await,
+            v => Ok(self.snapshot_at_version(v).await?.snapshot),
+        }
+    }
+}
+
+/// A snapshot's flags and segments as a desired set, ordered by key.
+pub(super) fn desired(snapshot: &Snapshot) -> (Vec<Flag>, Vec<Segment>) {
+    let mut flags: Vec<_> = snapshot.flags.values().cloned().collect();
+    flags.sort_by(|a, b| a.key.cmp(&b.key));
+    let mut segments: Vec<_> = snapshot.segments.values().cloned().collect();
+    segments.sort_by(|a, b| a.key.cmp(&b.key));
+    (flags, segments)
+}
@@ -2,11 +2,17 @@
 //! the full [`Snapshot`]; writes are the gRPC Admin surface. The checked-in `.sqlx`
 //! cache lets CI build with `SQLX_OFFLINE=true` (no database).
 
+mod history;
+mod rollouts;
+mod scheduled;
 mod types;
+mod usage;
+mod webhooks;
 
 use crate::error::{AppError, AppResult};
 use crate::model::{
-    Constraint, ConstraintGroup, Distribution, Flag, Rule, Segment, Snapshot, ValueType, Variant,
+    Constraint, ConstraintGroup, Distribution, Flag, Operator, Rule, Segment, Snapshot, ValueType,
+    Variant,
 };
 use serde_json::Value as Json;
 use sqlx::PgPool;
@@ -17,9 +23,33 @@ use types::{
 };
 use uuid::Uuid;
 
+pub use history::{ConfigSnapshot, HistoryRetention, VersionDiff};
+pub use rollouts::{Rollout, RolloutStatus, RolloutStep, linear_steps};
+pub use scheduled::{ScheduleStatus, ScheduledAction, ScheduledChange};
+pub use usage::{EvaluationKey, FlagUsage, StaleFlag, StaleReason};
+pub use webhooks::{
+    DeliveryStatus, DueDelivery, MAX_DELIVERY_ATTEMPTS, Webhook, WebhookDelivery, retry_delay,
+};
+
+/// The environment requests use when they don't name one, and the one config that
+/// predates environments was migrated into.
+pub const DEFAULT_ENVIRONMENT: &str = "default";
+
+/// Postgres access scoped to one environment: every read and write below sees only
+/// that environment's flags, segments, audit log and schedules. Cheap to clone, so
+/// [`Store::in_environment`] is called per request.
 #[derive(Clone)]
 pub struct Store {
     pool: PgPool,
+    environment: String,
+}
+
+/// One row of the `environments` table.
+#[derive(Debug)]
+pub struct Environment {
+    pub name: String,
+    pub version: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
 /// One row of the `flag_changes` audit log, surfaced read-only to the admin UI.
@@ -62,36 +92,130 @@ pub struct ApplyOutcome {
     pub applied: bool,
 }
 
+/// The result of a [`Store::promote_flag`] call.
+#[derive(Debug)]
+pub struct Promotion {
+    /// Whether the flag is new to the target environment.
+    pub created: bool,
+    /// Segments the flag references that the target lacked and were copied with it.
+    pub copied_segments: Vec<String>,
+    /// False when the target already matched, so nothing was written.
+    pub changed: bool,
+    pub version: i64,
+}
+
 /// Upper bound on audit rows returned in a single request, regardless of the
 /// caller-supplied limit, to keep responses bounded.
 const MAX_CHANGES: i64 = 500;
 
 impl Store {
+    /// A store scoped to [`DEFAULT_ENVIRONMENT`].
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            environment: DEFAULT_ENVIRONMENT.to_owned(),
+        }
+    }
+
+    /// The same pool scoped to `environment`. Doesn't check it exists; reads of a
+    /// missing environment come back `NotFound` and writes fail.
+    pub fn in_environment(&self, environment: &str) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            environment: environment.to_owned(),
+        }
+    }
+
+    pub fn environment(&self) -> &str {
+        &self.environment
     }
 
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
 
+    /// Open a write transaction attributed to this store's environment, so the bump
+    /// triggers advance its version alone (see `0011_environments.sql`).
+    async fn begin(&self) -> AppResult<sqlx::Transaction<'static, sqlx::Postgres>> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query_scalar!(
+            "SELECT set_config('ff.environment', $1, true)",
+            self.environment
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        Ok(tx)
+    }
+
     pub async fn config_version(&self) -> AppResult<i64> {
-        Ok(
-            sqlx::query_scalar!("SELECT version FROM config_version WHERE id")
-                .fetch_one(&self.pool)
-                .await?,
+        sqlx::query_scalar!(
+            "SELECT version FROM environments WHERE name = $1",
+            self.environment
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("environment `{}`", self.environment)))
+    }
+
+    pub async fn list_environments(&self) -> AppResult<Vec<Environment>> {
+        Ok(sqlx::query_as!(
+            Environment,
+            "SELECT name, version, created_at FROM environments ORDER BY name"
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Create an empty environment named `name`, audited in the new environment's own
+    /// log.
+    pub async fn create_environment(&self, actor: &str, name: &str) -> AppResult<Environment> {
+        let scoped = self.in_environment(name);
+        let mut tx = scoped.begin().await?;
+        let created = sqlx::query_as!(
+            Environment,
+            "INSERT INTO environments (name) VALUES ($1) RETURNING name, version, created_at",
+            name
         )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db) if db.is_check_violation() => AppError::Invalid(
+                format!("environment name `{name}` must be lowercase letters, digits, `-` or `_`"),
+            ),
+            e => unique_violation(format!("environment `{name}` already exists"))(e),
+        })?;
+        scoped
+            .record_change(&mut tx, actor, "create_environment", "environment", name, Json::Null)
+            .await?;
+        scoped.commit(tx).await?;
+        Ok(created)
     }
 
     pub async fn load_snapshot(&self) -> AppResult<Snapshot> {
-        let version = self.config_version().await?;
+        let mut conn = self.pool.acquire().await?;
+        self.load_snapshot_on(&mut conn).await
+    }
+
+    /// [`Store::load_snapshot`] on a given connection, so a write transaction can read
+    /// the state it is about to commit.
+    async fn load_snapshot_on(&self, conn: &mut sqlx::PgConnection) -> AppResult<Snapshot> {
+        let version = sqlx::query_scalar!(
+            "SELECT version FROM environments WHERE name = $1",
+            self.environment
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("environment `{}`", self.environment)))?;
 
         let mut flags: HashMap<Uuid, Flag> = HashMap::new();
 
         for row in sqlx::query!(
-            "SELECT id, key, value_type, enabled, default_variant_key, archived FROM flags"
+            "SELECT id, key, value_type, enabled, default_variant_key, archived, tags, owners, \
+               description, expires_at \
+             FROM flags WHERE environment = $1",
+            self.environment
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
         .await?
         {
             flags.insert(
@@ -104,13 +228,22 @@ impl Store {
                     archived: row.archived,
                     variants: Vec::new(),
                     rules: Vec::new(),
+                    tags: row.tags,
+                    owners: row.owners,
+                    description: row.description,
+                    expires_at: row.expires_at,
                 },
             );
         }
 
-        for row in sqlx::query!(r#"SELECT flag_id, key, value as "value: Json" FROM variants"#)
-            .fetch_all(&self.pool)
-            .await?
+        for row in sqlx::query!(
+            r#"SELECT v.flag_id, v.key, v.value as "value: Json"
+               FROM variants v JOIN flags f ON f.id = v.flag_id
+               WHERE f.environment = $1"#,
+            self.environment
+        )
+        .fetch_all(&mut *conn)
+        .await?
         {
             if let Some(flag) = flags.get_mut(&row.flag_id) {
                 flag.variants.push(Variant {
@@ -121,9 +254,14 @@ impl Store {
         }
 
         let mut distributions: HashMap<Uuid, Vec<Distribution>> = HashMap::new();
-        for row in sqlx::query!("SELECT rule_id, variant_key, weight FROM rule_distributions")
-            .fetch_all(&self.pool)
-            .await?
+        for row in sqlx::query!(
+            "SELECT d.rule_id, d.variant_key, d.weight FROM rule_distributions d \
+             JOIN flag_rules r ON r.id = d.rule_id JOIN flags f ON f.id = r.flag_id \
+             WHERE f.environment = $1",
+            self.environment
+        )
+        .fetch_all(&mut *conn)
+        .await?
         {
             distributions
                 .entry(row.rule_id)
@@ -138,9 +276,13 @@ impl Store {
         // index form one OR-group, and the groups are AND-combined (CNF).
         let mut rule_groups: HashMap<Uuid, BTreeMap<i32, Vec<Constraint>>> = HashMap::new();
         for row in sqlx::query!(
-            r#"SELECT rule_id, group_index, attribute, operator, values as "values: Json" FROM rule_constraints"#
+            r#"SELECT c.rule_id, c.group_index, c.attribute, c.operator, c.values as "values: Json"
+               FROM rule_constraints c
+               JOIN flag_rules r ON r.id = c.rule_id JOIN flags f ON f.id = r.flag_id
+               WHERE f.environment = $1"#,
+            self.environment
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
         .await?
         {
             rule_groups
@@ -156,9 +298,12 @@ impl Store {
         }
 
         for row in sqlx::query!(
-            "SELECT id, flag_id, rank, segment_key, variant_key, bucket_salt FROM flag_rules ORDER BY rank"
+            "SELECT r.id, r.flag_id, r.rank, r.segment_key, r.variant_key, r.bucket_salt \
+             FROM flag_rules r JOIN flags f ON f.id = r.flag_id \
+             WHERE f.environment = $1 ORDER BY r.rank",
+            self.environment
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
         .await?
         {
             if let Some(flag) = flags.get_mut(&row.flag_id) {
@@ -179,32 +324,89 @@ impl Store {
         }
 
         let mut segments: HashMap<Uuid, Segment> = HashMap::new();
-        for row in sqlx::query!("SELECT id, key, name FROM segments")
-            .fetch_all(&self.pool)
-            .await?
+        for row in sqlx::query!(
+            "SELECT id, key, name FROM segments WHERE environment = $1",
+            self.environment
+        )
+        .fetch_all(&mut *conn)
+        .await?
         {
             segments.insert(
                 row.id,
                 Segment {
                     key: row.key,
                     name: row.name,
-                    constraints: Vec::new(),
+                    ..Default::default()
                 },
             );
         }
 
+        let mut segment_groups: HashMap<Uuid, BTreeMap<i32, Vec<Constraint>>> = HashMap::new();
         for row in sqlx::query!(
-            r#"SELECT segment_id, attribute, operator, values as "values: Json" FROM segment_constraints"#
+            r#"SELECT c.segment_id, c.group_index, c.attribute, c.operator, c.values as "values: Json"
+               FROM segment_constraints c JOIN segments s ON s.id = c.segment_id
+               WHERE s.environment = $1"#,
+            self.environment
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
+        .await?
+        {
+            let constraint = Constraint {
+                attribute: row.attribute,
+                operator: operator_from_str(&row.operator)?,
+                values: json_array(row.values),
+            };
+            match row.group_index {
+                Some(index) => segment_groups
+                    .entry(row.segment_id)
+                    .or_default()
+                    .entry(index)
+                    .or_default()
+                    .push(constraint),
+                None => {
+                    if let Some(segment) = segments.get_mut(&row.segment_id) {
+                        segment.constraints.push(constraint);
+                    }
+                }
+            }
+        }
+        for (segment_id, groups) in segment_groups {
+            if let Some(segment) = segments.get_mut(&segment_id) {
+                segment.constraint_groups = groups
+                    .into_values()
+                    .map(|constraints| ConstraintGroup { constraints })
+                    .collect();
+            }
+        }
+
+        for row in sqlx::query!(
+            "SELECT r.segment_id, r.referenced_key, r.excluded \
+             FROM segment_references r JOIN segments s ON s.id = r.segment_id \
+             WHERE s.environment = $1 ORDER BY r.position",
+            self.environment
+        )
+        .fetch_all(&mut *conn)
         .await?
         {
             if let Some(segment) = segments.get_mut(&row.segment_id) {
-                segment.constraints.push(Constraint {
-                    attribute: row.attribute,
-                    operator: operator_from_str(&row.operator)?,
-                    values: json_array(row.values),
-                });
+                match row.excluded {
+                    true => segment.excluded_segments.push(row.referenced_key),
+                    false => segment.included_segments.push(row.referenced_key),
+                }
+            }
+        }
+
+        for row in sqlx::query!(
+            "SELECT k.segment_id, k.targeting_key \
+             FROM segment_targeting_keys k JOIN segments s ON s.id = k.segment_id \
+             WHERE s.environment = $1",
+            self.environment
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        {
+            if let Some(segment) = segments.get_mut(&row.segment_id) {
+                segment.targeting_keys.insert(row.targeting_key);
             }
         }
 
@@ -218,6 +420,7 @@ impl Store {
     /// Append an audit row inside the caller's transaction, stamping the version the
     /// bump trigger has already advanced to.
     async fn record_change(
+        &self,
         tx: &mut sqlx::PgConnection,
         actor: &str,
         action: &str,
@@ -226,8 +429,10 @@ impl Store {
         detail: Json,
     ) -> AppResult<()> {
         sqlx::query!(
-            "INSERT INTO flag_changes (version, actor, action, target_kind, target_key, detail) \
-             VALUES ((SELECT version FROM config_version), $1, $2, $3, $4, $5)",
+            "INSERT INTO flag_changes \
+               (environment, version, actor, action, target_kind, target_key, detail) \
+             VALUES ($1, (SELECT version FROM environments WHERE name = $1), $2, $3, $4, $5, $6)",
+            self.environment,
             actor,
             action,
             target_kind,
@@ -259,13 +464,15 @@ impl Store {
             r#"SELECT id, version, actor, action, target_kind, target_key,
                       detail as "detail: Json", created_at
                FROM flag_changes
-               WHERE ($1::text IS NULL OR target_kind = $1)
+               WHERE environment = $4
+                 AND ($1::text IS NULL OR target_kind = $1)
                  AND ($2::text IS NULL OR target_key = $2)
                ORDER BY created_at DESC
                LIMIT $3"#,
             kind,
             key,
             limit,
+            self.environment,
         )
         .fetch_all(&self.pool)
         .await?;
@@ -280,8 +487,12 @@ impl Store {
     }
 
     pub async fn flag_id(&self, key: &str) -> AppResult<Uuid> {
-        sqlx::query_scalar!("SELECT id FROM flags WHERE key = $1", key)
-            .fetch_optional(&self.pool)
+        sqlx::query_scalar!(
+            "SELECT id FROM flags WHERE environment = $1 AND key = $2",
+            self.environment,
+            key
+        )
+        .fetch_optional(&self.pool)
             .await?
             .ok_or_else(|| AppError::NotFound(format!("flag `{key}`")))
     }
@@ -304,10 +515,11 @@ impl Store {
             check_variant_type(value_type, v)?;
         }
 
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         let flag_id = sqlx::query_scalar!(
-            "INSERT INTO flags (key, value_type, enabled, default_variant_key) \
-             VALUES ($1, $2, $3, $4) RETURNING id",
+            "INSERT INTO flags (environment, key, value_type, enabled, default_variant_key) \
+             VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            self.environment,
             key,
             value_type_to_str(value_type),
             enabled,
@@ -327,7 +539,7 @@ impl Store {
             .execute(&mut *tx)
             .await?;
         }
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             "create_flag",
@@ -340,7 +552,7 @@ impl Store {
             }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
@@ -361,13 +573,14 @@ impl Store {
                 "default variant `{default_variant_key}` is not among flag `{key}`'s variants"
             )));
         }
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         let affected = sqlx::query!(
             "UPDATE flags SET enabled = $2, default_variant_key = $3, updated_at = now() \
-             WHERE key = $1",
+             WHERE key = $1 AND environment = $4",
             key,
             enabled,
             default_variant_key,
+            self.environment,
         )
         .execute(&mut *tx)
         .await?
@@ -375,7 +588,7 @@ impl Store {
         if affected == 0 {
             return Err(AppError::NotFound(format!("flag `{key}`")));
         }
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             "update_flag",
@@ -384,16 +597,18 @@ impl Store {
             serde_json::json!({ "enabled": enabled, "default_variant_key": default_variant_key }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
     pub async fn archive_flag(&self, actor: &str, key: &str, archived: bool) -> AppResult<()> {
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         let affected = sqlx::query!(
-            "UPDATE flags SET archived = $2, updated_at = now() WHERE key = $1",
+            "UPDATE flags SET archived = $2, updated_at = now() \
+             WHERE key = $1 AND environment = $3",
             key,
             archived,
+            self.environment,
         )
         .execute(&mut *tx)
         .await?
@@ -401,7 +616,7 @@ impl Store {
         if affected == 0 {
             return Err(AppError::NotFound(format!("flag `{key}`")));
         }
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             if archived {
@@ -414,21 +629,25 @@ impl Store {
             serde_json::json!({ "archived": archived }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
     pub async fn delete_flag(&self, actor: &str, key: &str) -> AppResult<()> {
-        let mut tx = self.pool.begin().await?;
-        let affected = sqlx::query!("DELETE FROM flags WHERE key = $1", key)
-            .execute(&mut *tx)
+        let mut tx = self.begin().await?;
+        let affected = sqlx::query!(
+            "DELETE FROM flags WHERE key = $1 AND environment = $2",
+            key,
+            self.environment
+        )
+        .execute(&mut *tx)
             .await?
             .rows_affected();
         if affected == 0 {
             return Err(AppError::NotFound(format!("flag `{key}`")));
         }
-        Self::record_change(&mut tx, actor, "delete_flag", "flag", key, Json::Null).await?;
-        tx.commit().await?;
+        self.record_change(&mut tx, actor, "delete_flag", "flag", key, Json::Null).await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
@@ -438,13 +657,17 @@ impl Store {
         flag_key: &str,
         variant: &Variant,
     ) -> AppResult<()> {
-        let flag = sqlx::query!("SELECT id, value_type FROM flags WHERE key = $1", flag_key)
-            .fetch_optional(&self.pool)
+        let flag = sqlx::query!(
+            "SELECT id, value_type FROM flags WHERE key = $1 AND environment = $2",
+            flag_key,
+            self.environment
+        )
+        .fetch_optional(&self.pool)
             .await?
             .ok_or_else(|| AppError::NotFound(format!("flag `{flag_key}`")))?;
         check_variant_type(value_type_from_str(&flag.value_type)?, variant)?;
         let flag_id = flag.id;
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         sqlx::query!(
             "INSERT INTO variants (flag_id, key, value) VALUES ($1, $2, $3) \
              ON CONFLICT (flag_id, key) DO UPDATE SET value = EXCLUDED.value",
@@ -454,7 +677,7 @@ impl Store {
         )
         .execute(&mut *tx)
         .await?;
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             "upsert_variant",
@@ -463,7 +686,7 @@ impl Store {
             serde_json::json!({ "variant_key": variant.key, "value": variant.value }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
@@ -474,7 +697,7 @@ impl Store {
         variant_key: &str,
     ) -> AppResult<()> {
         let flag_id = self.flag_id(flag_key).await?;
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         sqlx::query!(
             "DELETE FROM variants WHERE flag_id = $1 AND key = $2",
             flag_id,
@@ -482,7 +705,7 @@ impl Store {
         )
         .execute(&mut *tx)
         .await?;
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             "delete_variant",
@@ -491,64 +714,106 @@ impl Store {
             serde_json::json!({ "variant_key": variant_key }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
     pub async fn upsert_segment(&self, actor: &str, segment: &Segment) -> AppResult<()> {
-        let mut tx = self.pool.begin().await?;
-        let segment_id = sqlx::query_scalar!(
-            "INSERT INTO segments (key, name) VALUES ($1, $2) \
-             ON CONFLICT (key) DO UPDATE SET name = EXCLUDED.name RETURNING id",
-            segment.key,
-            segment.name,
+        validate_segment(segment)?;
+        let mut tx = self.begin().await?;
+        self.upsert_segment_tx(&mut tx, segment).await?;
+        self.record_change(
+            &mut tx,
+            actor,
+            "upsert_segment",
+            "segment",
+            &segment.key,
+            serde_json::json!({
+                "name": segment.name,
+                "constraints": segment.constraints.len(),
+                "constraint_groups": segment.constraint_groups.len(),
+                "included_segments": segment.included_segments,
+                "excluded_segments": segment.excluded_segments,
+                "targeting_keys": segment.targeting_keys.len(),
+            }),
         )
-        .fetch_one(&mut *tx)
         .await?;
+        self.commit(tx).await?;
+        Ok(())
+    }
 
-        sqlx::query!(
-            "DELETE FROM segment_constraints WHERE segment_id = $1",
-            segment_id
+    /// Add and remove targeting keys on an existing segment without rewriting the rest
+    /// of it, returning the list's new size. A key in both `add` and `remove` ends up
+    /// removed.
+    pub async fn update_segment_keys(
+        &self,
+        actor: &str,
+        segment_key: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> AppResult<i64> {
+        let mut tx = self.begin().await?;
+        let segment_id = sqlx::query_scalar!(
+            "SELECT id FROM segments WHERE key = $1 AND environment = $2 FOR UPDATE",
+            segment_key,
+            self.environment
         )
-        .execute(&mut *tx)
-        .await?;
-
-        for c in &segment.constraints {
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("segment `{segment_key}`")))?;
+        if !add.is_empty() {
             sqlx::query!(
-                "INSERT INTO segment_constraints (segment_id, attribute, operator, values) \
-                 VALUES ($1, $2, $3, $4)",
+                "INSERT INTO segment_targeting_keys (segment_id, targeting_key) \
+                 SELECT $1, k FROM UNNEST($2::text[]) AS k ON CONFLICT DO NOTHING",
                 segment_id,
-                c.attribute,
-                operator_to_str(c.operator),
-                Json::Array(c.values.clone()),
+                add,
             )
             .execute(&mut *tx)
             .await?;
         }
-        Self::record_change(
+        if !remove.is_empty() {
+            sqlx::query!(
+                "DELETE FROM segment_targeting_keys WHERE segment_id = $1 AND targeting_key = ANY($2)",
+                segment_id,
+                remove,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        let total = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM segment_targeting_keys WHERE segment_id = $1"#,
+            segment_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        self.record_change(
             &mut tx,
             actor,
-            "upsert_segment",
+            "update_segment_keys",
             "segment",
-            &segment.key,
-            serde_json::json!({ "name": segment.name, "constraints": segment.constraints.len() }),
+            segment_key,
+            serde_json::json!({ "added": add.len(), "removed": remove.len(), "total": total }),
         )
         .await?;
-        tx.commit().await?;
-        Ok(())
+        self.commit(tx).await?;
+        Ok(total)
     }
 
     pub async fn delete_segment(&self, actor: &str, key: &str) -> AppResult<()> {
-        let mut tx = self.pool.begin().await?;
-        let affected = sqlx::query!("DELETE FROM segments WHERE key = $1", key)
-            .execute(&mut *tx)
+        let mut tx = self.begin().await?;
+        let affected = sqlx::query!(
+            "DELETE FROM segments WHERE key = $1 AND environment = $2",
+            key,
+            self.environment
+        )
+        .execute(&mut *tx)
             .await?
             .rows_affected();
         if affected == 0 {
             return Err(AppError::NotFound(format!("segment `{key}`")));
         }
-        Self::record_change(&mut tx, actor, "delete_segment", "segment", key, Json::Null).await?;
-        tx.commit().await?;
+        self.record_change(&mut tx, actor, "delete_segment", "segment", key, Json::Null).await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
@@ -560,7 +825,7 @@ impl Store {
     ) -> AppResult<()> {
         let flag_id = self.flag_id(flag_key).await?;
         validate_rules(flag_key, rules, &self.variant_keys(flag_id).await?)?;
-        let mut tx = self.pool.begin().await?;
+        let mut tx = self.begin().await?;
         sqlx::query!("DELETE FROM flag_rules WHERE flag_id = $1", flag_id)
             .execute(&mut *tx)
             .await?;
@@ -606,7 +871,7 @@ impl Store {
                 }
             }
         }
-        Self::record_change(
+        self.record_change(
             &mut tx,
             actor,
             "set_flag_rules",
@@ -615,7 +880,7 @@ impl Store {
             serde_json::json!({ "rule_count": rules.len() }),
         )
         .await?;
-        tx.commit().await?;
+        self.commit(tx).await?;
         Ok(())
     }
 
@@ -634,6 +899,21 @@ impl Store {
         segments: &[Segment],
         dry_run: bool,
         expected_version: i64,
+    ) -> AppResult<ApplyOutcome> {
+        self.reconcile(actor, flags, segments, dry_run, expected_version, None)
+            .await
+    }
+
+    /// [`Store::apply_config`], with each audit row's detail noting `rollback_to` when
+    /// the desired set was restored from that version.
+    async fn reconcile(
+        &self,
+        actor: &str,
+        flags: &[Flag],
+        segments: &[Segment],
+        dry_run: bool,
+        expected_version: i64,
+        rollback_to: Option<i64>,
     ) -> AppResult<ApplyOutcome> {
         validate_desired(flags, segments)?;
 
@@ -649,11 +929,13 @@ impl Store {
             });
         }
 
-        let mut tx = self.pool.begin().await?;
-        let locked =
-            sqlx::query_scalar!("SELECT version FROM config_version WHERE id FOR UPDATE")
-                .fetch_one(&mut *tx)
-                .await?;
+        let mut tx = self.begin().await?;
+        let locked = sqlx::query_scalar!(
+            "SELECT version FROM environments WHERE name = $1 FOR UPDATE",
+            self.environment
+        )
+        .fetch_one(&mut *tx)
+        .await?;
         if locked != current.version || (expected_version != 0 && locked != expected_version) {
             return Err(AppError::Aborted(
                 "configuration changed since the plan was computed; re-run plan".into(),
@@ -667,41 +949,58 @@ impl Store {
             let desired_segment = || segments.iter().find(|s| s.key == change.target_key);
             match (change.target_kind, change.op) {
                 ("segment", ChangeOp::Create | ChangeOp::Update) => {
-                    Self::upsert_segment_tx(&mut tx, desired_segment().unwrap()).await?;
+                    self.upsert_segment_tx(&mut tx, desired_segment().unwrap()).await?;
                 }
                 ("flag", ChangeOp::Create | ChangeOp::Update) => {
-                    Self::upsert_flag_tx(&mut tx, desired_flag().unwrap()).await?;
+                    self.upsert_flag_tx(&mut tx, desired_flag().unwrap()).await?;
                 }
                 _ => {}
             }
         }
         for change in &changes {
             if change.target_kind == "flag" && change.op == ChangeOp::Delete {
-                sqlx::query!("DELETE FROM flags WHERE key = $1", change.target_key)
-                    .execute(&mut *tx)
-                    .await?;
+                sqlx::query!(
+                    "DELETE FROM flags WHERE key = $1 AND environment = $2",
+                    change.target_key,
+                    self.environment
+                )
+                .execute(&mut *tx)
+                .await?;
             }
         }
         for change in &changes {
             if change.target_kind == "segment" && change.op == ChangeOp::Delete {
-                sqlx::query!("DELETE FROM segments WHERE key = $1", change.target_key)
-                    .execute(&mut *tx)
-                    .await?;
+                sqlx::query!(
+                    "DELETE FROM segments WHERE key = $1 AND environment = $2",
+                    change.target_key,
+                    self.environment
+                )
+                .execute(&mut *tx)
+                .await?;
             }
         }
 
         for change in &changes {
-            Self::record_change(
+            let detail = match (rollback_to, &change.detail) {
+                (None, detail) => detail.clone(),
+                (Some(version), Json::Object(fields)) => {
+                    let mut fields = fields.clone();
+                    fields.insert("rollback_to".into(), version.into());
+                    Json::Object(fields)
+                }
+                (Some(version), _) => serde_json::json!({ "rollback_to": version }),
+            };
+            self.record_change(
                 &mut tx,
                 actor,
                 audit_action(change.target_kind, change.op),
                 change.target_kind,
                 &change.target_key,
-                change.detail.clone(),
+                detail,
             )
             .await?;
         }
-        tx.commit().await?;
+        self.commit(tx).await?;
 
         let to_version = self.config_version().await?;
         Ok(ApplyOutcome {
@@ -712,23 +1011,156 @@ impl Store {
         })
     }
 
+    /// Copy flag `flag_key`'s config (variants, default, enabled, archived, rules, tags,
+    /// owners, description and expiry) from this store's environment to `target`'s, in
+    /// one transaction there. Segments the rules reference, directly or through nested
+    /// segments, are copied only if `target` lacks them: an existing segment keeps its
+    /// per-environment definition. Flags the rules or copied segments depend on through
+    /// `flag_matches` constraints aren't copied; if `target` lacks any of them the
+    /// promotion is refused, since they would otherwise never match there.
+    pub async fn promote_flag(
+        &self,
+        actor: &str,
+        flag_key: &str,
+        target: &Store,
+    ) -> AppResult<Promotion> {
+        if target.environment == self.environment {
+            return Err(AppError::Invalid(format!(
+                "cannot promote flag `{flag_key}` to its own environment"
+            )));
+        }
+        let source = self.load_snapshot().await?;
+        let flag = source
+            .flags
+            .get(flag_key)
+            .ok_or_else(|| AppError::NotFound(format!("flag `{flag_key}`")))?;
+        let live = target.load_snapshot().await?;
+
+        let mut pending: Vec<&str> = flag
+            .rules
+            .iter()
+            .filter_map(|r| r.segment_key.as_deref())
+            .collect();
+        let mut seen = HashSet::new();
+        let mut copied = Vec::new();
+        while let Some(key) = pending.pop() {
+            if !seen.insert(key) || live.segments.contains_key(key) {
+                continue;
+            }
+            // A dangling reference in the source stays dangling in the target.
+            if let Some(segment) = source.segments.get(key) {
+                pending.extend(segment.included_segments.iter().map(String::as_str));
+                pending.extend(segment.excluded_segments.iter().map(String::as_str));
+                copied.push(segment);
+            }
+        }
+        copied.sort_by(|a, b| a.key.cmp(&b.key));
+
+        let segment_constraints = copied.iter().flat_map(|s| {
+            s.constraints
+                .iter()
+                .chain(s.constraint_groups.iter().flat_map(|g| &g.constraints))
+        });
+        let mut missing: Vec<&str> = flag
+            .rules
+            .iter()
+            .flat_map(|r| &r.constraint_groups)
+            .flat_map(|g| &g.constraints)
+            .chain(segment_constraints)
+            .filter(|c| c.operator == Operator::FlagMatches)
+            .map(|c| c.attribute.as_str())
+            .filter(|key| *key != flag_key && !live.flags.contains_key(*key))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort_unstable();
+            missing.dedup();
+            let missing: Vec<String> = missing.iter().map(|k| format!("`{k}`")).collect();
+            return Err(AppError::Invalid(format!(
+                "flag `{flag_key}` depends on flags missing from environment `{}`: {}; \
+                 promote them first",
+                target.environment,
+                missing.join(", ")
+            )));
+        }
+
+        let existing = live.flags.get(flag_key);
+        if copied.is_empty() && existing.is_some_and(|l| flag_diff_fields(l, flag).is_empty()) {
+            return Ok(Promotion {
+                created: false,
+                copied_segments: Vec::new(),
+                changed: false,
+                version: live.version,
+            });
+        }
+
+        let mut tx = target.begin().await?;
+        for segment in &copied {
+            target.upsert_segment_tx(&mut tx, segment).await?;
+            target
+                .record_change(
+                    &mut tx,
+                    actor,
+                    "promote_segment",
+                    "segment",
+                    &segment.key,
+                    serde_json::json!({ "from": self.environment }),
+                )
+                .await?;
+        }
+        target.upsert_flag_tx(&mut tx, flag).await?;
+        let fields = match existing {
+            Some(live) => flag_diff_fields(live, flag),
+            None => Vec::new(),
+        };
+        target
+            .record_change(
+                &mut tx,
+                actor,
+                "promote_flag",
+                "flag",
+                flag_key,
+                serde_json::json!({
+                    "from": self.environment,
+                    "created": existing.is_none(),
+                    "fields": fields,
+                }),
+            )
+            .await?;
+        target.commit(tx).await?;
+
+        Ok(Promotion {
+            created: existing.is_none(),
+            copied_segments: copied.into_iter().map(|s| s.key.clone()).collect(),
+            changed: true,
+            version: target.config_version().await?,
+        })
+    }
+
     /// Upsert a flag and reconcile its variants and rules within the caller's
     /// transaction: the flag row is inserted-or-updated, variants absent from the
     /// desired set are dropped, and the rule set is replaced wholesale.
-    async fn upsert_flag_tx(tx: &mut sqlx::PgConnection, flag: &Flag) -> AppResult<()> {
+    async fn upsert_flag_tx(&self, tx: &mut sqlx::PgConnection, flag: &Flag) -> AppResult<()> {
         let flag_id = sqlx::query_scalar!(
-            "INSERT INTO flags (key, value_type, enabled, default_variant_key, archived) \
-             VALUES ($1, $2, $3, $4, $5) \
-             ON CONFLICT (key) DO UPDATE SET \
+            "INSERT INTO flags (key, value_type, enabled, default_variant_key, archived, environment, \
+               tags, owners, description, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+             ON CONFLICT (environment, key) DO UPDATE SET \
                value_type = EXCLUDED.value_type, enabled = EXCLUDED.enabled, \
                default_variant_key = EXCLUDED.default_variant_key, \
-               archived = EXCLUDED.archived, updated_at = now() \
+               archived = EXCLUDED.archived, tags = EXCLUDED.tags, owners = EXCLUDED.owners, \
+               description = EXCLUDED.description, expires_at = EXCLUDED.expires_at, \
+               updated_at = now() \
              RETURNING id",
             flag.key,
             value_type_to_str(flag.value_type),
             flag.enabled,
             flag.default_variant_key,
             flag.archived,
+            self.environment,
+            &flag.tags,
+            &flag.owners,
+            flag.description,
+            flag.expires_at,
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -807,11 +1239,17 @@ impl Store {
         Ok(())
     }
 
-    /// Upsert a segment and replace its constraints within the caller's transaction.
-    async fn upsert_segment_tx(tx: &mut sqlx::PgConnection, segment: &Segment) -> AppResult<()> {
+    /// Upsert a segment and replace its constraints, references and targeting keys
+    /// within the caller's transaction.
+    async fn upsert_segment_tx(
+        &self,
+        tx: &mut sqlx::PgConnection,
+        segment: &Segment,
+    ) -> AppResult<()> {
         let segment_id = sqlx::query_scalar!(
-            "INSERT INTO segments (key, name) VALUES ($1, $2) \
-             ON CONFLICT (key) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+            "INSERT INTO segments (environment, key, name) VALUES ($1, $2, $3) \
+             ON CONFLICT (environment, key) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+            self.environment,
             segment.key,
             segment.name,
         )
@@ -823,11 +1261,17 @@ impl Store {
         )
         .execute(&mut *tx)
         .await?;
-        for c in &segment.constraints {
+        let mut constraints: Vec<(Option<i32>, &Constraint)> =
+            segment.constraints.iter().map(|c| (None, c)).collect();
+        for (index, group) in segment.constraint_groups.iter().enumerate() {
+            constraints.extend(group.constraints.iter().map(|c| (Some(index as i32), c)));
+        }
+        for (group_index, c) in constraints {
             sqlx::query!(
-                "INSERT INTO segment_constraints (segment_id, attribute, operator, values) \
-                 VALUES ($1, $2, $3, $4)",
+                "INSERT INTO segment_constraints (segment_id, group_index, attribute, operator, values) \
+                 VALUES ($1, $2, $3, $4, $5)",
                 segment_id,
+                group_index,
                 c.attribute,
                 operator_to_str(c.operator),
                 Json::Array(c.values.clone()),
@@ -835,6 +1279,49 @@ impl Store {
             .execute(&mut *tx)
             .await?;
         }
+
+        sqlx::query!(
+            "DELETE FROM segment_references WHERE segment_id = $1",
+            segment_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        let references: Vec<(&String, bool)> = segment
+            .included_segments
+            .iter()
+            .map(|k| (k, false))
+            .chain(segment.excluded_segments.iter().map(|k| (k, true)))
+            .collect();
+        for (position, (referenced_key, excluded)) in references.into_iter().enumerate() {
+            sqlx::query!(
+                "INSERT INTO segment_references (segment_id, referenced_key, excluded, position) \
+                 VALUES ($1, $2, $3, $4)",
+                segment_id,
+                referenced_key,
+                excluded,
+                position as i32,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            "DELETE FROM segment_targeting_keys WHERE segment_id = $1",
+            segment_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if !segment.targeting_keys.is_empty() {
+            let keys: Vec<&str> = segment.targeting_keys.iter().map(String::as_str).collect();
+            sqlx::query!(
+                "INSERT INTO segment_targeting_keys (segment_id, targeting_key) \
+                 SELECT $1, k FROM UNNEST($2::text[]) AS k",
+                segment_id,
+                &keys as &[&str],
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
         Ok(())
     }
 }
@@ -854,7 +1341,10 @@ fn audit_action(target_kind: &str, op: ChangeOp) -> &'static str {
 /// Validate the full desired set the way the per-operation writers would, so a plan
 /// surfaces bad input (unknown default variant, type mismatch, malformed split)
 /// before anything is written.
-fn validate_desired(flags: &[Flag], _segments: &[Segment]) -> AppResult<()> {
+fn validate_desired(flags: &[Flag], segments: &[Segment]) -> AppResult<()> {
+    for segment in segments {
+        validate_segment(segment)?;
+    }
     for flag in flags {
         if flag.variants.iter().all(|v| v.key != flag.default_variant_key) {
             return Err(AppError::Invalid(format!(
@@ -986,6 +1476,18 @@ fn flag_diff_fields(live: &Flag, desired: &Flag) -> Vec<&'static str> {
     if !rules_equal(&live.rules, &desired.rules) {
         fields.push("rules");
     }
+    if live.tags != desired.tags {
+        fields.push("tags");
+    }
+    if live.owners != desired.owners {
+        fields.push("owners");
+    }
+    if live.description != desired.description {
+        fields.push("description");
+    }
+    if live.expires_at != desired.expires_at {
+        fields.push("expires_at");
+    }
     fields
 }
 
@@ -1001,9 +1503,40 @@ fn segment_diff_fields(live: &Segment, desired: &Segment) -> Vec<&'static str> {
     if live.constraints != desired.constraints {
         fields.push("constraints");
     }
+    if live.constraint_groups != desired.constraint_groups {
+        fields.push("constraint_groups");
+    }
+    if live.included_segments != desired.included_segments
+        || live.excluded_segments != desired.excluded_segments
+    {
+        fields.push("segments");
+    }
+    if live.targeting_keys != desired.targeting_keys {
+        fields.push("targeting_keys");
+    }
     fields
 }
 
+/// Reject references the engine could never satisfy. Longer cycles through other
+/// segments are left to evaluation, which treats them as never matching.
+fn validate_segment(segment: &Segment) -> AppResult<()> {
+    let mut seen = HashSet::new();
+    for key in segment.included_segments.iter().chain(&segment.excluded_segments) {
+        if key == &segment.key {
+            return Err(AppError::Invalid(format!(
+                "segment `{key}` cannot reference itself"
+            )));
+        }
+        if !seen.insert(key) {
+            return Err(AppError::Invalid(format!(
+                "segment `{}` references segment `{key}` more than once",
+                segment.key
+            )));
+        }
+    }
+    Ok(())
+}
+
 /// Reject a variant whose JSON value doesn't match the flag's declared type, so the
 /// mismatch surfaces at write time rather than as a `TYPE_MISMATCH` during evaluation.
 fn check_variant_type(value_type: ValueType, variant: &Variant) -> AppResult<()> {
@@ -1038,22 +1571,38 @@ fn validate_rules(flag_key: &str, rules: &[Rule], variants: &HashSet<String>) ->
         }
 
         if !rule.distributions.is_empty() {
-            let mut total = 0u32;
-            for d in &rule.distributions {
-                if !variants.contains(&d.variant_key) {
-                    return Err(AppError::Invalid(format!(
-                        "rule references variant `{}` not defined on flag `{flag_key}`",
-                        d.variant_key
-                    )));
-                }
-                total += d.weight;
-            }
-            if total != 100 {
-                return Err(AppError::Invalid(format!(
-                    "rule {rank} distribution weights sum to {total}, expected 100"
-                )));
-            }
+            validate_distributions(
+                flag_key,
+                &format!("rule {rank}"),
+                &rule.distributions,
+                variants,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `what` names the split in the error, e.g. `rule 2` or `rollout step 3`.
+fn validate_distributions(
+    flag_key: &str,
+    what: &str,
+    distributions: &[Distribution],
+    variants: &HashSet<String>,
+) -> AppResult<()> {
+    let mut total = 0u32;
+    for d in distributions {
+        if !variants.contains(&d.variant_key) {
+            return Err(AppError::Invalid(format!(
+                "{what} references variant `{}` not defined on flag `{flag_key}`",
+                d.variant_key
+            )));
         }
+        total += d.weight;
+    }
+    if total != 100 {
+        return Err(AppError::Invalid(format!(
+            "{what} distribution weights sum to {total}, expected 100"
+        )));
     }
     Ok(())
 }
@@ -0,0 +1,634 @@
+//! Progressive rollouts: one rule's distribution stepped through a sequence of splits,
+//! each held for a while before the next is written. A rule is addressed by its rank,
+//! so before every write the rollout checks the rule still carries the split it last
+//! wrote; if someone replaced the rules in between, the manual edit wins and the
+//! rollout aborts rather than overwriting it.
+
+use super::types::unique_violation;
+use super::{Store, validate_distributions};
+use crate::error::{AppError, AppResult};
+use crate::model::Distribution;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value as Json, json};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// One stage of a rollout: the rule's split while the stage is held.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RolloutStep {
+    pub distributions: Vec<Distribution>,
+    /// Ignored on the final step.
+    pub hold_secs: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RolloutStatus {
+    Active,
+    Paused,
+    Completed,
+    Aborted,
+}
+
+impl RolloutStatus {
+    fn from_str(s: &str) -> AppResult<Self> {
+        Ok(match s {
+            "active" => RolloutStatus::Active,
+            "paused" => RolloutStatus::Paused,
+            "completed" => RolloutStatus::Completed,
+            "aborted" => RolloutStatus::Aborted,
+            other => {
+                return Err(AppError::Invalid(format!(
+                    "unknown rollout status `{other}`"
+                )));
+            }
+        })
+    }
+}
+
+/// One row of `rollouts`.
+#[derive(Clone, Debug)]
+pub struct Rollout {
+    pub id: Uuid,
+    pub flag_key: String,
+    pub rule_rank: u32,
+    pub steps: Vec<RolloutStep>,
+    /// Index into `steps` of the split currently written to the rule.
+    pub current_step: usize,
+    pub status: RolloutStatus,
+    pub step_started_at: DateTime<Utc>,
+    /// Who started the rollout; the audit-log actor for every step.
+    pub actor: String,
+    /// Why the server aborted the rollout.
+    pub error: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Rollout {
+    /// When the current step's hold ends, if the rollout is active with steps left.
+    pub fn next_step_at(&self) -> Option<DateTime<Utc>> {
+        (self.status == RolloutStatus::Active && self.current_step + 1 < self.steps.len()).then(
+            || {
+                self.step_started_at
+                    + Duration::seconds(self.steps[self.current_step].hold_secs.into())
+            },
+        )
+    }
+}
+
+/// Expand a linear ramp into steps: `variant_key` goes from `start` to `end` percent in
+/// `increment`s, every `step_secs`, with `baseline_variant_key` taking the remainder.
+pub fn linear_steps(
+    variant_key: &str,
+    baseline_variant_key: &str,
+    start: u32,
+    end: u32,
+    increment: u32,
+    step_secs: u32,
+) -> AppResult<Vec<RolloutStep>> {
+    if variant_key == baseline_variant_key {
+        return Err(AppError::Invalid(
+            "linear ramp needs two different variants".into(),
+        ));
+    }
+    if start >= end || end > 100 || increment == 0 {
+        return Err(AppError::Invalid(format!(
+            "linear ramp must rise within 0..=100 by a positive increment, got {start} -> {end} by {increment}"
+        )));
+    }
+
+    let mut weights: Vec<u32> = (start..end).step_by(increment as usize).collect();
+    weights.push(end);
+    Ok(weights
+        .into_iter()
+        .map(|weight| RolloutStep {
+            distributions: [(variant_key, weight), (baseline_variant_key, 100 - weight)]
+                .into_iter()
+                .filter(|(_, w)| *w > 0)
+                .map(|(key, weight)| Distribution {
+                    variant_key: key.to_owned(),
+                    weight,
+                })
+                .collect(),
+            hold_secs: step_secs,
+        })
+        .collect())
+}
+
+struct Row {
+    id: Uuid,
+    flag_key: String,
+    rule_rank: i32,
+    steps: Json,
+    current_step: i32,
+    status: String,
+    step_started_at: DateTime<Utc>,
+    actor: String,
+    error: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<Row> for Rollout {
+    type Error = AppError;
+
+    fn try_from(row: Row) -> AppResult<Self> {
+        Ok(Rollout {
+            id: row.id,
+            flag_key: row.flag_key,
+            rule_rank: row.rule_rank as u32,
+            steps: serde_json::from_value(row.steps)
+                .map_err(|e| AppError::Invalid(format!("rollout {}: {e}", row.id)))?,
+            current_step: row.current_step as usize,
+            status: RolloutStatus::from_str(&row.status)?,
+            step_started_at: row.step_started_at,
+            actor: row.actor,
+            error: row.error,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+/// Whether two splits are the same, regardless of the order rows come back in.
+fn same_split(a: &[Distribution], b: &[Distribution]) -> bool {
+    let sorted = |d: &[Distribution]| {
+        let mut pairs: Vec<_> = d
+            .iter()
+            .map(|d| (d.variant_key.clone(), d.weight))
+            .collect();
+        pairs.sort();
+        pairs
+    };
+    sorted(a) == sorted(b)
+}
+
+impl Store {
+    /// Start ramping rule `rule_rank` of `flag_key` through `steps`, writing the first
+    /// step now. A single-step rollout is just that write and completes immediately.
+    pub async fn start_rollout(
+        &self,
+        actor: &str,
+        flag_key: &str,
+        rule_rank: u32,
+        steps: &[RolloutStep],
+    ) -> AppResult<Rollout> {
+        let Some(first) = steps.first() else {
+            return Err(AppError::Invalid("rollout needs at least one step".into()));
+        };
+        let flag_id = self.flag_id(flag_key).await?;
+        let variants = self.variant_keys(flag_id).await?;
+        for (i, step) in steps.iter().enumerate() {
+            validate_distributions(
+                flag_key,
+                &format!("rollout step {i}"),
+                &step.distributions,
+                &variants,
+            )?;
+        }
+
+        let mut tx = self.begin().await?;
+        let rule_id = self
+            .rule_id_tx(&mut tx, flag_key, rule_rank)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("rule {rule_rank} of flag `{flag_key}`")))?;
+        let original = Self::rule_distributions_tx(&mut tx, rule_id).await?;
+        let status = if steps.len() == 1 {
+            "completed"
+        } else {
+            "active"
+        };
+        let row = sqlx::query_as!(
+            Row,
+            "INSERT INTO rollouts (flag_key, rule_rank, steps, original, status, actor, environment) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7) \
+             RETURNING id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                       actor, error, created_at, updated_at",
+            flag_key,
+            rule_rank as i32,
+            serde_json::to_value(steps).map_err(anyhow::Error::from)?,
+            serde_json::to_value(&original).map_err(anyhow::Error::from)?,
+            status,
+            actor,
+            self.environment,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(unique_violation(format!(
+            "rule {rule_rank} of flag `{flag_key}` already has a rollout in progress"
+        )))?;
+
+        Self::write_distributions_tx(&mut tx, rule_id, &first.distributions).await?;
+        self.record_change(
+            &mut tx,
+            actor,
+            "start_rollout",
+            "flag",
+            flag_key,
+            json!({
+                "rollout_id": row.id,
+                "rule_rank": rule_rank,
+                "step": 0,
+                "distributions": first.distributions,
+            }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        row.try_into()
+    }
+
+    /// Rollouts newest first, optionally for one flag. Completed and aborted ones are
+    /// omitted unless `include_finished`.
+    pub async fn list_rollouts(
+        &self,
+        flag_key: &str,
+        include_finished: bool,
+    ) -> AppResult<Vec<Rollout>> {
+        let key = (!flag_key.is_empty()).then(|| flag_key.to_owned());
+        sqlx::query_as!(
+            Row,
+            "SELECT id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                    actor, error, created_at, updated_at \
+             FROM rollouts \
+             WHERE environment = $3 AND ($1::text IS NULL OR flag_key = $1) \
+               AND ($2 OR status IN ('active', 'paused')) \
+             ORDER BY created_at DESC",
+            key,
+            include_finished,
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Rollout::try_from)
+        .collect()
+    }
+
+    /// The flag rollout `id` belongs to, for authorizing changes to it by id.
+    pub async fn rollout_flag_key(&self, id: Uuid) -> AppResult<String> {
+        sqlx::query_scalar!(
+            "SELECT flag_key FROM rollouts WHERE id = $1 AND environment = $2",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("rollout `{id}`")))
+    }
+
+    pub async fn pause_rollout(&self, actor: &str, id: Uuid) -> AppResult<Rollout> {
+        let mut tx = self.begin().await?;
+        let row = sqlx::query_as!(
+            Row,
+            "UPDATE rollouts SET status = 'paused', paused_at = now(), updated_at = now() \
+             WHERE id = $1 AND environment = $2 AND status = 'active' \
+             RETURNING id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                       actor, error, created_at, updated_at",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Err(self.rollout_state_error(id, "active").await);
+        };
+        self.record_change(
+            &mut tx,
+            actor,
+            "pause_rollout",
+            "flag",
+            &row.flag_key,
+            json!({ "rollout_id": id, "step": row.current_step }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        row.try_into()
+    }
+
+    /// Resume a paused rollout. Time spent paused doesn't count towards the step's
+    /// hold, so the step runs for whatever remained of it when paused.
+    pub async fn resume_rollout(&self, actor: &str, id: Uuid) -> AppResult<Rollout> {
+        let mut tx = self.begin().await?;
+        let row = sqlx::query_as!(
+            Row,
+            "UPDATE rollouts \
+             SET status = 'active', step_started_at = step_started_at + (now() - paused_at), \
+                 paused_at = NULL, updated_at = now() \
+             WHERE id = $1 AND environment = $2 AND status = 'paused' \
+             RETURNING id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                       actor, error, created_at, updated_at",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Err(self.rollout_state_error(id, "paused").await);
+        };
+        self.record_change(
+            &mut tx,
+            actor,
+            "resume_rollout",
+            "flag",
+            &row.flag_key,
+            json!({ "rollout_id": id, "step": row.current_step }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        row.try_into()
+    }
+
+    /// Abort an active or paused rollout, restoring the rule's pre-rollout split. If
+    /// the rule no longer carries the rollout's split it has been edited since, and is
+    /// left alone.
+    pub async fn abort_rollout(&self, actor: &str, id: Uuid) -> AppResult<Rollout> {
+        let mut tx = self.begin().await?;
+        let live = sqlx::query!(
+            "SELECT flag_key, rule_rank, steps, original, current_step FROM rollouts \
+             WHERE id = $1 AND environment = $2 AND status IN ('active', 'paused') FOR UPDATE",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(live) = live else {
+            return Err(self.rollout_state_error(id, "active or paused").await);
+        };
+        let steps: Vec<RolloutStep> =
+            serde_json::from_value(live.steps).map_err(anyhow::Error::from)?;
+        let original: Vec<Distribution> =
+            serde_json::from_value(live.original).map_err(anyhow::Error::from)?;
+
+        let mut restored = false;
+        if let Some(rule_id) = self
+            .rule_id_tx(&mut tx, &live.flag_key, live.rule_rank as u32)
+            .await?
+        {
+            let current = Self::rule_distributions_tx(&mut tx, rule_id).await?;
+            if same_split(&current, &steps[live.current_step as usize].distributions) {
+                Self::write_distributions_tx(&mut tx, rule_id, &original).await?;
+                restored = true;
+            }
+        }
+
+        let row = Self::finish_rollout_tx(&mut tx, id, "aborted", "").await?;
+        self.record_change(
+            &mut tx,
+            actor,
+            "abort_rollout",
+            "flag",
+            &row.flag_key,
+            json!({
+                "rollout_id": id,
+                "rule_rank": live.rule_rank,
+                "restored": restored,
+                "distributions": original,
+            }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        row.try_into()
+    }
+
+    /// Move every active rollout whose current step's hold has elapsed by `now` on to
+    /// its next step. Returns the rollouts that changed.
+    pub async fn advance_rollouts(&self, now: DateTime<Utc>) -> AppResult<Vec<Rollout>> {
+        let active = sqlx::query_as!(
+            Row,
+            "SELECT id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                    actor, error, created_at, updated_at \
+             FROM rollouts WHERE environment = $1 AND status = 'active' ORDER BY created_at",
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut advanced = Vec::new();
+        for row in active {
+            let rollout = Rollout::try_from(row)?;
+            if rollout.next_step_at().is_some_and(|due| due <= now)
+                && let Some(next) = self.advance_rollout(&rollout, now).await?
+            {
+                advanced.push(next);
+            }
+        }
+        Ok(advanced)
+    }
+
+    /// Write `rollout`'s next step, or abort it if its rule changed underneath it.
+    /// Returns `None` if it was paused or advanced since it was read.
+    async fn advance_rollout(
+        &self,
+        rollout: &Rollout,
+        now: DateTime<Utc>,
+    ) -> AppResult<Option<Rollout>> {
+        let mut tx = self.begin().await?;
+        let still_due = sqlx::query_scalar!(
+            "SELECT current_step FROM rollouts WHERE id = $1 AND status = 'active' FOR UPDATE",
+            rollout.id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some_and(|step| step as usize == rollout.current_step);
+        if !still_due {
+            return Ok(None);
+        }
+
+        let key = &rollout.flag_key;
+        let rank = rollout.rule_rank;
+        let current = &rollout.steps[rollout.current_step];
+        let rule_id = match self.rule_id_tx(&mut tx, key, rank).await? {
+            Some(rule_id)
+                if same_split(
+                    &Self::rule_distributions_tx(&mut tx, rule_id).await?,
+                    &current.distributions,
+                ) =>
+            {
+                rule_id
+            }
+            _ => {
+                let reason = format!(
+                    "rule {rank} of flag `{key}` was edited or removed outside the rollout"
+                );
+                let row = Self::finish_rollout_tx(&mut tx, rollout.id, "aborted", &reason).await?;
+                self.commit(tx).await?;
+                return Ok(Some(row.try_into()?));
+            }
+        };
+
+        let step = rollout.current_step + 1;
+        let next = &rollout.steps[step];
+        Self::write_distributions_tx(&mut tx, rule_id, &next.distributions).await?;
+        let status = if step + 1 == rollout.steps.len() {
+            "completed"
+        } else {
+            "active"
+        };
+        let row = sqlx::query_as!(
+            Row,
+            "UPDATE rollouts \
+             SET current_step = $2, step_started_at = $3, status = $4, updated_at = now() \
+             WHERE id = $1 \
+             RETURNING id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                       actor, error, created_at, updated_at",
+            rollout.id,
+            step as i32,
+            now,
+            status,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        self.record_change(
+            &mut tx,
+            &rollout.actor,
+            "rollout_step",
+            "flag",
+            key,
+            json!({
+                "rollout_id": rollout.id,
+                "rule_rank": rank,
+                "step": step,
+                "distributions": next.distributions,
+            }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        Ok(Some(row.try_into()?))
+    }
+
+    async fn finish_rollout_tx(
+        tx: &mut PgConnection,
+        id: Uuid,
+        status: &str,
+        error: &str,
+    ) -> AppResult<Row> {
+        Ok(sqlx::query_as!(
+            Row,
+            "UPDATE rollouts SET status = $2, error = $3, paused_at = NULL, updated_at = now() \
+             WHERE id = $1 \
+             RETURNING id, flag_key, rule_rank, steps, current_step, status, step_started_at, \
+                       actor, error, created_at, updated_at",
+            id,
+            status,
+            error,
+        )
+        .fetch_one(&mut *tx)
+        .await?)
+    }
+
+    /// The error for a pause/resume/abort whose rollout isn't in the `expected` state.
+    async fn rollout_state_error(&self, id: Uuid, expected: &str) -> AppError {
+        match sqlx::query_scalar!(
+            "SELECT status FROM rollouts WHERE id = $1 AND environment = $2",
+            id,
+            self.environment
+        )
+        .fetch_optional(&self.pool)
+        .await
+        {
+            Ok(Some(status)) => {
+                AppError::Invalid(format!("rollout `{id}` is {status}, expected {expected}"))
+            }
+            Ok(None) => AppError::NotFound(format!("rollout `{id}`")),
+            Err(e) => e.into(),
+        }
+    }
+
+    async fn rule_id_tx(
+        &self,
+        tx: &mut PgConnection,
+        flag_key: &str,
+        rank: u32,
+    ) -> AppResult<Option<Uuid>> {
+        Ok(sqlx::query_scalar!(
+            "SELECT r.id FROM flag_rules r JOIN flags f ON f.id = r.flag_id \
+             WHERE f.environment = $3 AND f.key = $1 AND r.rank = $2 FOR UPDATE OF r",
+            flag_key,
+            rank as i32,
+            self.environment,
+        )
+        .fetch_optional(&mut *tx)
+        .await?)
+    }
+
+    async fn rule_distributions_tx(
+        tx: &mut PgConnection,
+        rule_id: Uuid,
+    ) -> AppResult<Vec<Distribution>> {
+        Ok(sqlx::query!(
+            "SELECT variant_key, weight FROM rule_distributions WHERE rule_id = $1",
+            rule_id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| Distribution {
+            variant_key: row.variant_key,
+            weight: row.weight as u32,
+        })
+        .collect())
+    }
+
+    async fn write_distributions_tx(
+        tx: &mut PgConnection,
+        rule_id: Uuid,
+        distributions: &[Distribution],
+    ) -> AppResult<()> {
+        sqlx::query!("DELETE FROM rule_distributions WHERE rule_id = $1", rule_id)
+            .execute(&mut *tx)
+            .await?;
+        for d in distributions {
+            sqlx::query!(
+                "INSERT INTO rule_distributions (rule_id, variant_key, weight) \
+                 VALUES ($1, $2, $3)",
+                rule_id,
+                d.variant_key,
+                d.weight as i32,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights(steps: &[RolloutStep]) -> Vec<Vec<(&str, u32)>> {
+        steps
+            .iter()
+            .map(|s| {
+                s.distributions
+                    .iter()
+                    .map(|d| (d.variant_key.as_str(), d.weight))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn linear_ramp_ends_exactly_at_target() {
+        let steps = linear_steps("on", "off", 0, 50, 20, 3600).unwrap();
+        assert_eq!(
+            weights(&steps),
+            [
+                vec![("off", 100)],
+                vec![("on", 20), ("off", 80)],
+                vec![("on", 40), ("off", 60)],
+                vec![("on", 50), ("off", 50)],
+            ]
+        );
+        assert!(steps.iter().all(|s| s.hold_secs == 3600));
+
+        let full = linear_steps("on", "off", 10, 100, 45, 60).unwrap();
+        assert_eq!(weights(&full).last().unwrap(), &[("on", 100)]);
+
+        assert!(linear_steps("on", "on", 0, 100, 10, 60).is_err());
+        assert!(linear_steps("on", "off", 50, 50, 10, 60).is_err());
+        assert!(linear_steps("on", "off", 0, 120, 10, 60).is_err());
+        assert!(linear_steps("on", "off", 0, 100, 0, 60).is_err());
+    }
+}
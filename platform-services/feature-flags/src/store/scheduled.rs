@@ -0,0 +1,389 @@
+//! Flag changes queued for a future time. Scheduling validates the change against the
+//! flag as it stands; applying re-validates against the flag as it is then, so a change
+//! whose variant was deleted in the meantime fails visibly rather than half-applying.
+
+use super::{Store, validate_rules};
+use crate::error::{AppError, AppResult};
+use crate::model::Rule;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Due changes applied per pass, so a backlog (e.g. after downtime) can't hold one
+/// transaction-heavy pass open indefinitely; the rest go on the next tick.
+const MAX_DUE_PER_PASS: i64 = 100;
+
+/// What a scheduled change does to its flag when applied.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduledAction {
+    /// Enable/disable and/or change the default variant; `None` keeps the flag's value
+    /// at apply time.
+    UpdateFlag {
+        enabled: Option<bool>,
+        default_variant_key: Option<String>,
+    },
+    /// Replace the flag's rules wholesale, as [`Store::set_flag_rules`].
+    SetFlagRules { rules: Vec<Rule> },
+}
+
+impl ScheduledAction {
+    /// The audit-log action recorded when applied, shared with the immediate writes.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ScheduledAction::UpdateFlag { .. } => "update_flag",
+            ScheduledAction::SetFlagRules { .. } => "set_flag_rules",
+        }
+    }
+
+    fn validate(&self, flag_key: &str, variants: &HashSet<String>) -> AppResult<()> {
+        match self {
+            ScheduledAction::UpdateFlag {
+                enabled: None,
+                default_variant_key: None,
+            } => Err(AppError::Invalid(
+                "scheduled update changes nothing; set enabled and/or default variant".into(),
+            )),
+            ScheduledAction::UpdateFlag {
+                default_variant_key: Some(default),
+                ..
+            } if !variants.contains(default) => Err(AppError::Invalid(format!(
+                "default variant `{default}` is not among flag `{flag_key}`'s variants"
+            ))),
+            ScheduledAction::UpdateFlag { .. } => Ok(()),
+            ScheduledAction::SetFlagRules { rules } => validate_rules(flag_key, rules, variants),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleStatus {
+    Pending,
+    Applied,
+    Cancelled,
+    /// Due, but invalid against the flag at that point; `error` says why.
+    Failed,
+}
+
+impl ScheduleStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ScheduleStatus::Pending => "pending",
+            ScheduleStatus::Applied => "applied",
+            ScheduleStatus::Cancelled => "cancelled",
+            ScheduleStatus::Failed => "failed",
+        }
+    }
+
+    fn from_str(s: &str) -> AppResult<Self> {
+        Ok(match s {
+            "pending" => ScheduleStatus::Pending,
+            "applied" => ScheduleStatus::Applied,
+            "cancelled" => ScheduleStatus::Cancelled,
+            "failed" => ScheduleStatus::Failed,
+            other => {
+                return Err(AppError::Invalid(format!(
+                    "unknown schedule status `{other}`"
+                )));
+            }
+        })
+    }
+}
+
+/// One row of `scheduled_changes`.
+#[derive(Clone, Debug)]
+pub struct ScheduledChange {
+    pub id: Uuid,
+    pub flag_key: String,
+    pub action: ScheduledAction,
+    pub apply_at: DateTime<Utc>,
+    /// Who scheduled it; the audit-log actor when it applies.
+    pub actor: String,
+    pub status: ScheduleStatus,
+    pub error: String,
+    pub created_at: DateTime<Utc>,
+    /// When it was applied, cancelled or failed.
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+struct Row {
+    id: Uuid,
+    flag_key: String,
+    payload: Json,
+    apply_at: DateTime<Utc>,
+    actor: String,
+    status: String,
+    error: String,
+    created_at: DateTime<Utc>,
+    resolved_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<Row> for ScheduledChange {
+    type Error = AppError;
+
+    fn try_from(row: Row) -> AppResult<Self> {
+        Ok(ScheduledChange {
+            id: row.id,
+            flag_key: row.flag_key,
+            action: serde_json::from_value(row.payload)
+                .map_err(|e| AppError::Invalid(format!("scheduled change {}: {e}", row.id)))?,
+            apply_at: row.apply_at,
+            actor: row.actor,
+            status: ScheduleStatus::from_str(&row.status)?,
+            error: row.error,
+            created_at: row.created_at,
+            resolved_at: row.resolved_at,
+        })
+    }
+}
+
+impl Store {
+    pub async fn schedule_change(
+        &self,
+        actor: &str,
+        flag_key: &str,
+        action: &ScheduledAction,
+        apply_at: DateTime<Utc>,
+    ) -> AppResult<ScheduledChange> {
+        if apply_at <= Utc::now() {
+            return Err(AppError::Invalid(format!(
+                "apply_at {} is not in the future",
+                apply_at.to_rfc3339()
+            )));
+        }
+        let flag_id = self.flag_id(flag_key).await?;
+        action.validate(flag_key, &self.variant_keys(flag_id).await?)?;
+
+        let payload = serde_json::to_value(action).map_err(anyhow::Error::from)?;
+        let row = sqlx::query_as!(
+            Row,
+            "INSERT INTO scheduled_changes (flag_key, kind, payload, apply_at, actor, environment) \
+             VALUES ($1, $2, $3, $4, $5, $6) \
+             RETURNING id, flag_key, payload, apply_at, actor, status, error, created_at, resolved_at",
+            flag_key,
+            action.kind(),
+            payload,
+            apply_at,
+            actor,
+            self.environment,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        row.try_into()
+    }
+
+    /// Scheduled changes soonest first, optionally for one flag. Resolved (applied,
+    /// cancelled, failed) changes are omitted unless `include_resolved`.
+    pub async fn list_scheduled_changes(
+        &self,
+        flag_key: &str,
+        include_resolved: bool,
+    ) -> AppResult<Vec<ScheduledChange>> {
+        let key = (!flag_key.is_empty()).then(|| flag_key.to_owned());
+        sqlx::query_as!(
+            Row,
+            "SELECT id, flag_key, payload, apply_at, actor, status, error, created_at, resolved_at \
+             FROM scheduled_changes \
+             WHERE environment = $3 AND ($1::text IS NULL OR flag_key = $1) \
+               AND ($2 OR status = 'pending') \
+             ORDER BY apply_at, created_at",
+            key,
+            include_resolved,
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(ScheduledChange::try_from)
+        .collect()
+    }
+
+    /// The flag scheduled change `id` targets, for authorizing changes to it by id.
+    pub async fn scheduled_change_flag_key(&self, id: Uuid) -> AppResult<String> {
+        sqlx::query_scalar!(
+            "SELECT flag_key FROM scheduled_changes WHERE id = $1 AND environment = $2",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("scheduled change `{id}`")))
+    }
+
+    pub async fn cancel_scheduled_change(&self, id: Uuid) -> AppResult<ScheduledChange> {
+        let cancelled = sqlx::query_as!(
+            Row,
+            "UPDATE scheduled_changes SET status = 'cancelled', resolved_at = now() \
+             WHERE id = $1 AND environment = $2 AND status = 'pending' \
+             RETURNING id, flag_key, payload, apply_at, actor, status, error, created_at, resolved_at",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(row) = cancelled {
+            return row.try_into();
+        }
+
+        let status = sqlx::query_scalar!(
+            "SELECT status FROM scheduled_changes WHERE id = $1 AND environment = $2",
+            id,
+            self.environment
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("scheduled change `{id}`")))?;
+        Err(AppError::Invalid(format!(
+            "scheduled change `{id}` is already {status}"
+        )))
+    }
+
+    /// Apply every pending change due at `now`, oldest first, each in its own
+    /// transaction. A change that no longer validates, or whose stored payload can't be
+    /// read, is marked failed and the rest continue; a database error stops the pass and
+    /// leaves the remainder pending for the next one. Returns the changes resolved by
+    /// this pass, less any unreadable ones, which are only logged.
+    pub async fn apply_due_changes(&self, now: DateTime<Utc>) -> AppResult<Vec<ScheduledChange>> {
+        let due = sqlx::query_as!(
+            Row,
+            "SELECT id, flag_key, payload, apply_at, actor, status, error, created_at, resolved_at \
+             FROM scheduled_changes \
+             WHERE environment = $3 AND status = 'pending' AND apply_at <= $1 \
+             ORDER BY apply_at, created_at \
+             LIMIT $2",
+            now,
+            MAX_DUE_PER_PASS,
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut resolved = Vec::with_capacity(due.len());
+        for row in due {
+            let id = row.id;
+            let mut change = match ScheduledChange::try_from(row) {
+                Ok(change) => change,
+                // An unreadable payload can never apply; fail it rather than let it
+                // stall every change queued behind it.
+                Err(e) => {
+                    let reason = e.to_string();
+                    self.fail_scheduled(id, &reason).await?;
+                    tracing::warn!(%id, error = reason, "scheduled change failed");
+                    continue;
+                }
+            };
+            match self.apply_scheduled(&change).await {
+                Ok(true) => change.status = ScheduleStatus::Applied,
+                // Cancelled between the scan and the row lock.
+                Ok(false) => continue,
+                Err(AppError::Invalid(reason) | AppError::NotFound(reason)) => {
+                    self.fail_scheduled(change.id, &reason).await?;
+                    change.status = ScheduleStatus::Failed;
+                    change.error = reason;
+                }
+                Err(e) => return Err(e),
+            }
+            change.resolved_at = Some(Utc::now());
+            resolved.push(change);
+        }
+        Ok(resolved)
+    }
+
+    /// Write one scheduled change with the same effect and audit trail as the
+    /// immediate admin write it stands in for. Returns false if it's no longer pending.
+    async fn apply_scheduled(&self, change: &ScheduledChange) -> AppResult<bool> {
+        let mut tx = self.begin().await?;
+        let status = sqlx::query_scalar!(
+            "SELECT status FROM scheduled_changes WHERE id = $1 FOR UPDATE",
+            change.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if status != ScheduleStatus::Pending.as_str() {
+            return Ok(false);
+        }
+
+        let key = &change.flag_key;
+        let flag = sqlx::query!(
+            "SELECT id, enabled, default_variant_key FROM flags \
+             WHERE key = $1 AND environment = $2 FOR UPDATE",
+            key,
+            self.environment
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("flag `{key}`")))?;
+        let variants: HashSet<String> =
+            sqlx::query_scalar!("SELECT key FROM variants WHERE flag_id = $1", flag.id)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .collect();
+        change.action.validate(key, &variants)?;
+
+        let detail = match &change.action {
+            ScheduledAction::UpdateFlag {
+                enabled,
+                default_variant_key,
+            } => {
+                let enabled = enabled.unwrap_or(flag.enabled);
+                let default = default_variant_key
+                    .as_deref()
+                    .unwrap_or(&flag.default_variant_key);
+                sqlx::query!(
+                    "UPDATE flags SET enabled = $2, default_variant_key = $3, updated_at = now() \
+                     WHERE id = $1",
+                    flag.id,
+                    enabled,
+                    default,
+                )
+                .execute(&mut *tx)
+                .await?;
+                serde_json::json!({
+                    "enabled": enabled,
+                    "default_variant_key": default,
+                    "scheduled_change_id": change.id,
+                })
+            }
+            ScheduledAction::SetFlagRules { rules } => {
+                Self::replace_rules_tx(&mut tx, flag.id, rules).await?;
+                serde_json::json!({
+                    "rule_count": rules.len(),
+                    "scheduled_change_id": change.id,
+                })
+            }
+        };
+        self.record_change(
+            &mut tx,
+            &change.actor,
+            change.action.kind(),
+            "flag",
+            key,
+            detail,
+        )
+        .await?;
+
+        sqlx::query!(
+            "UPDATE scheduled_changes SET status = 'applied', resolved_at = now() WHERE id = $1",
+            change.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        self.commit(tx).await?;
+        Ok(true)
+    }
+
+    async fn fail_scheduled(&self, id: Uuid, reason: &str) -> AppResult<()> {
+        sqlx::query!(
+            "UPDATE scheduled_changes SET status = 'failed', error = $2, resolved_at = now() \
+             WHERE id = $1 AND status = 'pending'",
+            id,
+            reason,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -53,6 +53,14 @@ pub(super) fn operator_to_str(op: Operator) -> &'static str {
         Operator::Exists => "exists",
         Operator::Regex => "regex",
         Operator::FlagMatches => "flag_matches",
+        Operator::SemverEq => "semver_eq",
+        Operator::SemverGt => "semver_gt",
+        Operator::SemverGte => "semver_gte",
+        Operator::SemverLt => "semver_lt",
+        Operator::SemverLte => "semver_lte",
+        Operator::Before => "before",
+        Operator::After => "after",
+        Operator::PercentOf => "percent_of",
     }
 }
 
@@ -72,6 +80,14 @@ pub(super) fn operator_from_str(s: &str) -> AppResult<Operator> {
         "exists" => Operator::Exists,
         "regex" => Operator::Regex,
         "flag_matches" => Operator::FlagMatches,
+        "semver_eq" => Operator::SemverEq,
+        "semver_gt" => Operator::SemverGt,
+        "semver_gte" => Operator::SemverGte,
+        "semver_lt" => Operator::SemverLt,
+        "semver_lte" => Operator::SemverLte,
+        "before" => Operator::Before,
+        "after" => Operator::After,
+        "percent_of" => Operator::PercentOf,
         other => return Err(AppError::Invalid(format!("unknown operator `{other}`"))),
     })
 }
@@ -0,0 +1,177 @@
+//! Evaluation counts, bucketed by UTC day. Written in batches by
+//! [`Telemetry`](crate::telemetry::Telemetry) and read back for usage reports and
+//! stale-flag detection.
+
+use super::Store;
+use crate::error::AppResult;
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, Utc};
+use std::collections::HashMap;
+
+/// Evaluations of one flag in one environment that served one variant to one client.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EvaluationKey {
+    pub environment: String,
+    pub flag_key: String,
+    pub variant_key: String,
+    pub client_id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct FlagUsage {
+    pub flag_key: String,
+    pub variant_key: String,
+    pub client_id: String,
+    pub count: i64,
+    pub last_evaluated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StaleReason {
+    NotEvaluated,
+    /// Every evaluation in the window served this variant.
+    SingleVariant(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct StaleFlag {
+    pub flag_key: String,
+    pub reason: StaleReason,
+    /// The last evaluation ever recorded, in or before the window.
+    pub last_evaluated_at: Option<DateTime<Utc>>,
+}
+
+/// The first UTC day of a `days`-long window ending today.
+fn window_start(now: DateTime<Utc>, days: u32) -> NaiveDate {
+    now.date_naive()
+        .checked_sub_days(Days::new(u64::from(days.max(1)) - 1))
+        .unwrap_or(NaiveDate::MIN)
+}
+
+impl Store {
+    /// Add `counts` to the day containing `at`. Each count carries its own environment,
+    /// so this store's scope doesn't matter.
+    pub async fn record_evaluations(
+        &self,
+        at: DateTime<Utc>,
+        counts: &HashMap<EvaluationKey, i64>,
+    ) -> AppResult<()> {
+        if counts.is_empty() {
+            return Ok(());
+        }
+        let mut environments = Vec::with_capacity(counts.len());
+        let mut flag_keys = Vec::with_capacity(counts.len());
+        let mut variant_keys = Vec::with_capacity(counts.len());
+        let mut client_ids = Vec::with_capacity(counts.len());
+        let mut totals = Vec::with_capacity(counts.len());
+        for (key, count) in counts {
+            environments.push(key.environment.clone());
+            flag_keys.push(key.flag_key.clone());
+            variant_keys.push(key.variant_key.clone());
+            client_ids.push(key.client_id.clone());
+            totals.push(*count);
+        }
+        sqlx::query!(
+            "INSERT INTO flag_evaluations
+                 (environment, flag_key, variant_key, client_id, day, count, last_evaluated_at)
+             SELECT e, f, v, c, $5, n, $6
+             FROM UNNEST($7::text[], $1::text[], $2::text[], $3::text[], $4::int8[])
+                 AS t(e, f, v, c, n)
+             ON CONFLICT (environment, flag_key, variant_key, client_id, day) DO UPDATE
+             SET count = flag_evaluations.count + EXCLUDED.count,
+                 last_evaluated_at = GREATEST(flag_evaluations.last_evaluated_at,
+                                              EXCLUDED.last_evaluated_at)",
+            &flag_keys,
+            &variant_keys,
+            &client_ids,
+            &totals,
+            at.date_naive(),
+            at,
+            &environments,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Counts over the `days` ending at `now`, summed per flag, variant and client. An
+    /// empty `flag_key` covers every flag.
+    pub async fn flag_usage(
+        &self,
+        flag_key: &str,
+        days: u32,
+        now: DateTime<Utc>,
+    ) -> AppResult<Vec<FlagUsage>> {
+        let key = (!flag_key.is_empty()).then(|| flag_key.to_owned());
+        let rows = sqlx::query_as!(
+            FlagUsage,
+            r#"SELECT flag_key, variant_key, client_id,
+                      SUM(count)::int8 AS "count!",
+                      MAX(last_evaluated_at) AS "last_evaluated_at!"
+               FROM flag_evaluations
+               WHERE environment = $3 AND ($1::text IS NULL OR flag_key = $1) AND day >= $2
+               GROUP BY flag_key, variant_key, client_id
+               ORDER BY flag_key, 4 DESC, variant_key, client_id"#,
+            key,
+            window_start(now, days),
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Live flags older than the window that were either not evaluated in it or served
+    /// one variant to every evaluation in it. Flags created within the window are left
+    /// out: a flag merged yesterday hasn't had the chance to be called yet.
+    pub async fn stale_flags(&self, days: u32, now: DateTime<Utc>) -> AppResult<Vec<StaleFlag>> {
+        let start = window_start(now, days);
+        let rows = sqlx::query!(
+            r#"SELECT f.key,
+                      MAX(e.last_evaluated_at) AS last_evaluated_at,
+                      COUNT(DISTINCT e.variant_key) FILTER (WHERE e.day >= $1) AS "variants!",
+                      MIN(e.variant_key) FILTER (WHERE e.day >= $1) AS variant_key
+               FROM flags f
+               LEFT JOIN flag_evaluations e
+                   ON e.environment = f.environment AND e.flag_key = f.key
+               WHERE f.environment = $3 AND NOT f.archived AND f.created_at < $2
+               GROUP BY f.key
+               ORDER BY f.key"#,
+            start,
+            start.and_time(NaiveTime::MIN).and_utc(),
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let reason = match (row.variants, row.variant_key) {
+                    (0, _) => StaleReason::NotEvaluated,
+                    (1, Some(variant)) => StaleReason::SingleVariant(variant),
+                    _ => return None,
+                };
+                Some(StaleFlag {
+                    flag_key: row.key,
+                    reason,
+                    last_evaluated_at: row.last_evaluated_at,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_counts_today() {
+        let now = DateTime::parse_from_rfc3339("2026-03-10T23:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let day = |d| NaiveDate::from_ymd_opt(2026, 3, d).unwrap();
+        assert_eq!(window_start(now, 1), day(10));
+        assert_eq!(window_start(now, 0), day(10));
+        assert_eq!(window_start(now, 7), day(4));
+    }
+}
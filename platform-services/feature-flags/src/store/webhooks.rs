@@ -0,0 +1,421 @@
+//! Outbound webhooks notified of config changes. The writing transaction queues one
+//! delivery per webhook in its environment as it commits (see [`Store::commit`]), so
+//! a committed change can't be missed; [`crate::webhooks`] sends them, retrying with
+//! backoff until they succeed or run out of attempts.
+
+use super::history::desired;
+use super::{ChangeOp, Store, diff_config};
+use crate::error::{AppError, AppResult};
+use crate::model::Snapshot;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{Value as Json, json};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Attempts before a delivery is marked failed. With [`retry_delay`] the last one
+/// lands about an hour after the first.
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+/// Deliveries claimed per pass.
+const MAX_DUE_PER_PASS: i64 = 50;
+
+/// How long a claimed delivery stays invisible to other replicas. A replica that dies
+/// mid-send leaves it to be retried once this passes.
+const CLAIM_LEASE: TimeDelta = TimeDelta::seconds(120);
+
+/// Cap on deliveries returned by [`Store::list_webhook_deliveries`].
+const MAX_DELIVERIES: i64 = 500;
+
+/// One row of `webhooks`. The signing secret is deliberately absent: it is only
+/// returned once, by [`Store::create_webhook`].
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub description: String,
+    /// Who registered it.
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Every attempt failed; `error` holds the last reason.
+    Failed,
+}
+
+impl DeliveryStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    fn from_str(s: &str) -> AppResult<Self> {
+        Ok(match s {
+            "pending" => DeliveryStatus::Pending,
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            other => {
+                return Err(AppError::Invalid(format!(
+                    "unknown delivery status `{other}`"
+                )));
+            }
+        })
+    }
+}
+
+/// One row of `webhook_deliveries`: the delivery log.
+#[derive(Clone, Debug)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    /// The config version the change produced.
+    pub version: i64,
+    pub payload: Json,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    /// The last attempt's HTTP status, 0 if it got no response.
+    pub response_status: i32,
+    pub error: String,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+struct DeliveryRow {
+    id: Uuid,
+    webhook_id: Uuid,
+    version: i64,
+    payload: Json,
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    response_status: i32,
+    error: String,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<DeliveryRow> for WebhookDelivery {
+    type Error = AppError;
+
+    fn try_from(row: DeliveryRow) -> AppResult<Self> {
+        Ok(WebhookDelivery {
+            id: row.id,
+            webhook_id: row.webhook_id,
+            version: row.version,
+            payload: row.payload,
+            status: DeliveryStatus::from_str(&row.status)?,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at,
+            response_status: row.response_status,
+            error: row.error,
+            created_at: row.created_at,
+            delivered_at: row.delivered_at,
+        })
+    }
+}
+
+/// A delivery claimed for sending, with what the sender needs.
+#[derive(Clone, Debug)]
+pub struct DueDelivery {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub payload: Json,
+    /// Including the attempt being made.
+    pub attempts: i32,
+}
+
+/// The wait after failed attempt `attempts`: 30s doubling per attempt, capped at an
+/// hour.
+pub fn retry_delay(attempts: i32) -> TimeDelta {
+    let exponent = attempts.clamp(1, 8) - 1;
+    TimeDelta::seconds(30 << exponent).min(TimeDelta::hours(1))
+}
+
+impl Store {
+    /// Register a webhook for this environment. Returns it with its generated signing
+    /// secret, which can't be read back later.
+    pub async fn create_webhook(
+        &self,
+        actor: &str,
+        url: &str,
+        description: &str,
+    ) -> AppResult<(Webhook, String)> {
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|e| AppError::Invalid(format!("webhook url `{url}`: {e}")))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(AppError::Invalid(format!(
+                "webhook url `{url}` must be http or https"
+            )));
+        }
+        // Two v4 UUIDs: 244 random bits from the OS generator, without another crate.
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+        let mut tx = self.begin().await?;
+        let webhook = sqlx::query_as!(
+            Webhook,
+            "INSERT INTO webhooks (environment, url, description, secret, actor) \
+             VALUES ($1, $2, $3, $4, $5) \
+             RETURNING id, url, description, actor, created_at",
+            self.environment,
+            url,
+            description,
+            secret,
+            actor,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        self.record_change(
+            &mut tx,
+            actor,
+            "create_webhook",
+            "webhook",
+            &webhook.id.to_string(),
+            json!({ "url": url }),
+        )
+        .await?;
+        self.commit(tx).await?;
+        Ok((webhook, secret))
+    }
+
+    pub async fn list_webhooks(&self) -> AppResult<Vec<Webhook>> {
+        Ok(sqlx::query_as!(
+            Webhook,
+            "SELECT id, url, description, actor, created_at FROM webhooks \
+             WHERE environment = $1 ORDER BY created_at",
+            self.environment,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Delete a webhook along with its delivery log and anything still queued.
+    pub async fn delete_webhook(&self, actor: &str, id: Uuid) -> AppResult<()> {
+        let mut tx = self.begin().await?;
+        let url = sqlx::query_scalar!(
+            "DELETE FROM webhooks WHERE id = $1 AND environment = $2 RETURNING url",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("webhook `{id}`")))?;
+        self.record_change(
+            &mut tx,
+            actor,
+            "delete_webhook",
+            "webhook",
+            &id.to_string(),
+            json!({ "url": url }),
+        )
+        .await?;
+        self.commit(tx).await
+    }
+
+    /// The delivery log newest-first, optionally for one webhook. `limit` is clamped to
+    /// [`MAX_DELIVERIES`].
+    pub async fn list_webhook_deliveries(
+        &self,
+        webhook_id: Option<Uuid>,
+        limit: i64,
+    ) -> AppResult<Vec<WebhookDelivery>> {
+        let limit = if limit <= 0 {
+            MAX_DELIVERIES
+        } else {
+            limit.min(MAX_DELIVERIES)
+        };
+        sqlx::query_as!(
+            DeliveryRow,
+            "SELECT d.id, d.webhook_id, d.version, d.payload, d.status, d.attempts, \
+                    d.next_attempt_at, d.response_status, d.error, d.created_at, d.delivered_at \
+             FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id \
+             WHERE w.environment = $1 AND ($2::uuid IS NULL OR d.webhook_id = $2) \
+             ORDER BY d.created_at DESC, d.version DESC \
+             LIMIT $3",
+            self.environment,
+            webhook_id,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(WebhookDelivery::try_from)
+        .collect()
+    }
+
+    /// Queue a delivered or failed delivery to be sent again, with a fresh set of
+    /// attempts.
+    pub async fn redeliver(&self, id: Uuid) -> AppResult<WebhookDelivery> {
+        let row = sqlx::query_as!(
+            DeliveryRow,
+            "UPDATE webhook_deliveries d \
+             SET status = 'pending', attempts = 0, next_attempt_at = now(), error = '' \
+             FROM webhooks w \
+             WHERE d.id = $1 AND w.id = d.webhook_id AND w.environment = $2 \
+             RETURNING d.id, d.webhook_id, d.version, d.payload, d.status, d.attempts, \
+                       d.next_attempt_at, d.response_status, d.error, d.created_at, \
+                       d.delivered_at",
+            id,
+            self.environment,
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("webhook delivery `{id}`")))?;
+        row.try_into()
+    }
+
+    /// Queue a delivery of the change that produced `snapshot` to every webhook in the
+    /// environment, diffed against the previous saved version. Nothing is queued
+    /// without a previous version to diff against, or when the diff is empty (a write
+    /// that left the config as it was).
+    pub(super) async fn queue_deliveries(
+        &self,
+        tx: &mut PgConnection,
+        snapshot: &Snapshot,
+    ) -> AppResult<()> {
+        let hooks = sqlx::query_scalar!(
+            "SELECT count(*) FROM webhooks WHERE environment = $1",
+            self.environment
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if hooks == Some(0) {
+            return Ok(());
+        }
+        let Some(previous) = sqlx::query!(
+            "SELECT version, snapshot FROM config_snapshots \
+             WHERE environment = $1 AND version < $2 \
+             ORDER BY version DESC LIMIT 1",
+            self.environment,
+            snapshot.version,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(());
+        };
+        let before: Snapshot = serde_json::from_value(previous.snapshot).map_err(|e| {
+            AppError::Other(anyhow::anyhow!(
+                "decoding snapshot of version {}: {e}",
+                previous.version
+            ))
+        })?;
+        let (flags, segments) = desired(snapshot);
+        let changes = diff_config(&before, &flags, &segments);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        // Writes audit after they bump, so this transaction's entries are exactly those
+        // past the previous saved version.
+        let actors = sqlx::query_scalar!(
+            "SELECT DISTINCT actor FROM flag_changes \
+             WHERE environment = $1 AND version > $2 ORDER BY actor",
+            self.environment,
+            previous.version,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let keys = |kind: &str| -> Vec<&str> {
+            changes
+                .iter()
+                .filter(|c| c.target_kind == kind)
+                .map(|c| c.target_key.as_str())
+                .collect()
+        };
+        let payload = json!({
+            "event": "config_change",
+            "environment": self.environment,
+            "version": snapshot.version,
+            "previous_version": previous.version,
+            "actor": actors.join(", "),
+            "flag_keys": keys("flag"),
+            "segment_keys": keys("segment"),
+            "changes": changes.iter().map(|c| json!({
+                "target_kind": c.target_kind,
+                "target_key": c.target_key,
+                "op": match c.op {
+                    ChangeOp::Create => "create",
+                    ChangeOp::Update => "update",
+                    ChangeOp::Delete => "delete",
+                },
+                "detail": c.detail,
+            })).collect::<Vec<_>>(),
+        });
+        sqlx::query!(
+            "INSERT INTO webhook_deliveries (webhook_id, version, payload) \
+             SELECT id, $2, $3 FROM webhooks WHERE environment = $1",
+            self.environment,
+            snapshot.version,
+            payload,
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    /// Claim pending deliveries due at `now`, in every environment, oldest first. Each
+    /// claimed delivery has its attempt counted and is hidden from other replicas for
+    /// [`CLAIM_LEASE`], so every replica can work the queue at once.
+    pub async fn claim_due_deliveries(&self, now: DateTime<Utc>) -> AppResult<Vec<DueDelivery>> {
+        Ok(sqlx::query_as!(
+            DueDelivery,
+            "WITH due AS ( \
+               SELECT id FROM webhook_deliveries \
+               WHERE status = 'pending' AND next_attempt_at <= $1 \
+               ORDER BY next_attempt_at, created_at \
+               LIMIT $2 \
+               FOR UPDATE SKIP LOCKED) \
+             UPDATE webhook_deliveries d \
+             SET attempts = d.attempts + 1, next_attempt_at = $3 \
+             FROM due, webhooks w \
+             WHERE d.id = due.id AND w.id = d.webhook_id \
+             RETURNING d.id, w.url, w.secret, d.payload, d.attempts",
+            now,
+            MAX_DUE_PER_PASS,
+            now + CLAIM_LEASE,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Record the outcome of sending a claimed delivery: `error` is `None` on success.
+    /// A failure is retried after [`retry_delay`] until [`MAX_DELIVERY_ATTEMPTS`].
+    pub async fn finish_delivery(
+        &self,
+        delivery: &DueDelivery,
+        response_status: i32,
+        error: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let (status, next_attempt_at) = match error {
+            None => (DeliveryStatus::Delivered, now),
+            Some(_) if delivery.attempts >= MAX_DELIVERY_ATTEMPTS => (DeliveryStatus::Failed, now),
+            Some(_) => (
+                DeliveryStatus::Pending,
+                now + retry_delay(delivery.attempts),
+            ),
+        };
+        sqlx::query!(
+            "UPDATE webhook_deliveries \
+             SET status = $2, next_attempt_at = $3, response_status = $4, error = $5, \
+                 delivered_at = CASE WHEN $2 = 'delivered' THEN $3::timestamptz END \
+             WHERE id = $1",
+            delivery.id,
+            status.as_str(),
+            next_attempt_at,
+            response_status,
+            error.unwrap_or_default(),
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-environment, per-flag, per-variant, per-client evaluation counting. Counts accumulate in memory
+//! on the hot path and are flushed to `flag_evaluations` periodically, so evaluation
+//! never waits on Postgres. Every replica flushes its own counts; the table sums them.
+
+use crate::metrics;
+use crate::store::{EvaluationKey, Store};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Distinct (environment, flag, variant, client) rows held between flushes, including
+/// across failed ones. Counts for new rows beyond it are dropped, so neither a long
+/// Postgres outage nor a flood of client ids can grow memory without bound.
+const MAX_PENDING: usize = 50_000;
+
+pub struct Telemetry {
+    store: Store,
+    pending: Mutex<HashMap<EvaluationKey, i64>>,
+}
+
+impl Telemetry {
+    pub fn new(store: Store) -> Arc<Self> {
+        Arc::new(Self {
+            store,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// One evaluation served by this replica.
+    pub fn record(&self, environment: &str, client_id: &str, flag_key: &str, variant_key: &str) {
+        metrics::record_evaluations(flag_key, variant_key, "server", 1);
+        self.add(environment, client_id, flag_key, variant_key, 1);
+    }
+
+    /// A batch of counts a local-mode client evaluated in-process.
+    pub fn record_reported(
+        &self,
+        environment: &str,
+        client_id: &str,
+        counts: &[(String, String, u64)],
+    ) {
+        for (flag_key, variant_key, n) in counts {
+            if *n == 0 || variant_key.is_empty() {
+                continue;
+            }
+            metrics::record_evaluations(flag_key, variant_key, "client", *n);
+            self.add(environment, client_id, flag_key, variant_key, *n);
+        }
+    }
+
+    fn add(&self, environment: &str, client_id: &str, flag_key: &str, variant_key: &str, n: u64) {
+        let key = EvaluationKey {
+            environment: environment.to_owned(),
+            flag_key: flag_key.to_owned(),
+            variant_key: variant_key.to_owned(),
+            client_id: client_id.to_owned(),
+        };
+        let n = i64::try_from(n).unwrap_or(i64::MAX);
+        let mut pending = self.pending.lock().unwrap();
+        Self::merge(&mut pending, key, n);
+    }
+
+    /// Add `n` to `key`'s pending count, unless that would start a row past
+    /// [`MAX_PENDING`].
+    fn merge(pending: &mut HashMap<EvaluationKey, i64>, key: EvaluationKey, n: i64) {
+        if pending.len() >= MAX_PENDING && !pending.contains_key(&key) {
+            metrics::record_usage_dropped(1);
+            return;
+        }
+        let count = pending.entry(key).or_default();
+        *count = count.saturating_add(n);
+    }
+
+    /// Write everything counted since the last flush. On failure the counts are kept
+    /// for the next attempt, up to [`MAX_PENDING`] rows.
+    pub async fn flush(&self) {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        if batch.is_empty() {
+            return;
+        }
+        let Err(e) = self.store.record_evaluations(Utc::now(), &batch).await else {
+            return;
+        };
+        tracing::warn!("flushing evaluation counts failed: {e}");
+        let mut pending = self.pending.lock().unwrap();
+        for (key, n) in batch {
+            Self::merge(&mut pending, key, n);
+        }
+    }
+
+    /// Long-lived task flushing every [`FLUSH_INTERVAL`].
+    pub async fn run(self: Arc<Self>) {
+        let mut tick = tokio::time::interval(FLUSH_INTERVAL);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            self.flush().await;
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Sends the webhook deliveries writes queue (see [`Store::create_webhook`]). Every
+//! replica runs the loop: claiming skips rows another replica holds, so each delivery
+//! is attempted by one replica at a time without a leader.
+//!
+//! Each delivery is a `POST` of the JSON payload with headers:
+//!
+//! - `X-Flag-Event: config_change`
+//! - `X-Flag-Delivery`: the delivery id, stable across retries, for deduplication.
+//! - `X-Flag-Signature-256`: `sha256=<hex HMAC-SHA256(secret, body)>`, the same scheme
+//!   as GitHub's `X-Hub-Signature-256`.
+//!
+//! A 2xx response counts as delivered; anything else is retried with backoff.
+
+use crate::error::AppResult;
+use crate::metrics;
+use crate::store::{DueDelivery, MAX_DELIVERY_ATTEMPTS, Store};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+
+pub const SIGNATURE_HEADER: &str = "x-flag-signature-256";
+pub const DELIVERY_HEADER: &str = "x-flag-delivery";
+pub const EVENT_HEADER: &str = "x-flag-event";
+
+const TICK: Duration = Duration::from_secs(5);
+
+/// Per-attempt timeout, well inside the claim lease.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Long-lived task.
+pub async fn run(store: Store) {
+    let client = client();
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        tick.tick().await;
+        if let Err(e) = deliver_due(&store, &client, Utc::now()).await {
+            tracing::error!("sending webhook deliveries failed: {e}");
+        }
+    }
+}
+
+/// The HTTP client deliveries are sent with.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(SEND_TIMEOUT)
+        .build()
+        .expect("building the webhook HTTP client")
+}
+
+/// Claim the deliveries due at `now`, send them concurrently and record each outcome,
+/// timed from when the sends finished so a slow receiver doesn't eat into the backoff.
+/// An outcome that fails to record is logged and left to the claim lease to retry.
+/// Returns how many were delivered.
+pub async fn deliver_due(
+    store: &Store,
+    client: &reqwest::Client,
+    now: DateTime<Utc>,
+) -> AppResult<usize> {
+    let due = store.claim_due_deliveries(now).await?;
+    let sent = futures::future::join_all(due.iter().map(|delivery| send(client, delivery))).await;
+    let mut delivered = 0;
+    for (delivery, (status, error)) in due.iter().zip(sent) {
+        let outcome = match &error {
+            None => "delivered",
+            Some(_) if delivery.attempts >= MAX_DELIVERY_ATTEMPTS => "failed",
+            Some(_) => "retrying",
+        };
+        if let Some(error) = &error {
+            tracing::warn!(
+                id = %delivery.id,
+                url = delivery.url,
+                attempt = delivery.attempts,
+                outcome,
+                "webhook delivery failed: {error}"
+            );
+        }
+        metrics::record_webhook_delivery(outcome);
+        if let Err(e) = store
+            .finish_delivery(delivery, status, error.as_deref(), Utc::now())
+            .await
+        {
+            tracing::error!(id = %delivery.id, "recording webhook delivery failed: {e}");
+            continue;
+        }
+        delivered += usize::from(error.is_none());
+    }
+    Ok(delivered)
+}
+
+/// `POST` one delivery. Returns the response status (0 without a response) and, if it
+/// failed, why.
+async fn send(client: &reqwest::Client, delivery: &DueDelivery) -> (i32, Option<String>) {
+    let body = delivery.payload.to_string().into_bytes();
+    let result = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, "config_change")
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(SIGNATURE_HEADER, sign(&delivery.secret, &body))
+        .body(body)
+        .send()
+        .await;
+    match result {
+        Ok(response) if response.status().is_success() => {
+            (i32::from(response.status().as_u16()), None)
+        }
+        Ok(response) => {
+            let status = response.status();
+            (i32::from(status.as_u16()), Some(format!("HTTP {status}")))
+        }
+        Err(e) => (0, Some(e.to_string())),
+    }
+}
+
+/// The `X-Flag-Signature-256` value for `body`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
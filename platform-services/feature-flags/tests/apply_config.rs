@@ -1,37 +1,12 @@
-use feature_flags::model::{Flag, Segment, ValueType, Variant};
+mod common;
+
+use chrono::{SubsecRound, Utc};
+use common::{bool_flag, segment};
+use feature_flags::model::Flag;
 use feature_flags::store::{ChangeOp, Store};
 use serde_json::json;
 use sqlx::PgPool;
 
-fn bool_flag(key: &str, enabled: bool) -> Flag {
-    Flag {
-        key: key.into(),
-        value_type: ValueType::Boolean,
-        enabled,
-        default_variant_key: "off".into(),
-        archived: false,
-        variants: vec![
-            Variant {
-                key: "on".into(),
-                value: json!(true),
-            },
-            Variant {
-                key: "off".into(),
-                value: json!(false),
-            },
-        ],
-        rules: vec![],
-    }
-}
-
-fn segment(key: &str) -> Segment {
-    Segment {
-        key: key.into(),
-        name: "Beta".into(),
-        constraints: vec![],
-    }
-}
-
 #[sqlx::test(migrations = "./migrations")]
 async fn dry_run_reports_diff_without_writing(pool: PgPool) {
     let store = Store::new(pool);
@@ -89,7 +64,16 @@ async fn update_is_detected(pool: PgPool) {
         .unwrap();
 
     let out = store
-        .apply_config("alice", &[bool_flag("f", false)], &[], true, 0)
+        .apply_config(
+            "alice",
+            &[Flag {
+                enabled: false,
+                ..bool_flag("f", true)
+            }],
+            &[],
+            true,
+            0,
+        )
         .await
         .unwrap();
     assert_eq!(out.changes.len(), 1);
@@ -97,6 +81,42 @@ async fn update_is_detected(pool: PgPool) {
     assert_eq!(out.changes[0].detail, json!({ "fields": ["enabled"] }));
 }
 
+#[sqlx::test(migrations = "./migrations")]
+async fn metadata_round_trips_and_is_diffed(pool: PgPool) {
+    let store = Store::new(pool);
+    // Postgres keeps microseconds.
+    let expires_at = Utc::now().trunc_subsecs(6);
+    let flag = Flag {
+        tags: vec!["checkout".into()],
+        owners: vec!["team:payments".into()],
+        description: "New checkout flow".into(),
+        expires_at: Some(expires_at),
+        ..bool_flag("f", true)
+    };
+    store
+        .apply_config("alice", std::slice::from_ref(&flag), &[], false, 0)
+        .await
+        .unwrap();
+    let live = store.load_snapshot().await.unwrap();
+    assert_eq!(live.flags["f"], flag);
+    assert!(live.flags["f"].is_expired(expires_at));
+
+    let extended = Flag {
+        description: "New checkout flow, extended".into(),
+        expires_at: Some(expires_at + chrono::Duration::days(30)),
+        ..flag
+    };
+    let out = store
+        .apply_config("alice", &[extended], &[], true, 0)
+        .await
+        .unwrap();
+    assert_eq!(out.changes.len(), 1);
+    assert_eq!(
+        out.changes[0].detail,
+        json!({ "fields": ["description", "expires_at"] })
+    );
+}
+
 #[sqlx::test(migrations = "./migrations")]
 async fn absent_flags_and_segments_are_pruned(pool: PgPool) {
     let store = Store::new(pool);
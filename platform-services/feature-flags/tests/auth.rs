@@ -0,0 +1,260 @@
+mod common;
+
+use common::{bool_flag, connect_admin, spawn_server_with_auth};
+use feature_flags::auth::{AuthConfig, Authenticator, Role, RoleBinding, TokenConfig};
+use feature_flags::model::Flag;
+use feature_flags::pb;
+use feature_flags::store::Store;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tonic::Code;
+
+fn token(name: &str, groups: &[&str]) -> TokenConfig {
+    TokenConfig {
+        name: name.into(),
+        sha256: hex::encode(Sha256::digest(format!("{name}-secret"))),
+        groups: groups.iter().map(|g| g.to_string()).collect(),
+    }
+}
+
+fn binding(subject: &str, role: Role, flag_prefixes: &[&str]) -> RoleBinding {
+    RoleBinding {
+        subjects: vec![subject.into()],
+        role,
+        flag_prefixes: flag_prefixes.iter().map(|p| p.to_string()).collect(),
+        flag_tags: vec![],
+        environments: vec![],
+    }
+}
+
+fn authenticator() -> Authenticator {
+    Authenticator::new(AuthConfig {
+        tokens: vec![
+            token("root", &[]),
+            token("payments-bot", &["payments"]),
+            token("auditor", &[]),
+        ],
+        oidc: None,
+        bindings: vec![
+            binding("token:root", Role::Owner, &[]),
+            binding("group:payments", Role::Editor, &["payments."]),
+            binding("group:payments", Role::Viewer, &[]),
+            binding("token:auditor", Role::Viewer, &["payments."]),
+        ],
+    })
+    .unwrap()
+}
+
+/// `msg` authenticated as the named token, with an `actor` header the service must
+/// ignore.
+fn as_token<T>(name: &str, msg: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(msg);
+    let metadata = request.metadata_mut();
+    metadata.insert(
+        "authorization",
+        format!("Bearer {name}-secret").parse().unwrap(),
+    );
+    metadata.insert("actor", "mallory".parse().unwrap());
+    request
+}
+
+fn create_flag(key: &str) -> pb::CreateFlagRequest {
+    pb::CreateFlagRequest {
+        key: key.into(),
+        value_type: pb::ValueType::Boolean as i32,
+        enabled: true,
+        default_variant_key: "on".into(),
+        variants: vec![pb::Variant {
+            key: "on".into(),
+            value: Some(prost_types::Value {
+                kind: Some(prost_types::value::Kind::BoolValue(true)),
+            }),
+        }],
+    }
+}
+
+fn update_flag(key: &str) -> pb::UpdateFlagRequest {
+    pb::UpdateFlagRequest {
+        key: key.into(),
+        enabled: false,
+        default_variant_key: "on".into(),
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn admin_rpcs_require_a_token_and_a_covering_role(pool: PgPool) {
+    let (endpoint, server_handle) = spawn_server_with_auth(pool, authenticator()).await;
+    let mut admin = connect_admin(&endpoint).await;
+
+    let err = admin
+        .list_flags(pb::ListFlagsRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    let err = admin
+        .list_flags(as_token("unknown", pb::ListFlagsRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    for key in ["payments.checkout", "search.v2"] {
+        admin
+            .create_flag(as_token("root", create_flag(key)))
+            .await
+            .unwrap();
+    }
+
+    // Editors can change flags under their prefix but not create them or touch others.
+    admin
+        .update_flag(as_token("payments-bot", update_flag("payments.checkout")))
+        .await
+        .unwrap();
+    let err = admin
+        .update_flag(as_token("payments-bot", update_flag("search.v2")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = admin
+        .create_flag(as_token("payments-bot", create_flag("payments.refunds")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    let err = admin
+        .create_segment(as_token(
+            "payments-bot",
+            pb::CreateSegmentRequest {
+                segment: Some(pb::Segment {
+                    key: "beta".into(),
+                    ..Default::default()
+                }),
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // Listings only include what the caller may view.
+    let keys = |flags: pb::ListFlagsResponse| -> Vec<String> {
+        flags.flags.into_iter().map(|f| f.key).collect()
+    };
+    let all = admin
+        .list_flags(as_token("payments-bot", pb::ListFlagsRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(keys(all), ["payments.checkout", "search.v2"]);
+    let scoped = admin
+        .list_flags(as_token("auditor", pb::ListFlagsRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(keys(scoped), ["payments.checkout"]);
+    let err = admin
+        .get_flag(as_token(
+            "auditor",
+            pb::GetFlagRequest {
+                key: "search.v2".into(),
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // The audit log records the authenticated principal, not the `actor` header.
+    let changes = admin
+        .list_changes(as_token(
+            "root",
+            pb::ListChangesRequest {
+                target_kind: "flag".into(),
+                target_key: "payments.checkout".into(),
+                limit: 0,
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner()
+        .changes;
+    let actors: Vec<_> = changes.iter().map(|c| c.actor.as_str()).collect();
+    assert_eq!(actors, ["token:payments-bot", "token:root"]);
+
+    server_handle.abort();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn tag_bindings_cover_tagged_flags_but_cannot_retag_them(pool: PgPool) {
+    let auth = Authenticator::new(AuthConfig {
+        tokens: vec![token("root", &[]), token("checkout-bot", &["checkout"])],
+        oidc: None,
+        bindings: vec![
+            binding("token:root", Role::Owner, &[]),
+            RoleBinding {
+                flag_tags: vec!["checkout".into()],
+                ..binding("group:checkout", Role::Owner, &[])
+            },
+        ],
+    })
+    .unwrap();
+    let store = Store::new(pool.clone());
+    store.create_environment("alice", "prod").await.unwrap();
+    let tagged = |tags: &[&str]| Flag {
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        ..bool_flag("cart", true)
+    };
+    store
+        .apply_config(
+            "alice",
+            &[tagged(&["checkout"]), bool_flag("search", true)],
+            &[],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    store
+        .in_environment("prod")
+        .apply_config("alice", &[tagged(&["checkout"])], &[], false, 0)
+        .await
+        .unwrap();
+
+    let (endpoint, server_handle) = spawn_server_with_auth(pool, auth).await;
+    let mut admin = connect_admin(&endpoint).await;
+
+    admin
+        .update_flag(as_token("checkout-bot", update_flag("cart")))
+        .await
+        .unwrap();
+    let err = admin
+        .update_flag(as_token("checkout-bot", update_flag("search")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // Promoting `cart` as it is keeps its tags, so owning it by tag is enough.
+    let promote = || pb::PromoteFlagRequest {
+        flag_key: "cart".into(),
+        from_environment: "default".into(),
+        to_environment: "prod".into(),
+    };
+    admin
+        .promote_flag(as_token("checkout-bot", promote()))
+        .await
+        .unwrap();
+
+    // Promoting a retag would hand the flag to other bindings: that's for owners of the
+    // whole environment.
+    store
+        .apply_config("alice", &[tagged(&["checkout", "web"])], &[], false, 0)
+        .await
+        .unwrap();
+    let err = admin
+        .promote_flag(as_token("checkout-bot", promote()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    admin
+        .promote_flag(as_token("root", promote()))
+        .await
+        .unwrap();
+
+    server_handle.abort();
+}
@@ -1,23 +1,37 @@
 #![allow(dead_code)]
 
+use feature_flags::auth::Authenticator;
 use feature_flags::grpc::{AdminService, EvaluationService};
+use feature_flags::model::{Flag, Segment, ValueType, Variant};
 use feature_flags::pb::admin_client::AdminClient;
 use feature_flags::pb::admin_server::AdminServer;
 use feature_flags::pb::evaluation_client::EvaluationClient;
 use feature_flags::pb::evaluation_server::EvaluationServer;
-use feature_flags::snapshot::SnapshotManager;
+use feature_flags::snapshot::Environments;
 use feature_flags::store::Store;
+use feature_flags::telemetry::Telemetry;
+use serde_json::json;
 use sqlx::PgPool;
 use tokio::task::JoinHandle;
 use tonic::transport::Channel;
 
 pub async fn spawn_server(pool: PgPool) -> (String, JoinHandle<()>) {
+    serve(pool, None).await
+}
+
+/// Like [`spawn_server`], with the Admin service requiring `auth`.
+pub async fn spawn_server_with_auth(pool: PgPool, auth: Authenticator) -> (String, JoinHandle<()>) {
+    serve(pool, Some(auth)).await
+}
+
+async fn serve(pool: PgPool, auth: Option<Authenticator>) -> (String, JoinHandle<()>) {
     let store = Store::new(pool);
-    let manager = SnapshotManager::bootstrap(store.clone(), None)
-        .await
-        .unwrap();
-    let admin = AdminService::new(store, manager.clone());
-    let evaluation = EvaluationService::new(manager);
+    let envs = Environments::bootstrap(store.clone(), None).await.unwrap();
+    let evaluation = EvaluationService::new(envs.clone(), Telemetry::new(store));
+    let admin = match auth {
+        Some(auth) => AdminService::new(envs).with_auth(auth),
+        None => AdminService::new(envs),
+    };
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
@@ -50,3 +64,38 @@ pub fn eval_request<T>(msg: T) -> tonic::Request<T> {
         .insert("client-id", "integration-test".parse().unwrap());
     request
 }
+
+/// A boolean flag serving `value` to everyone.
+pub fn bool_flag(key: &str, value: bool) -> Flag {
+    Flag {
+        key: key.into(),
+        value_type: ValueType::Boolean,
+        enabled: true,
+        default_variant_key: if value { "on" } else { "off" }.into(),
+        archived: false,
+        variants: vec![
+            Variant {
+                key: "on".into(),
+                value: json!(true),
+            },
+            Variant {
+                key: "off".into(),
+                value: json!(false),
+            },
+        ],
+        rules: vec![],
+        tags: vec![],
+        owners: vec![],
+        description: String::new(),
+        expires_at: None,
+    }
+}
+
+/// A segment with no constraints, named after its key.
+pub fn segment(key: &str) -> Segment {
+    Segment {
+        key: key.into(),
+        name: key.into(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use common::{bool_flag, connect_admin, connect_eval, eval_request, spawn_server};
+use feature_flags::error::AppError;
+use feature_flags::model::{Constraint, ConstraintGroup, Flag, Operator, Rule, Segment};
+use feature_flags::pb;
+use feature_flags::store::{DEFAULT_ENVIRONMENT, Store};
+use serde_json::json;
+use sqlx::PgPool;
+
+fn in_env<T>(environment: &str, msg: T) -> tonic::Request<T> {
+    let mut request = eval_request(msg);
+    request
+        .metadata_mut()
+        .insert("environment", environment.parse().unwrap());
+    request
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn environments_keep_independent_config_versions_and_audit(pool: PgPool) {
+    let store = Store::new(pool);
+    store.create_environment("alice", "staging").await.unwrap();
+    let staging = store.in_environment("staging");
+
+    let err = store
+        .create_environment("alice", "staging")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("already exists"), "{err}");
+    let err = store
+        .create_environment("alice", "Bad Name")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::Invalid(_)), "{err}");
+
+    let default_before = store.config_version().await.unwrap();
+    staging
+        .apply_config("alice", &[bool_flag("f", false)], &[], false, 0)
+        .await
+        .unwrap();
+    store
+        .apply_config("bob", &[bool_flag("f", true)], &[], false, 0)
+        .await
+        .unwrap();
+
+    // The same key lives independently in each environment.
+    let default_flag = &store.load_snapshot().await.unwrap().flags["f"];
+    assert_eq!(default_flag.default_variant_key, "on");
+    let staging_flag = &staging.load_snapshot().await.unwrap().flags["f"];
+    assert_eq!(staging_flag.default_variant_key, "off");
+    assert!(store.config_version().await.unwrap() > default_before);
+
+    // A write in one environment leaves the other's version alone.
+    let staging_version = staging.config_version().await.unwrap();
+    store.archive_flag("bob", "f", true).await.unwrap();
+    assert_eq!(staging.config_version().await.unwrap(), staging_version);
+
+    let actors: Vec<_> = staging
+        .list_changes("flag", "f", 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| c.actor)
+        .collect();
+    assert_eq!(actors, ["alice"]);
+
+    let names: Vec<_> = store
+        .list_environments()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(names, [DEFAULT_ENVIRONMENT, "staging"]);
+    assert!(matches!(
+        store.in_environment("missing").config_version().await,
+        Err(AppError::NotFound(_))
+    ));
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn promote_copies_missing_segments_only(pool: PgPool) {
+    let store = Store::new(pool);
+    store.create_environment("alice", "prod").await.unwrap();
+    let prod = store.in_environment("prod");
+
+    let beta = Segment {
+        key: "beta".into(),
+        name: "Beta".into(),
+        included_segments: vec!["staff".into()],
+        targeting_keys: ["u1".to_owned()].into(),
+        ..Default::default()
+    };
+    let staff = Segment {
+        key: "staff".into(),
+        name: "Staff".into(),
+        targeting_keys: ["u2".to_owned()].into(),
+        ..Default::default()
+    };
+    let rule = Rule {
+        rank: 0,
+        segment_key: Some("beta".into()),
+        variant_key: Some("off".into()),
+        distributions: vec![],
+        constraint_groups: vec![],
+        bucket_salt: String::new(),
+    };
+    let flag = Flag {
+        rules: vec![rule],
+        ..bool_flag("f", true)
+    };
+    store
+        .apply_config(
+            "alice",
+            std::slice::from_ref(&flag),
+            &[beta, staff],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    // prod already defines `staff` its own way; promotion must not overwrite it.
+    let prod_staff = Segment {
+        key: "staff".into(),
+        name: "Prod staff".into(),
+        ..Default::default()
+    };
+    prod.upsert_segment("alice", &prod_staff).await.unwrap();
+
+    let promotion = store.promote_flag("carol", "f", &prod).await.unwrap();
+    assert!(promotion.created);
+    assert!(promotion.changed);
+    assert_eq!(promotion.copied_segments, ["beta"]);
+    assert_eq!(promotion.version, prod.config_version().await.unwrap());
+
+    let snap = prod.load_snapshot().await.unwrap();
+    assert_eq!(snap.flags["f"], flag);
+    assert_eq!(snap.segments["staff"], prod_staff);
+    let audit = prod.list_changes("flag", "f", 0).await.unwrap();
+    assert_eq!(audit[0].action, "promote_flag");
+    assert_eq!(audit[0].detail["from"], DEFAULT_ENVIRONMENT);
+
+    // Promoting again is a no-op.
+    let again = store.promote_flag("carol", "f", &prod).await.unwrap();
+    assert!(!again.changed);
+    assert!(again.copied_segments.is_empty());
+    assert_eq!(again.version, promotion.version);
+
+    let err = store.promote_flag("carol", "f", &store).await.unwrap_err();
+    assert!(matches!(err, AppError::Invalid(_)), "{err}");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn promote_refuses_a_flag_whose_prerequisites_the_target_lacks(pool: PgPool) {
+    let store = Store::new(pool);
+    store.create_environment("alice", "prod").await.unwrap();
+    let prod = store.in_environment("prod");
+
+    let rule = Rule {
+        rank: 0,
+        segment_key: None,
+        variant_key: Some("on".into()),
+        distributions: vec![],
+        constraint_groups: vec![ConstraintGroup {
+            constraints: vec![Constraint {
+                attribute: "base".into(),
+                operator: Operator::FlagMatches,
+                values: vec![json!("on")],
+            }],
+        }],
+        bucket_salt: String::new(),
+    };
+    let flag = Flag {
+        rules: vec![rule],
+        ..bool_flag("f", false)
+    };
+    store
+        .apply_config("alice", &[bool_flag("base", true), flag], &[], false, 0)
+        .await
+        .unwrap();
+
+    let err = store.promote_flag("carol", "f", &prod).await.unwrap_err();
+    assert!(
+        matches!(&err, AppError::Invalid(m) if m.contains("`base`")),
+        "{err}"
+    );
+    assert!(prod.load_snapshot().await.unwrap().flags.is_empty());
+
+    // Once the prerequisite is in place, the flag follows.
+    store.promote_flag("carol", "base", &prod).await.unwrap();
+    let promotion = store.promote_flag("carol", "f", &prod).await.unwrap();
+    assert!(promotion.created);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn requests_are_scoped_by_environment_metadata(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    store.create_environment("alice", "staging").await.unwrap();
+    store
+        .in_environment("staging")
+        .apply_config("alice", &[bool_flag("f", false)], &[], false, 0)
+        .await
+        .unwrap();
+    store
+        .apply_config("alice", &[bool_flag("f", true)], &[], false, 0)
+        .await
+        .unwrap();
+
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut eval = connect_eval(&endpoint).await;
+    let mut admin = connect_admin(&endpoint).await;
+
+    let resolve = |environment: Option<&str>| {
+        let msg = pb::ResolveRequest {
+            flag_key: "f".into(),
+            context: None,
+        };
+        match environment {
+            Some(env) => in_env(env, msg),
+            None => eval_request(msg),
+        }
+    };
+    // Without the header the request reads `default`.
+    let value = eval.resolve_boolean(resolve(None)).await.unwrap();
+    assert!(value.into_inner().value);
+    let value = eval
+        .resolve_boolean(resolve(Some("staging")))
+        .await
+        .unwrap();
+    assert!(!value.into_inner().value);
+    let err = eval
+        .resolve_boolean(resolve(Some("missing")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    // An environment created after startup is loaded on first use.
+    admin
+        .create_environment(pb::CreateEnvironmentRequest { name: "qa".into() })
+        .await
+        .unwrap();
+    let promoted = admin
+        .promote_flag(pb::PromoteFlagRequest {
+            flag_key: "f".into(),
+            from_environment: "staging".into(),
+            to_environment: "qa".into(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(promoted.created);
+    let value = eval.resolve_boolean(resolve(Some("qa"))).await.unwrap();
+    assert!(!value.into_inner().value);
+
+    let flags = admin
+        .list_flags(in_env("qa", pb::ListFlagsRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(flags.flags.len(), 1);
+    let environments = admin
+        .list_environments(pb::ListEnvironmentsRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .environments;
+    assert_eq!(environments.len(), 3);
+
+    server_handle.abort();
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{connect_eval, eval_request, spawn_server};
+use feature_flags::model::{Distribution, Rule, ValueType, Variant};
+use feature_flags::pb;
+use feature_flags::store::Store;
+use serde_json::Value;
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn explain_resolve_traces_rules_and_split(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    let variants = [
+        Variant {
+            key: "on".into(),
+            value: Value::Bool(true),
+        },
+        Variant {
+            key: "off".into(),
+            value: Value::Bool(false),
+        },
+    ];
+    store
+        .create_flag(
+            "alice",
+            "checkout",
+            ValueType::Boolean,
+            true,
+            "off",
+            &variants,
+        )
+        .await
+        .unwrap();
+    let staff = Rule {
+        rank: 0,
+        segment_key: Some("staff".into()),
+        variant_key: Some("on".into()),
+        distributions: vec![],
+        constraint_groups: vec![],
+        bucket_salt: String::new(),
+    };
+    let half = Rule {
+        rank: 1,
+        segment_key: None,
+        variant_key: None,
+        distributions: vec![
+            Distribution {
+                variant_key: "on".into(),
+                weight: 50,
+            },
+            Distribution {
+                variant_key: "off".into(),
+                weight: 50,
+            },
+        ],
+        constraint_groups: vec![],
+        bucket_salt: String::new(),
+    };
+    store
+        .set_flag_rules("alice", "checkout", &[staff, half])
+        .await
+        .unwrap();
+
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut client = connect_eval(&endpoint).await;
+    let request = pb::ResolveRequest {
+        flag_key: "checkout".into(),
+        context: Some(pb::EvaluationContext {
+            targeting_key: "user-1".into(),
+            attributes: None,
+        }),
+    };
+
+    let err = client
+        .explain_resolve(request.clone())
+        .await
+        .expect_err("expected rejection");
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    let trace = client
+        .explain_resolve(eval_request(request.clone()))
+        .await
+        .unwrap()
+        .into_inner()
+        .trace
+        .unwrap();
+    let meta = trace.meta.unwrap();
+    assert_eq!(meta.reason, pb::Reason::Split as i32);
+
+    // The staff segment doesn't exist, so rule 0 is tried and skipped.
+    assert_eq!(trace.rules.len(), 2);
+    let segment = trace.rules[0].segment.as_ref().unwrap();
+    assert!(!segment.found && !trace.rules[0].matched);
+    let split = trace.rules[1].split.as_ref().unwrap();
+    assert_eq!(split.variant_key, meta.variant);
+    assert_eq!(split.variant_key == "on", split.bucket < 50);
+
+    // The trace agrees with the typed resolution.
+    let resolved = client
+        .resolve_boolean(eval_request(request))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(resolved.meta.unwrap().variant, meta.variant);
+
+    server_handle.abort();
+}
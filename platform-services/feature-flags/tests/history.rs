@@ -0,0 +1,254 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{bool_flag, connect_admin, segment, spawn_server};
+use feature_flags::error::AppError;
+use feature_flags::pb;
+use feature_flags::store::{ChangeOp, HistoryRetention, Store};
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn every_write_saves_a_snapshot_that_can_be_diffed(pool: PgPool) {
+    let store = Store::new(pool);
+    store.record_snapshot().await.unwrap();
+    let empty = store.config_version().await.unwrap();
+
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", true)],
+            &[segment("beta")],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let v1 = store.config_version().await.unwrap();
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", false), bool_flag("b", true)],
+            &[],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let v2 = store.config_version().await.unwrap();
+
+    let at_v1 = store.snapshot_at_version(v1).await.unwrap().snapshot;
+    assert_eq!(at_v1.version, v1);
+    assert_eq!(at_v1.flags["a"], bool_flag("a", true));
+    assert!(at_v1.segments.contains_key("beta"));
+    assert!(
+        store
+            .snapshot_at_version(empty)
+            .await
+            .unwrap()
+            .snapshot
+            .flags
+            .is_empty()
+    );
+    assert!(matches!(
+        store.snapshot_at_version(v2 + 1).await,
+        Err(AppError::NotFound(_))
+    ));
+
+    let diff = store.diff_versions(v1, 0).await.unwrap();
+    assert_eq!((diff.from_version, diff.to_version), (v1, v2));
+    let changes: Vec<_> = diff
+        .changes
+        .iter()
+        .map(|c| (c.target_kind, c.target_key.as_str(), c.op))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("segment", "beta", ChangeOp::Delete),
+            ("flag", "a", ChangeOp::Update),
+            ("flag", "b", ChangeOp::Create),
+        ]
+    );
+    assert!(
+        store
+            .diff_versions(v2, v2)
+            .await
+            .unwrap()
+            .changes
+            .is_empty()
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rollback_restores_a_version_or_a_single_flag(pool: PgPool) {
+    let store = Store::new(pool);
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", true)],
+            &[segment("beta")],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let v1 = store.config_version().await.unwrap();
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", false), bool_flag("b", true)],
+            &[],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let v2 = store.config_version().await.unwrap();
+
+    // A dry run plans without writing.
+    let plan = store
+        .rollback_to_version("bob", v1, None, true, 0)
+        .await
+        .unwrap();
+    assert!(!plan.applied);
+    assert_eq!(plan.changes.len(), 3);
+    assert_eq!(store.config_version().await.unwrap(), v2);
+
+    // Restoring one flag leaves the rest live.
+    let outcome = store
+        .rollback_to_version("bob", v1, Some("a"), false, 0)
+        .await
+        .unwrap();
+    assert!(outcome.applied);
+    assert_eq!(outcome.changes.len(), 1);
+    let live = store.load_snapshot().await.unwrap();
+    assert_eq!(live.flags["a"], bool_flag("a", true));
+    assert!(live.flags.contains_key("b"));
+    assert!(!live.segments.contains_key("beta"));
+
+    let audit = store.list_changes("flag", "a", 1).await.unwrap();
+    assert_eq!(audit[0].actor, "bob");
+    assert_eq!(audit[0].detail["rollback_to"], v1);
+
+    // A stale optimistic lock aborts.
+    let err = store
+        .rollback_to_version("bob", v1, None, false, v2)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::Aborted(_)), "{err}");
+
+    // Restoring the whole version recreates and deletes as needed.
+    store
+        .rollback_to_version("bob", v1, None, false, 0)
+        .await
+        .unwrap();
+    let live = store.load_snapshot().await.unwrap();
+    assert_eq!(live.flags.keys().collect::<Vec<_>>(), ["a"]);
+    assert!(live.segments.contains_key("beta"));
+    // The rollback is itself a version in history.
+    let restored = store.snapshot_at_version(live.version).await.unwrap();
+    assert_eq!(restored.snapshot.flags, live.flags);
+
+    let err = store
+        .rollback_to_version("bob", v1, Some("missing"), false, 0)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)), "{err}");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn history_past_its_retention_is_pruned(pool: PgPool) {
+    let store = Store::new(pool);
+    store.record_snapshot().await.unwrap();
+    let first = store.config_version().await.unwrap();
+    let mut versions = vec![];
+    for enabled in [true, false, true] {
+        store
+            .apply_config("alice", &[bool_flag("a", enabled)], &[], false, 0)
+            .await
+            .unwrap();
+        versions.push(store.config_version().await.unwrap());
+    }
+    let retention = HistoryRetention {
+        versions: 2,
+        max_age: Duration::days(30),
+    };
+
+    // Nothing is old enough yet.
+    assert_eq!(store.prune_history(retention, Utc::now()).await.unwrap(), 0);
+    assert!(store.snapshot_at_version(first).await.is_ok());
+
+    // Once it is, all but the newest two versions go.
+    let later = Utc::now() + Duration::days(31);
+    assert_eq!(store.prune_history(retention, later).await.unwrap(), 2);
+    assert!(matches!(
+        store.snapshot_at_version(first).await,
+        Err(AppError::NotFound(_))
+    ));
+    for version in &versions[1..] {
+        assert!(store.snapshot_at_version(*version).await.is_ok());
+    }
+    assert_eq!(store.prune_history(retention, later).await.unwrap(), 0);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn rollback_over_grpc_serves_the_restored_flag(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    store
+        .apply_config("alice", &[bool_flag("a", true)], &[], false, 0)
+        .await
+        .unwrap();
+    let v1 = store.config_version().await.unwrap();
+    store
+        .apply_config("alice", &[bool_flag("a", false)], &[], false, 0)
+        .await
+        .unwrap();
+
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut admin = connect_admin(&endpoint).await;
+
+    let snapshot = admin
+        .get_snapshot_at_version(pb::GetSnapshotAtVersionRequest { version: v1 })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(snapshot.version, v1);
+    assert_eq!(snapshot.flags[0].default_variant_key, "on");
+
+    let diff = admin
+        .diff_versions(pb::DiffVersionsRequest {
+            from_version: 0,
+            to_version: v1,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(diff.changes.len(), 1);
+    assert_eq!(diff.to_version, v1);
+
+    let resp = admin
+        .rollback_to_version(pb::RollbackToVersionRequest {
+            version: v1,
+            flag_key: "a".into(),
+            dry_run: false,
+            expected_version: 0,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(resp.applied);
+    let flag = admin
+        .get_flag(pb::GetFlagRequest { key: "a".into() })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(flag.default_variant_key, "on");
+
+    let err = admin
+        .get_snapshot_at_version(pb::GetSnapshotAtVersionRequest { version: 999 })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    server_handle.abort();
+}
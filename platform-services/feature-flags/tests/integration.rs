@@ -4,12 +4,15 @@ use insta::assert_yaml_snapshot;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::PgPool;
+use std::sync::Arc;
 
 use common::{connect_admin, connect_eval, eval_request, spawn_server};
 use feature_flags::convert;
+use feature_flags::engine::{Engine, EvalContext};
 use feature_flags::pb;
 use feature_flags::pb::admin_client::AdminClient;
 use feature_flags::pb::evaluation_client::EvaluationClient;
+use feature_flags::store::Store;
 
 #[derive(Deserialize)]
 struct Fixture {
@@ -79,6 +82,14 @@ struct SegmentDef {
     name: String,
     #[serde(default)]
     constraints: Vec<ConstraintDef>,
+    #[serde(default)]
+    constraint_groups: Vec<Vec<ConstraintDef>>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    targeting_keys: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -125,6 +136,14 @@ fn operator(s: &str) -> pb::ConstraintOperator {
         "exists" => Op::Exists,
         "regex" => Op::Regex,
         "flag_matches" => Op::FlagMatches,
+        "semver_eq" => Op::SemverEq,
+        "semver_gt" => Op::SemverGt,
+        "semver_gte" => Op::SemverGte,
+        "semver_lt" => Op::SemverLt,
+        "semver_lte" => Op::SemverLte,
+        "before" => Op::Before,
+        "after" => Op::After,
+        "percent_of" => Op::PercentOf,
         other => panic!("unknown operator: {other}"),
     }
 }
@@ -215,6 +234,21 @@ fixture_test!(
 fixture_test!(resolve_object_default, "resolve", "object-default");
 fixture_test!(resolve_prerequisite_met, "resolve", "prerequisite-met");
 fixture_test!(resolve_prerequisite_unmet, "resolve", "prerequisite-unmet");
+fixture_test!(
+    resolve_semver_and_date_match,
+    "resolve",
+    "semver-and-date-match"
+);
+fixture_test!(
+    resolve_nested_segment_match,
+    "resolve",
+    "nested-segment-match"
+);
+fixture_test!(
+    resolve_nested_segment_excluded,
+    "resolve",
+    "nested-segment-excluded"
+);
 fixture_test!(resolve_all_mixed, "resolve_all", "mixed");
 
 async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
@@ -222,12 +256,25 @@ async fn run_fixture(pool: PgPool, dir: &str, file: &str) {
     let content = std::fs::read_to_string(format!("tests/fixtures/{dir}/{file}.yaml")).unwrap();
     let fixture: Fixture = serde_yaml::from_str(&content).unwrap();
 
-    let (endpoint, server_handle) = spawn_server(pool).await;
+    let (endpoint, server_handle) = spawn_server(pool.clone()).await;
     let mut admin_client = connect_admin(&endpoint).await;
     let mut eval_client = connect_eval(&endpoint).await;
 
     seed(&mut admin_client, &fixture).await;
 
+    // Explain mode walks the rules separately from evaluate; it must land on the
+    // same result for every fixture.
+    if let Some(resolve) = &fixture.resolve {
+        let snapshot = Store::new(pool).load_snapshot().await.unwrap();
+        let engine = Engine::new(Arc::new(snapshot));
+        let ctx: EvalContext = eval_context(&resolve.context).into();
+        assert_eq!(
+            engine.explain(&resolve.flag_key, &ctx).result,
+            engine.evaluate(&resolve.flag_key, &ctx),
+            "explain and evaluate disagree on {dir}/{file}"
+        );
+    }
+
     let snapshot = if let Some(resolve) = &fixture.resolve {
         let result = run_resolve(&mut eval_client, resolve).await;
         SnapshotOutput::Resolve(result)
@@ -263,6 +310,16 @@ async fn seed(client: &mut AdminClient<tonic::transport::Channel>, fixture: &Fix
             key: seg.key.clone(),
             name: seg.name.clone(),
             constraints: seg.constraints.iter().map(constraint).collect(),
+            constraint_groups: seg
+                .constraint_groups
+                .iter()
+                .map(|group| pb::ConstraintGroup {
+                    constraints: group.iter().map(constraint).collect(),
+                })
+                .collect(),
+            included_segments: seg.include.clone(),
+            excluded_segments: seg.exclude.clone(),
+            targeting_keys: seg.targeting_keys.clone(),
         };
         client
             .create_segment(pb::CreateSegmentRequest {
@@ -0,0 +1,205 @@
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use feature_flags::model::{Constraint, ConstraintGroup, Operator, Rule, ValueType, Variant};
+use feature_flags::ofrep;
+use feature_flags::snapshot::{Environments, SnapshotManager};
+use feature_flags::store::{DEFAULT_ENVIRONMENT, Store};
+use feature_flags::telemetry::Telemetry;
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+async fn router(pool: PgPool) -> (Store, Arc<SnapshotManager>, Router) {
+    let store = Store::new(pool);
+    let variants = [
+        Variant {
+            key: "on".into(),
+            value: Value::Bool(true),
+        },
+        Variant {
+            key: "off".into(),
+            value: Value::Bool(false),
+        },
+    ];
+    store
+        .create_flag(
+            "alice",
+            "checkout",
+            ValueType::Boolean,
+            true,
+            "off",
+            &variants,
+        )
+        .await
+        .unwrap();
+    let pro = Rule {
+        rank: 0,
+        segment_key: None,
+        variant_key: Some("on".into()),
+        distributions: vec![],
+        constraint_groups: vec![ConstraintGroup {
+            constraints: vec![Constraint {
+                attribute: "plan".into(),
+                operator: Operator::Eq,
+                values: vec![json!("pro")],
+            }],
+        }],
+        bucket_salt: String::new(),
+    };
+    store
+        .set_flag_rules("alice", "checkout", &[pro])
+        .await
+        .unwrap();
+    let envs = Environments::bootstrap(store.clone(), None).await.unwrap();
+    let manager = envs.get(DEFAULT_ENVIRONMENT).await.unwrap();
+    let router = ofrep::router(envs, Telemetry::new(store.clone()));
+    (store, manager, router)
+}
+
+fn post(uri: &str) -> axum::http::request::Builder {
+    Request::post(uri)
+        .header("client-id", "ofrep-test")
+        .header(header::CONTENT_TYPE, "application/json")
+}
+
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Option<String>, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .map(|v| v.to_str().unwrap().to_owned());
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, etag, body)
+}
+
+fn json_body(body: Value) -> Body {
+    Body::from(body.to_string())
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn evaluates_single_flag_with_context(pool: PgPool) {
+    let (_, _, router) = router(pool).await;
+    let context = json!({ "context": { "targetingKey": "user-1", "plan": "pro" } });
+
+    let (status, _, body) = send(
+        &router,
+        post("/ofrep/v1/evaluate/flags/checkout")
+            .body(json_body(context))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "key": "checkout",
+            "value": true,
+            "reason": "TARGETING_MATCH",
+            "variant": "on",
+            "metadata": {},
+        })
+    );
+
+    let (status, _, body) = send(
+        &router,
+        post("/ofrep/v1/evaluate/flags/missing")
+            .body(json_body(json!({ "context": {} })))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["errorCode"], "FLAG_NOT_FOUND");
+
+    let (status, _, body) = send(
+        &router,
+        post("/ofrep/v1/evaluate/flags/checkout")
+            .body(json_body(json!({ "context": "pro" })))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["errorCode"], "INVALID_CONTEXT");
+
+    let anonymous = Request::post("/ofrep/v1/evaluate/flags/checkout")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = send(&router, anonymous).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_evaluation_honours_etag(pool: PgPool) {
+    let (store, manager, router) = router(pool).await;
+    let context = json!({ "context": { "targetingKey": "user-1", "plan": "free" } });
+    let bulk = |etag: Option<&str>, context: &Value| {
+        let mut request = post("/ofrep/v1/evaluate/flags");
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        request.body(json_body(context.clone())).unwrap()
+    };
+
+    let (status, etag, body) = send(&router, bulk(None, &context)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["flags"][0]["key"], "checkout");
+    assert_eq!(body["flags"][0]["value"], false);
+    let etag = etag.expect("bulk response carries an ETag");
+
+    let (status, same, _) = send(&router, bulk(Some(&etag), &context)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(same.as_deref(), Some(etag.as_str()));
+
+    // A different context evaluates afresh even with the old tag.
+    let pro = json!({ "context": { "targetingKey": "user-1", "plan": "pro" } });
+    let (status, _, body) = send(&router, bulk(Some(&etag), &pro)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["flags"][0]["value"], true);
+
+    // As does a config change, once the snapshot has picked it up.
+    store
+        .update_flag("alice", "checkout", false, "off")
+        .await
+        .unwrap();
+    manager.reload().await.unwrap();
+    let (status, _, _) = send(&router, bulk(Some(&etag), &context)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn bulk_evaluation_of_time_dependent_flags_carries_no_etag(pool: PgPool) {
+    let (store, manager, router) = router(pool).await;
+    let launch = Rule {
+        rank: 0,
+        segment_key: None,
+        variant_key: Some("on".into()),
+        distributions: vec![],
+        constraint_groups: vec![ConstraintGroup {
+            constraints: vec![Constraint {
+                attribute: "now".into(),
+                operator: Operator::After,
+                values: vec![json!("2030-01-01T00:00:00Z")],
+            }],
+        }],
+        bucket_salt: String::new(),
+    };
+    store
+        .set_flag_rules("alice", "checkout", &[launch])
+        .await
+        .unwrap();
+    manager.reload().await.unwrap();
+
+    // The result changes at the launch time with no new version, so it's not cacheable.
+    let request = post("/ofrep/v1/evaluate/flags")
+        .body(json_body(json!({ "context": {} })))
+        .unwrap();
+    let (status, etag, body) = send(&router, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(etag, None);
+    assert_eq!(body["flags"][0]["key"], "checkout");
+}
@@ -0,0 +1,210 @@
+use chrono::{Duration, Utc};
+use feature_flags::model::{Distribution, Rule, ValueType, Variant};
+use feature_flags::store::{RolloutStatus, RolloutStep, Store, linear_steps};
+use serde_json::Value;
+use sqlx::PgPool;
+
+async fn store_with_rule(pool: PgPool) -> Store {
+    let store = Store::new(pool);
+    let variants = [
+        Variant {
+            key: "on".into(),
+            value: Value::Bool(true),
+        },
+        Variant {
+            key: "off".into(),
+            value: Value::Bool(false),
+        },
+    ];
+    store
+        .create_flag(
+            "alice",
+            "launch",
+            ValueType::Boolean,
+            true,
+            "off",
+            &variants,
+        )
+        .await
+        .unwrap();
+    store
+        .set_flag_rules("alice", "launch", &[rule("off")])
+        .await
+        .unwrap();
+    store
+}
+
+fn rule(variant: &str) -> Rule {
+    Rule {
+        rank: 0,
+        segment_key: None,
+        variant_key: Some(variant.into()),
+        distributions: vec![],
+        constraint_groups: vec![],
+        bucket_salt: String::new(),
+    }
+}
+
+/// The live rule's split as `(variant, weight)`, sorted.
+async fn split(store: &Store) -> Vec<(String, u32)> {
+    let snapshot = store.load_snapshot().await.unwrap();
+    let mut split: Vec<_> = snapshot.flags["launch"].rules[0]
+        .distributions
+        .iter()
+        .map(|d| (d.variant_key.clone(), d.weight))
+        .collect();
+    split.sort();
+    split
+}
+
+fn pairs(items: &[(&str, u32)]) -> Vec<(String, u32)> {
+    items.iter().map(|(k, w)| (k.to_string(), *w)).collect()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn linear_rollout_steps_to_completion_with_audit(pool: PgPool) {
+    let store = store_with_rule(pool).await;
+    let steps = linear_steps("on", "off", 0, 100, 50, 3600).unwrap();
+    let rollout = store
+        .start_rollout("bob", "launch", 0, &steps)
+        .await
+        .unwrap();
+    assert_eq!(rollout.status, RolloutStatus::Active);
+    assert_eq!(split(&store).await, pairs(&[("off", 100)]));
+
+    // Not due yet.
+    assert!(store.advance_rollouts(Utc::now()).await.unwrap().is_empty());
+
+    let first_due = rollout.next_step_at().unwrap();
+    let advanced = store.advance_rollouts(first_due).await.unwrap();
+    assert_eq!(advanced[0].current_step, 1);
+    assert_eq!(advanced[0].status, RolloutStatus::Active);
+    assert_eq!(split(&store).await, pairs(&[("off", 50), ("on", 50)]));
+
+    let audit = store.list_changes("flag", "launch", 1).await.unwrap();
+    assert_eq!(audit[0].action, "rollout_step");
+    assert_eq!(audit[0].actor, "bob");
+    assert_eq!(audit[0].detail["step"], 1);
+
+    // Each step is held in full from when it was written.
+    let second_due = advanced[0].next_step_at().unwrap();
+    assert_eq!(second_due, first_due + Duration::hours(1));
+    let done = store.advance_rollouts(second_due).await.unwrap();
+    assert_eq!(done[0].status, RolloutStatus::Completed);
+    assert!(done[0].next_step_at().is_none());
+    assert_eq!(split(&store).await, pairs(&[("on", 100)]));
+
+    assert!(
+        store
+            .list_rollouts("launch", false)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(store.list_rollouts("launch", true).await.unwrap().len(), 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn pause_holds_and_abort_restores_original_split(pool: PgPool) {
+    let store = store_with_rule(pool).await;
+    let steps = linear_steps("on", "off", 10, 30, 10, 60).unwrap();
+    let rollout = store
+        .start_rollout("bob", "launch", 0, &steps)
+        .await
+        .unwrap();
+    assert!(
+        store
+            .start_rollout("carol", "launch", 0, &steps)
+            .await
+            .is_err(),
+        "a rule takes one live rollout at a time"
+    );
+
+    let paused = store.pause_rollout("carol", rollout.id).await.unwrap();
+    assert_eq!(paused.status, RolloutStatus::Paused);
+    assert!(paused.next_step_at().is_none());
+    let later = Utc::now() + Duration::hours(1);
+    assert!(store.advance_rollouts(later).await.unwrap().is_empty());
+    assert!(store.pause_rollout("carol", rollout.id).await.is_err());
+
+    let resumed = store.resume_rollout("carol", rollout.id).await.unwrap();
+    assert_eq!(resumed.status, RolloutStatus::Active);
+    assert!(resumed.next_step_at().unwrap() >= rollout.next_step_at().unwrap());
+    store.advance_rollouts(later).await.unwrap();
+    assert_eq!(split(&store).await, pairs(&[("off", 80), ("on", 20)]));
+
+    let aborted = store.abort_rollout("dave", rollout.id).await.unwrap();
+    assert_eq!(aborted.status, RolloutStatus::Aborted);
+    // The rule had no split before, so it's back to serving its fixed variant.
+    assert!(split(&store).await.is_empty());
+    let audit = store.list_changes("flag", "launch", 1).await.unwrap();
+    assert_eq!(audit[0].action, "abort_rollout");
+    assert_eq!(audit[0].actor, "dave");
+    assert!(store.abort_rollout("dave", rollout.id).await.is_err());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn manual_rule_edit_aborts_rollout(pool: PgPool) {
+    let store = store_with_rule(pool).await;
+    let steps = [
+        RolloutStep {
+            distributions: vec![
+                Distribution {
+                    variant_key: "on".into(),
+                    weight: 5,
+                },
+                Distribution {
+                    variant_key: "off".into(),
+                    weight: 95,
+                },
+            ],
+            hold_secs: 60,
+        },
+        RolloutStep {
+            distributions: vec![Distribution {
+                variant_key: "on".into(),
+                weight: 100,
+            }],
+            hold_secs: 0,
+        },
+    ];
+    let bad = [RolloutStep {
+        distributions: vec![Distribution {
+            variant_key: "on".into(),
+            weight: 50,
+        }],
+        hold_secs: 0,
+    }];
+    assert!(store.start_rollout("bob", "launch", 0, &bad).await.is_err());
+    assert!(
+        store
+            .start_rollout("bob", "launch", 3, &steps)
+            .await
+            .is_err()
+    );
+
+    let rollout = store
+        .start_rollout("bob", "launch", 0, &steps)
+        .await
+        .unwrap();
+    store
+        .set_flag_rules("carol", "launch", &[rule("on")])
+        .await
+        .unwrap();
+
+    let advanced = store
+        .advance_rollouts(rollout.next_step_at().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(advanced[0].status, RolloutStatus::Aborted);
+    assert!(
+        advanced[0].error.contains("edited"),
+        "{}",
+        advanced[0].error
+    );
+    // Carol's rules stand.
+    let snapshot = store.load_snapshot().await.unwrap();
+    let live = &snapshot.flags["launch"].rules[0];
+    assert_eq!(live.variant_key.as_deref(), Some("on"));
+    assert!(live.distributions.is_empty());
+}
@@ -0,0 +1,231 @@
+use chrono::{Duration, Utc};
+use feature_flags::model::{Rule, ValueType, Variant};
+use feature_flags::store::{ScheduleStatus, ScheduledAction, Store};
+use serde_json::Value;
+use sqlx::PgPool;
+
+async fn store_with_flag(pool: PgPool) -> Store {
+    let store = Store::new(pool);
+    let variants = [
+        Variant {
+            key: "on".into(),
+            value: Value::Bool(true),
+        },
+        Variant {
+            key: "off".into(),
+            value: Value::Bool(false),
+        },
+    ];
+    store
+        .create_flag(
+            "alice",
+            "launch",
+            ValueType::Boolean,
+            false,
+            "off",
+            &variants,
+        )
+        .await
+        .unwrap();
+    store
+}
+
+fn enable() -> ScheduledAction {
+    ScheduledAction::UpdateFlag {
+        enabled: Some(true),
+        default_variant_key: None,
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn due_change_applies_with_scheduling_actor(pool: PgPool) {
+    let store = store_with_flag(pool).await;
+    let apply_at = Utc::now() + Duration::hours(1);
+    let scheduled = store
+        .schedule_change("bob", "launch", &enable(), apply_at)
+        .await
+        .unwrap();
+    assert_eq!(scheduled.status, ScheduleStatus::Pending);
+
+    // Nothing is due yet.
+    assert!(
+        store
+            .apply_due_changes(Utc::now())
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let before = store.load_snapshot().await.unwrap();
+    assert!(!before.flags["launch"].enabled);
+
+    let resolved = store.apply_due_changes(apply_at).await.unwrap();
+    assert_eq!(resolved.len(), 1);
+    assert_eq!(resolved[0].status, ScheduleStatus::Applied);
+
+    let after = store.load_snapshot().await.unwrap();
+    assert!(after.flags["launch"].enabled);
+    // The untouched default survives the partial update.
+    assert_eq!(after.flags["launch"].default_variant_key, "off");
+    assert!(after.version > before.version);
+
+    let audit = store.list_changes("flag", "launch", 1).await.unwrap();
+    assert_eq!(audit[0].action, "update_flag");
+    assert_eq!(audit[0].actor, "bob");
+    assert_eq!(
+        audit[0].detail["scheduled_change_id"],
+        scheduled.id.to_string()
+    );
+
+    // Applied changes leave the pending list but stay in the full history.
+    assert!(
+        store
+            .list_scheduled_changes("launch", false)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let history = store.list_scheduled_changes("launch", true).await.unwrap();
+    assert_eq!(history[0].status, ScheduleStatus::Applied);
+    assert!(history[0].resolved_at.is_some());
+    // A second pass finds nothing left to do.
+    assert!(store.apply_due_changes(apply_at).await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn scheduled_rules_replace_and_stale_changes_fail(pool: PgPool) {
+    let store = store_with_flag(pool).await;
+    let apply_at = Utc::now() + Duration::minutes(5);
+    let rules = vec![Rule {
+        rank: 0,
+        segment_key: None,
+        variant_key: Some("off".into()),
+        distributions: vec![],
+        constraint_groups: vec![],
+        bucket_salt: String::new(),
+    }];
+    store
+        .schedule_change(
+            "bob",
+            "launch",
+            &ScheduledAction::SetFlagRules { rules },
+            apply_at,
+        )
+        .await
+        .unwrap();
+    let stale = store
+        .schedule_change(
+            "bob",
+            "launch",
+            &ScheduledAction::UpdateFlag {
+                enabled: None,
+                default_variant_key: Some("on".into()),
+            },
+            apply_at + Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+    // The variant the second change needs is removed before it is due.
+    store.delete_variant("carol", "launch", "on").await.unwrap();
+
+    let resolved = store
+        .apply_due_changes(apply_at + Duration::minutes(2))
+        .await
+        .unwrap();
+    assert_eq!(resolved.len(), 2);
+    assert_eq!(resolved[0].status, ScheduleStatus::Applied);
+    assert_eq!(resolved[1].id, stale.id);
+    assert_eq!(resolved[1].status, ScheduleStatus::Failed);
+    assert!(resolved[1].error.contains("`on`"), "{}", resolved[1].error);
+
+    let flag = &store.load_snapshot().await.unwrap().flags["launch"];
+    assert_eq!(flag.rules.len(), 1);
+    assert_eq!(flag.default_variant_key, "off");
+    let audit = store.list_changes("flag", "launch", 1).await.unwrap();
+    assert_eq!(audit[0].action, "set_flag_rules");
+
+    let history = store.list_scheduled_changes("", true).await.unwrap();
+    assert_eq!(history[1].status, ScheduleStatus::Failed);
+    assert_eq!(history[1].error, resolved[1].error);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn unreadable_change_fails_without_stalling_the_rest(pool: PgPool) {
+    let store = store_with_flag(pool.clone()).await;
+    let apply_at = Utc::now() + Duration::minutes(5);
+    // A payload no current action deserializes from, e.g. written by a newer build.
+    let bad: uuid::Uuid = sqlx::query_scalar(
+        "INSERT INTO scheduled_changes (flag_key, kind, payload, apply_at, actor) \
+         VALUES ('launch', 'rename_flag', '{\"kind\": \"rename_flag\"}', $1, 'bob') \
+         RETURNING id",
+    )
+    .bind(apply_at)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let good = store
+        .schedule_change("bob", "launch", &enable(), apply_at + Duration::minutes(1))
+        .await
+        .unwrap();
+
+    let resolved = store
+        .apply_due_changes(apply_at + Duration::minutes(2))
+        .await
+        .unwrap();
+    assert_eq!(resolved.len(), 1);
+    assert_eq!(resolved[0].id, good.id);
+    assert_eq!(resolved[0].status, ScheduleStatus::Applied);
+    assert!(store.load_snapshot().await.unwrap().flags["launch"].enabled);
+
+    let (status, error): (String, String) =
+        sqlx::query_as("SELECT status, error FROM scheduled_changes WHERE id = $1")
+            .bind(bad)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(status, "failed");
+    assert!(error.contains("rename_flag"), "{error}");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn schedule_rejects_invalid_and_cancel_is_final(pool: PgPool) {
+    let store = store_with_flag(pool).await;
+    let future = Utc::now() + Duration::hours(1);
+
+    let past = store
+        .schedule_change(
+            "bob",
+            "launch",
+            &enable(),
+            Utc::now() - Duration::minutes(1),
+        )
+        .await;
+    assert!(past.is_err());
+    let unknown_variant = ScheduledAction::UpdateFlag {
+        enabled: None,
+        default_variant_key: Some("maybe".into()),
+    };
+    assert!(
+        store
+            .schedule_change("bob", "launch", &unknown_variant, future)
+            .await
+            .is_err()
+    );
+    assert!(
+        store
+            .schedule_change("bob", "missing", &enable(), future)
+            .await
+            .is_err()
+    );
+
+    let scheduled = store
+        .schedule_change("bob", "launch", &enable(), future)
+        .await
+        .unwrap();
+    let cancelled = store.cancel_scheduled_change(scheduled.id).await.unwrap();
+    assert_eq!(cancelled.status, ScheduleStatus::Cancelled);
+    assert!(store.cancel_scheduled_change(scheduled.id).await.is_err());
+
+    // A cancelled change is never applied.
+    assert!(store.apply_due_changes(future).await.unwrap().is_empty());
+    assert!(!store.load_snapshot().await.unwrap().flags["launch"].enabled);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{connect_admin, spawn_server};
+use feature_flags::model::{Constraint, ConstraintGroup, Operator, Segment};
+use feature_flags::pb;
+use feature_flags::store::Store;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = "./migrations")]
+async fn nested_segments_round_trip_through_apply(pool: PgPool) {
+    let store = Store::new(pool);
+    let anz = Segment {
+        key: "anz".into(),
+        name: "ANZ".into(),
+        constraint_groups: vec![
+            ConstraintGroup {
+                constraints: vec![Constraint {
+                    attribute: "country".into(),
+                    operator: Operator::In,
+                    values: vec![json!("AU"), json!("NZ")],
+                }],
+            },
+            ConstraintGroup {
+                constraints: vec![
+                    Constraint {
+                        attribute: "plan".into(),
+                        operator: Operator::Eq,
+                        values: vec![json!("pro")],
+                    },
+                    Constraint {
+                        attribute: "staff".into(),
+                        operator: Operator::Exists,
+                        values: vec![],
+                    },
+                ],
+            },
+        ],
+        ..Default::default()
+    };
+    let beta = Segment {
+        key: "beta".into(),
+        name: "Beta".into(),
+        included_segments: vec!["anz".into()],
+        excluded_segments: vec!["churned".into()],
+        targeting_keys: (0..20_000).map(|i| format!("user-{i}")).collect(),
+        ..Default::default()
+    };
+    let segments = [anz, beta];
+
+    store
+        .apply_config("alice", &[], &segments, false, 0)
+        .await
+        .unwrap();
+    let snap = store.load_snapshot().await.unwrap();
+    assert_eq!(snap.segments["anz"], segments[0]);
+    assert_eq!(snap.segments["beta"], segments[1]);
+
+    // Groups, references and keys all read back identically, so re-applying is a no-op.
+    let again = store
+        .apply_config("alice", &[], &segments, true, 0)
+        .await
+        .unwrap();
+    assert!(again.changes.is_empty());
+
+    let self_ref = Segment {
+        key: "loop".into(),
+        excluded_segments: vec!["loop".into()],
+        ..Default::default()
+    };
+    let err = store.upsert_segment("alice", &self_ref).await.unwrap_err();
+    assert!(err.to_string().contains("cannot reference itself"), "{err}");
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn update_segment_keys_edits_the_list_in_place(pool: PgPool) {
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut admin = connect_admin(&endpoint).await;
+
+    admin
+        .create_segment(pb::CreateSegmentRequest {
+            segment: Some(pb::Segment {
+                key: "testers".into(),
+                name: "Testers".into(),
+                targeting_keys: vec!["a".into(), "b".into()],
+                ..Default::default()
+            }),
+        })
+        .await
+        .unwrap();
+
+    let resp = admin
+        .update_segment_keys(pb::UpdateSegmentKeysRequest {
+            segment_key: "testers".into(),
+            add: vec!["b".into(), "c".into(), "d".into()],
+            remove: vec!["a".into(), "d".into()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(resp.key_count, 2);
+
+    let segment = admin
+        .get_segment(pb::GetSegmentRequest {
+            key: "testers".into(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(segment.targeting_keys, ["b", "c"]);
+    assert_eq!(segment.name, "Testers");
+
+    let err = admin
+        .update_segment_keys(pb::UpdateSegmentKeysRequest {
+            segment_key: "missing".into(),
+            add: vec!["a".into()],
+            remove: vec![],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    server_handle.abort();
+}
@@ -0,0 +1,311 @@
+mod common;
+
+use common::{bool_flag, connect_admin, connect_eval, eval_request, segment, spawn_server};
+use feature_flags::convert;
+use feature_flags::model::{Constraint, ConstraintGroup, Flag, Operator, Rule, Snapshot};
+use feature_flags::pb;
+use feature_flags::store::Store;
+use serde_json::json;
+use sqlx::PgPool;
+
+fn tagged(key: &str, tags: &[&str]) -> Flag {
+    Flag {
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        ..bool_flag(key, true)
+    }
+}
+
+fn sorted_keys<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<&'a str> {
+    let mut keys: Vec<_> = keys.map(String::as_str).collect();
+    keys.sort();
+    keys
+}
+
+async fn open(
+    client: &mut pb::evaluation_client::EvaluationClient<tonic::transport::Channel>,
+    known_version: i64,
+    known_scope: &str,
+    tags: &[&str],
+) -> tonic::Streaming<pb::SnapshotDelta> {
+    client
+        .stream_snapshot_deltas(eval_request(pb::StreamSnapshotDeltasRequest {
+            known_version,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            known_scope: known_scope.into(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn deltas_carry_only_what_changed(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", true), bool_flag("b", true)],
+            &[],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut admin = connect_admin(&endpoint).await;
+    let mut client = connect_eval(&endpoint).await;
+
+    let mut stream = open(&mut client, 0, "", &[]).await;
+    let head = stream.message().await.unwrap().unwrap();
+    assert!(head.full);
+    assert_eq!(head.flags.len(), 2);
+    let mut local = convert::apply_delta(&Snapshot::default(), &head).unwrap();
+
+    admin
+        .create_flag(pb::CreateFlagRequest {
+            key: "c".into(),
+            value_type: pb::ValueType::Boolean as i32,
+            enabled: true,
+            default_variant_key: "on".into(),
+            variants: vec![pb::Variant {
+                key: "on".into(),
+                value: Some(convert::json_to_prost_value(&json!(true))),
+            }],
+        })
+        .await
+        .unwrap();
+    let delta = stream.message().await.unwrap().unwrap();
+    assert!(!delta.full);
+    assert_eq!(delta.base_version, local.version);
+    let keys: Vec<_> = delta.flags.iter().map(|f| f.key.as_str()).collect();
+    assert_eq!(keys, ["c"]);
+    assert!(delta.removed_flag_keys.is_empty());
+    local = convert::apply_delta(&local, &delta).unwrap();
+
+    admin
+        .delete_flag(pb::DeleteFlagRequest { key: "b".into() })
+        .await
+        .unwrap();
+    let delta = stream.message().await.unwrap().unwrap();
+    assert_eq!(delta.base_version, local.version);
+    assert!(delta.flags.is_empty());
+    assert_eq!(delta.removed_flag_keys, ["b"]);
+    local = convert::apply_delta(&local, &delta).unwrap();
+
+    let live = store.load_snapshot().await.unwrap();
+    assert_eq!(local.version, live.version);
+    assert_eq!(local.flags, live.flags);
+
+    server_handle.abort();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn reconnecting_catches_up_from_the_known_version(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    store
+        .apply_config("alice", &[bool_flag("a", true)], &[], false, 0)
+        .await
+        .unwrap();
+    let v1 = store.config_version().await.unwrap();
+    store
+        .apply_config(
+            "alice",
+            &[bool_flag("a", false), bool_flag("b", true)],
+            &[],
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+    let live = store.load_snapshot().await.unwrap();
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut client = connect_eval(&endpoint).await;
+    let head = open(&mut client, 0, "", &[])
+        .await
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    let scope = head.scope.as_str();
+
+    // A version history still has: only the changes since.
+    let at_v1 = store.snapshot_at_version(v1).await.unwrap().snapshot;
+    let delta = open(&mut client, v1, scope, &[])
+        .await
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!delta.full);
+    assert_eq!((delta.base_version, delta.version), (v1, live.version));
+    assert_eq!(delta.flags.len(), 2);
+    let caught_up = convert::apply_delta(&at_v1, &delta).unwrap();
+    assert_eq!(caught_up.flags, live.flags);
+
+    // Already current: nothing to send.
+    let delta = open(&mut client, live.version, scope, &[])
+        .await
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!delta.full);
+    assert_eq!(
+        (delta.base_version, delta.version),
+        (live.version, live.version)
+    );
+    assert!(delta.flags.is_empty());
+
+    // A version the server can't place: the whole snapshot.
+    let delta = open(&mut client, live.version + 100, scope, &[])
+        .await
+        .message()
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(delta.full);
+    assert_eq!(delta.version, live.version);
+    assert_eq!(delta.flags.len(), 2);
+
+    server_handle.abort();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn tagged_snapshots_carry_only_their_flags_and_dependencies(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    // `checkout` depends on `master` and the `beta` segment; neither is tagged.
+    let checkout = Flag {
+        rules: vec![Rule {
+            rank: 0,
+            segment_key: Some("beta".into()),
+            variant_key: Some("off".into()),
+            distributions: vec![],
+            constraint_groups: vec![ConstraintGroup {
+                constraints: vec![Constraint {
+                    attribute: "master".into(),
+                    operator: Operator::FlagMatches,
+                    values: vec![json!("on")],
+                }],
+            }],
+            bucket_salt: String::new(),
+        }],
+        ..tagged("checkout", &["checkout"])
+    };
+    let segments = [segment("beta"), segment("other")];
+    let mut flags = vec![
+        checkout,
+        bool_flag("master", true),
+        tagged("search", &["search"]),
+    ];
+    store
+        .apply_config("alice", &flags, &segments, false, 0)
+        .await
+        .unwrap();
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut admin = connect_admin(&endpoint).await;
+    let mut client = connect_eval(&endpoint).await;
+
+    let snapshot = client
+        .get_snapshot(eval_request(pb::GetSnapshotRequest {
+            tags: vec!["checkout".into()],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        sorted_keys(snapshot.flags.iter().map(|f| &f.key)),
+        ["checkout", "master"]
+    );
+    assert_eq!(snapshot.segments.len(), 1);
+    assert_eq!(snapshot.segments[0].key, "beta");
+
+    let mut stream = open(&mut client, 0, "", &["checkout"]).await;
+    let head = stream.message().await.unwrap().unwrap();
+    assert!(head.full);
+    let mut local = convert::apply_delta(&Snapshot::default(), &head).unwrap();
+    assert_eq!(sorted_keys(local.flags.keys()), ["checkout", "master"]);
+
+    // Tagging another flag brings it into scope.
+    flags[2] = tagged("search", &["search", "checkout"]);
+    admin
+        .apply_config(pb::ApplyConfigRequest {
+            flags: flags.iter().map(pb::Flag::from).collect(),
+            segments: segments.iter().map(pb::Segment::from).collect(),
+            dry_run: false,
+            expected_version: 0,
+        })
+        .await
+        .unwrap();
+    let delta = stream.message().await.unwrap().unwrap();
+    assert!(!delta.full);
+    assert_eq!(delta.base_version, local.version);
+    let keys: Vec<_> = delta.flags.iter().map(|f| f.key.as_str()).collect();
+    assert_eq!(keys, ["search"]);
+    local = convert::apply_delta(&local, &delta).unwrap();
+
+    let live = store.load_snapshot().await.unwrap();
+    let expected = live.scoped(&["checkout".into()]);
+    assert_eq!(local.version, live.version);
+    assert_eq!(local.flags, expected.flags);
+    assert_eq!(local.segments, expected.segments);
+
+    server_handle.abort();
+}
+
+#[sqlx::test(migrations = "./migrations")]
+#[serial_test::serial]
+async fn resuming_requires_the_same_scope(pool: PgPool) {
+    let store = Store::new(pool.clone());
+    let mut flags = vec![tagged("checkout", &["checkout"]), bool_flag("other", true)];
+    store
+        .apply_config("alice", &flags, &[], false, 0)
+        .await
+        .unwrap();
+    let v1 = store.config_version().await.unwrap();
+    let (endpoint, server_handle) = spawn_server(pool).await;
+    let mut client = connect_eval(&endpoint).await;
+    let first = |mut stream: tonic::Streaming<pb::SnapshotDelta>| async move {
+        stream.message().await.unwrap().unwrap()
+    };
+
+    let scoped = first(open(&mut client, 0, "", &["checkout"]).await).await;
+    assert_eq!(scoped.version, v1);
+    assert_eq!(scoped.flags.len(), 1);
+
+    // The client's scope was dropped (or it reloaded a scoped cache file): its
+    // snapshot lacks `other`, so it can't resume the unscoped stream.
+    let unscoped = first(open(&mut client, v1, &scoped.scope, &[]).await).await;
+    assert!(unscoped.full);
+    assert_eq!(unscoped.flags.len(), 2);
+    assert_ne!(unscoped.scope, scoped.scope);
+    // Nor can a snapshot of unknown scope.
+    assert!(first(open(&mut client, v1, "", &[]).await).await.full);
+
+    // Under the same scope, it resumes from the scoped snapshot it holds.
+    flags.push(tagged("search", &["search", "checkout"]));
+    let mut stream = open(&mut client, v1, &scoped.scope, &["checkout"]).await;
+    stream.message().await.unwrap().unwrap();
+    connect_admin(&endpoint)
+        .await
+        .apply_config(pb::ApplyConfigRequest {
+            flags: flags.iter().map(pb::Flag::from).collect(),
+            segments: vec![],
+            dry_run: false,
+            expected_version: 0,
+        })
+        .await
+        .unwrap();
+    // Wait for the server to take the change before resuming.
+    stream.message().await.unwrap().unwrap();
+    let resumed = first(open(&mut client, v1, &scoped.scope, &["checkout"]).await).await;
+    assert!(!resumed.full);
+    assert_eq!(resumed.base_version, v1);
+    assert_eq!(resumed.scope, scoped.scope);
+    let keys: Vec<_> = resumed.flags.iter().map(|f| f.key.as_str()).collect();
+    assert_eq!(keys, ["search"]);
+
+    server_handle.abort();
+}
@@ -0,0 +1,110 @@
+use chrono::{Duration, Utc};
+use feature_flags::model::{ValueType, Variant};
+use feature_flags::store::{DEFAULT_ENVIRONMENT, StaleReason, Store};
+use feature_flags::telemetry::Telemetry;
+use serde_json::Value;
+use sqlx::PgPool;
+
+async fn store_with_flags(pool: PgPool, keys: &[&str]) -> Store {
+    let store = Store::new(pool);
+    let variants = [
+        Variant {
+            key: "on".into(),
+            value: Value::Bool(true),
+        },
+        Variant {
+            key: "off".into(),
+            value: Value::Bool(false),
+        },
+    ];
+    for key in keys {
+        store
+            .create_flag("alice", key, ValueType::Boolean, true, "off", &variants)
+            .await
+            .unwrap();
+    }
+    store
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn server_and_reported_counts_are_summed(pool: PgPool) {
+    let store = store_with_flags(pool, &["checkout"]).await;
+    let telemetry = Telemetry::new(store.clone());
+
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "checkout", "on");
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "checkout", "on");
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "checkout", "off");
+    telemetry.record_reported(
+        DEFAULT_ENVIRONMENT,
+        "worker",
+        &[
+            ("checkout".into(), "on".into(), 40),
+            ("checkout".into(), "".into(), 7),
+        ],
+    );
+    telemetry.flush().await;
+    // Flushing again adds to the same day's rows rather than replacing them.
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "checkout", "on");
+    telemetry.flush().await;
+
+    let usage = store.flag_usage("checkout", 7, Utc::now()).await.unwrap();
+    let rows: Vec<_> = usage
+        .iter()
+        .map(|u| (u.variant_key.as_str(), u.client_id.as_str(), u.count))
+        .collect();
+    assert_eq!(
+        rows,
+        [("on", "worker", 40), ("on", "web", 3), ("off", "web", 1)]
+    );
+    assert!(
+        store
+            .flag_usage("other", 7, Utc::now())
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn stale_flags_are_unevaluated_or_single_variant(pool: PgPool) {
+    let store = store_with_flags(pool, &["dead", "settled", "live"]).await;
+    let telemetry = Telemetry::new(store.clone());
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "dead", "on");
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "settled", "on");
+    telemetry.record(DEFAULT_ENVIRONMENT, "web", "live", "on");
+    telemetry.flush().await;
+
+    // Flags younger than the window are never reported.
+    assert!(store.stale_flags(30, Utc::now()).await.unwrap().is_empty());
+
+    // Forty days on, "dead" hasn't been evaluated since and "settled" only ever
+    // served "on"; "live" served both.
+    let later = Utc::now() + Duration::days(40);
+    let mut counts = std::collections::HashMap::new();
+    for (flag, variant) in [("settled", "on"), ("live", "on"), ("live", "off")] {
+        counts.insert(
+            feature_flags::store::EvaluationKey {
+                environment: DEFAULT_ENVIRONMENT.into(),
+                flag_key: flag.into(),
+                variant_key: variant.into(),
+                client_id: "web".into(),
+            },
+            5,
+        );
+    }
+    store.record_evaluations(later, &counts).await.unwrap();
+
+    let stale = store.stale_flags(30, later).await.unwrap();
+    let found: Vec<_> = stale
+        .iter()
+        .map(|f| (f.flag_key.as_str(), f.reason.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("dead", StaleReason::NotEvaluated),
+            ("settled", StaleReason::SingleVariant("on".into())),
+        ]
+    );
+    assert!(stale[0].last_evaluated_at.is_some());
+}
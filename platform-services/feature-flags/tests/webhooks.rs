@@ -0,0 +1,190 @@
+mod common;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use chrono::{SubsecRound, Utc};
+use common::bool_flag;
+use feature_flags::store::{DeliveryStatus, MAX_DELIVERY_ATTEMPTS, Store, retry_delay};
+use feature_flags::webhooks::{self, DELIVERY_HEADER, SIGNATURE_HEADER};
+use serde_json::{Value as Json, json};
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A webhook receiver recording each request's headers and body, answering with
+/// `status`.
+#[derive(Clone, Default)]
+struct Receiver {
+    received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    status: Arc<AtomicU16>,
+}
+
+impl Receiver {
+    async fn spawn() -> (Self, String) {
+        let receiver = Receiver::default();
+        receiver.status.store(200, Ordering::SeqCst);
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(r): State<Receiver>, headers: HeaderMap, body: Bytes| async move {
+                        r.received.lock().unwrap().push((headers, body));
+                        StatusCode::from_u16(r.status.load(Ordering::SeqCst)).unwrap()
+                    },
+                ),
+            )
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (receiver, url)
+    }
+
+    fn take(&self) -> Vec<(HeaderMap, Bytes)> {
+        std::mem::take(&mut self.received.lock().unwrap())
+    }
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn config_changes_are_delivered_signed(pool: PgPool) {
+    let store = Store::new(pool);
+    store.record_snapshot().await.unwrap();
+    let (receiver, url) = Receiver::spawn().await;
+    let (webhook, secret) = store.create_webhook("alice", &url, "chat").await.unwrap();
+    let client = webhooks::client();
+
+    store
+        .apply_config("bob", &[bool_flag("checkout", true)], &[], false, 0)
+        .await
+        .unwrap();
+    let version = store.config_version().await.unwrap();
+    let delivered = webhooks::deliver_due(&store, &client, Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(delivered, 1);
+
+    let received = receiver.take();
+    assert_eq!(received.len(), 1);
+    let (headers, body) = &received[0];
+    assert_eq!(
+        headers[SIGNATURE_HEADER].to_str().unwrap(),
+        webhooks::sign(&secret, body)
+    );
+    let payload: Json = serde_json::from_slice(body).unwrap();
+    assert_eq!(payload["version"], version);
+    assert_eq!(payload["actor"], "bob");
+    assert_eq!(payload["flag_keys"], json!(["checkout"]));
+    assert_eq!(payload["changes"][0]["op"], "create");
+
+    let log = store
+        .list_webhook_deliveries(Some(webhook.id), 0)
+        .await
+        .unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].status, DeliveryStatus::Delivered);
+    assert_eq!(log[0].response_status, 200);
+    assert_eq!(
+        headers[DELIVERY_HEADER].to_str().unwrap(),
+        log[0].id.to_string()
+    );
+
+    // A write that leaves the config as it was sends nothing.
+    store
+        .apply_config("bob", &[bool_flag("checkout", true)], &[], false, 0)
+        .await
+        .unwrap();
+    store.delete_webhook("alice", webhook.id).await.unwrap();
+    store.delete_flag("bob", "checkout").await.unwrap();
+    webhooks::deliver_due(&store, &client, Utc::now())
+        .await
+        .unwrap();
+    assert!(receiver.take().is_empty());
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn failed_deliveries_back_off_then_give_up(pool: PgPool) {
+    let store = Store::new(pool);
+    store.record_snapshot().await.unwrap();
+    let (receiver, url) = Receiver::spawn().await;
+    receiver.status.store(503, Ordering::SeqCst);
+    store.create_webhook("alice", &url, "").await.unwrap();
+    let client = webhooks::client();
+    store
+        .apply_config("bob", &[bool_flag("checkout", false)], &[], false, 0)
+        .await
+        .unwrap();
+
+    // Postgres keeps microseconds.
+    let now = Utc::now().trunc_subsecs(6);
+    webhooks::deliver_due(&store, &client, now).await.unwrap();
+    let sent = Utc::now();
+    let delivery = &store.list_webhook_deliveries(None, 0).await.unwrap()[0];
+    assert_eq!(delivery.status, DeliveryStatus::Pending);
+    assert_eq!(delivery.attempts, 1);
+    assert_eq!(delivery.response_status, 503);
+    // The backoff runs from when the send finished, not from when it was claimed.
+    assert!(delivery.next_attempt_at >= now + retry_delay(1));
+    assert!(delivery.next_attempt_at <= sent + retry_delay(1));
+
+    // Not due again until the backoff passes.
+    webhooks::deliver_due(&store, &client, now).await.unwrap();
+    assert_eq!(receiver.take().len(), 1);
+
+    for _ in 2..=MAX_DELIVERY_ATTEMPTS {
+        let due = store.list_webhook_deliveries(None, 0).await.unwrap()[0].next_attempt_at;
+        webhooks::deliver_due(&store, &client, due).await.unwrap();
+    }
+    assert_eq!(receiver.take().len() as i32, MAX_DELIVERY_ATTEMPTS - 1);
+    let delivery = &store.list_webhook_deliveries(None, 0).await.unwrap()[0];
+    assert_eq!(delivery.status, DeliveryStatus::Failed);
+    assert_eq!(delivery.attempts, MAX_DELIVERY_ATTEMPTS);
+
+    // Redelivering starts over, and succeeds once the receiver recovers.
+    receiver.status.store(204, Ordering::SeqCst);
+    store.redeliver(delivery.id).await.unwrap();
+    let delivered = webhooks::deliver_due(&store, &client, Utc::now())
+        .await
+        .unwrap();
+    assert_eq!(delivered, 1);
+    let delivery = &store.list_webhook_deliveries(None, 0).await.unwrap()[0];
+    assert_eq!(delivery.status, DeliveryStatus::Delivered);
+    assert_eq!(delivery.attempts, 1);
+}
+
+#[sqlx::test(migrations = "./migrations")]
+async fn webhooks_are_scoped_to_their_environment(pool: PgPool) {
+    let store = Store::new(pool);
+    store.create_environment("alice", "prod").await.unwrap();
+    let prod = store.in_environment("prod");
+    prod.record_snapshot().await.unwrap();
+    store.record_snapshot().await.unwrap();
+    let (receiver, url) = Receiver::spawn().await;
+    prod.create_webhook("alice", &url, "").await.unwrap();
+
+    let err = store
+        .create_webhook("alice", "ftp://example.com", "")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("http"), "{err}");
+
+    store
+        .apply_config("bob", &[bool_flag("f", true)], &[], false, 0)
+        .await
+        .unwrap();
+    prod.apply_config("carol", &[bool_flag("f", false)], &[], false, 0)
+        .await
+        .unwrap();
+    webhooks::deliver_due(&store, &webhooks::client(), Utc::now())
+        .await
+        .unwrap();
+
+    let received = receiver.take();
+    assert_eq!(received.len(), 1);
+    let payload: Json = serde_json::from_slice(&received[0].1).unwrap();
+    assert_eq!(payload["environment"], "prod");
+    assert_eq!(payload["actor"], "carol");
+    assert!(store.list_webhooks().await.unwrap().is_empty());
+}
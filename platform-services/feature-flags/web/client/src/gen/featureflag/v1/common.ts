@@ -54,6 +54,26 @@ export enum ConstraintOperator {
    * the variant keys in values. Lets a rule depend on another flag's resolution.
    */
   CONSTRAINT_OPERATOR_FLAG_MATCHES = 14,
+  /**
+   * CONSTRAINT_OPERATOR_SEMVER_EQ - Semantic-version comparisons against values[0]. A leading "v" and missing
+   * minor/patch components are accepted; unparseable versions never match.
+   */
+  CONSTRAINT_OPERATOR_SEMVER_EQ = 15,
+  CONSTRAINT_OPERATOR_SEMVER_GT = 16,
+  CONSTRAINT_OPERATOR_SEMVER_GTE = 17,
+  CONSTRAINT_OPERATOR_SEMVER_LT = 18,
+  CONSTRAINT_OPERATOR_SEMVER_LTE = 19,
+  /**
+   * CONSTRAINT_OPERATOR_BEFORE - RFC 3339 timestamp comparisons against values[0]. Either side may be "now":
+   * the operand literally, or a "now" attribute the context doesn't set.
+   */
+  CONSTRAINT_OPERATOR_BEFORE = 20,
+  CONSTRAINT_OPERATOR_AFTER = 21,
+  /**
+   * CONSTRAINT_OPERATOR_PERCENT_OF - Matches values[0] percent of the attribute's values, bucketed like
+   * targeting keys. An optional values[1] salts the bucketing.
+   */
+  CONSTRAINT_OPERATOR_PERCENT_OF = 22,
   UNRECOGNIZED = -1,
 }
 
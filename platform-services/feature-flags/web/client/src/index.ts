@@ -24,6 +24,16 @@ import {
 
 export * from "./model.js";
 
+/**
+ * Who an Admin call is made on behalf of. `accessToken` is the caller's bearer token,
+ * forwarded as `authorization` for the backend to authenticate and authorize; `actor`
+ * names the user for the audit log when the backend runs without auth.
+ */
+export interface Caller {
+  actor?: string;
+  accessToken?: string;
+}
+
 type UnaryMethod<Req, Res> = (
   request: Req,
   metadata: Metadata,
@@ -65,85 +75,86 @@ export class FeatureFlagClient {
 
   /**
    * Metadata carried on every request: `client-id` identifies this service to the backend
-   * (required on the evaluation path), and an optional `actor` names the acting user for
-   * the audit log.
+   * (required on the evaluation path), and the optional {@link Caller} adds its bearer
+   * token as `authorization` and its name as `actor`.
    */
-  private meta(actor?: string): Metadata {
+  private meta(caller?: Caller): Metadata {
     const metadata = new Metadata();
     metadata.set("client-id", this.clientId);
-    if (actor) metadata.set("actor", actor);
+    if (caller?.accessToken) metadata.set("authorization", `Bearer ${caller.accessToken}`);
+    if (caller?.actor) metadata.set("actor", caller.actor);
     return metadata;
   }
 
-  listFlags(includeArchived = false): Promise<ListFlagsResponse> {
-    return unary(this.admin.listFlags.bind(this.admin), { includeArchived }, this.meta());
+  listFlags(includeArchived = false, caller?: Caller): Promise<ListFlagsResponse> {
+    return unary(this.admin.listFlags.bind(this.admin), { includeArchived }, this.meta(caller));
   }
 
-  getFlag(key: string): Promise<Flag> {
-    return unary(this.admin.getFlag.bind(this.admin), { key }, this.meta());
+  getFlag(key: string, caller?: Caller): Promise<Flag> {
+    return unary(this.admin.getFlag.bind(this.admin), { key }, this.meta(caller));
   }
 
-  createFlag(request: CreateFlagRequest, actor?: string): Promise<Flag> {
-    return unary(this.admin.createFlag.bind(this.admin), request, this.meta(actor));
+  createFlag(request: CreateFlagRequest, caller?: Caller): Promise<Flag> {
+    return unary(this.admin.createFlag.bind(this.admin), request, this.meta(caller));
   }
 
   updateFlag(
     key: string,
     enabled: boolean,
     defaultVariantKey: string,
-    actor?: string,
+    caller?: Caller,
   ): Promise<Flag> {
     return unary(
       this.admin.updateFlag.bind(this.admin),
       { key, enabled, defaultVariantKey },
-      this.meta(actor),
+      this.meta(caller),
     );
   }
 
-  archiveFlag(key: string, archived: boolean, actor?: string): Promise<Flag> {
-    return unary(this.admin.archiveFlag.bind(this.admin), { key, archived }, this.meta(actor));
+  archiveFlag(key: string, archived: boolean, caller?: Caller): Promise<Flag> {
+    return unary(this.admin.archiveFlag.bind(this.admin), { key, archived }, this.meta(caller));
   }
 
-  deleteFlag(key: string, actor?: string): Promise<void> {
-    return unary(this.admin.deleteFlag.bind(this.admin), { key }, this.meta(actor)).then(
+  deleteFlag(key: string, caller?: Caller): Promise<void> {
+    return unary(this.admin.deleteFlag.bind(this.admin), { key }, this.meta(caller)).then(
       () => undefined,
     );
   }
 
-  upsertVariant(flagKey: string, variant: Variant, actor?: string): Promise<Flag> {
+  upsertVariant(flagKey: string, variant: Variant, caller?: Caller): Promise<Flag> {
     return unary(
       this.admin.upsertVariant.bind(this.admin),
       { flagKey, variant },
-      this.meta(actor),
+      this.meta(caller),
     );
   }
 
-  deleteVariant(flagKey: string, variantKey: string, actor?: string): Promise<Flag> {
+  deleteVariant(flagKey: string, variantKey: string, caller?: Caller): Promise<Flag> {
     return unary(
       this.admin.deleteVariant.bind(this.admin),
       { flagKey, variantKey },
-      this.meta(actor),
+      this.meta(caller),
     );
   }
 
-  setFlagRules(flagKey: string, rules: Rule[], actor?: string): Promise<Flag> {
+  setFlagRules(flagKey: string, rules: Rule[], caller?: Caller): Promise<Flag> {
     return unary(
       this.admin.setFlagRules.bind(this.admin),
       { flagKey, rules },
-      this.meta(actor),
+      this.meta(caller),
     );
   }
 
-  listSegments(): Promise<ListSegmentsResponse> {
-    return unary(this.admin.listSegments.bind(this.admin), {}, this.meta());
+  listSegments(caller?: Caller): Promise<ListSegmentsResponse> {
+    return unary(this.admin.listSegments.bind(this.admin), {}, this.meta(caller));
   }
 
-  upsertSegment(segment: Segment, actor?: string): Promise<Segment> {
-    return unary(this.admin.updateSegment.bind(this.admin), { segment }, this.meta(actor));
+  upsertSegment(segment: Segment, caller?: Caller): Promise<Segment> {
+    return unary(this.admin.updateSegment.bind(this.admin), { segment }, this.meta(caller));
   }
 
-  deleteSegment(key: string, actor?: string): Promise<void> {
-    return unary(this.admin.deleteSegment.bind(this.admin), { key }, this.meta(actor)).then(
+  deleteSegment(key: string, caller?: Caller): Promise<void> {
+    return unary(this.admin.deleteSegment.bind(this.admin), { key }, this.meta(caller)).then(
       () => undefined,
     );
   }
@@ -156,11 +167,12 @@ export class FeatureFlagClient {
     targetKind = "",
     targetKey = "",
     limit = 0,
+    caller?: Caller,
   ): Promise<ListChangesResponse> {
     return unary(
       this.admin.listChanges.bind(this.admin),
       { targetKind, targetKey, limit },
-      this.meta(),
+      this.meta(caller),
     );
   }
 
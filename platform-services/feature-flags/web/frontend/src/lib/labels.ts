@@ -31,6 +31,14 @@ export const operatorLabels: Record<number, string> = {
   [ConstraintOperator.CONSTRAINT_OPERATOR_EXISTS]: "exists",
   [ConstraintOperator.CONSTRAINT_OPERATOR_REGEX]: "regex",
   [ConstraintOperator.CONSTRAINT_OPERATOR_FLAG_MATCHES]: "flag matches",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_SEMVER_EQ]: "semver_eq",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_SEMVER_GT]: "semver_gt",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_SEMVER_GTE]: "semver_gte",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_SEMVER_LT]: "semver_lt",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_SEMVER_LTE]: "semver_lte",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_BEFORE]: "before",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_AFTER]: "after",
+  [ConstraintOperator.CONSTRAINT_OPERATOR_PERCENT_OF]: "percent_of",
 };
 
 export const operatorOptions = Object.entries(operatorLabels).map(([value, label]) => ({
@@ -1,3 +1,4 @@
+import type { Caller } from "@accurate0/feature-flag-client";
 import { env } from "$env/dynamic/private";
 
 /**
@@ -32,14 +33,22 @@ function tokenMeta(token: string): { jti: string; exp: number } | undefined {
 }
 
 /**
- * The acting user for audit logging, resolved from the OIDC userinfo endpoint using the
- * access token the gateway forwarded. Returns undefined when no identity is available, so
- * the backend falls back to recording `unknown` rather than failing the mutation.
+ * The caller to act as on the backend: the access token the gateway forwarded, which the
+ * backend authenticates and authorizes, and the acting user's name for audit logging when
+ * it runs without auth. Both are omitted when the request carries no token.
  */
-export async function actorFromRequest(request: Request): Promise<string | undefined> {
-  const token = bearer(request);
-  if (!token) return undefined;
+export async function callerFromRequest(request: Request): Promise<Caller> {
+  const accessToken = bearer(request);
+  if (!accessToken) return {};
+  return { accessToken, actor: await actorFor(accessToken) };
+}
 
+/**
+ * The acting user, resolved from the OIDC userinfo endpoint. Returns undefined when no
+ * identity is available, so the backend falls back to recording `unknown` rather than
+ * failing the mutation.
+ */
+async function actorFor(token: string): Promise<string | undefined> {
   const meta = tokenMeta(token);
   const now = Date.now();
   if (meta) {
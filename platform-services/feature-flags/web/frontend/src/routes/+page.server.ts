@@ -1,11 +1,11 @@
 import type { Actions, PageServerLoad } from "./$types";
 import { client } from "$lib/server/client";
-import { actorFromRequest } from "$lib/server/actor";
+import { callerFromRequest } from "$lib/server/actor";
 import { fail } from "@sveltejs/kit";
 
-export const load: PageServerLoad = async ({ url }) => {
+export const load: PageServerLoad = async ({ url, request }) => {
   const includeArchived = url.searchParams.get("archived") === "1";
-  const { flags } = await client.listFlags(includeArchived);
+  const { flags } = await client.listFlags(includeArchived, await callerFromRequest(request));
   return { flags, includeArchived };
 };
 
@@ -16,7 +16,7 @@ export const actions: Actions = {
     const defaultVariantKey = String(data.get("defaultVariantKey"));
     const enabled = data.get("enabled") === "true";
     try {
-      await client.updateFlag(key, enabled, defaultVariantKey, await actorFromRequest(request));
+      await client.updateFlag(key, enabled, defaultVariantKey, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
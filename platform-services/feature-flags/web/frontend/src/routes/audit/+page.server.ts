@@ -1,7 +1,8 @@
 import type { PageServerLoad } from "./$types";
 import { client } from "$lib/server/client";
+import { callerFromRequest } from "$lib/server/actor";
 
-export const load: PageServerLoad = async () => {
-  const { changes } = await client.listChanges();
+export const load: PageServerLoad = async ({ request }) => {
+  const { changes } = await client.listChanges("", "", 0, await callerFromRequest(request));
   return { changes };
 };
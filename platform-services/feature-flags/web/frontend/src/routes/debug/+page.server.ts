@@ -1,9 +1,10 @@
 import type { Actions, PageServerLoad } from "./$types";
 import { client } from "$lib/server/client";
+import { callerFromRequest } from "$lib/server/actor";
 import { fail } from "@sveltejs/kit";
 
-export const load: PageServerLoad = async () => {
-  const { flags } = await client.listFlags();
+export const load: PageServerLoad = async ({ request }) => {
+  const { flags } = await client.listFlags(false, await callerFromRequest(request));
   return { flags };
 };
 
@@ -29,7 +30,7 @@ export const actions: Actions = {
 
     try {
       if (flagKey) {
-        const flag = await client.getFlag(flagKey);
+        const flag = await client.getFlag(flagKey, await callerFromRequest(request));
         const evaluated = await client.resolve(flagKey, flag.valueType, targetingKey, attributes);
         return { flags: [evaluated], single: true, context, values };
       }
@@ -1,12 +1,13 @@
 import type { Actions, PageServerLoad } from "./$types";
 import { client, type Rule, type Constraint } from "$lib/server/client";
-import { actorFromRequest } from "$lib/server/actor";
+import { callerFromRequest } from "$lib/server/actor";
 import { error, fail, redirect } from "@sveltejs/kit";
 
-export const load: PageServerLoad = async ({ params }) => {
+export const load: PageServerLoad = async ({ params, request }) => {
+  const caller = await callerFromRequest(request);
   try {
-    const flag = await client.getFlag(params.key);
-    const { segments } = await client.listSegments();
+    const flag = await client.getFlag(params.key, caller);
+    const { segments } = await client.listSegments(caller);
     return { flag, segments };
   } catch (e) {
     error(404, (e as Error).message);
@@ -19,7 +20,7 @@ export const actions: Actions = {
     const enabled = data.get("enabled") === "on";
     const defaultVariantKey = String(data.get("defaultVariantKey"));
     try {
-      await client.updateFlag(params.key, enabled, defaultVariantKey, await actorFromRequest(request));
+      await client.updateFlag(params.key, enabled, defaultVariantKey, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
@@ -30,7 +31,7 @@ export const actions: Actions = {
     const data = await request.formData();
     const archived = data.get("archived") === "true";
     try {
-      await client.archiveFlag(params.key, archived, await actorFromRequest(request));
+      await client.archiveFlag(params.key, archived, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
@@ -39,7 +40,7 @@ export const actions: Actions = {
 
   delete: async ({ request, params }) => {
     try {
-      await client.deleteFlag(params.key, await actorFromRequest(request));
+      await client.deleteFlag(params.key, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
@@ -57,7 +58,7 @@ export const actions: Actions = {
       return fail(400, { message: `invalid variant value JSON: ${(e as Error).message}` });
     }
     try {
-      await client.upsertVariant(params.key, { key, value }, await actorFromRequest(request));
+      await client.upsertVariant(params.key, { key, value }, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
@@ -67,7 +68,7 @@ export const actions: Actions = {
   deleteVariant: async ({ request, params }) => {
     const data = await request.formData();
     try {
-      await client.deleteVariant(params.key, String(data.get("variantKey")), await actorFromRequest(request));
+      await client.deleteVariant(params.key, String(data.get("variantKey")), await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
@@ -108,7 +109,7 @@ export const actions: Actions = {
       return fail(400, { message: `invalid rules: ${(e as Error).message}` });
     }
     try {
-      await client.setFlagRules(params.key, rules, await actorFromRequest(request));
+      await client.setFlagRules(params.key, rules, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }
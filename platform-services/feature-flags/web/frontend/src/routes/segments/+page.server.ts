@@ -1,6 +1,6 @@
 import type { Actions, PageServerLoad } from "./$types";
 import { client, type Constraint } from "$lib/server/client";
-import { actorFromRequest } from "$lib/server/actor";
+import { callerFromRequest } from "$lib/server/actor";
 import { operatorLabels } from "$lib/labels";
 import { fail } from "@sveltejs/kit";
 
@@ -8,8 +8,8 @@ const operatorByLabel = new Map(
   Object.entries(operatorLabels).map(([value, label]) => [label, Number(value)]),
 );
 
-export const load: PageServerLoad = async () => {
-  const { segments } = await client.listSegments();
+export const load: PageServerLoad = async ({ request }) => {
+  const { segments } = await client.listSegments(await callerFromRequest(request));
   return { segments };
 };
 
@@ -38,7 +38,7 @@ export const actions: Actions = {
     }
 
     try {
-      await client.upsertSegment({ key, name, constraints }, await actorFromRequest(request));
+      await client.upsertSegment({ key, name, constraints }, await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message, values: { key, name, constraintsRaw } });
     }
@@ -48,7 +48,7 @@ export const actions: Actions = {
   delete: async ({ request }) => {
     const data = await request.formData();
     try {
-      await client.deleteSegment(String(data.get("key")), await actorFromRequest(request));
+      await client.deleteSegment(String(data.get("key")), await callerFromRequest(request));
     } catch (e) {
       return fail(400, { message: (e as Error).message });
     }